@@ -0,0 +1,8 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The gRPC service is generated from its definition, and only when
+    // it's wanted.
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/seymour.proto")?;
+
+    Ok(())
+}
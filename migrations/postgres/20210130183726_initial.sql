@@ -0,0 +1,53 @@
+-- Timestamps are stored as "YYYY-MM-DD HH:MM:SS" text in UTC to match the
+-- SQLite schema, so queries can be shared between the two backends.
+
+CREATE TABLE IF NOT EXISTS feeds (
+  id BIGSERIAL PRIMARY KEY,
+  url TEXT NOT NULL,
+  content_hash TEXT,
+  fetch_after TEXT,
+  failure_count BIGINT NOT NULL DEFAULT 0,
+  last_error TEXT,
+  feed_kind TEXT,
+  proxy TEXT,
+  apply_title_edits BIGINT NOT NULL DEFAULT 1,
+  gone_at TEXT,
+  title TEXT,
+  subtitle TEXT,
+
+  UNIQUE(url)
+);
+
+CREATE TABLE IF NOT EXISTS feed_entries (
+  id BIGSERIAL PRIMARY KEY,
+  feed_id BIGINT NOT NULL REFERENCES feeds(id) ON DELETE CASCADE,
+  title TEXT NOT NULL,
+  published_at TEXT NOT NULL,
+  url TEXT NOT NULL,
+
+  UNIQUE(feed_id, published_at, url)
+);
+
+CREATE TABLE IF NOT EXISTS users (
+  id BIGSERIAL PRIMARY KEY,
+  username TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS views (
+  user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  feed_entry_id BIGINT NOT NULL REFERENCES feed_entries(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS subscriptions (
+  user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  feed_id BIGINT NOT NULL REFERENCES feeds(id) ON DELETE CASCADE,
+
+  UNIQUE(user_id, feed_id)
+);
+
+CREATE TABLE IF NOT EXISTS entry_contents (
+  feed_entry_id BIGINT PRIMARY KEY REFERENCES feed_entries(id) ON DELETE CASCADE,
+  mime_type TEXT NOT NULL,
+  content TEXT NOT NULL,
+  fetched_at TEXT NOT NULL
+);
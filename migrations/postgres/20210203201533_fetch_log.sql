@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS fetch_log (
+  id BIGSERIAL PRIMARY KEY,
+  feed_id BIGINT NOT NULL REFERENCES feeds(id) ON DELETE CASCADE,
+  fetched_at TEXT NOT NULL,
+  duration_ms BIGINT NOT NULL,
+  -- NULL when the fetch failed before a response was received.
+  status BIGINT,
+  bytes BIGINT NOT NULL DEFAULT 0,
+  entries_inserted BIGINT NOT NULL DEFAULT 0,
+  error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS fetch_log_fetched_at ON fetch_log (fetched_at);
+CREATE INDEX IF NOT EXISTS fetch_log_feed_id ON fetch_log (feed_id);
@@ -0,0 +1,5 @@
+ALTER TABLE subscriptions ADD COLUMN created_at TEXT;
+-- Entries published before this aren't listed as unread. NULL exposes the
+-- feed's whole archive, which is what subscriptions made before this
+-- column existed get.
+ALTER TABLE subscriptions ADD COLUMN backlog_from TEXT;
@@ -0,0 +1,2 @@
+-- Whether new entries of the feed are announced to the user in chat.
+ALTER TABLE subscriptions ADD COLUMN notify BIGINT NOT NULL DEFAULT 0;
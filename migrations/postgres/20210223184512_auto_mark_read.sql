@@ -0,0 +1,2 @@
+-- Entries unread for this many days are marked read. NULL never does.
+ALTER TABLE users ADD COLUMN auto_mark_read_days BIGINT;
@@ -0,0 +1,2 @@
+-- Whether the user's subscriptions are left out of DISCOVER.
+ALTER TABLE users ADD COLUMN hide_from_directory BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,12 @@
+-- Whether the user may run admin commands. `seymour grantadmin` sets it.
+ALTER TABLE users ADD COLUMN is_admin BIGINT NOT NULL DEFAULT 0;
+
+-- Every admin command run, and by whom.
+CREATE TABLE IF NOT EXISTS audit_log (
+  id BIGSERIAL PRIMARY KEY,
+  username TEXT NOT NULL,
+  command TEXT NOT NULL,
+  created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_created_at ON audit_log (created_at);
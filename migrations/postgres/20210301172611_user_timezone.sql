@@ -0,0 +1,3 @@
+-- An IANA timezone name, e.g. 'Europe/Berlin', dates are shown to the user
+-- in. NULL is UTC.
+ALTER TABLE users ADD COLUMN timezone TEXT;
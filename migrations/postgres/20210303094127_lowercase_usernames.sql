@@ -0,0 +1,10 @@
+-- Usernames are case-insensitive and stored lowercase from now on. Those
+-- differing only by case from another user are left alone, and warned about
+-- at startup so they can be merged by hand.
+UPDATE users
+SET username = LOWER(username)
+WHERE username != LOWER(username)
+    AND NOT EXISTS (
+        SELECT 1 FROM users AS other
+        WHERE other.id != users.id AND LOWER(other.username) = LOWER(users.username)
+    );
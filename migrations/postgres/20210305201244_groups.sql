@@ -0,0 +1,21 @@
+-- Groups of users, e.g. a household, who share a set of feeds. Members are
+-- subscribed to each group feed individually, so read state stays their own.
+CREATE TABLE IF NOT EXISTS user_groups (
+  id BIGSERIAL PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS group_members (
+  group_id BIGINT NOT NULL REFERENCES user_groups(id) ON DELETE CASCADE,
+  user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+  UNIQUE(group_id, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS group_feeds (
+  group_id BIGINT NOT NULL REFERENCES user_groups(id) ON DELETE CASCADE,
+  feed_id BIGINT NOT NULL REFERENCES feeds(id) ON DELETE CASCADE,
+
+  UNIQUE(group_id, feed_id)
+);
@@ -0,0 +1,2 @@
+-- Until when the subscription's entries are left out of the unread list.
+ALTER TABLE subscriptions ADD COLUMN snoozed_until TEXT;
@@ -0,0 +1,3 @@
+-- How many of the feed's newest unread entries the user keeps. Older ones
+-- are marked read. NULL keeps them all.
+ALTER TABLE subscriptions ADD COLUMN max_unread BIGINT;
@@ -0,0 +1,12 @@
+-- Users are created with an upsert on their username from now on, which
+-- needs the name to be unique. Any exact duplicates left by concurrent
+-- sign-ins keep the oldest user's name; the rest are renamed to something
+-- invalid, so they're warned about at startup and can be merged by hand.
+UPDATE users
+SET username = username || '#' || CAST(id AS TEXT)
+WHERE EXISTS (
+    SELECT 1 FROM users AS older
+    WHERE older.username = users.username AND older.id < users.id
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS users_username ON users (username);
@@ -0,0 +1,57 @@
+-- Changes to users' read state and subscriptions, so SYNC can send a client
+-- only what changed since it last synced. Triggers record them whichever
+-- code path makes the change.
+CREATE TABLE IF NOT EXISTS sync_changes (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL,
+  -- 'read', 'unread', 'subscribe' or 'unsubscribe'.
+  kind TEXT NOT NULL,
+  feed_id BIGINT,
+  feed_entry_id BIGINT,
+  changed_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sync_changes_user_id ON sync_changes (user_id, id);
+CREATE INDEX IF NOT EXISTS sync_changes_changed_at ON sync_changes (changed_at);
+
+CREATE OR REPLACE FUNCTION record_view_sync_change() RETURNS trigger AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    INSERT INTO sync_changes (user_id, kind, feed_entry_id, changed_at)
+    VALUES (NEW.user_id, 'read', NEW.feed_entry_id,
+            to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'));
+    RETURN NEW;
+  END IF;
+
+  INSERT INTO sync_changes (user_id, kind, feed_entry_id, changed_at)
+  VALUES (OLD.user_id, 'unread', OLD.feed_entry_id,
+          to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'));
+  RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION record_subscription_sync_change() RETURNS trigger AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    INSERT INTO sync_changes (user_id, kind, feed_id, changed_at)
+    VALUES (NEW.user_id, 'subscribe', NEW.feed_id,
+            to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'));
+    RETURN NEW;
+  END IF;
+
+  INSERT INTO sync_changes (user_id, kind, feed_id, changed_at)
+  VALUES (OLD.user_id, 'unsubscribe', OLD.feed_id,
+          to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'));
+  RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS views_sync_changes ON views;
+CREATE TRIGGER views_sync_changes
+AFTER INSERT OR DELETE ON views
+FOR EACH ROW EXECUTE PROCEDURE record_view_sync_change();
+
+DROP TRIGGER IF EXISTS subscriptions_sync_changes ON subscriptions;
+CREATE TRIGGER subscriptions_sync_changes
+AFTER INSERT OR DELETE ON subscriptions
+FOR EACH ROW EXECUTE PROCEDURE record_subscription_sync_change();
@@ -0,0 +1,11 @@
+-- Read markers copied from another instance for entries this one hasn't
+-- fetched yet. The fetcher turns them into views when the entry shows up.
+CREATE TABLE IF NOT EXISTS pending_views (
+  user_id BIGINT NOT NULL,
+  feed_id BIGINT NOT NULL,
+  entry_url TEXT NOT NULL,
+  viewed_at TEXT,
+  UNIQUE (user_id, feed_id, entry_url)
+);
+
+CREATE INDEX IF NOT EXISTS pending_views_feed_id ON pending_views (feed_id, entry_url);
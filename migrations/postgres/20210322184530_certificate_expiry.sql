@@ -0,0 +1,4 @@
+-- When a pinned certificate expires, and when it last replaced an expired
+-- one.
+ALTER TABLE known_certificates ADD COLUMN not_after TEXT;
+ALTER TABLE known_certificates ADD COLUMN rotated_at TEXT;
@@ -0,0 +1,4 @@
+-- The newest entry seen in each feed, so older ones can be skipped while
+-- it's still there unchanged.
+ALTER TABLE feeds ADD COLUMN last_seen_published_at TEXT;
+ALTER TABLE feeds ADD COLUMN last_seen_url TEXT;
@@ -0,0 +1,3 @@
+-- When a feed passed the operator's limit on consecutive fetch failures,
+-- until it next fetches successfully.
+ALTER TABLE feeds ADD COLUMN failing_since TEXT;
@@ -0,0 +1,139 @@
+-- How many entries of each subscribed feed a user hasn't read, kept up to
+-- date by triggers so counting unread entries doesn't scan them. Snoozes and
+-- dedupe are applied when reading the counts.
+CREATE TABLE IF NOT EXISTS unread_counts (
+  user_id BIGINT NOT NULL,
+  feed_id BIGINT NOT NULL,
+  count BIGINT NOT NULL,
+  PRIMARY KEY (user_id, feed_id)
+);
+
+INSERT INTO unread_counts (user_id, feed_id, count)
+SELECT subscriptions.user_id, subscriptions.feed_id, (
+  SELECT COUNT(*) FROM feed_entries
+  WHERE feed_entries.feed_id = subscriptions.feed_id
+    AND (subscriptions.backlog_from IS NULL
+         OR feed_entries.published_at >= subscriptions.backlog_from)
+    AND NOT EXISTS (
+      SELECT 1 FROM views
+      WHERE views.user_id = subscriptions.user_id AND views.feed_entry_id = feed_entries.id
+    )
+)
+FROM subscriptions
+ON CONFLICT DO NOTHING;
+
+-- Adds `delta` to the counts of the users who'd see `entry_id` in `feed`
+-- and haven't read it.
+CREATE OR REPLACE FUNCTION adjust_unread_counts(
+  entry_id BIGINT, feed BIGINT, published_at TEXT, delta BIGINT
+) RETURNS void AS $$
+BEGIN
+  UPDATE unread_counts SET count = count + delta
+  WHERE unread_counts.feed_id = feed
+    AND unread_counts.user_id IN (
+      SELECT subscriptions.user_id FROM subscriptions
+      WHERE subscriptions.feed_id = feed
+        AND (subscriptions.backlog_from IS NULL
+             OR adjust_unread_counts.published_at >= subscriptions.backlog_from)
+    )
+    AND NOT EXISTS (
+      SELECT 1 FROM views
+      WHERE views.user_id = unread_counts.user_id AND views.feed_entry_id = entry_id
+    );
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION count_subscription_unread() RETURNS trigger AS $$
+BEGIN
+  IF TG_OP = 'DELETE' THEN
+    DELETE FROM unread_counts WHERE user_id = OLD.user_id AND feed_id = OLD.feed_id;
+    RETURN OLD;
+  END IF;
+
+  INSERT INTO unread_counts (user_id, feed_id, count)
+  SELECT NEW.user_id, NEW.feed_id, COUNT(*) FROM feed_entries
+  WHERE feed_entries.feed_id = NEW.feed_id
+    AND (NEW.backlog_from IS NULL OR feed_entries.published_at >= NEW.backlog_from)
+    AND NOT EXISTS (
+      SELECT 1 FROM views
+      WHERE views.user_id = NEW.user_id AND views.feed_entry_id = feed_entries.id
+    )
+  ON CONFLICT (user_id, feed_id) DO UPDATE SET count = EXCLUDED.count;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Deletions are counted before the row goes, so the views of the entry are
+-- still there to tell who had read it.
+CREATE OR REPLACE FUNCTION count_entry_unread() RETURNS trigger AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    PERFORM adjust_unread_counts(NEW.id, NEW.feed_id, NEW.published_at, 1);
+    RETURN NEW;
+  ELSIF TG_OP = 'DELETE' THEN
+    PERFORM adjust_unread_counts(OLD.id, OLD.feed_id, OLD.published_at, -1);
+    RETURN OLD;
+  END IF;
+
+  -- Entries move between feeds when duplicate feeds are merged.
+  IF OLD.feed_id <> NEW.feed_id OR OLD.published_at <> NEW.published_at THEN
+    PERFORM adjust_unread_counts(OLD.id, OLD.feed_id, OLD.published_at, -1);
+    PERFORM adjust_unread_counts(NEW.id, NEW.feed_id, NEW.published_at, 1);
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Views can repeat, and row triggers only see a statement's views once all
+-- of them are in, so the subscriptions whose entries were read or unread are
+-- counted again instead. Views removed because their entry was deleted find
+-- no entry, which count_entry_unread has already accounted for.
+CREATE OR REPLACE FUNCTION count_view_unread() RETURNS trigger AS $$
+BEGIN
+  UPDATE unread_counts SET count = (
+    SELECT COUNT(*) FROM feed_entries
+    INNER JOIN subscriptions ON subscriptions.feed_id = feed_entries.feed_id
+    WHERE subscriptions.user_id = unread_counts.user_id
+      AND subscriptions.feed_id = unread_counts.feed_id
+      AND (subscriptions.backlog_from IS NULL
+           OR feed_entries.published_at >= subscriptions.backlog_from)
+      AND NOT EXISTS (
+        SELECT 1 FROM views
+        WHERE views.user_id = unread_counts.user_id AND views.feed_entry_id = feed_entries.id
+      )
+  )
+  WHERE (unread_counts.user_id, unread_counts.feed_id) IN (
+    SELECT DISTINCT changed_views.user_id, feed_entries.feed_id
+    FROM changed_views
+    INNER JOIN feed_entries ON feed_entries.id = changed_views.feed_entry_id
+  );
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS subscriptions_unread_counts ON subscriptions;
+CREATE TRIGGER subscriptions_unread_counts
+AFTER INSERT OR DELETE OR UPDATE OF backlog_from ON subscriptions
+FOR EACH ROW EXECUTE PROCEDURE count_subscription_unread();
+
+DROP TRIGGER IF EXISTS feed_entries_unread_counts ON feed_entries;
+CREATE TRIGGER feed_entries_unread_counts
+AFTER INSERT OR UPDATE OF feed_id, published_at ON feed_entries
+FOR EACH ROW EXECUTE PROCEDURE count_entry_unread();
+
+DROP TRIGGER IF EXISTS feed_entries_delete_unread_counts ON feed_entries;
+CREATE TRIGGER feed_entries_delete_unread_counts
+BEFORE DELETE ON feed_entries
+FOR EACH ROW EXECUTE PROCEDURE count_entry_unread();
+
+DROP TRIGGER IF EXISTS views_insert_unread_counts ON views;
+CREATE TRIGGER views_insert_unread_counts
+AFTER INSERT ON views
+REFERENCING NEW TABLE AS changed_views
+FOR EACH STATEMENT EXECUTE PROCEDURE count_view_unread();
+
+DROP TRIGGER IF EXISTS views_delete_unread_counts ON views;
+CREATE TRIGGER views_delete_unread_counts
+AFTER DELETE ON views
+REFERENCING OLD TABLE AS changed_views
+FOR EACH STATEMENT EXECUTE PROCEDURE count_view_unread();
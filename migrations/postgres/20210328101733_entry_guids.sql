@@ -0,0 +1,3 @@
+-- Atom entries' IDs, which identify them even when their links change.
+ALTER TABLE feed_entries ADD COLUMN guid TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS feed_entries_guid ON feed_entries (feed_id, guid);
@@ -0,0 +1,15 @@
+-- An entry is read once per user, so marking it read again changes nothing.
+-- Repeats from before are dropped, keeping the first. Their sync trigger is
+-- left out while they go, since the entry hasn't become unread.
+ALTER TABLE views DISABLE TRIGGER views_sync_changes;
+
+DELETE FROM views AS repeat
+USING views AS first
+WHERE repeat.user_id = first.user_id
+  AND repeat.feed_entry_id = first.feed_entry_id
+  AND repeat.ctid > first.ctid;
+
+ALTER TABLE views ENABLE TRIGGER views_sync_changes;
+
+DROP INDEX IF EXISTS views_user_entry;
+CREATE UNIQUE INDEX IF NOT EXISTS views_user_entry ON views (user_id, feed_entry_id);
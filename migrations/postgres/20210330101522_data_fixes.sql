@@ -0,0 +1,6 @@
+-- One-time fixes to existing data that need more than SQL, which run after
+-- the migrations and are recorded here so they never run again.
+CREATE TABLE IF NOT EXISTS data_fixes (
+  name TEXT PRIMARY KEY NOT NULL,
+  applied_at TEXT NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS entry_contents (
+  feed_entry_id INTEGER PRIMARY KEY NOT NULL,
+  mime_type TEXT NOT NULL,
+  content TEXT NOT NULL,
+  fetched_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+  FOREIGN KEY(feed_entry_id) REFERENCES feed_entries(id) ON DELETE CASCADE
+);
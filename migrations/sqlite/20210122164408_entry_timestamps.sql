@@ -0,0 +1,4 @@
+-- Entries used to be stored with date-only YYYY-MM-DD timestamps.
+UPDATE feed_entries
+SET published_at = published_at || ' 00:00:00'
+WHERE length(published_at) = 10;
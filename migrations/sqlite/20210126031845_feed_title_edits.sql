@@ -0,0 +1 @@
+ALTER TABLE feeds ADD COLUMN apply_title_edits INTEGER NOT NULL DEFAULT 1;
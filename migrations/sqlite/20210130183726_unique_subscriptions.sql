@@ -0,0 +1,6 @@
+DELETE FROM subscriptions
+WHERE rowid NOT IN (
+  SELECT MIN(rowid) FROM subscriptions GROUP BY user_id, feed_id
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS subscriptions_user_feed ON subscriptions (user_id, feed_id);
@@ -0,0 +1,18 @@
+-- Foreign keys are only enforced when a connection enables them, so views
+-- of deleted entries and users could be left behind. Clean those up and use
+-- triggers so the cascade happens regardless.
+DELETE FROM views
+WHERE feed_entry_id NOT IN (SELECT id FROM feed_entries)
+  OR user_id NOT IN (SELECT id FROM users);
+
+CREATE TRIGGER IF NOT EXISTS feed_entries_delete_views
+AFTER DELETE ON feed_entries
+BEGIN
+  DELETE FROM views WHERE feed_entry_id = old.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS users_delete_views
+AFTER DELETE ON users
+BEGIN
+  DELETE FROM views WHERE user_id = old.id;
+END;
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS fetch_log (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  feed_id INTEGER NOT NULL,
+  fetched_at TEXT NOT NULL,
+  duration_ms INTEGER NOT NULL,
+  -- NULL when the fetch failed before a response was received.
+  status INTEGER,
+  bytes INTEGER NOT NULL DEFAULT 0,
+  entries_inserted INTEGER NOT NULL DEFAULT 0,
+  error TEXT,
+
+  FOREIGN KEY(feed_id) REFERENCES feeds(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS fetch_log_fetched_at ON fetch_log (fetched_at);
+CREATE INDEX IF NOT EXISTS fetch_log_feed_id ON fetch_log (feed_id);
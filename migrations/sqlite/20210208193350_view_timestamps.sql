@@ -0,0 +1,2 @@
+-- NULL for entries marked read before this was recorded.
+ALTER TABLE views ADD COLUMN viewed_at TEXT;
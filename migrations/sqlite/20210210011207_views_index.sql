@@ -0,0 +1,3 @@
+-- Lets the unread query check whether an entry has been read with an index
+-- lookup instead of scanning all of a user's views.
+CREATE INDEX IF NOT EXISTS views_user_entry ON views (user_id, feed_entry_id);
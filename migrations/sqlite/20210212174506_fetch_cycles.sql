@@ -0,0 +1,5 @@
+-- A single row recording when the fetcher last finished checking feeds.
+CREATE TABLE IF NOT EXISTS fetch_cycles (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  completed_at TEXT NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Links Gemini client certificates, by SHA-256 fingerprint, to users.
+CREATE TABLE IF NOT EXISTS client_certificates (
+  fingerprint TEXT PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  created_at TEXT NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Bearer tokens for the HTTP API, stored as SHA-256 hashes.
+CREATE TABLE IF NOT EXISTS api_tokens (
+  token_hash TEXT PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  created_at TEXT NOT NULL
+);
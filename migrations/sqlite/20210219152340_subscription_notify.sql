@@ -0,0 +1,2 @@
+-- Whether new entries of the feed are announced to the user in chat.
+ALTER TABLE subscriptions ADD COLUMN notify INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,25 @@
+-- Groups of users, e.g. a household, who share a set of feeds. Members are
+-- subscribed to each group feed individually, so read state stays their own.
+CREATE TABLE IF NOT EXISTS user_groups (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  name TEXT NOT NULL UNIQUE,
+  created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS group_members (
+  group_id INTEGER NOT NULL,
+  user_id INTEGER NOT NULL,
+
+  UNIQUE(group_id, user_id),
+  FOREIGN KEY(group_id) REFERENCES user_groups(id) ON DELETE CASCADE,
+  FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS group_feeds (
+  group_id INTEGER NOT NULL,
+  feed_id INTEGER NOT NULL,
+
+  UNIQUE(group_id, feed_id),
+  FOREIGN KEY(group_id) REFERENCES user_groups(id) ON DELETE CASCADE,
+  FOREIGN KEY(feed_id) REFERENCES feeds(id) ON DELETE CASCADE
+);
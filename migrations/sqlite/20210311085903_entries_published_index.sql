@@ -0,0 +1,3 @@
+-- Lets LISTUNREAD produce entries in publication order without sorting
+-- them all first.
+CREATE INDEX IF NOT EXISTS feed_entries_published_at ON feed_entries (published_at, id);
@@ -0,0 +1,6 @@
+-- An entry's URL without its scheme and host, which together with its
+-- publication time identifies it across feed URL changes. Filled in for
+-- existing entries at startup.
+ALTER TABLE feed_entries ADD COLUMN entry_key TEXT;
+
+CREATE INDEX IF NOT EXISTS feed_entries_entry_key ON feed_entries (entry_key, published_at);
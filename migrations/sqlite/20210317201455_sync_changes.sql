@@ -0,0 +1,43 @@
+-- Changes to users' read state and subscriptions, so SYNC can send a client
+-- only what changed since it last synced. Triggers record them whichever
+-- code path makes the change.
+CREATE TABLE IF NOT EXISTS sync_changes (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  user_id INTEGER NOT NULL,
+  -- 'read', 'unread', 'subscribe' or 'unsubscribe'.
+  kind TEXT NOT NULL,
+  feed_id INTEGER,
+  feed_entry_id INTEGER,
+  changed_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sync_changes_user_id ON sync_changes (user_id, id);
+CREATE INDEX IF NOT EXISTS sync_changes_changed_at ON sync_changes (changed_at);
+
+CREATE TRIGGER IF NOT EXISTS views_insert_sync_changes
+AFTER INSERT ON views
+BEGIN
+  INSERT INTO sync_changes (user_id, kind, feed_entry_id, changed_at)
+  VALUES (new.user_id, 'read', new.feed_entry_id, strftime('%Y-%m-%d %H:%M:%S', 'now'));
+END;
+
+CREATE TRIGGER IF NOT EXISTS views_delete_sync_changes
+AFTER DELETE ON views
+BEGIN
+  INSERT INTO sync_changes (user_id, kind, feed_entry_id, changed_at)
+  VALUES (old.user_id, 'unread', old.feed_entry_id, strftime('%Y-%m-%d %H:%M:%S', 'now'));
+END;
+
+CREATE TRIGGER IF NOT EXISTS subscriptions_insert_sync_changes
+AFTER INSERT ON subscriptions
+BEGIN
+  INSERT INTO sync_changes (user_id, kind, feed_id, changed_at)
+  VALUES (new.user_id, 'subscribe', new.feed_id, strftime('%Y-%m-%d %H:%M:%S', 'now'));
+END;
+
+CREATE TRIGGER IF NOT EXISTS subscriptions_delete_sync_changes
+AFTER DELETE ON subscriptions
+BEGIN
+  INSERT INTO sync_changes (user_id, kind, feed_id, changed_at)
+  VALUES (old.user_id, 'unsubscribe', old.feed_id, strftime('%Y-%m-%d %H:%M:%S', 'now'));
+END;
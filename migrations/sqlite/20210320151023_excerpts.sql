@@ -0,0 +1,2 @@
+-- Whether entry listings include excerpts of their fetched content.
+ALTER TABLE users ADD COLUMN excerpts INTEGER NOT NULL DEFAULT 0;
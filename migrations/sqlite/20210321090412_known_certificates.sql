@@ -0,0 +1,9 @@
+-- Certificates pinned on first use, with TLS_POLICY=tofu.
+CREATE TABLE IF NOT EXISTS known_certificates (
+  host TEXT NOT NULL,
+  port INTEGER NOT NULL,
+  -- Hex SHA-256 of the leaf certificate.
+  fingerprint TEXT NOT NULL,
+  first_seen_at TEXT NOT NULL,
+  PRIMARY KEY (host, port)
+);
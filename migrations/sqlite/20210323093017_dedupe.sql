@@ -0,0 +1,5 @@
+-- Whether unread entries leave out ones whose URL the user already has from
+-- another feed, as with aggregators.
+ALTER TABLE users ADD COLUMN dedupe INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS feed_entries_url ON feed_entries (url);
@@ -0,0 +1,2 @@
+-- What the feed says about an entry, kept with ENTRY_SUMMARIES.
+ALTER TABLE feed_entries ADD COLUMN summary TEXT;
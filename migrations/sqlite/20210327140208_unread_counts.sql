@@ -0,0 +1,159 @@
+-- How many entries of each subscribed feed a user hasn't read, kept up to
+-- date by triggers so counting unread entries doesn't scan them. Snoozes and
+-- dedupe are applied when reading the counts.
+CREATE TABLE IF NOT EXISTS unread_counts (
+  user_id INTEGER NOT NULL,
+  feed_id INTEGER NOT NULL,
+  count INTEGER NOT NULL,
+  PRIMARY KEY (user_id, feed_id)
+);
+
+INSERT INTO unread_counts (user_id, feed_id, count)
+SELECT subscriptions.user_id, subscriptions.feed_id, (
+  SELECT COUNT(*) FROM feed_entries
+  WHERE feed_entries.feed_id = subscriptions.feed_id
+    AND (subscriptions.backlog_from IS NULL
+         OR feed_entries.published_at >= subscriptions.backlog_from)
+    AND NOT EXISTS (
+      SELECT 1 FROM views
+      WHERE views.user_id = subscriptions.user_id AND views.feed_entry_id = feed_entries.id
+    )
+)
+FROM subscriptions;
+
+CREATE TRIGGER IF NOT EXISTS subscriptions_insert_unread_counts
+AFTER INSERT ON subscriptions
+BEGIN
+  INSERT INTO unread_counts (user_id, feed_id, count)
+  SELECT new.user_id, new.feed_id, COUNT(*) FROM feed_entries
+  WHERE feed_entries.feed_id = new.feed_id
+    AND (new.backlog_from IS NULL OR feed_entries.published_at >= new.backlog_from)
+    AND NOT EXISTS (
+      SELECT 1 FROM views
+      WHERE views.user_id = new.user_id AND views.feed_entry_id = feed_entries.id
+    );
+END;
+
+CREATE TRIGGER IF NOT EXISTS subscriptions_backlog_unread_counts
+AFTER UPDATE OF backlog_from ON subscriptions
+BEGIN
+  UPDATE unread_counts SET count = (
+    SELECT COUNT(*) FROM feed_entries
+    WHERE feed_entries.feed_id = new.feed_id
+      AND (new.backlog_from IS NULL OR feed_entries.published_at >= new.backlog_from)
+      AND NOT EXISTS (
+        SELECT 1 FROM views
+        WHERE views.user_id = new.user_id AND views.feed_entry_id = feed_entries.id
+      )
+  )
+  WHERE user_id = new.user_id AND feed_id = new.feed_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS subscriptions_delete_unread_counts
+AFTER DELETE ON subscriptions
+BEGIN
+  DELETE FROM unread_counts WHERE user_id = old.user_id AND feed_id = old.feed_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS feed_entries_insert_unread_counts
+AFTER INSERT ON feed_entries
+BEGIN
+  UPDATE unread_counts SET count = count + 1
+  WHERE feed_id = new.feed_id
+    AND user_id IN (
+      SELECT user_id FROM subscriptions
+      WHERE feed_id = new.feed_id
+        AND (backlog_from IS NULL OR new.published_at >= backlog_from)
+    );
+END;
+
+-- Before, so the views of the entry are still there to tell who had read it.
+CREATE TRIGGER IF NOT EXISTS feed_entries_delete_unread_counts
+BEFORE DELETE ON feed_entries
+BEGIN
+  UPDATE unread_counts SET count = count - 1
+  WHERE feed_id = old.feed_id
+    AND user_id IN (
+      SELECT user_id FROM subscriptions
+      WHERE feed_id = old.feed_id
+        AND (backlog_from IS NULL OR old.published_at >= backlog_from)
+    )
+    AND NOT EXISTS (
+      SELECT 1 FROM views
+      WHERE views.user_id = unread_counts.user_id AND views.feed_entry_id = old.id
+    );
+END;
+
+-- Entries move between feeds when duplicate feeds are merged.
+CREATE TRIGGER IF NOT EXISTS feed_entries_update_unread_counts
+AFTER UPDATE OF feed_id, published_at ON feed_entries
+WHEN old.feed_id <> new.feed_id OR old.published_at <> new.published_at
+BEGIN
+  UPDATE unread_counts SET count = count - 1
+  WHERE feed_id = old.feed_id
+    AND user_id IN (
+      SELECT user_id FROM subscriptions
+      WHERE feed_id = old.feed_id
+        AND (backlog_from IS NULL OR old.published_at >= backlog_from)
+    )
+    AND NOT EXISTS (
+      SELECT 1 FROM views
+      WHERE views.user_id = unread_counts.user_id AND views.feed_entry_id = old.id
+    );
+
+  UPDATE unread_counts SET count = count + 1
+  WHERE feed_id = new.feed_id
+    AND user_id IN (
+      SELECT user_id FROM subscriptions
+      WHERE feed_id = new.feed_id
+        AND (backlog_from IS NULL OR new.published_at >= backlog_from)
+    )
+    AND NOT EXISTS (
+      SELECT 1 FROM views
+      WHERE views.user_id = unread_counts.user_id AND views.feed_entry_id = new.id
+    );
+END;
+
+-- Only the first view of an entry makes it read, and only the last one
+-- removed makes it unread again.
+CREATE TRIGGER IF NOT EXISTS views_insert_unread_counts
+AFTER INSERT ON views
+WHEN (
+  SELECT COUNT(*) FROM views
+  WHERE user_id = new.user_id AND feed_entry_id = new.feed_entry_id
+) = 1
+BEGIN
+  UPDATE unread_counts SET count = count - 1
+  WHERE user_id = new.user_id
+    AND feed_id = (SELECT feed_id FROM feed_entries WHERE id = new.feed_entry_id)
+    AND EXISTS (
+      SELECT 1 FROM subscriptions
+      INNER JOIN feed_entries ON feed_entries.feed_id = subscriptions.feed_id
+      WHERE subscriptions.user_id = new.user_id
+        AND feed_entries.id = new.feed_entry_id
+        AND (subscriptions.backlog_from IS NULL
+             OR feed_entries.published_at >= subscriptions.backlog_from)
+    );
+END;
+
+-- Views removed because their entry was deleted find no entry, which
+-- feed_entries_delete_unread_counts has already accounted for.
+CREATE TRIGGER IF NOT EXISTS views_delete_unread_counts
+AFTER DELETE ON views
+WHEN NOT EXISTS (
+  SELECT 1 FROM views
+  WHERE user_id = old.user_id AND feed_entry_id = old.feed_entry_id
+)
+BEGIN
+  UPDATE unread_counts SET count = count + 1
+  WHERE user_id = old.user_id
+    AND feed_id = (SELECT feed_id FROM feed_entries WHERE id = old.feed_entry_id)
+    AND EXISTS (
+      SELECT 1 FROM subscriptions
+      INNER JOIN feed_entries ON feed_entries.feed_id = subscriptions.feed_id
+      WHERE subscriptions.user_id = old.user_id
+        AND feed_entries.id = old.feed_entry_id
+        AND (subscriptions.backlog_from IS NULL
+             OR feed_entries.published_at >= subscriptions.backlog_from)
+    );
+END;
@@ -0,0 +1,19 @@
+-- An entry is read once per user, so marking it read again changes nothing.
+-- Repeats from before are dropped, keeping the first. Their sync trigger is
+-- left out while they go, since the entry hasn't become unread.
+DROP TRIGGER IF EXISTS views_delete_sync_changes;
+
+DELETE FROM views
+WHERE rowid NOT IN (
+  SELECT MIN(rowid) FROM views GROUP BY user_id, feed_entry_id
+);
+
+CREATE TRIGGER IF NOT EXISTS views_delete_sync_changes
+AFTER DELETE ON views
+BEGIN
+  INSERT INTO sync_changes (user_id, kind, feed_entry_id, changed_at)
+  VALUES (old.user_id, 'unread', old.feed_entry_id, strftime('%Y-%m-%d %H:%M:%S', 'now'));
+END;
+
+DROP INDEX IF EXISTS views_user_entry;
+CREATE UNIQUE INDEX IF NOT EXISTS views_user_entry ON views (user_id, feed_entry_id);
@@ -0,0 +1,6 @@
+-- Feeds by their canonical URL without a trailing slash, so that adding a
+-- feed twice under different spellings trips the unique index. Existing
+-- feeds get theirs from the merge_duplicate_feeds data fix.
+ALTER TABLE feeds ADD COLUMN url_key TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS feeds_unique_url_key ON feeds (url_key);
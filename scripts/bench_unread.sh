@@ -0,0 +1,87 @@
+#!/bin/bash
+
+# Seeds a throwaway SQLite database and times the unread query as it was
+# before the views_user_entry index and its rewrite as joins, and after.
+#
+# Usage: scripts/bench_unread.sh [users] [feeds] [entries per feed]
+
+set -e
+
+USERS=${1:-50}
+FEEDS=${2:-500}
+ENTRIES=${3:-400}
+
+DATABASE_FILE=$(mktemp --suffix .db)
+trap 'rm -f "$DATABASE_FILE"' EXIT
+
+cat migrations/sqlite/*.sql | sqlite3 "$DATABASE_FILE"
+
+echo "Seeding $USERS users, $FEEDS feeds and $((FEEDS * ENTRIES)) entries"
+
+sqlite3 "$DATABASE_FILE" <<SQL
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < $USERS)
+INSERT INTO users (username) SELECT 'user' || i FROM n;
+
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < $FEEDS)
+INSERT INTO feeds (url) SELECT 'gemini://capsule' || i || '.example/gemlog/' FROM n;
+
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < $ENTRIES)
+INSERT INTO feed_entries (feed_id, title, published_at, url)
+SELECT feeds.id, 'Entry ' || n.i, datetime('2020-01-01', '+' || n.i || ' days'), feeds.url || n.i || '.gmi'
+FROM feeds, n;
+
+-- Everyone subscribes to a tenth of the feeds and has read most of them.
+INSERT INTO subscriptions (user_id, feed_id)
+SELECT users.id, feeds.id FROM users, feeds WHERE (users.id + feeds.id) % 10 = 0;
+
+INSERT INTO views (user_id, feed_entry_id, viewed_at)
+SELECT subscriptions.user_id, feed_entries.id, '2021-01-01 00:00:00'
+FROM subscriptions
+INNER JOIN feed_entries ON feed_entries.feed_id = subscriptions.feed_id
+WHERE feed_entries.id % 4 != 0;
+
+ANALYZE;
+SQL
+
+echo
+echo "Subqueries without views_user_entry (before):"
+sqlite3 "$DATABASE_FILE" <<SQL | grep "Run Time"
+DROP INDEX views_user_entry;
+.timer on
+SELECT
+    feed_entries.id, feed_entries.feed_id, feeds.url AS feed_url, feed_entries.url, feed_entries.title
+FROM feed_entries
+LEFT JOIN feeds ON feed_entries.feed_id = feeds.id
+INNER JOIN subscriptions ON feed_entries.feed_id = subscriptions.feed_id
+    WHERE subscriptions.user_id = 1
+    AND feed_entries.id NOT IN (
+        SELECT feed_entry_id FROM views WHERE user_id = 1
+    )
+    AND (
+        subscriptions.backlog_from IS NULL
+        OR feed_entries.published_at >= subscriptions.backlog_from
+    )
+ORDER BY feed_entries.published_at;
+SQL
+
+echo
+echo "Joins with views_user_entry (after):"
+sqlite3 "$DATABASE_FILE" <<SQL | grep "Run Time"
+CREATE INDEX views_user_entry ON views (user_id, feed_entry_id);
+ANALYZE;
+.timer on
+SELECT
+    feed_entries.id, feed_entries.feed_id, feeds.url AS feed_url, feed_entries.url, feed_entries.title
+FROM subscriptions
+INNER JOIN feeds ON subscriptions.feed_id = feeds.id
+INNER JOIN feed_entries ON feed_entries.feed_id = subscriptions.feed_id
+LEFT JOIN views
+    ON views.feed_entry_id = feed_entries.id AND views.user_id = subscriptions.user_id
+WHERE subscriptions.user_id = 1
+    AND views.feed_entry_id IS NULL
+    AND (
+        subscriptions.backlog_from IS NULL
+        OR feed_entries.published_at >= subscriptions.backlog_from
+    )
+ORDER BY feed_entries.published_at;
+SQL
@@ -0,0 +1,14 @@
+#!/bin/bash
+
+# Builds, lints and tests the server with each optional feature on its own
+# and with all of them, since the default build leaves their code out.
+#
+# Usage: scripts/check_features.sh
+
+set -e
+
+for features in "" grpc postgres grpc,postgres; do
+  echo "Checking features: ${features:-(default)}"
+  cargo clippy --all-targets --features "$features" -- -D warnings
+  cargo test --features "$features"
+done
@@ -16,6 +16,6 @@ if [[ -f "$DATABASE_FILE" ]]; then
 fi
 
 sqlx database create
-sqlx migrate run
+sqlx migrate --source migrations/sqlite run
 
 sqlite3 "$DATABASE_FILE" < scripts/test.sql
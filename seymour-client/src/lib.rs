@@ -0,0 +1,341 @@
+//! An async client for seymour's line protocol, for writing bots and
+//! readers without handling the protocol by hand.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), seymour_client::Error> {
+//! let mut client = seymour_client::Client::connect("localhost:2003").await?;
+//! client.login("alice").await?;
+//! for entry in client.list_unread().await? {
+//!     println!("{} {}", entry.title, entry.url);
+//!     client.mark_read(entry.id).await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::io;
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+pub use seymour_protocol::{
+    Command, ErrorCode, ErrorDetail, FeedStatus, InvalidResponse, Response,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The server closed the connection.
+    Closed,
+    InvalidResponse(InvalidResponse),
+    /// The server answered with a 4x or 5x response.
+    Server(Response),
+    /// The server answered with a response that doesn't fit the command.
+    Unexpected(Response),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Closed => write!(f, "server closed the connection"),
+            Error::InvalidResponse(e) => write!(f, "{}", e),
+            Error::Server(response) => write!(f, "server error: {}", response),
+            Error::Unexpected(response) => write!(f, "unexpected response: {}", response),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<InvalidResponse> for Error {
+    fn from(error: InvalidResponse) -> Self {
+        Error::InvalidResponse(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Subscription {
+    /// The feed's ID.
+    pub id: i64,
+    pub url: String,
+    pub status: FeedStatus,
+    /// How many of the feed's entries haven't been read.
+    #[serde(default)]
+    pub unread: i64,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: i64,
+    pub feed_id: i64,
+    pub feed_url: String,
+    pub url: String,
+    pub title: String,
+    /// Not sent with read history.
+    #[serde(default)]
+    pub feed_title: Option<String>,
+}
+
+/// What an entry links to, as fetched by the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntryContent {
+    pub id: i64,
+    pub mime_type: String,
+    pub content: String,
+}
+
+/// An entry the user has read, and when.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub entry: Entry,
+    /// In the user's timezone.
+    pub viewed_at: DateTime<FixedOffset>,
+}
+
+fn entries(responses: Vec<Response>) -> Result<Vec<Entry>, Error> {
+    responses
+        .into_iter()
+        // Sent to users who asked for excerpts, which `Entry` leaves out.
+        .filter(|response| !matches!(response, Response::Excerpt { .. }))
+        .map(|response| match response {
+            Response::Entry {
+                id,
+                feed_id,
+                feed_url,
+                url,
+                title,
+                feed_title,
+            } => Ok(Entry {
+                id,
+                feed_id,
+                feed_url,
+                url,
+                title,
+                feed_title,
+            }),
+            response => Err(Error::Unexpected(response)),
+        })
+        .collect()
+}
+
+pub struct Client {
+    lines: Lines<BufReader<ReadHalf<TcpStream>>>,
+    writer: WriteHalf<TcpStream>,
+}
+
+impl Client {
+    pub async fn connect(address: impl ToSocketAddrs) -> Result<Self, Error> {
+        let stream = TcpStream::connect(address).await?;
+        let (reader, writer) = tokio::io::split(stream);
+
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        })
+    }
+
+    async fn read_response(&mut self) -> Result<Response, Error> {
+        let line = self.lines.next_line().await?.ok_or(Error::Closed)?;
+        Ok(line.parse()?)
+    }
+
+    /// Sends a command and reads the first line of its response.
+    async fn send_one(&mut self, command: &Command) -> Result<Response, Error> {
+        self.writer
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await?;
+
+        let response = self.read_response().await?;
+        if response.is_error() {
+            return Err(Error::Server(response));
+        }
+
+        Ok(response)
+    }
+
+    /// Sends any command, returning its response, or a list's items
+    /// without its start and end.
+    pub async fn send(&mut self, command: &Command) -> Result<Vec<Response>, Error> {
+        let first = self.send_one(command).await?;
+        if !first.starts_list() {
+            return Ok(vec![first]);
+        }
+
+        self.read_list().await
+    }
+
+    /// Reads a list's items, after its start, up to its end. A list the
+    /// server failed partway through has an error before its end, which is
+    /// returned instead.
+    async fn read_list(&mut self) -> Result<Vec<Response>, Error> {
+        let mut items = Vec::new();
+        let mut error = None;
+        loop {
+            match self.read_response().await? {
+                Response::EndList => break,
+                item if item.is_error() => error = Some(item),
+                item => items.push(item),
+            }
+        }
+
+        match error {
+            Some(error) => Err(Error::Server(error)),
+            None => Ok(items),
+        }
+    }
+
+    /// Acts as `username` from now on, creating the user if needed.
+    /// Returns the user's ID.
+    pub async fn login(&mut self, username: &str) -> Result<i64, Error> {
+        let command = Command::User {
+            username: username.to_string(),
+        };
+        match self.send_one(&command).await? {
+            Response::AckUser { id } => Ok(id),
+            response => Err(Error::Unexpected(response)),
+        }
+    }
+
+    /// Proves the logged in user is who they say with one of their API
+    /// tokens, which admin commands need.
+    pub async fn authenticate(&mut self, token: &str) -> Result<(), Error> {
+        let command = Command::Auth {
+            token: token.to_string(),
+        };
+        match self.send_one(&command).await? {
+            Response::AckUser { .. } => Ok(()),
+            response => Err(Error::Unexpected(response)),
+        }
+    }
+
+    /// Subscribes to the feed at `url`, returning the feed's ID and the URL
+    /// actually subscribed to, which feed discovery may have changed.
+    /// Subscribes to a feed, with `mark_old` marking its entries so far
+    /// read.
+    pub async fn subscribe(&mut self, url: &str, mark_old: bool) -> Result<(i64, String), Error> {
+        let command = Command::Subscribe {
+            url: url.to_string(),
+            mark_old,
+        };
+        match self.send_one(&command).await? {
+            Response::AckSubscribe { id, url } => Ok((id, url)),
+            response => Err(Error::Unexpected(response)),
+        }
+    }
+
+    pub async fn unsubscribe(&mut self, feed_id: i64) -> Result<(), Error> {
+        match self.send_one(&Command::Unsubscribe { id: feed_id }).await? {
+            Response::AckUnsubscribe => Ok(()),
+            response => Err(Error::Unexpected(response)),
+        }
+    }
+
+    pub async fn subscriptions(&mut self) -> Result<Vec<Subscription>, Error> {
+        self.send(&Command::ListSubscriptions)
+            .await?
+            .into_iter()
+            .map(|response| match response {
+                Response::Subscription {
+                    id,
+                    url,
+                    status,
+                    unread,
+                    title,
+                    subtitle,
+                } => Ok(Subscription {
+                    id,
+                    url,
+                    status,
+                    unread,
+                    title,
+                    subtitle,
+                }),
+                response => Err(Error::Unexpected(response)),
+            })
+            .collect()
+    }
+
+    pub async fn list_unread(&mut self) -> Result<Vec<Entry>, Error> {
+        entries(self.send(&Command::ListUnread { grouped: false }).await?)
+    }
+
+    /// Entries of subscribed feeds whose titles contain `query`, newest
+    /// first.
+    pub async fn search(&mut self, query: &str) -> Result<Vec<Entry>, Error> {
+        let command = Command::Search {
+            query: query.to_string(),
+        };
+        entries(self.send(&command).await?)
+    }
+
+    /// The content the server fetched for an entry, which it only does
+    /// with `FETCH_ENTRY_CONTENTS` on.
+    pub async fn entry_content(&mut self, entry_id: i64) -> Result<EntryContent, Error> {
+        let (id, mime_type) = match self.send_one(&Command::GetEntry { id: entry_id }).await? {
+            Response::StartEntryContent { id, mime_type } => (id, mime_type),
+            response => return Err(Error::Unexpected(response)),
+        };
+
+        let mut lines = Vec::new();
+        for response in self.read_list().await? {
+            match response {
+                Response::ContentLine(line) => lines.push(line),
+                response => return Err(Error::Unexpected(response)),
+            }
+        }
+
+        Ok(EntryContent {
+            id,
+            mime_type,
+            content: lines.join("\n"),
+        })
+    }
+
+    pub async fn mark_read(&mut self, entry_id: i64) -> Result<(), Error> {
+        match self.send_one(&Command::MarkRead { id: entry_id }).await? {
+            Response::AckMarkRead => Ok(()),
+            response => Err(Error::Unexpected(response)),
+        }
+    }
+
+    /// The `limit` entries most recently read, newest first.
+    pub async fn history(&mut self, limit: i64) -> Result<Vec<HistoryEntry>, Error> {
+        self.send(&Command::History { limit })
+            .await?
+            .into_iter()
+            .map(|response| match response {
+                Response::HistoryEntry {
+                    id,
+                    feed_id,
+                    feed_url,
+                    url,
+                    viewed_at,
+                    title,
+                } => Ok(HistoryEntry {
+                    entry: Entry {
+                        id,
+                        feed_id,
+                        feed_url,
+                        url,
+                        title,
+                        feed_title: None,
+                    },
+                    viewed_at,
+                }),
+                response => Err(Error::Unexpected(response)),
+            })
+            .collect()
+    }
+}
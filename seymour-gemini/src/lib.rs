@@ -0,0 +1,805 @@
+//! Fetches Gemini pages, for seymour's fetcher and anything else that needs
+//! to.
+//!
+//! How server certificates are trusted is up to the caller's [`TlsPolicy`].
+//! Most Gemini servers use self-signed certificates, so
+//! [`TlsPolicy::AllowSelfSigned`] is the default.
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! let config = seymour_gemini::FetchConfig::default();
+//! let page = seymour_gemini::fetch("gemini://example.com/", &config).await?;
+//! println!("{}", page.body.unwrap_or_default());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{format_err, Context, Result};
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use encoding_rs::{Encoding, UTF_8};
+use idna::domain_to_ascii;
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::timeout;
+use tokio_rustls::rustls::{
+    Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, Session,
+    TLSError,
+};
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
+use tokio_socks::tcp::Socks5Stream;
+use tracing::{debug, info, warn};
+use url::Url;
+
+const DEFAULT_PORT: u16 = 1965;
+const MAX_REDIRECTS: usize = 5;
+/// How long looked up addresses are used for. The system resolver doesn't
+/// say how long records may be cached, so this is kept short.
+const DNS_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+/// Two digit status, a space, up to 1024 bytes of meta, and CRLF.
+const MAX_HEADER_LENGTH: usize = 1024 + 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Input,
+    SensitiveInput,
+    Success,
+    TemporaryRedirect,
+    PermanentRedirect,
+    TemporaryFailure,
+    ServerUnavailable,
+    CgiError,
+    ProxyError,
+    SlowDown,
+    PermanentFailure,
+    NotFound,
+    Gone,
+    ProxyRequestRefused,
+    BadRequest,
+    ClientCertificateRequired,
+    CertificateNotAuthorized,
+    CertificateNotValid,
+}
+
+impl Status {
+    fn from_code(code: &str) -> Result<Self> {
+        Ok(match code {
+            "10" => Status::Input,
+            "11" => Status::SensitiveInput,
+            "20" => Status::Success,
+            "30" => Status::TemporaryRedirect,
+            "31" => Status::PermanentRedirect,
+            "40" => Status::TemporaryFailure,
+            "41" => Status::ServerUnavailable,
+            "42" => Status::CgiError,
+            "43" => Status::ProxyError,
+            "44" => Status::SlowDown,
+            "50" => Status::PermanentFailure,
+            "51" => Status::NotFound,
+            "52" => Status::Gone,
+            "53" => Status::ProxyRequestRefused,
+            "59" => Status::BadRequest,
+            "60" => Status::ClientCertificateRequired,
+            "61" => Status::CertificateNotAuthorized,
+            "62" => Status::CertificateNotValid,
+            // Clients are supposed to fall back to the first digit for
+            // codes they don't know about.
+            _ => match code.chars().next() {
+                Some('1') => Status::Input,
+                Some('2') => Status::Success,
+                Some('3') => Status::TemporaryRedirect,
+                Some('4') => Status::TemporaryFailure,
+                Some('5') => Status::PermanentFailure,
+                Some('6') => Status::ClientCertificateRequired,
+                _ => return Err(format_err!("invalid status code \"{}\"", code)),
+            },
+        })
+    }
+
+    /// The two digit code for the status.
+    pub fn code(self) -> u8 {
+        match self {
+            Status::Input => 10,
+            Status::SensitiveInput => 11,
+            Status::Success => 20,
+            Status::TemporaryRedirect => 30,
+            Status::PermanentRedirect => 31,
+            Status::TemporaryFailure => 40,
+            Status::ServerUnavailable => 41,
+            Status::CgiError => 42,
+            Status::ProxyError => 43,
+            Status::SlowDown => 44,
+            Status::PermanentFailure => 50,
+            Status::NotFound => 51,
+            Status::Gone => 52,
+            Status::ProxyRequestRefused => 53,
+            Status::BadRequest => 59,
+            Status::ClientCertificateRequired => 60,
+            Status::CertificateNotAuthorized => 61,
+            Status::CertificateNotValid => 62,
+        }
+    }
+
+    fn is_redirect(self) -> bool {
+        matches!(self, Status::TemporaryRedirect | Status::PermanentRedirect)
+    }
+}
+
+#[derive(Debug)]
+pub struct Header {
+    pub status: Status,
+    pub meta: String,
+}
+
+/// A MIME type from a response meta, e.g. `text/gemini; charset=utf-8`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MimeType {
+    /// The lowercased `type/subtype`.
+    pub essence: String,
+    /// Parameters with lowercased names, in the order they appeared.
+    pub parameters: Vec<(String, String)>,
+}
+
+impl MimeType {
+    pub fn parse(meta: &str) -> Self {
+        let mut parts = meta.split(';');
+        let essence = parts.next().unwrap_or("").trim().to_lowercase();
+
+        let parameters = parts
+            .filter_map(|parameter| {
+                let mut parameter = parameter.splitn(2, '=');
+                let name = parameter.next()?.trim().to_lowercase();
+                let value = parameter.next()?.trim().trim_matches('"').to_string();
+                Some((name, value))
+            })
+            .collect();
+
+        Self {
+            essence,
+            parameters,
+        }
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(parameter, _)| parameter == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The declared charset, which Gemini defines as UTF-8 when absent.
+    pub fn charset(&self) -> &str {
+        self.parameter("charset").unwrap_or("utf-8")
+    }
+
+    pub fn is_gemtext(&self) -> bool {
+        self.essence == "text/gemini"
+    }
+
+    pub fn is_xml(&self) -> bool {
+        matches!(
+            self.essence.as_str(),
+            "application/atom+xml" | "application/xml" | "text/xml"
+        )
+    }
+}
+
+impl fmt::Display for MimeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.essence)?;
+        for (name, value) in &self.parameters {
+            write!(f, "; {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl Header {
+    /// The MIME type of a successful response, which defaults to
+    /// text/gemini when the server leaves the meta empty.
+    pub fn mime(&self) -> MimeType {
+        match self.meta.trim() {
+            "" => MimeType::parse("text/gemini"),
+            meta => MimeType::parse(meta),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Page {
+    pub url: Url,
+    pub header: Header,
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchConfig {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    /// Responses with bodies larger than this many bytes are rejected.
+    pub max_body_size: Option<u64>,
+    /// SOCKS5 proxy (`host:port`) to make connections through.
+    pub proxy: Option<String>,
+    pub tls: TlsPolicy,
+    /// How long looking up a host's addresses may take.
+    pub resolve_timeout: Duration,
+    /// Shared by clones of the config, so a fetch cycle that clears it
+    /// first looks each host up once.
+    pub dns_cache: Arc<DnsCache>,
+    /// Only follows redirects to the host that was asked for.
+    pub same_host_redirects: bool,
+    /// Fetches from and follows redirects to loopback, private, link-local
+    /// and other internal addresses, which are otherwise refused so neither
+    /// feed URLs nor servers can point the fetcher at internal services.
+    pub allow_private_addresses: bool,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            max_body_size: None,
+            proxy: None,
+            tls: TlsPolicy::AllowSelfSigned,
+            resolve_timeout: Duration::from_secs(5),
+            dns_cache: Arc::new(DnsCache::default()),
+            same_host_redirects: false,
+            allow_private_addresses: false,
+        }
+    }
+}
+
+/// Hosts' addresses, as looked up since the cache was last cleared and at
+/// most `DNS_CACHE_TTL` ago.
+#[derive(Debug, Default)]
+pub struct DnsCache {
+    addresses: Mutex<HashMap<(String, u16), LookedUp>>,
+}
+
+/// When a host was looked up, and what it resolved to.
+type LookedUp = (Instant, Vec<SocketAddr>);
+
+impl DnsCache {
+    /// Forgets every address, so they're looked up again.
+    pub fn clear(&self) {
+        self.addresses
+            .lock()
+            .expect("DNS cache lock poisoned")
+            .clear();
+    }
+
+    fn forget(&self, host: &str, port: u16) {
+        self.addresses
+            .lock()
+            .expect("DNS cache lock poisoned")
+            .remove(&(host.to_string(), port));
+    }
+
+    /// Looks up a host's addresses without blocking, unless they're cached.
+    async fn resolve(&self, host: &str, port: u16, limit: Duration) -> Result<Vec<SocketAddr>> {
+        let key = (host.to_string(), port);
+        if let Some((_, addresses)) = self
+            .addresses
+            .lock()
+            .expect("DNS cache lock poisoned")
+            .get(&key)
+            .filter(|(looked_up, _)| looked_up.elapsed() < DNS_CACHE_TTL)
+        {
+            return Ok(addresses.clone());
+        }
+
+        let addresses: Vec<SocketAddr> = timeout(limit, lookup_host((host, port)))
+            .await
+            .with_context(|| format!("timed out resolving {}", host))?
+            .with_context(|| format!("failed to resolve {}", host))?
+            .collect();
+        if addresses.is_empty() {
+            return Err(format_err!("{} has no addresses", host));
+        }
+
+        self.addresses
+            .lock()
+            .expect("DNS cache lock poisoned")
+            .insert(key, (Instant::now(), addresses.clone()));
+        Ok(addresses)
+    }
+}
+
+/// Which server certificates are trusted.
+#[derive(Clone)]
+pub enum TlsPolicy {
+    /// Only certificates chaining to the Mozilla roots, as on the web.
+    WebPki,
+    /// Trust on first use: whatever certificate a host presents first is
+    /// remembered in the store, and any other is refused after that.
+    Tofu(Arc<dyn CertificateStore>),
+    /// Any certificate at all, including self-signed and expired ones.
+    AllowSelfSigned,
+}
+
+impl fmt::Debug for TlsPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsPolicy::WebPki => write!(f, "WebPki"),
+            TlsPolicy::Tofu(_) => write!(f, "Tofu"),
+            TlsPolicy::AllowSelfSigned => write!(f, "AllowSelfSigned"),
+        }
+    }
+}
+
+/// A certificate as [`TlsPolicy::Tofu`] remembers it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinnedCertificate {
+    /// The hex SHA-256 of the leaf certificate.
+    pub fingerprint: String,
+    /// When the certificate expires, in UTC, if it could be read.
+    pub not_after: Option<NaiveDateTime>,
+}
+
+/// Where [`TlsPolicy::Tofu`] keeps the certificates it has seen.
+#[async_trait]
+pub trait CertificateStore: Send + Sync {
+    async fn pinned(&self, host: &str, port: u16) -> Result<Option<PinnedCertificate>>;
+
+    /// Pins the first certificate seen for a host. A host already pinned
+    /// to another certificate keeps it.
+    async fn remember(&self, host: &str, port: u16, certificate: &PinnedCertificate) -> Result<()>;
+
+    /// Replaces a host's expired certificate with the one it now presents.
+    async fn rotate(&self, host: &str, port: u16, certificate: &PinnedCertificate) -> Result<()>;
+}
+
+/// Keeps certificates only for as long as the process runs.
+#[derive(Default)]
+pub struct MemoryCertificateStore {
+    certificates: Mutex<HashMap<(String, u16), PinnedCertificate>>,
+}
+
+#[async_trait]
+impl CertificateStore for MemoryCertificateStore {
+    async fn pinned(&self, host: &str, port: u16) -> Result<Option<PinnedCertificate>> {
+        Ok(self
+            .certificates
+            .lock()
+            .expect("certificate store lock poisoned")
+            .get(&(host.to_string(), port))
+            .cloned())
+    }
+
+    async fn remember(&self, host: &str, port: u16, certificate: &PinnedCertificate) -> Result<()> {
+        let mut certificates = self
+            .certificates
+            .lock()
+            .expect("certificate store lock poisoned");
+        let pinned = certificates
+            .entry((host.to_string(), port))
+            .or_insert_with(|| certificate.clone());
+        if pinned.fingerprint == certificate.fingerprint {
+            pinned.not_after = certificate.not_after;
+        }
+        Ok(())
+    }
+
+    async fn rotate(&self, host: &str, port: u16, certificate: &PinnedCertificate) -> Result<()> {
+        self.certificates
+            .lock()
+            .expect("certificate store lock poisoned")
+            .insert((host.to_string(), port), certificate.clone());
+        Ok(())
+    }
+}
+
+/// Accepts every certificate during the handshake, leaving TOFU to check
+/// them afterwards.
+struct AcceptAnyCertificate;
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn tls_connector(policy: &TlsPolicy) -> TlsConnector {
+    let mut config = ClientConfig::new();
+    match policy {
+        TlsPolicy::WebPki => {
+            config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        }
+        TlsPolicy::Tofu(_) | TlsPolicy::AllowSelfSigned => {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(AcceptAnyCertificate));
+        }
+    }
+
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Refuses a certificate other than the one first seen for the host,
+/// unless that one has expired.
+async fn check_pinned(
+    store: &dyn CertificateStore,
+    host: &str,
+    port: u16,
+    certificates: Option<Vec<Certificate>>,
+) -> Result<()> {
+    let certificate = certificates
+        .and_then(|certificates| certificates.into_iter().next())
+        .ok_or_else(|| format_err!("{}:{} presented no certificate", host, port))?;
+    let presented = PinnedCertificate {
+        fingerprint: format!("{:x}", Sha256::digest(&certificate.0)),
+        not_after: not_after(&certificate.0),
+    };
+
+    match store.pinned(host, port).await? {
+        Some(pinned) if pinned.fingerprint == presented.fingerprint => {
+            // Certificates pinned before expiry was recorded get it now.
+            if pinned.not_after.is_none() && presented.not_after.is_some() {
+                store.remember(host, port, &presented).await?;
+            }
+            Ok(())
+        }
+        Some(pinned)
+            if pinned
+                .not_after
+                .is_some_and(|not_after| not_after < Utc::now().naive_utc()) =>
+        {
+            warn!(
+                "Certificate for {}:{} expired and was replaced, {} is now {}",
+                host, port, pinned.fingerprint, presented.fingerprint
+            );
+            store.rotate(host, port, &presented).await
+        }
+        Some(pinned) => Err(format_err!(
+            "certificate for {}:{} changed from {} to {}",
+            host,
+            port,
+            pinned.fingerprint,
+            presented.fingerprint
+        )),
+        None => {
+            info!(
+                "Trusting certificate {} for {}:{}",
+                presented.fingerprint, host, port
+            );
+            store.remember(host, port, &presented).await
+        }
+    }
+}
+
+/// Splits the first DER element off `input`, returning its tag, its
+/// contents and what follows it.
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = if first & 0x80 == 0 {
+        (usize::from(first), rest)
+    } else {
+        let bytes = usize::from(first & 0x7f);
+        if bytes == 0 || bytes > 4 || rest.len() < bytes {
+            return None;
+        }
+        let length = rest[..bytes]
+            .iter()
+            .fold(0, |length, &byte| (length << 8) | usize::from(byte));
+        (length, &rest[bytes..])
+    };
+    if rest.len() < length {
+        return None;
+    }
+
+    Some((tag, &rest[..length], &rest[length..]))
+}
+
+/// When a DER-encoded X.509 certificate expires, read straight from its
+/// validity rather than pulling in a full parser.
+fn not_after(certificate: &[u8]) -> Option<NaiveDateTime> {
+    let (_, certificate, _) = der_element(certificate)?;
+    let (_, tbs_certificate, _) = der_element(certificate)?;
+
+    // The version is optional and tagged [0]; the serial number follows.
+    let (tag, _, mut rest) = der_element(tbs_certificate)?;
+    if tag == 0xa0 {
+        rest = der_element(rest)?.2;
+    }
+    // The signature algorithm and issuer.
+    let rest = der_element(rest)?.2;
+    let rest = der_element(rest)?.2;
+
+    let (_, validity, _) = der_element(rest)?;
+    let (_, _, validity) = der_element(validity)?;
+    let (tag, time, _) = der_element(validity)?;
+    let time = std::str::from_utf8(time).ok()?;
+    match tag {
+        // UTCTime, whose two digit years are 1950 to 2049.
+        0x17 => {
+            let century = if time.get(..2)? >= "50" { "19" } else { "20" };
+            NaiveDateTime::parse_from_str(&format!("{}{}", century, time), "%Y%m%d%H%M%SZ").ok()
+        }
+        // GeneralizedTime.
+        0x18 => NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%SZ").ok(),
+        _ => None,
+    }
+}
+
+/// The ASCII host and port a Gemini URL connects to, which is what TOFU
+/// pins certificates for.
+pub fn host_and_port(url: &Url) -> Result<(String, u16)> {
+    Ok((ascii_host(url)?, url.port().unwrap_or(DEFAULT_PORT)))
+}
+
+/// Returns the ASCII (punycode) form of the URL's host.
+///
+/// `url` treats gemini:// as a non-special scheme and so leaves unicode
+/// hosts percent-encoded rather than applying IDNA itself.
+fn ascii_host(url: &Url) -> Result<String> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| format_err!("URL \"{}\" has no host", url))?;
+    let host = percent_decode_str(host)
+        .decode_utf8()
+        .with_context(|| format!("host \"{}\" is not valid UTF-8", host))?;
+
+    domain_to_ascii(&host).map_err(|e| format_err!("invalid domain name \"{}\": {:?}", host, e))
+}
+
+async fn connect(host: &str, port: u16, config: &FetchConfig) -> Result<TcpStream> {
+    // The proxy resolves the host itself.
+    if let Some(proxy) = &config.proxy {
+        return Ok(Socks5Stream::connect(proxy.as_str(), (host, port))
+            .await
+            .with_context(|| format!("failed to connect through proxy {}", proxy))?
+            .into_inner());
+    }
+
+    let addresses = config
+        .dns_cache
+        .resolve(host, port, config.resolve_timeout)
+        .await?;
+    let mut last_error = None;
+    for address in addresses {
+        match TcpStream::connect(address).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    // The host may have moved, so it's looked up again next time.
+    config.dns_cache.forget(host, port);
+    Err(last_error
+        .map(Into::into)
+        .unwrap_or_else(|| format_err!("{} has no addresses", host)))
+}
+
+async fn fetch_once(url: &Url, config: &FetchConfig) -> Result<Page> {
+    let (host, port) = host_and_port(url)?;
+    let host = host.as_str();
+
+    let stream = timeout(config.connect_timeout, connect(host, port, config))
+        .await
+        .with_context(|| format!("timed out connecting to {}:{}", host, port))?
+        .with_context(|| format!("failed to connect to {}:{}", host, port))?;
+
+    let domain = DNSNameRef::try_from_ascii_str(host)
+        .map_err(|_| format_err!("invalid DNS name \"{}\"", host))?;
+    let mut stream = timeout(
+        config.connect_timeout,
+        tls_connector(&config.tls).connect(domain, stream),
+    )
+    .await
+    .with_context(|| format!("timed out during TLS handshake with {}", host))?
+    .with_context(|| format!("TLS handshake with {} failed", host))?;
+
+    if let TlsPolicy::Tofu(store) = &config.tls {
+        let certificates = stream.get_ref().1.get_peer_certificates();
+        check_pinned(&**store, host, port, certificates).await?;
+    }
+
+    timeout(
+        config.read_timeout,
+        stream.write_all(format!("{}\r\n", url).as_bytes()),
+    )
+    .await
+    .with_context(|| format!("timed out sending request for \"{}\"", url))??;
+
+    let mut reader = BufReader::new(stream);
+
+    let mut header = Vec::new();
+    timeout(
+        config.read_timeout,
+        (&mut reader)
+            .take(MAX_HEADER_LENGTH as u64)
+            .read_until(b'\n', &mut header),
+    )
+    .await
+    .with_context(|| format!("timed out reading response header for \"{}\"", url))??;
+
+    let header = String::from_utf8(header)
+        .with_context(|| format!("response header for \"{}\" is not UTF-8", url))?;
+    let header = header
+        .strip_suffix("\r\n")
+        .ok_or_else(|| format_err!("malformed response header for \"{}\"", url))?;
+    let (status, meta) = match header.find(' ') {
+        Some(index) => (&header[..index], header[index + 1..].to_string()),
+        None => (header, String::new()),
+    };
+    let status = Status::from_code(status)?;
+
+    let body = if status == Status::Success {
+        let mut body = Vec::new();
+        // Read one byte past the limit so oversized bodies can be detected.
+        let limit = config.max_body_size.map_or(u64::MAX, |max| max + 1);
+        timeout(
+            config.read_timeout,
+            reader.take(limit).read_to_end(&mut body),
+        )
+        .await
+        .with_context(|| format!("timed out reading body of \"{}\"", url))??;
+
+        if let Some(max) = config.max_body_size {
+            if body.len() as u64 > max {
+                return Err(format_err!(
+                    "body of \"{}\" is larger than {} bytes",
+                    url,
+                    max
+                ));
+            }
+        }
+
+        let mime = MimeType::parse(&meta);
+        let encoding = Encoding::for_label(mime.charset().as_bytes()).unwrap_or_else(|| {
+            debug!(
+                "unknown charset \"{}\" for \"{}\", assuming UTF-8",
+                mime.charset(),
+                url
+            );
+            UTF_8
+        });
+        let (body, _, _) = encoding.decode(&body);
+
+        Some(body.into_owned())
+    } else {
+        None
+    };
+
+    Ok(Page {
+        url: url.clone(),
+        header: Header { status, meta },
+        body,
+    })
+}
+
+/// Whether an address is loopback, private, link-local, multicast or
+/// otherwise not somewhere on the internet.
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, _, _] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // "This network", 0.0.0.0/8, which includes unspecified.
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && (b & 0xc0) == 64)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            // IPv4-mapped addresses, ::ffff:0:0/96, are checked as IPv4.
+            if segments[..5] == [0; 5] && segments[5] == 0xffff {
+                if let Some(ip) = ip.to_ipv4() {
+                    return is_internal(IpAddr::V4(ip));
+                }
+            }
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local, fc00::/7.
+                || (segments[0] & 0xfe00) == 0xfc00
+                // Link-local, fe80::/10.
+                || (segments[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Refuses URLs whose host is at an internal address, unless those are
+/// allowed.
+async fn check_address(url: &Url, config: &FetchConfig) -> Result<()> {
+    if config.allow_private_addresses {
+        return Ok(());
+    }
+
+    // Literal addresses are checked as they are. Names are looked up into
+    // the cache, so the connection that follows uses the addresses checked
+    // here. A proxy looks names up itself, including ones only it can
+    // resolve, like Tor's onion services, so a name that doesn't resolve
+    // here is left to it.
+    let (host, port) = host_and_port(url)?;
+    let addresses = match host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(ip) => vec![ip],
+        Err(_) => match config
+            .dns_cache
+            .resolve(&host, port, config.resolve_timeout)
+            .await
+        {
+            Ok(addresses) => addresses.into_iter().map(|address| address.ip()).collect(),
+            Err(_) if config.proxy.is_some() => return Ok(()),
+            Err(e) => return Err(e),
+        },
+    };
+    if addresses.into_iter().any(is_internal) {
+        return Err(format_err!(
+            "refusing to fetch internal address \"{}\"",
+            url
+        ));
+    }
+
+    Ok(())
+}
+
+/// Refuses redirects away from Gemini, or to another host when only the
+/// same host is allowed. Where they lead is then checked like any other
+/// URL.
+async fn check_redirect(from: &Url, to: &Url, config: &FetchConfig) -> Result<()> {
+    if to.scheme() != "gemini" {
+        return Err(format_err!(
+            "refusing redirect to non-Gemini URL \"{}\"",
+            to
+        ));
+    }
+    if config.same_host_redirects && host_and_port(from)?.0 != host_and_port(to)?.0 {
+        return Err(format_err!("refusing redirect to another host \"{}\"", to));
+    }
+
+    check_address(to, config).await
+}
+
+/// Fetches a Gemini page, following up to `MAX_REDIRECTS` redirects.
+///
+/// Non-success statuses other than redirects are returned to the caller
+/// rather than treated as errors. The URL is checked with `check_address`
+/// first, and redirects with `check_redirect` before they're followed.
+pub async fn fetch(url: &str, config: &FetchConfig) -> Result<Page> {
+    let mut url = Url::parse(url).with_context(|| format!("invalid URL \"{}\"", url))?;
+    check_address(&url, config).await?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let page = fetch_once(&url, config).await?;
+
+        if !page.header.status.is_redirect() {
+            return Ok(page);
+        }
+
+        let target = url
+            .join(&page.header.meta)
+            .with_context(|| format!("invalid redirect target \"{}\"", page.header.meta))?;
+        check_redirect(&url, &target, config).await?;
+        url = target;
+    }
+
+    Err(format_err!("too many redirects fetching \"{}\"", url))
+}
@@ -0,0 +1,20 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use seymour_protocol::Command;
+
+fuzz_target!(|data: &[u8]| {
+    let line = match std::str::from_utf8(data) {
+        Ok(line) => line,
+        Err(_) => return,
+    };
+
+    // Parsing must never panic, and whatever parses must print as a line
+    // that parses back to the same command.
+    if let Ok(command) = line.parse::<Command>() {
+        let printed = command.to_string();
+        let reparsed: Command = printed
+            .parse()
+            .unwrap_or_else(|e| panic!("\"{}\" doesn't parse: {}", printed, e));
+        assert_eq!(command, reparsed);
+    }
+});
@@ -0,0 +1,2000 @@
+//! The line protocol spoken between clients and the server.
+//!
+//! Every command and response is a single line terminated by CRLF. Commands
+//! are an uppercase verb followed by space-separated arguments. Responses
+//! start with a two digit code: 1x frames lists and their items, 2x
+//! acknowledges a command, 3x and then 6x are for lists, items and
+//! acknowledgements added after 1x and 2x ran out, 4x is a client error,
+//! and 5x a server error.
+//!
+//! A list starts with one of the responses `Response::starts_list` names
+//! and ends with `13`. When the server fails partway through a list, the
+//! error comes just before `13`, and the items before it are incomplete.
+//!
+//! Free-text fields, which may contain spaces, always come last in a
+//! response. When a response has more than one they're separated by tabs,
+//! and a missing optional field is left empty.
+//!
+//! Errors follow their code with an `ErrorCode` symbol saying what went
+//! wrong and then a message for people, e.g. `42 FEED_NOT_FOUND :no
+//! subscription with feed ID 3 exists`. Symbols are stable, so clients can
+//! match on them instead of on messages.
+//!
+//! # Versioning
+//!
+//! The crate follows semver, with both the wire format and the Rust types
+//! as its contract. Until 1.0, breaking releases bump the minor version:
+//!
+//! * Adding a command or response is compatible. `Command` and `Response`
+//!   are `#[non_exhaustive]` so clients keep compiling.
+//! * Adding a field to an existing command or response is breaking, since
+//!   code that builds or matches the variant stops compiling. On the wire
+//!   the field goes last, so older clients keep working as long as they
+//!   ignore fields they don't know.
+//! * Changing what an existing line looks like, or what a code means, is
+//!   breaking.
+//!
+//! The serde representations follow the Rust types, not the wire format,
+//! and change along with them.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How many entries HISTORY lists when no limit is given.
+pub const DEFAULT_HISTORY_LIMIT: i64 = 50;
+/// How many entries SEARCH lists.
+pub const DEFAULT_SEARCH_LIMIT: i64 = 50;
+/// How many commands AUDITLOG lists when no limit is given.
+pub const DEFAULT_AUDIT_LOG_LIMIT: i64 = 50;
+/// How many feeds DISCOVER lists.
+pub const DEFAULT_DIRECTORY_LIMIT: i64 = 50;
+/// How many feeds RECOMMEND suggests.
+pub const DEFAULT_RECOMMEND_LIMIT: i64 = 10;
+/// How many of the user's most read feeds READSTATS lists.
+pub const READ_STATS_FEEDS: i64 = 5;
+/// How many fetch attempts FEEDLOG lists when no limit is given.
+pub const DEFAULT_FEED_LOG_LIMIT: i64 = 10;
+/// How many feeds SLOWFEEDS lists when no limit is given.
+pub const DEFAULT_SLOW_FEEDS_LIMIT: i64 = 10;
+
+/// Upper bounds of the fetch duration buckets in `SlowFeed`. Each count is
+/// of fetches below its bound, and a last one counts the rest.
+pub const FETCH_DURATION_BUCKETS_MS: [i64; 3] = [100, 1_000, 10_000];
+/// Upper bounds of the response size buckets in `SlowFeed`, like
+/// `FETCH_DURATION_BUCKETS_MS`.
+pub const FETCH_SIZE_BUCKETS_BYTES: [i64; 3] = [16 * 1024, 256 * 1024, 1024 * 1024];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Command {
+    User {
+        username: String,
+    },
+    /// Proves the selected user is who they say with one of their API
+    /// tokens. Admin commands need it.
+    Auth {
+        token: String,
+    },
+    ListSubscriptions,
+    /// Subscribes to a feed. With `mark_old`, written `MARKOLD`, its
+    /// entries so far are marked read instead of joining the unread list.
+    Subscribe {
+        url: String,
+        mark_old: bool,
+    },
+    Unsubscribe {
+        id: i64,
+    },
+    /// Lists unread entries in the order they were published, oldest first,
+    /// with entries published at the same time in ID order. With `grouped`,
+    /// written `GROUPED`, each feed's entries follow an `UnreadFeed` header
+    /// instead, feeds in the order of their oldest unread entry.
+    ListUnread {
+        grouped: bool,
+    },
+    MarkRead {
+        id: i64,
+    },
+    History {
+        limit: i64,
+    },
+    FeedInfo {
+        id: i64,
+    },
+    /// Lists a subscribed feed's most recent fetch attempts still in the
+    /// fetch log, newest first.
+    FeedLog {
+        id: i64,
+        limit: i64,
+    },
+    /// Reports a subscribed feed's fetch failures and, for pinned TLS
+    /// certificates, when the certificate expires and when it last rotated.
+    FeedHealth {
+        id: i64,
+    },
+    ShowArchive {
+        id: i64,
+    },
+    Backup,
+    FeedStats,
+    Health,
+    ListUsers,
+    /// Lists every feed the server knows about, including those nobody
+    /// subscribes to any more that are waiting to be purged.
+    ListAllFeeds,
+    Refresh {
+        id: i64,
+    },
+    /// Turns chat announcements of a subscribed feed on or off.
+    Notify {
+        id: i64,
+        enabled: bool,
+    },
+    /// Lists entries of subscribed feeds whose titles contain `query`.
+    Search {
+        query: String,
+    },
+    /// Sends the content fetched for an entry of a subscribed feed.
+    GetEntry {
+        id: i64,
+    },
+    /// Lists the feeds that took the longest to fetch in total.
+    SlowFeeds {
+        limit: i64,
+    },
+    /// Sets one of the user's preferences. The server knows which names
+    /// exist and what values they take.
+    SetPref {
+        name: String,
+        value: String,
+    },
+    /// Lists feeds other users subscribe to, optionally only those whose
+    /// URL or title contains `query`.
+    Discover {
+        query: Option<String>,
+    },
+    /// Makes every feed due for fetching right away.
+    RefreshAll,
+    /// Lists the admin commands most recently run, newest first.
+    AuditLog {
+        limit: i64,
+    },
+    /// Sends the user's reading stats.
+    ReadStats,
+    /// Suggests feeds often subscribed to alongside the user's.
+    Recommend,
+    /// Creates a group with the user as its first member.
+    CreateGroup {
+        name: String,
+    },
+    /// Adds a user to a group the user belongs to, subscribing them to the
+    /// group's feeds.
+    AddMember {
+        group: String,
+        username: String,
+    },
+    /// Subscribes every member of a group the user belongs to, including
+    /// members added later.
+    GroupSubscribe {
+        group: String,
+        url: String,
+    },
+    /// Stops subscribing new members of a group to a feed. Existing members
+    /// keep their subscriptions.
+    GroupUnsubscribe {
+        group: String,
+        id: i64,
+    },
+    /// Lists the groups the user belongs to.
+    ListGroups,
+    /// Lists a group's feeds.
+    GroupFeeds {
+        group: String,
+    },
+    /// Leaves a subscribed feed's entries out of the unread list until the
+    /// start of `until` in the user's timezone. Entries are still
+    /// collected meanwhile.
+    SnoozeFeed {
+        id: i64,
+        until: NaiveDate,
+    },
+    /// Lists the user's snoozed feeds.
+    ListSnoozed,
+    /// Keeps only a subscribed feed's `limit` newest unread entries,
+    /// marking older ones read, or all of them with `None`.
+    KeepUnread {
+        id: i64,
+        limit: Option<i64>,
+    },
+    /// Lists what changed for the user since `token` was issued: new
+    /// entries of subscribed feeds, then entries marked read or unread and
+    /// feeds subscribed or unsubscribed, each as of its latest change.
+    /// Without a token, only issues one, which a client takes before
+    /// listing everything.
+    Sync {
+        token: Option<String>,
+    },
+}
+
+// Only serializable, since it borrows the names of commands and arguments.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum ParseError {
+    Empty,
+    UnknownCommand(String),
+    MissingArgument {
+        command: &'static str,
+        argument: &'static str,
+    },
+    InvalidArgument {
+        command: &'static str,
+        argument: &'static str,
+        value: String,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty command"),
+            ParseError::UnknownCommand(command) => write!(f, "unknown command \"{}\"", command),
+            ParseError::MissingArgument { command, argument } => {
+                write!(f, "{} requires argument <{}>", command, argument)
+            }
+            ParseError::InvalidArgument {
+                command,
+                argument,
+                value,
+            } => write!(
+                f,
+                "invalid value \"{}\" for {} argument <{}>",
+                value, command, argument
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn required<'a>(
+    arguments: &mut impl Iterator<Item = &'a str>,
+    command: &'static str,
+    argument: &'static str,
+) -> Result<&'a str, ParseError> {
+    arguments
+        .next()
+        .ok_or(ParseError::MissingArgument { command, argument })
+}
+
+/// An optional limit on how many items a list has, which must be at least
+/// one.
+fn parse_limit<'a>(
+    arguments: &mut impl Iterator<Item = &'a str>,
+    command: &'static str,
+    default: i64,
+) -> Result<i64, ParseError> {
+    match arguments.next() {
+        Some(value) => value
+            .parse()
+            .ok()
+            .filter(|limit| *limit > 0)
+            .ok_or_else(|| ParseError::InvalidArgument {
+                command,
+                argument: "limit",
+                value: value.to_string(),
+            }),
+        None => Ok(default),
+    }
+}
+
+fn parse_id<'a>(
+    arguments: &mut impl Iterator<Item = &'a str>,
+    command: &'static str,
+    argument: &'static str,
+) -> Result<i64, ParseError> {
+    let value = required(arguments, command, argument)?;
+    value.parse().map_err(|_| ParseError::InvalidArgument {
+        command,
+        argument,
+        value: value.to_string(),
+    })
+}
+
+impl FromStr for Command {
+    type Err = ParseError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut arguments = line.split_whitespace();
+        let verb = arguments.next().ok_or(ParseError::Empty)?;
+
+        Ok(match verb.to_uppercase().as_str() {
+            "USER" => Command::User {
+                username: required(&mut arguments, "USER", "username")?.to_string(),
+            },
+            "AUTH" => Command::Auth {
+                token: required(&mut arguments, "AUTH", "token")?.to_string(),
+            },
+            "LISTSUBSCRIPTIONS" => Command::ListSubscriptions,
+            "SUBSCRIBE" => Command::Subscribe {
+                url: required(&mut arguments, "SUBSCRIBE", "url")?.to_string(),
+                mark_old: match arguments.next() {
+                    Some(option) if option.eq_ignore_ascii_case("MARKOLD") => true,
+                    Some(option) => {
+                        return Err(ParseError::InvalidArgument {
+                            command: "SUBSCRIBE",
+                            argument: "MARKOLD",
+                            value: option.to_string(),
+                        })
+                    }
+                    None => false,
+                },
+            },
+            "UNSUBSCRIBE" => Command::Unsubscribe {
+                id: parse_id(&mut arguments, "UNSUBSCRIBE", "id")?,
+            },
+            "LISTUNREAD" => Command::ListUnread {
+                grouped: match arguments.next() {
+                    Some(option) if option.eq_ignore_ascii_case("GROUPED") => true,
+                    Some(option) => {
+                        return Err(ParseError::InvalidArgument {
+                            command: "LISTUNREAD",
+                            argument: "GROUPED",
+                            value: option.to_string(),
+                        })
+                    }
+                    None => false,
+                },
+            },
+            "MARKREAD" => Command::MarkRead {
+                id: parse_id(&mut arguments, "MARKREAD", "id")?,
+            },
+            "HISTORY" => Command::History {
+                limit: parse_limit(&mut arguments, "HISTORY", DEFAULT_HISTORY_LIMIT)?,
+            },
+            "FEEDINFO" => Command::FeedInfo {
+                id: parse_id(&mut arguments, "FEEDINFO", "id")?,
+            },
+            "FEEDLOG" => Command::FeedLog {
+                id: parse_id(&mut arguments, "FEEDLOG", "id")?,
+                limit: parse_limit(&mut arguments, "FEEDLOG", DEFAULT_FEED_LOG_LIMIT)?,
+            },
+            "FEEDHEALTH" => Command::FeedHealth {
+                id: parse_id(&mut arguments, "FEEDHEALTH", "id")?,
+            },
+            "SHOWARCHIVE" => Command::ShowArchive {
+                id: parse_id(&mut arguments, "SHOWARCHIVE", "id")?,
+            },
+            "BACKUP" => Command::Backup,
+            "FEEDSTATS" => Command::FeedStats,
+            "HEALTH" => Command::Health,
+            "LISTUSERS" => Command::ListUsers,
+            "LISTALLFEEDS" => Command::ListAllFeeds,
+            "REFRESH" => Command::Refresh {
+                id: parse_id(&mut arguments, "REFRESH", "id")?,
+            },
+            "SLOWFEEDS" => Command::SlowFeeds {
+                limit: parse_limit(&mut arguments, "SLOWFEEDS", DEFAULT_SLOW_FEEDS_LIMIT)?,
+            },
+            "SETPREF" => Command::SetPref {
+                name: required(&mut arguments, "SETPREF", "name")?.to_lowercase(),
+                value: required(&mut arguments, "SETPREF", "value")?.to_string(),
+            },
+            "REFRESHALL" => Command::RefreshAll,
+            "READSTATS" => Command::ReadStats,
+            "RECOMMEND" => Command::Recommend,
+            "CREATEGROUP" => Command::CreateGroup {
+                name: required(&mut arguments, "CREATEGROUP", "name")?.to_string(),
+            },
+            "ADDMEMBER" => Command::AddMember {
+                group: required(&mut arguments, "ADDMEMBER", "group")?.to_string(),
+                username: required(&mut arguments, "ADDMEMBER", "username")?.to_string(),
+            },
+            "GROUPSUBSCRIBE" => Command::GroupSubscribe {
+                group: required(&mut arguments, "GROUPSUBSCRIBE", "group")?.to_string(),
+                url: required(&mut arguments, "GROUPSUBSCRIBE", "url")?.to_string(),
+            },
+            "GROUPUNSUBSCRIBE" => Command::GroupUnsubscribe {
+                group: required(&mut arguments, "GROUPUNSUBSCRIBE", "group")?.to_string(),
+                id: parse_id(&mut arguments, "GROUPUNSUBSCRIBE", "id")?,
+            },
+            "LISTGROUPS" => Command::ListGroups,
+            "SNOOZEFEED" => Command::SnoozeFeed {
+                id: parse_id(&mut arguments, "SNOOZEFEED", "id")?,
+                until: {
+                    let until = required(&mut arguments, "SNOOZEFEED", "until")?;
+                    NaiveDate::parse_from_str(until, "%Y-%m-%d").map_err(|_| {
+                        ParseError::InvalidArgument {
+                            command: "SNOOZEFEED",
+                            argument: "until",
+                            value: until.to_string(),
+                        }
+                    })?
+                },
+            },
+            "LISTSNOOZED" => Command::ListSnoozed,
+            "KEEPUNREAD" => Command::KeepUnread {
+                id: parse_id(&mut arguments, "KEEPUNREAD", "id")?,
+                limit: match required(&mut arguments, "KEEPUNREAD", "limit|off")? {
+                    limit if limit.eq_ignore_ascii_case("off") => None,
+                    limit => Some(limit.parse().ok().filter(|limit| *limit > 0).ok_or_else(
+                        || ParseError::InvalidArgument {
+                            command: "KEEPUNREAD",
+                            argument: "limit|off",
+                            value: limit.to_string(),
+                        },
+                    )?),
+                },
+            },
+            "SYNC" => Command::Sync {
+                token: arguments.next().map(str::to_string),
+            },
+            "GROUPFEEDS" => Command::GroupFeeds {
+                group: required(&mut arguments, "GROUPFEEDS", "group")?.to_string(),
+            },
+            "AUDITLOG" => Command::AuditLog {
+                limit: parse_limit(&mut arguments, "AUDITLOG", DEFAULT_AUDIT_LOG_LIMIT)?,
+            },
+            "GETENTRY" => Command::GetEntry {
+                id: parse_id(&mut arguments, "GETENTRY", "id")?,
+            },
+            "SEARCH" => {
+                let query = arguments.collect::<Vec<_>>().join(" ");
+                if query.is_empty() {
+                    return Err(ParseError::MissingArgument {
+                        command: "SEARCH",
+                        argument: "query",
+                    });
+                }
+                Command::Search { query }
+            }
+            "DISCOVER" => {
+                let query = arguments.collect::<Vec<_>>().join(" ");
+                Command::Discover {
+                    query: if query.is_empty() { None } else { Some(query) },
+                }
+            }
+            "NOTIFY" => Command::Notify {
+                id: parse_id(&mut arguments, "NOTIFY", "id")?,
+                enabled: match required(&mut arguments, "NOTIFY", "on|off")?
+                    .to_lowercase()
+                    .as_str()
+                {
+                    "on" => true,
+                    "off" => false,
+                    value => {
+                        return Err(ParseError::InvalidArgument {
+                            command: "NOTIFY",
+                            argument: "on|off",
+                            value: value.to_string(),
+                        })
+                    }
+                },
+            },
+            _ => return Err(ParseError::UnknownCommand(verb.to_string())),
+        })
+    }
+}
+
+impl Command {
+    /// The command's name, as it starts the command line.
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Command::User { .. } => "USER",
+            Command::Auth { .. } => "AUTH",
+            Command::ListSubscriptions => "LISTSUBSCRIPTIONS",
+            Command::Subscribe { .. } => "SUBSCRIBE",
+            Command::Unsubscribe { .. } => "UNSUBSCRIBE",
+            Command::ListUnread { .. } => "LISTUNREAD",
+            Command::MarkRead { .. } => "MARKREAD",
+            Command::History { .. } => "HISTORY",
+            Command::FeedInfo { .. } => "FEEDINFO",
+            Command::FeedLog { .. } => "FEEDLOG",
+            Command::FeedHealth { .. } => "FEEDHEALTH",
+            Command::ShowArchive { .. } => "SHOWARCHIVE",
+            Command::Backup => "BACKUP",
+            Command::FeedStats => "FEEDSTATS",
+            Command::Health => "HEALTH",
+            Command::ListUsers => "LISTUSERS",
+            Command::ListAllFeeds => "LISTALLFEEDS",
+            Command::Refresh { .. } => "REFRESH",
+            Command::Notify { .. } => "NOTIFY",
+            Command::Search { .. } => "SEARCH",
+            Command::GetEntry { .. } => "GETENTRY",
+            Command::SlowFeeds { .. } => "SLOWFEEDS",
+            Command::SetPref { .. } => "SETPREF",
+            Command::Discover { .. } => "DISCOVER",
+            Command::RefreshAll => "REFRESHALL",
+            Command::AuditLog { .. } => "AUDITLOG",
+            Command::ReadStats => "READSTATS",
+            Command::Recommend => "RECOMMEND",
+            Command::CreateGroup { .. } => "CREATEGROUP",
+            Command::AddMember { .. } => "ADDMEMBER",
+            Command::GroupSubscribe { .. } => "GROUPSUBSCRIBE",
+            Command::GroupUnsubscribe { .. } => "GROUPUNSUBSCRIBE",
+            Command::ListGroups => "LISTGROUPS",
+            Command::GroupFeeds { .. } => "GROUPFEEDS",
+            Command::SnoozeFeed { .. } => "SNOOZEFEED",
+            Command::ListSnoozed => "LISTSNOOZED",
+            Command::KeepUnread { .. } => "KEEPUNREAD",
+            Command::Sync { .. } => "SYNC",
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::User { username } => write!(f, "USER {}", username),
+            Command::Auth { token } => write!(f, "AUTH {}", token),
+            Command::ListSubscriptions => write!(f, "LISTSUBSCRIPTIONS"),
+            Command::Subscribe {
+                url,
+                mark_old: false,
+            } => write!(f, "SUBSCRIBE {}", url),
+            Command::Subscribe {
+                url,
+                mark_old: true,
+            } => write!(f, "SUBSCRIBE {} MARKOLD", url),
+            Command::Unsubscribe { id } => write!(f, "UNSUBSCRIBE {}", id),
+            Command::ListUnread { grouped: false } => write!(f, "LISTUNREAD"),
+            Command::ListUnread { grouped: true } => write!(f, "LISTUNREAD GROUPED"),
+            Command::MarkRead { id } => write!(f, "MARKREAD {}", id),
+            Command::History { limit } => write!(f, "HISTORY {}", limit),
+            Command::FeedInfo { id } => write!(f, "FEEDINFO {}", id),
+            Command::FeedLog { id, limit } => write!(f, "FEEDLOG {} {}", id, limit),
+            Command::FeedHealth { id } => write!(f, "FEEDHEALTH {}", id),
+            Command::ShowArchive { id } => write!(f, "SHOWARCHIVE {}", id),
+            Command::Backup => write!(f, "BACKUP"),
+            Command::FeedStats => write!(f, "FEEDSTATS"),
+            Command::Health => write!(f, "HEALTH"),
+            Command::ListUsers => write!(f, "LISTUSERS"),
+            Command::ListAllFeeds => write!(f, "LISTALLFEEDS"),
+            Command::Refresh { id } => write!(f, "REFRESH {}", id),
+            Command::Search { query } => write!(f, "SEARCH {}", query),
+            Command::GetEntry { id } => write!(f, "GETENTRY {}", id),
+            Command::SlowFeeds { limit } => write!(f, "SLOWFEEDS {}", limit),
+            Command::SetPref { name, value } => write!(f, "SETPREF {} {}", name, value),
+            Command::Discover { query: Some(query) } => write!(f, "DISCOVER {}", query),
+            Command::Discover { query: None } => write!(f, "DISCOVER"),
+            Command::RefreshAll => write!(f, "REFRESHALL"),
+            Command::AuditLog { limit } => write!(f, "AUDITLOG {}", limit),
+            Command::ReadStats => write!(f, "READSTATS"),
+            Command::Recommend => write!(f, "RECOMMEND"),
+            Command::CreateGroup { name } => write!(f, "CREATEGROUP {}", name),
+            Command::AddMember { group, username } => {
+                write!(f, "ADDMEMBER {} {}", group, username)
+            }
+            Command::GroupSubscribe { group, url } => write!(f, "GROUPSUBSCRIBE {} {}", group, url),
+            Command::GroupUnsubscribe { group, id } => {
+                write!(f, "GROUPUNSUBSCRIBE {} {}", group, id)
+            }
+            Command::ListGroups => write!(f, "LISTGROUPS"),
+            Command::GroupFeeds { group } => write!(f, "GROUPFEEDS {}", group),
+            Command::SnoozeFeed { id, until } => {
+                write!(f, "SNOOZEFEED {} {}", id, until.format("%Y-%m-%d"))
+            }
+            Command::ListSnoozed => write!(f, "LISTSNOOZED"),
+            Command::KeepUnread {
+                id,
+                limit: Some(limit),
+            } => write!(f, "KEEPUNREAD {} {}", id, limit),
+            Command::KeepUnread { id, limit: None } => write!(f, "KEEPUNREAD {} off", id),
+            Command::Sync { token: Some(token) } => write!(f, "SYNC {}", token),
+            Command::Sync { token: None } => write!(f, "SYNC"),
+            Command::Notify { id, enabled } => {
+                write!(f, "NOTIFY {} {}", id, if *enabled { "on" } else { "off" })
+            }
+        }
+    }
+}
+
+/// Whether the server is still fetching a feed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FeedStatus {
+    Active,
+    /// The feed responded with 52 (Gone) and is no longer fetched.
+    Gone,
+    /// The feed is still fetched, but has failed enough times in a row that
+    /// it's likely dead.
+    Failing,
+}
+
+impl FeedStatus {
+    pub fn new(gone: bool, failing: bool) -> Self {
+        if gone {
+            FeedStatus::Gone
+        } else if failing {
+            FeedStatus::Failing
+        } else {
+            FeedStatus::Active
+        }
+    }
+}
+
+impl fmt::Display for FeedStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedStatus::Active => write!(f, "active"),
+            FeedStatus::Gone => write!(f, "gone"),
+            FeedStatus::Failing => write!(f, "failing"),
+        }
+    }
+}
+
+impl FromStr for FeedStatus {
+    type Err = ();
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "active" => Ok(FeedStatus::Active),
+            "gone" => Ok(FeedStatus::Gone),
+            "failing" => Ok(FeedStatus::Failing),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What went wrong with a command. Each code belongs to one error
+/// response, which `Response::error` picks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    UnknownCommand,
+    MissingArgument,
+    InvalidArgument,
+    InvalidUsername,
+    GroupExists,
+    UnknownPreference,
+    InvalidPreferenceValue,
+    LineTooLong,
+    InvalidSyncToken,
+    UserRequired,
+    FeedNotFound,
+    EntryNotFound,
+    ContentNotFound,
+    GroupNotFound,
+    AdminRequired,
+    InvalidToken,
+    SyncTokenExpired,
+    Internal,
+    RateLimited,
+    TooManyConnections,
+    DatabaseBusy,
+    /// From a server predating error codes, or with a symbol this crate
+    /// doesn't know.
+    Unspecified,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            ErrorCode::UnknownCommand => "UNKNOWN_COMMAND",
+            ErrorCode::MissingArgument => "MISSING_ARGUMENT",
+            ErrorCode::InvalidArgument => "INVALID_ARGUMENT",
+            ErrorCode::InvalidUsername => "INVALID_USERNAME",
+            ErrorCode::GroupExists => "GROUP_EXISTS",
+            ErrorCode::UnknownPreference => "UNKNOWN_PREFERENCE",
+            ErrorCode::InvalidPreferenceValue => "INVALID_PREFERENCE_VALUE",
+            ErrorCode::LineTooLong => "LINE_TOO_LONG",
+            ErrorCode::InvalidSyncToken => "INVALID_SYNC_TOKEN",
+            ErrorCode::UserRequired => "USER_REQUIRED",
+            ErrorCode::FeedNotFound => "FEED_NOT_FOUND",
+            ErrorCode::EntryNotFound => "ENTRY_NOT_FOUND",
+            ErrorCode::ContentNotFound => "CONTENT_NOT_FOUND",
+            ErrorCode::GroupNotFound => "GROUP_NOT_FOUND",
+            ErrorCode::AdminRequired => "ADMIN_REQUIRED",
+            ErrorCode::InvalidToken => "INVALID_TOKEN",
+            ErrorCode::SyncTokenExpired => "SYNC_TOKEN_EXPIRED",
+            ErrorCode::Internal => "INTERNAL",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::TooManyConnections => "TOO_MANY_CONNECTIONS",
+            ErrorCode::DatabaseBusy => "DATABASE_BUSY",
+            ErrorCode::Unspecified => "UNSPECIFIED",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl FromStr for ErrorCode {
+    type Err = ();
+
+    fn from_str(symbol: &str) -> Result<Self, Self::Err> {
+        Ok(match symbol {
+            "UNKNOWN_COMMAND" => ErrorCode::UnknownCommand,
+            "MISSING_ARGUMENT" => ErrorCode::MissingArgument,
+            "INVALID_ARGUMENT" => ErrorCode::InvalidArgument,
+            "INVALID_USERNAME" => ErrorCode::InvalidUsername,
+            "GROUP_EXISTS" => ErrorCode::GroupExists,
+            "UNKNOWN_PREFERENCE" => ErrorCode::UnknownPreference,
+            "INVALID_PREFERENCE_VALUE" => ErrorCode::InvalidPreferenceValue,
+            "LINE_TOO_LONG" => ErrorCode::LineTooLong,
+            "INVALID_SYNC_TOKEN" => ErrorCode::InvalidSyncToken,
+            "USER_REQUIRED" => ErrorCode::UserRequired,
+            "FEED_NOT_FOUND" => ErrorCode::FeedNotFound,
+            "ENTRY_NOT_FOUND" => ErrorCode::EntryNotFound,
+            "CONTENT_NOT_FOUND" => ErrorCode::ContentNotFound,
+            "GROUP_NOT_FOUND" => ErrorCode::GroupNotFound,
+            "ADMIN_REQUIRED" => ErrorCode::AdminRequired,
+            "INVALID_TOKEN" => ErrorCode::InvalidToken,
+            "SYNC_TOKEN_EXPIRED" => ErrorCode::SyncTokenExpired,
+            "INTERNAL" => ErrorCode::Internal,
+            "RATE_LIMITED" => ErrorCode::RateLimited,
+            "TOO_MANY_CONNECTIONS" => ErrorCode::TooManyConnections,
+            "DATABASE_BUSY" => ErrorCode::DatabaseBusy,
+            "UNSPECIFIED" => ErrorCode::Unspecified,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// An error response's code and message, written `<symbol> :<message>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorDetail {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorDetail {
+    /// Parses an error's text, taking all of it as the message when it
+    /// doesn't start with a known symbol.
+    fn parse(text: &str) -> Self {
+        let known = text.find(" :").and_then(|i| {
+            let code = text[..i].parse().ok()?;
+            Some((code, &text[i + 2..]))
+        });
+        match known {
+            Some((code, message)) => Self {
+                code,
+                message: message.to_string(),
+            },
+            None => Self {
+                code: ErrorCode::Unspecified,
+                message: text.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ErrorDetail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} :{}", self.code, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Response {
+    StartUserList,
+    StartSubscriptionList,
+    /// Starts a list of entries. LISTUNREAD's are oldest first by
+    /// publication time and then by ID.
+    StartEntryList,
+    EndList,
+    StartFeedStatsList,
+    StartHistoryList,
+    User {
+        id: i64,
+        username: String,
+    },
+    /// Starts an entry's content, sent a line at a time and ended with
+    /// `EndList`.
+    StartEntryContent {
+        id: i64,
+        mime_type: String,
+    },
+    ContentLine(String),
+    StartSlowFeedList,
+    /// A feed's fetches still in the fetch log, with histograms of their
+    /// durations and sizes over `FETCH_DURATION_BUCKETS_MS` and
+    /// `FETCH_SIZE_BUCKETS_BYTES`.
+    SlowFeed {
+        id: i64,
+        url: String,
+        fetches: i64,
+        total_duration_ms: i64,
+        durations: Vec<i64>,
+        sizes: Vec<i64>,
+    },
+    StartDirectoryList,
+    StartAuditLog,
+    /// Starts the user's reading stats, followed by their most read feeds
+    /// and `EndList`. Weeks start on Monday, and weeks, months and streaks
+    /// of consecutive days with something read follow the user's timezone.
+    StartReadStats {
+        read_this_week: i64,
+        read_this_month: i64,
+        unread: i64,
+        longest_streak_days: i64,
+    },
+    /// A feed and how many of its entries the user has read.
+    FeedReads {
+        id: i64,
+        url: String,
+        reads: i64,
+        title: Option<String>,
+    },
+    StartRecommendationList,
+    /// A feed the user doesn't subscribe to. `score` counts, over every
+    /// other user subscribing to it, the feeds they share with the user.
+    Recommendation {
+        id: i64,
+        url: String,
+        score: i64,
+        title: Option<String>,
+    },
+    AckCreateGroup {
+        id: i64,
+    },
+    AckAddMember,
+    StartGroupList,
+    /// A group the user belongs to, with how many members and feeds it has.
+    Group {
+        id: i64,
+        name: String,
+        members: i64,
+        feeds: i64,
+    },
+    AckSnoozeFeed,
+    AckKeepUnread,
+    StartSnoozedList,
+    /// A snoozed feed, and when its entries are listed again in the user's
+    /// timezone.
+    SnoozedFeed {
+        id: i64,
+        url: String,
+        until: DateTime<FixedOffset>,
+        title: Option<String>,
+    },
+    StartAllFeedsList,
+    /// A feed and its health: how many fetches in a row have failed and
+    /// the last error, if any. Feeds waiting to be purged have no
+    /// subscribers.
+    ServerFeed {
+        id: i64,
+        url: String,
+        status: FeedStatus,
+        subscribers: i64,
+        failure_count: i64,
+        title: Option<String>,
+        last_error: Option<String>,
+    },
+    StartFeedLog,
+    /// A fetch attempt, at a time in the user's timezone. `status` is the
+    /// response's status code, missing when the fetch failed, in which
+    /// case `error` says why.
+    FetchAttempt {
+        fetched_at: DateTime<FixedOffset>,
+        status: Option<i64>,
+        duration_ms: i64,
+        error: Option<String>,
+    },
+    /// Starts SYNC's changes, ended with `EndList`. `token` is what to
+    /// sync from next time.
+    StartSync {
+        token: String,
+    },
+    /// An entry marked read, or unread.
+    ReadStateChange {
+        id: i64,
+        read: bool,
+    },
+    /// A feed the user subscribed to, or unsubscribed from.
+    SubscriptionChange {
+        id: i64,
+        subscribed: bool,
+    },
+    /// Starts a feed's entries in `LISTUNREAD GROUPED`, with how many
+    /// follow.
+    UnreadFeed {
+        id: i64,
+        url: String,
+        unread: i64,
+        title: Option<String>,
+    },
+    /// The start of an entry's summary or fetched content, right after the
+    /// entry, for users with `SETPREF excerpts on`.
+    Excerpt {
+        id: i64,
+        excerpt: String,
+    },
+    /// A feed's fetch failures in a row and last error, with when its
+    /// pinned certificate expires and last rotated, in the user's
+    /// timezone. The times are missing when the feed's host has no pinned
+    /// certificate, or it never rotated.
+    FeedHealth {
+        id: i64,
+        failure_count: i64,
+        certificate_expires_at: Option<DateTime<FixedOffset>>,
+        certificate_rotated_at: Option<DateTime<FixedOffset>>,
+        last_error: Option<String>,
+    },
+    /// An admin command, who ran it and when.
+    AuditEntry {
+        at: DateTime<Utc>,
+        username: String,
+        command: String,
+    },
+    /// A feed in the directory, with how many users who haven't hidden
+    /// themselves from it subscribe.
+    DirectoryFeed {
+        id: i64,
+        url: String,
+        subscribers: i64,
+        title: Option<String>,
+    },
+    /// A subscribed feed, with how many of its entries the user hasn't
+    /// read.
+    Subscription {
+        id: i64,
+        url: String,
+        status: FeedStatus,
+        unread: i64,
+        title: Option<String>,
+        subtitle: Option<String>,
+    },
+    /// An entry, with its feed's title if the feed has one. The feed title
+    /// follows the entry's after a tab.
+    Entry {
+        id: i64,
+        feed_id: i64,
+        feed_url: String,
+        url: String,
+        title: String,
+        feed_title: Option<String>,
+    },
+    /// An entry the user has read, and when they marked it read, in the
+    /// user's timezone.
+    HistoryEntry {
+        id: i64,
+        feed_id: i64,
+        feed_url: String,
+        url: String,
+        viewed_at: DateTime<FixedOffset>,
+        title: String,
+    },
+    /// Aggregates over the fetches still in the fetch log.
+    FeedStats {
+        id: i64,
+        url: String,
+        fetches: i64,
+        failures: i64,
+        avg_duration_ms: i64,
+        max_duration_ms: i64,
+        bytes: i64,
+        entries_inserted: i64,
+    },
+    /// Acknowledges USER, and AUTH once the token checks out.
+    AckUser {
+        id: i64,
+    },
+    /// `url` is the feed that was actually subscribed to, which may differ
+    /// from the requested URL after feed discovery.
+    AckSubscribe {
+        id: i64,
+        url: String,
+    },
+    AckUnsubscribe,
+    AckMarkRead,
+    FeedInfo {
+        id: i64,
+        url: String,
+        status: FeedStatus,
+        title: Option<String>,
+        subtitle: Option<String>,
+        failure_count: i64,
+        last_error: Option<String>,
+    },
+    /// `path` is where the snapshot was written on the server.
+    AckBackup {
+        path: String,
+    },
+    AckShowArchive,
+    /// The database is reachable. `last_fetch_cycle_age_secs` is how long
+    /// ago the fetcher last finished checking feeds, if it ever has, and
+    /// `database_busy_count` how many commands have failed with
+    /// `DatabaseBusy` since the server started.
+    Health {
+        last_fetch_cycle_age_secs: Option<i64>,
+        database_busy_count: i64,
+    },
+    AckRefresh,
+    AckNotify,
+    AckSetPref,
+    BadCommand(ErrorDetail),
+    NeedUser(ErrorDetail),
+    ResourceNotFound(ErrorDetail),
+    PermissionDenied(ErrorDetail),
+    /// A SYNC token older than the changes kept. The client needs to list
+    /// everything again.
+    SyncTokenExpired(ErrorDetail),
+    InternalError(ErrorDetail),
+    ServerBusy(ErrorDetail),
+    /// No database connection came free in time. Retrying later may work.
+    DatabaseBusy(ErrorDetail),
+}
+
+/// Writes a histogram's counts separated by commas.
+fn histogram(counts: &[i64]) -> String {
+    counts
+        .iter()
+        .map(i64::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Joins trailing free-text fields with tabs, leaving missing ones empty.
+fn text_fields(fields: &[&Option<String>]) -> String {
+    fields
+        .iter()
+        .map(|field| field.as_deref().unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::StartUserList => write!(f, "10"),
+            Response::StartSubscriptionList => write!(f, "11"),
+            Response::StartEntryList => write!(f, "12"),
+            Response::EndList => write!(f, "13"),
+            Response::Subscription {
+                id,
+                url,
+                status,
+                unread,
+                title,
+                subtitle,
+            } => write!(
+                f,
+                "14 {} {} {} {} {}",
+                id,
+                url,
+                status,
+                unread,
+                text_fields(&[title, subtitle])
+            ),
+            Response::Entry {
+                id,
+                feed_id,
+                feed_url,
+                url,
+                title,
+                feed_title,
+            } => write!(
+                f,
+                "15 {} {} {} {} {}\t{}",
+                id,
+                feed_id,
+                feed_url,
+                url,
+                title,
+                text_fields(&[feed_title])
+            ),
+            Response::StartFeedStatsList => write!(f, "16"),
+            Response::FeedStats {
+                id,
+                url,
+                fetches,
+                failures,
+                avg_duration_ms,
+                max_duration_ms,
+                bytes,
+                entries_inserted,
+            } => write!(
+                f,
+                "17 {} {} {} {} {} {} {} {}",
+                id,
+                url,
+                fetches,
+                failures,
+                avg_duration_ms,
+                max_duration_ms,
+                bytes,
+                entries_inserted
+            ),
+            Response::StartHistoryList => write!(f, "18"),
+            Response::HistoryEntry {
+                id,
+                feed_id,
+                feed_url,
+                url,
+                viewed_at,
+                title,
+            } => write!(
+                f,
+                "19 {} {} {} {} {} {}",
+                id,
+                feed_id,
+                feed_url,
+                url,
+                viewed_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                title
+            ),
+            Response::AckUser { id } => write!(f, "20 {}", id),
+            Response::AckSubscribe { id, url } => write!(f, "21 {} {}", id, url),
+            Response::AckUnsubscribe => write!(f, "22"),
+            Response::AckMarkRead => write!(f, "23"),
+            Response::FeedInfo {
+                id,
+                url,
+                status,
+                title,
+                subtitle,
+                failure_count,
+                last_error,
+            } => write!(
+                f,
+                "24 {} {} {} {} {}",
+                id,
+                url,
+                status,
+                failure_count,
+                text_fields(&[title, subtitle, last_error])
+            ),
+            Response::AckBackup { path } => write!(f, "25 {}", path),
+            Response::AckShowArchive => write!(f, "26"),
+            Response::Health {
+                last_fetch_cycle_age_secs: Some(age),
+                database_busy_count,
+            } => write!(f, "27 {} {}", age, database_busy_count),
+            Response::Health {
+                last_fetch_cycle_age_secs: None,
+                database_busy_count,
+            } => write!(f, "27 never {}", database_busy_count),
+            Response::AckRefresh => write!(f, "28"),
+            Response::AckNotify => write!(f, "29"),
+            Response::User { id, username } => write!(f, "30 {} {}", id, username),
+            Response::StartEntryContent { id, mime_type } => {
+                write!(f, "31 {} {}", id, mime_type)
+            }
+            Response::ContentLine(line) => write!(f, "32 {}", line),
+            Response::StartSlowFeedList => write!(f, "33"),
+            Response::SlowFeed {
+                id,
+                url,
+                fetches,
+                total_duration_ms,
+                durations,
+                sizes,
+            } => write!(
+                f,
+                "34 {} {} {} {} {} {}",
+                id,
+                url,
+                fetches,
+                total_duration_ms,
+                histogram(durations),
+                histogram(sizes)
+            ),
+            Response::AckSetPref => write!(f, "35"),
+            Response::StartDirectoryList => write!(f, "36"),
+            Response::DirectoryFeed {
+                id,
+                url,
+                subscribers,
+                title,
+            } => write!(
+                f,
+                "37 {} {} {} {}",
+                id,
+                url,
+                subscribers,
+                text_fields(&[title])
+            ),
+            Response::StartAuditLog => write!(f, "38"),
+            Response::AuditEntry {
+                at,
+                username,
+                command,
+            } => write!(
+                f,
+                "39 {} {} {}",
+                at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                username,
+                command
+            ),
+            Response::BadCommand(error) => write!(f, "40 {}", error),
+            Response::NeedUser(error) => write!(f, "41 {}", error),
+            Response::ResourceNotFound(error) => write!(f, "42 {}", error),
+            Response::PermissionDenied(error) => write!(f, "43 {}", error),
+            Response::SyncTokenExpired(error) => write!(f, "44 {}", error),
+            Response::InternalError(error) => write!(f, "50 {}", error),
+            Response::ServerBusy(error) => write!(f, "51 {}", error),
+            Response::DatabaseBusy(error) => write!(f, "52 {}", error),
+            Response::StartReadStats {
+                read_this_week,
+                read_this_month,
+                unread,
+                longest_streak_days,
+            } => write!(
+                f,
+                "60 {} {} {} {}",
+                read_this_week, read_this_month, unread, longest_streak_days
+            ),
+            Response::FeedReads {
+                id,
+                url,
+                reads,
+                title,
+            } => write!(f, "61 {} {} {} {}", id, url, reads, text_fields(&[title])),
+            Response::StartRecommendationList => write!(f, "62"),
+            Response::Recommendation {
+                id,
+                url,
+                score,
+                title,
+            } => write!(f, "63 {} {} {} {}", id, url, score, text_fields(&[title])),
+            Response::AckCreateGroup { id } => write!(f, "64 {}", id),
+            Response::AckAddMember => write!(f, "65"),
+            Response::StartGroupList => write!(f, "66"),
+            Response::Group {
+                id,
+                name,
+                members,
+                feeds,
+            } => write!(f, "67 {} {} {} {}", id, name, members, feeds),
+            Response::AckSnoozeFeed => write!(f, "68"),
+            Response::AckKeepUnread => write!(f, "71"),
+            Response::StartSnoozedList => write!(f, "69"),
+            Response::SnoozedFeed {
+                id,
+                url,
+                until,
+                title,
+            } => write!(
+                f,
+                "70 {} {} {} {}",
+                id,
+                url,
+                until.to_rfc3339_opts(SecondsFormat::Secs, true),
+                text_fields(&[title])
+            ),
+            Response::StartAllFeedsList => write!(f, "72"),
+            Response::ServerFeed {
+                id,
+                url,
+                status,
+                subscribers,
+                failure_count,
+                title,
+                last_error,
+            } => write!(
+                f,
+                "73 {} {} {} {} {} {}",
+                id,
+                url,
+                status,
+                subscribers,
+                failure_count,
+                text_fields(&[title, last_error])
+            ),
+            Response::StartFeedLog => write!(f, "74"),
+            Response::FetchAttempt {
+                fetched_at,
+                status,
+                duration_ms,
+                error,
+            } => write!(
+                f,
+                "75 {} {} {} {}",
+                fetched_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                match status {
+                    Some(status) => status.to_string(),
+                    None => "none".to_string(),
+                },
+                duration_ms,
+                text_fields(&[error])
+            ),
+            Response::StartSync { token } => write!(f, "76 {}", token),
+            Response::ReadStateChange { id, read } => {
+                write!(f, "77 {} {}", id, if *read { "read" } else { "unread" })
+            }
+            Response::SubscriptionChange { id, subscribed } => write!(
+                f,
+                "78 {} {}",
+                id,
+                if *subscribed {
+                    "subscribed"
+                } else {
+                    "unsubscribed"
+                }
+            ),
+            Response::UnreadFeed {
+                id,
+                url,
+                unread,
+                title,
+            } => write!(f, "79 {} {} {} {}", id, url, unread, text_fields(&[title])),
+            Response::Excerpt { id, excerpt } => write!(f, "80 {} {}", id, excerpt),
+            Response::FeedHealth {
+                id,
+                failure_count,
+                certificate_expires_at,
+                certificate_rotated_at,
+                last_error,
+            } => {
+                let time = |at: &Option<DateTime<FixedOffset>>| match at {
+                    Some(at) => at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    None => "none".to_string(),
+                };
+                write!(
+                    f,
+                    "81 {} {} {} {} {}",
+                    id,
+                    failure_count,
+                    time(certificate_expires_at),
+                    time(certificate_rotated_at),
+                    text_fields(&[last_error])
+                )
+            }
+        }
+    }
+}
+
+impl From<ParseError> for Response {
+    fn from(error: ParseError) -> Self {
+        let code = match error {
+            ParseError::Empty | ParseError::UnknownCommand(_) => ErrorCode::UnknownCommand,
+            ParseError::MissingArgument { .. } => ErrorCode::MissingArgument,
+            ParseError::InvalidArgument { .. } => ErrorCode::InvalidArgument,
+        };
+        Response::error(code, error.to_string())
+    }
+}
+
+impl Response {
+    /// The error response for `code`.
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        let error = ErrorDetail {
+            code,
+            message: message.into(),
+        };
+        match code {
+            ErrorCode::UnknownCommand
+            | ErrorCode::MissingArgument
+            | ErrorCode::InvalidArgument
+            | ErrorCode::InvalidUsername
+            | ErrorCode::GroupExists
+            | ErrorCode::UnknownPreference
+            | ErrorCode::InvalidPreferenceValue
+            | ErrorCode::LineTooLong
+            | ErrorCode::InvalidSyncToken => Response::BadCommand(error),
+            ErrorCode::UserRequired => Response::NeedUser(error),
+            ErrorCode::FeedNotFound
+            | ErrorCode::EntryNotFound
+            | ErrorCode::ContentNotFound
+            | ErrorCode::GroupNotFound => Response::ResourceNotFound(error),
+            ErrorCode::AdminRequired | ErrorCode::InvalidToken => Response::PermissionDenied(error),
+            ErrorCode::SyncTokenExpired => Response::SyncTokenExpired(error),
+            ErrorCode::Internal | ErrorCode::Unspecified => Response::InternalError(error),
+            ErrorCode::RateLimited | ErrorCode::TooManyConnections => Response::ServerBusy(error),
+            ErrorCode::DatabaseBusy => Response::DatabaseBusy(error),
+        }
+    }
+
+    /// What went wrong, for an error response.
+    pub fn error_detail(&self) -> Option<&ErrorDetail> {
+        match self {
+            Response::BadCommand(error)
+            | Response::NeedUser(error)
+            | Response::ResourceNotFound(error)
+            | Response::PermissionDenied(error)
+            | Response::SyncTokenExpired(error)
+            | Response::InternalError(error)
+            | Response::ServerBusy(error)
+            | Response::DatabaseBusy(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a 4x or 5x response, which ends the command.
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Response::BadCommand(_)
+                | Response::NeedUser(_)
+                | Response::ResourceNotFound(_)
+                | Response::PermissionDenied(_)
+                | Response::SyncTokenExpired(_)
+                | Response::InternalError(_)
+                | Response::ServerBusy(_)
+                | Response::DatabaseBusy(_)
+        )
+    }
+
+    /// Whether items follow this response until `EndList`.
+    pub fn starts_list(&self) -> bool {
+        matches!(
+            self,
+            Response::StartUserList
+                | Response::StartSubscriptionList
+                | Response::StartEntryList
+                | Response::StartFeedStatsList
+                | Response::StartHistoryList
+                | Response::StartEntryContent { .. }
+                | Response::StartSlowFeedList
+                | Response::StartDirectoryList
+                | Response::StartAuditLog
+                | Response::StartReadStats { .. }
+                | Response::StartRecommendationList
+                | Response::StartGroupList
+                | Response::StartSnoozedList
+                | Response::StartAllFeedsList
+                | Response::StartFeedLog
+                | Response::StartSync { .. }
+        )
+    }
+}
+
+/// A line that isn't a response the protocol defines.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InvalidResponse(pub String);
+
+impl fmt::Display for InvalidResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid response \"{}\"", self.0)
+    }
+}
+
+impl std::error::Error for InvalidResponse {}
+
+/// Splits a response's fields off one at a time, leaving free text last.
+struct Fields<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Fields<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let (field, rest) = match self.rest.find(' ') {
+            Some(i) => (&self.rest[..i], &self.rest[i + 1..]),
+            None => (self.rest, ""),
+        };
+        self.rest = rest;
+        Some(field)
+    }
+
+    fn parse<T: FromStr>(&mut self) -> Option<T> {
+        self.next()?.parse().ok()
+    }
+
+    fn histogram(&mut self) -> Option<Vec<i64>> {
+        self.next()?
+            .split(',')
+            .map(|count| count.parse().ok())
+            .collect()
+    }
+
+    /// Whatever is left, as a single free-text field.
+    fn text(self) -> String {
+        self.rest.to_string()
+    }
+
+    /// Whatever is left, as `count` tab-separated free-text fields.
+    fn text_fields(self, count: usize) -> Vec<Option<String>> {
+        let mut fields: Vec<Option<String>> = self
+            .rest
+            .splitn(count, '\t')
+            .map(|field| {
+                if field.is_empty() {
+                    None
+                } else {
+                    Some(field.to_string())
+                }
+            })
+            .collect();
+        fields.resize(count, None);
+        fields
+    }
+}
+
+fn parse_response(code: &str, mut fields: Fields<'_>) -> Option<Response> {
+    Some(match code {
+        "10" => Response::StartUserList,
+        "11" => Response::StartSubscriptionList,
+        "12" => Response::StartEntryList,
+        "13" => Response::EndList,
+        "14" => {
+            let id = fields.parse()?;
+            let url = fields.next()?.to_string();
+            let status = fields.parse()?;
+            let unread = fields.parse()?;
+            let mut text = fields.text_fields(2).into_iter();
+            Response::Subscription {
+                id,
+                url,
+                status,
+                unread,
+                title: text.next()?,
+                subtitle: text.next()?,
+            }
+        }
+        "15" => {
+            let id = fields.parse()?;
+            let feed_id = fields.parse()?;
+            let feed_url = fields.next()?.to_string();
+            let url = fields.next()?.to_string();
+            let mut text = fields.text_fields(2).into_iter();
+            Response::Entry {
+                id,
+                feed_id,
+                feed_url,
+                url,
+                title: text.next()?.unwrap_or_default(),
+                feed_title: text.next()?,
+            }
+        }
+        "16" => Response::StartFeedStatsList,
+        "17" => Response::FeedStats {
+            id: fields.parse()?,
+            url: fields.next()?.to_string(),
+            fetches: fields.parse()?,
+            failures: fields.parse()?,
+            avg_duration_ms: fields.parse()?,
+            max_duration_ms: fields.parse()?,
+            bytes: fields.parse()?,
+            entries_inserted: fields.parse()?,
+        },
+        "18" => Response::StartHistoryList,
+        "19" => Response::HistoryEntry {
+            id: fields.parse()?,
+            feed_id: fields.parse()?,
+            feed_url: fields.next()?.to_string(),
+            url: fields.next()?.to_string(),
+            viewed_at: DateTime::parse_from_rfc3339(fields.next()?).ok()?,
+            title: fields.text(),
+        },
+        "20" => Response::AckUser {
+            id: fields.parse()?,
+        },
+        "21" => Response::AckSubscribe {
+            id: fields.parse()?,
+            url: fields.next()?.to_string(),
+        },
+        "22" => Response::AckUnsubscribe,
+        "23" => Response::AckMarkRead,
+        "24" => {
+            let id = fields.parse()?;
+            let url = fields.next()?.to_string();
+            let status = fields.parse()?;
+            let failure_count = fields.parse()?;
+            let mut text = fields.text_fields(3).into_iter();
+            Response::FeedInfo {
+                id,
+                url,
+                status,
+                failure_count,
+                title: text.next()?,
+                subtitle: text.next()?,
+                last_error: text.next()?,
+            }
+        }
+        "25" => Response::AckBackup {
+            path: fields.text(),
+        },
+        "26" => Response::AckShowArchive,
+        "27" => Response::Health {
+            last_fetch_cycle_age_secs: match fields.next()? {
+                "never" => None,
+                age => Some(age.parse().ok()?),
+            },
+            // Servers before the count was added don't send it.
+            database_busy_count: match fields.next() {
+                Some(count) => count.parse().ok()?,
+                None => 0,
+            },
+        },
+        "28" => Response::AckRefresh,
+        "29" => Response::AckNotify,
+        "30" => Response::User {
+            id: fields.parse()?,
+            username: fields.text(),
+        },
+        "31" => Response::StartEntryContent {
+            id: fields.parse()?,
+            mime_type: fields.text(),
+        },
+        "32" => Response::ContentLine(fields.text()),
+        "33" => Response::StartSlowFeedList,
+        "34" => Response::SlowFeed {
+            id: fields.parse()?,
+            url: fields.next()?.to_string(),
+            fetches: fields.parse()?,
+            total_duration_ms: fields.parse()?,
+            durations: fields.histogram()?,
+            sizes: fields.histogram()?,
+        },
+        "35" => Response::AckSetPref,
+        "36" => Response::StartDirectoryList,
+        "37" => {
+            let id = fields.parse()?;
+            let url = fields.next()?.to_string();
+            let subscribers = fields.parse()?;
+            let mut text = fields.text_fields(1).into_iter();
+            Response::DirectoryFeed {
+                id,
+                url,
+                subscribers,
+                title: text.next()?,
+            }
+        }
+        "38" => Response::StartAuditLog,
+        "39" => Response::AuditEntry {
+            at: DateTime::parse_from_rfc3339(fields.next()?)
+                .ok()?
+                .with_timezone(&Utc),
+            username: fields.next()?.to_string(),
+            command: fields.text(),
+        },
+        "40" => Response::BadCommand(ErrorDetail::parse(&fields.text())),
+        "41" => Response::NeedUser(ErrorDetail::parse(&fields.text())),
+        "42" => Response::ResourceNotFound(ErrorDetail::parse(&fields.text())),
+        "43" => Response::PermissionDenied(ErrorDetail::parse(&fields.text())),
+        "44" => Response::SyncTokenExpired(ErrorDetail::parse(&fields.text())),
+        "50" => Response::InternalError(ErrorDetail::parse(&fields.text())),
+        "51" => Response::ServerBusy(ErrorDetail::parse(&fields.text())),
+        "52" => Response::DatabaseBusy(ErrorDetail::parse(&fields.text())),
+        "60" => Response::StartReadStats {
+            read_this_week: fields.parse()?,
+            read_this_month: fields.parse()?,
+            unread: fields.parse()?,
+            longest_streak_days: fields.parse()?,
+        },
+        "61" => {
+            let id = fields.parse()?;
+            let url = fields.next()?.to_string();
+            let reads = fields.parse()?;
+            let mut text = fields.text_fields(1).into_iter();
+            Response::FeedReads {
+                id,
+                url,
+                reads,
+                title: text.next()?,
+            }
+        }
+        "62" => Response::StartRecommendationList,
+        "63" => {
+            let id = fields.parse()?;
+            let url = fields.next()?.to_string();
+            let score = fields.parse()?;
+            let mut text = fields.text_fields(1).into_iter();
+            Response::Recommendation {
+                id,
+                url,
+                score,
+                title: text.next()?,
+            }
+        }
+        "64" => Response::AckCreateGroup {
+            id: fields.parse()?,
+        },
+        "65" => Response::AckAddMember,
+        "66" => Response::StartGroupList,
+        "67" => Response::Group {
+            id: fields.parse()?,
+            name: fields.next()?.to_string(),
+            members: fields.parse()?,
+            feeds: fields.parse()?,
+        },
+        "68" => Response::AckSnoozeFeed,
+        "69" => Response::StartSnoozedList,
+        "71" => Response::AckKeepUnread,
+        "70" => {
+            let id = fields.parse()?;
+            let url = fields.next()?.to_string();
+            let until = DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+            let mut text = fields.text_fields(1).into_iter();
+            Response::SnoozedFeed {
+                id,
+                url,
+                until,
+                title: text.next()?,
+            }
+        }
+        "72" => Response::StartAllFeedsList,
+        "73" => {
+            let id = fields.parse()?;
+            let url = fields.next()?.to_string();
+            let status = fields.parse()?;
+            let subscribers = fields.parse()?;
+            let failure_count = fields.parse()?;
+            let mut text = fields.text_fields(2).into_iter();
+            Response::ServerFeed {
+                id,
+                url,
+                status,
+                subscribers,
+                failure_count,
+                title: text.next()?,
+                last_error: text.next()?,
+            }
+        }
+        "74" => Response::StartFeedLog,
+        "75" => {
+            let fetched_at = DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+            let status = match fields.next()? {
+                "none" => None,
+                status => Some(status.parse().ok()?),
+            };
+            let duration_ms = fields.parse()?;
+            let mut text = fields.text_fields(1).into_iter();
+            Response::FetchAttempt {
+                fetched_at,
+                status,
+                duration_ms,
+                error: text.next()?,
+            }
+        }
+        "76" => Response::StartSync {
+            token: fields.next()?.to_string(),
+        },
+        "77" => Response::ReadStateChange {
+            id: fields.parse()?,
+            read: match fields.next()? {
+                "read" => true,
+                "unread" => false,
+                _ => return None,
+            },
+        },
+        "78" => Response::SubscriptionChange {
+            id: fields.parse()?,
+            subscribed: match fields.next()? {
+                "subscribed" => true,
+                "unsubscribed" => false,
+                _ => return None,
+            },
+        },
+        "79" => {
+            let id = fields.parse()?;
+            let url = fields.next()?.to_string();
+            let unread = fields.parse()?;
+            let mut text = fields.text_fields(1).into_iter();
+            Response::UnreadFeed {
+                id,
+                url,
+                unread,
+                title: text.next()?,
+            }
+        }
+        "80" => Response::Excerpt {
+            id: fields.parse()?,
+            excerpt: fields.text(),
+        },
+        "81" => {
+            let id = fields.parse()?;
+            let failure_count = fields.parse()?;
+            let mut time = || match fields.next()? {
+                "none" => Some(None),
+                at => DateTime::parse_from_rfc3339(at).ok().map(Some),
+            };
+            let certificate_expires_at = time()?;
+            let certificate_rotated_at = time()?;
+            let mut text = fields.text_fields(1).into_iter();
+            Response::FeedHealth {
+                id,
+                failure_count,
+                certificate_expires_at,
+                certificate_rotated_at,
+                last_error: text.next()?,
+            }
+        }
+        _ => return None,
+    })
+}
+
+impl FromStr for Response {
+    type Err = InvalidResponse;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut fields = Fields { rest: line };
+        let code = fields.next().unwrap_or_default();
+
+        parse_response(code, fields).ok_or_else(|| InvalidResponse(line.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_command() -> Vec<Command> {
+        vec![
+            Command::User {
+                username: "alice".to_string(),
+            },
+            Command::Auth {
+                token: "0123abcd".to_string(),
+            },
+            Command::ListSubscriptions,
+            Command::Subscribe {
+                url: "gemini://example.com/gemlog/".to_string(),
+                mark_old: false,
+            },
+            Command::Subscribe {
+                url: "gemini://example.com/gemlog/".to_string(),
+                mark_old: true,
+            },
+            Command::Unsubscribe { id: 3 },
+            Command::ListUnread { grouped: false },
+            Command::ListUnread { grouped: true },
+            Command::MarkRead { id: 7 },
+            Command::History { limit: 20 },
+            Command::FeedInfo { id: 3 },
+            Command::FeedLog { id: 3, limit: 5 },
+            Command::FeedHealth { id: 3 },
+            Command::ShowArchive { id: 3 },
+            Command::Backup,
+            Command::FeedStats,
+            Command::Health,
+            Command::ListUsers,
+            Command::ListAllFeeds,
+            Command::Refresh { id: 3 },
+            Command::Notify {
+                id: 3,
+                enabled: true,
+            },
+            Command::Notify {
+                id: 3,
+                enabled: false,
+            },
+            Command::Search {
+                query: "rust async".to_string(),
+            },
+            Command::GetEntry { id: 7 },
+            Command::SlowFeeds { limit: 10 },
+            Command::SetPref {
+                name: "backlog".to_string(),
+                value: "7".to_string(),
+            },
+            Command::Discover {
+                query: Some("space travel".to_string()),
+            },
+            Command::Discover { query: None },
+            Command::RefreshAll,
+            Command::AuditLog { limit: 50 },
+            Command::ReadStats,
+            Command::Recommend,
+            Command::CreateGroup {
+                name: "friends".to_string(),
+            },
+            Command::AddMember {
+                group: "friends".to_string(),
+                username: "bob".to_string(),
+            },
+            Command::GroupSubscribe {
+                group: "friends".to_string(),
+                url: "gemini://example.com/gemlog/".to_string(),
+            },
+            Command::GroupUnsubscribe {
+                group: "friends".to_string(),
+                id: 3,
+            },
+            Command::ListGroups,
+            Command::GroupFeeds {
+                group: "friends".to_string(),
+            },
+            Command::SnoozeFeed {
+                id: 3,
+                until: NaiveDate::from_ymd_opt(2021, 4, 1).unwrap(),
+            },
+            Command::ListSnoozed,
+            Command::KeepUnread {
+                id: 3,
+                limit: Some(10),
+            },
+            Command::KeepUnread { id: 3, limit: None },
+            Command::Sync {
+                token: Some("42".to_string()),
+            },
+            Command::Sync { token: None },
+        ]
+    }
+
+    #[test]
+    fn commands_parse_back_unchanged() {
+        for command in every_command() {
+            let line = command.to_string();
+            assert_eq!(line.parse::<Command>(), Ok(command), "{}", line);
+        }
+    }
+
+    #[test]
+    fn verbs_and_options_ignore_case() {
+        assert_eq!(
+            "subscribe gemini://example.com/ markold".parse::<Command>(),
+            Ok(Command::Subscribe {
+                url: "gemini://example.com/".to_string(),
+                mark_old: true,
+            })
+        );
+        assert_eq!(
+            "ListUnread grouped".parse::<Command>(),
+            Ok(Command::ListUnread { grouped: true })
+        );
+        assert_eq!(
+            "KEEPUNREAD 3 OFF".parse::<Command>(),
+            Ok(Command::KeepUnread { id: 3, limit: None })
+        );
+    }
+
+    #[test]
+    fn limits_default_when_left_out() {
+        assert_eq!(
+            "HISTORY".parse::<Command>(),
+            Ok(Command::History {
+                limit: DEFAULT_HISTORY_LIMIT,
+            })
+        );
+    }
+
+    #[test]
+    fn empty_and_unknown_commands_are_errors() {
+        assert_eq!("".parse::<Command>(), Err(ParseError::Empty));
+        assert_eq!("   ".parse::<Command>(), Err(ParseError::Empty));
+        assert_eq!(
+            "FROBNICATE 3".parse::<Command>(),
+            Err(ParseError::UnknownCommand("FROBNICATE".to_string()))
+        );
+    }
+
+    #[test]
+    fn missing_arguments_are_errors() {
+        assert_eq!(
+            "UNSUBSCRIBE".parse::<Command>(),
+            Err(ParseError::MissingArgument {
+                command: "UNSUBSCRIBE",
+                argument: "id",
+            })
+        );
+        assert_eq!(
+            "SEARCH".parse::<Command>(),
+            Err(ParseError::MissingArgument {
+                command: "SEARCH",
+                argument: "query",
+            })
+        );
+        assert_eq!(
+            "SETPREF backlog".parse::<Command>(),
+            Err(ParseError::MissingArgument {
+                command: "SETPREF",
+                argument: "value",
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_arguments_are_errors() {
+        let invalid = |line: &str, command, argument, value: &str| {
+            assert_eq!(
+                line.parse::<Command>(),
+                Err(ParseError::InvalidArgument {
+                    command,
+                    argument,
+                    value: value.to_string(),
+                }),
+                "{}",
+                line
+            );
+        };
+
+        invalid("MARKREAD seven", "MARKREAD", "id", "seven");
+        invalid("HISTORY 0", "HISTORY", "limit", "0");
+        invalid("HISTORY -1", "HISTORY", "limit", "-1");
+        invalid("FEEDLOG 3 many", "FEEDLOG", "limit", "many");
+        invalid("KEEPUNREAD 3 0", "KEEPUNREAD", "limit|off", "0");
+        invalid("NOTIFY 3 maybe", "NOTIFY", "on|off", "maybe");
+        invalid("SNOOZEFEED 3 tomorrow", "SNOOZEFEED", "until", "tomorrow");
+        invalid(
+            "SUBSCRIBE gemini://example.com/ NOW",
+            "SUBSCRIBE",
+            "MARKOLD",
+            "NOW",
+        );
+    }
+}
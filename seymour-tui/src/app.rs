@@ -0,0 +1,258 @@
+//! What the reader shows and the actions keys trigger.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use ratatui::widgets::ListState;
+use seymour_client::{Client, Entry, EntryContent, Error, ErrorCode, Response};
+use tokio::runtime::Runtime;
+
+use crate::cache::Cache;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Focus {
+    Feeds,
+    Entries,
+    Entry,
+}
+
+/// The entry being read, and its content if the server fetched any.
+pub struct Viewing {
+    pub entry: Entry,
+    pub content: Option<EntryContent>,
+}
+
+pub struct App {
+    runtime: Runtime,
+    server: String,
+    username: String,
+    /// `None` while offline.
+    client: Option<Client>,
+    cache_path: Option<PathBuf>,
+    pub cache: Cache,
+    pub focus: Focus,
+    /// The first row is every feed, the rest are subscriptions in order.
+    pub feeds: ListState,
+    pub entries: ListState,
+    pub viewing: Option<Viewing>,
+    pub scroll: u16,
+    pub status: String,
+}
+
+impl App {
+    pub fn new(runtime: Runtime, server: String, username: String) -> Self {
+        let cache_path = Cache::path(&server, &username);
+        let cache = cache_path.as_deref().map(Cache::load).unwrap_or_default();
+
+        let mut feeds = ListState::default();
+        feeds.select(Some(0));
+        let mut entries = ListState::default();
+        entries.select(Some(0));
+
+        Self {
+            runtime,
+            server,
+            username,
+            client: None,
+            cache_path,
+            cache,
+            focus: Focus::Entries,
+            feeds,
+            entries,
+            viewing: None,
+            scroll: 0,
+            status: String::new(),
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.client.is_none()
+    }
+
+    /// Unread entries of the selected feed.
+    pub fn visible_entries(&self) -> Vec<&Entry> {
+        let feed_id = match self.feeds.selected() {
+            Some(0) | None => None,
+            Some(i) => self.cache.subscriptions.get(i - 1).map(|feed| feed.id),
+        };
+
+        self.cache
+            .unread
+            .iter()
+            .filter(|entry| feed_id.is_none_or(|id| entry.feed_id == id))
+            .collect()
+    }
+
+    fn selected_entry(&self) -> Option<Entry> {
+        let entries = self.visible_entries();
+        self.entries
+            .selected()
+            .and_then(|i| entries.get(i))
+            .map(|entry| (*entry).clone())
+    }
+
+    fn save_cache(&mut self) {
+        if let Some(path) = &self.cache_path {
+            if let Err(e) = self.cache.save(path) {
+                self.status = format!("Failed to save cache: {:#}", e);
+            }
+        }
+    }
+
+    /// Reconnects if needed, sends reads made offline, and reloads
+    /// subscriptions and unread entries.
+    pub fn refresh(&mut self) {
+        match self.try_refresh() {
+            Ok(()) => self.status = format!("{} unread", self.cache.unread.len()),
+            Err(e) => {
+                self.client = None;
+                self.status = format!("Offline, showing cached entries: {:#}", e);
+            }
+        }
+        self.clamp_selections();
+        self.save_cache();
+    }
+
+    fn try_refresh(&mut self) -> Result<()> {
+        let server = &self.server;
+        let username = &self.username;
+        let client_slot = &mut self.client;
+        let cache = &mut self.cache;
+
+        self.runtime.block_on(async move {
+            if client_slot.is_none() {
+                let mut client = Client::connect(server.as_str()).await?;
+                client.login(username).await?;
+                *client_slot = Some(client);
+            }
+            let client = client_slot.as_mut().expect("client was just connected");
+
+            while let Some(id) = cache.pending_reads.first().copied() {
+                mark_read(client, id).await?;
+                cache.pending_reads.remove(0);
+            }
+
+            cache.subscriptions = client.subscriptions().await?;
+            cache.unread = client.list_unread().await?;
+            cache.prune();
+
+            Ok(())
+        })
+    }
+
+    fn clamp_selections(&mut self) {
+        let feeds = self.cache.subscriptions.len() + 1;
+        if self.feeds.selected().is_none_or(|i| i >= feeds) {
+            self.feeds.select(Some(0));
+        }
+
+        let entries = self.visible_entries().len();
+        match self.entries.selected() {
+            _ if entries == 0 => self.entries.select(None),
+            Some(i) if i < entries => {}
+            _ => self.entries.select(Some(entries - 1)),
+        }
+    }
+
+    pub fn move_selection(&mut self, down: bool) {
+        let (state, len) = match self.focus {
+            Focus::Feeds => (&mut self.feeds, self.cache.subscriptions.len() + 1),
+            Focus::Entries => {
+                let len = self.visible_entries().len();
+                (&mut self.entries, len)
+            }
+            Focus::Entry => {
+                self.scroll = if down {
+                    self.scroll.saturating_add(1)
+                } else {
+                    self.scroll.saturating_sub(1)
+                };
+                return;
+            }
+        };
+        if len == 0 {
+            return;
+        }
+
+        let selected = state.selected().unwrap_or(0);
+        state.select(Some(if down {
+            (selected + 1).min(len - 1)
+        } else {
+            selected.saturating_sub(1)
+        }));
+
+        if self.focus == Focus::Feeds {
+            self.entries.select(Some(0));
+            self.clamp_selections();
+        }
+    }
+
+    /// Shows the selected entry and marks it read.
+    pub fn open_selected(&mut self) {
+        let entry = match self.selected_entry() {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        let mut content = self.cache.contents.get(&entry.id).cloned();
+        if content.is_none() {
+            if let Some(client) = self.client.as_mut() {
+                match self.runtime.block_on(client.entry_content(entry.id)) {
+                    Ok(fetched) => content = Some(fetched),
+                    // The server hasn't fetched it, which isn't a problem.
+                    Err(Error::Server(response))
+                        if is_error(&response, ErrorCode::ContentNotFound) => {}
+                    Err(e) => self.status = format!("Failed to load entry: {}", e),
+                }
+            }
+        }
+
+        self.mark_entry_read(entry.id);
+        self.viewing = Some(Viewing { entry, content });
+        self.focus = Focus::Entry;
+        self.scroll = 0;
+    }
+
+    /// Marks the selected entry read without opening it.
+    pub fn mark_selected_read(&mut self) {
+        if let Some(entry) = self.selected_entry() {
+            self.mark_entry_read(entry.id);
+            self.status = format!("Marked \"{}\" read", entry.title);
+        }
+    }
+
+    fn mark_entry_read(&mut self, id: i64) {
+        let result = match self.client.as_mut() {
+            Some(client) => self.runtime.block_on(mark_read(client, id)),
+            None => Err(anyhow::format_err!("offline")),
+        };
+        if result.is_err() {
+            self.client = None;
+            self.cache.pending_reads.push(id);
+        }
+
+        self.cache.unread.retain(|entry| entry.id != id);
+        self.cache.contents.remove(&id);
+        self.clamp_selections();
+        self.save_cache();
+    }
+
+    pub fn close_entry(&mut self) {
+        self.viewing = None;
+        self.focus = Focus::Entries;
+    }
+}
+
+/// Whether `response` is the error `code`.
+fn is_error(response: &Response, code: ErrorCode) -> bool {
+    response.error_detail().map(|error| error.code) == Some(code)
+}
+
+async fn mark_read(client: &mut Client, id: i64) -> Result<()> {
+    match client.mark_read(id).await {
+        // Someone else got to it first.
+        Ok(()) => Ok(()),
+        Err(Error::Server(response)) if is_error(&response, ErrorCode::EntryNotFound) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,58 @@
+//! Keeps the last view of the server on disk, so entries can be read while
+//! it's unreachable.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use seymour_client::{Entry, EntryContent, Subscription};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cache {
+    pub subscriptions: Vec<Subscription>,
+    pub unread: Vec<Entry>,
+    /// Fetched contents of unread entries, by entry ID.
+    pub contents: HashMap<i64, EntryContent>,
+    /// Entries read while offline, to mark read once reconnected.
+    pub pending_reads: Vec<i64>,
+}
+
+impl Cache {
+    /// Where the cache for `username` on `server` lives, under
+    /// `$XDG_CACHE_HOME` or `~/.cache`.
+    pub fn path(server: &str, username: &str) -> Option<PathBuf> {
+        let dir = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+        };
+        let name = format!("{}@{}.json", username, server).replace(&['/', ':'][..], "_");
+
+        Some(dir.join("seymour-tui").join(name))
+    }
+
+    /// Loads the cache, starting over when it's missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        fs::write(path, serde_json::to_vec(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Drops contents of entries that are no longer unread.
+    pub fn prune(&mut self) {
+        let unread = &self.unread;
+        self.contents
+            .retain(|id, _| unread.iter().any(|entry| entry.id == *id));
+    }
+}
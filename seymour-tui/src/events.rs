@@ -0,0 +1,68 @@
+//! Listens to the HTTP API's server-sent events, so new entries show up as
+//! they're fetched instead of on the next reload.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{format_err, Result};
+use hyper::body::HttpBody;
+use hyper::header::{ACCEPT, AUTHORIZATION};
+use hyper::{Body, Client, Request, StatusCode};
+
+/// How long to wait before reconnecting once the stream ends or fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Listens to `GET /events` of the API at `api_url` on its own thread. A
+/// message arrives on the returned channel whenever the unread entries
+/// should be reloaded: for every event, and whenever the stream drops,
+/// since events sent meanwhile are missed.
+pub fn watch(api_url: String, token: String) -> Result<Receiver<()>> {
+    let (changed, changes) = mpsc::channel();
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()?;
+
+    thread::spawn(move || {
+        runtime.block_on(async move {
+            // The stream only ends by failing. Sends fail once the reader
+            // has quit, which ends the thread too.
+            loop {
+                let _ = listen(&api_url, &token, &changed).await;
+                if changed.send(()).is_err() {
+                    return;
+                }
+                tokio::time::delay_for(RECONNECT_DELAY).await;
+            }
+        })
+    });
+
+    Ok(changes)
+}
+
+async fn listen(api_url: &str, token: &str, changed: &Sender<()>) -> Result<()> {
+    let request = Request::get(format!("{}/events", api_url.trim_end_matches('/')))
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .header(ACCEPT, "text/event-stream")
+        .body(Body::empty())?;
+    let mut response = Client::new().request(request).await?;
+    if response.status() != StatusCode::OK {
+        return Err(format_err!("GET /events failed: {}", response.status()));
+    }
+
+    // Events end with a blank line. Which event it was doesn't matter,
+    // since both new entries and missed ones mean reloading.
+    let mut buffer = Vec::new();
+    while let Some(chunk) = response.body_mut().data().await {
+        buffer.extend_from_slice(&chunk?);
+        while let Some(end) = buffer.windows(2).position(|window| window == b"\n\n") {
+            buffer.drain(..end + 2);
+            changed
+                .send(())
+                .map_err(|_| format_err!("the reader quit"))?;
+        }
+    }
+
+    Err(format_err!("the server closed the event stream"))
+}
@@ -0,0 +1,135 @@
+//! A terminal reader for seymour, built on `seymour-client`.
+//!
+//! Connects to `$SEYMOUR_SERVER` as `$SEYMOUR_USER` and keeps the last
+//! subscriptions and unread entries on disk, so it still works offline.
+//! With `$SEYMOUR_API_URL` and `$SEYMOUR_TOKEN` set, it reloads whenever the
+//! HTTP API pushes an event.
+
+mod app;
+mod cache;
+mod events;
+mod ui;
+
+use std::io::{self, Stdout};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use anyhow::{format_err, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::CrosstermBackend;
+use ratatui::Terminal;
+
+use crate::app::{App, Focus};
+
+/// Without the API's events, entries are reloaded this often.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often pushed events are checked for while waiting on the keyboard.
+const EVENT_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+type Term = Terminal<CrosstermBackend<Stdout>>;
+
+fn run(terminal: &mut Term, app: &mut App, changes: Option<Receiver<()>>) -> Result<()> {
+    app.refresh();
+    let mut last_refresh = Instant::now();
+
+    loop {
+        terminal.draw(|frame| ui::draw(frame, app))?;
+
+        let ready = match &changes {
+            Some(changes) => {
+                if changes.try_iter().count() > 0 {
+                    app.refresh();
+                    continue;
+                }
+                event::poll(EVENT_CHECK_INTERVAL)?
+            }
+            None => {
+                let timeout = REFRESH_INTERVAL
+                    .checked_sub(last_refresh.elapsed())
+                    .unwrap_or_default();
+                if !event::poll(timeout)? {
+                    app.refresh();
+                    last_refresh = Instant::now();
+                    continue;
+                }
+                true
+            }
+        };
+        if !ready {
+            continue;
+        }
+
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match (key.code, app.focus) {
+            (KeyCode::Char('q'), _) => return Ok(()),
+            (KeyCode::Char('r'), _) => {
+                app.refresh();
+                last_refresh = Instant::now();
+            }
+            (KeyCode::Esc, Focus::Entry) | (KeyCode::Char('h'), Focus::Entry) => app.close_entry(),
+            (KeyCode::Tab, Focus::Feeds) | (KeyCode::Char('l'), Focus::Feeds) => {
+                app.focus = Focus::Entries
+            }
+            (KeyCode::Tab, Focus::Entries) | (KeyCode::Char('h'), Focus::Entries) => {
+                app.focus = Focus::Feeds
+            }
+            (KeyCode::Enter, Focus::Entries) | (KeyCode::Char('l'), Focus::Entries) => {
+                app.open_selected()
+            }
+            (KeyCode::Char('m'), Focus::Entries) => app.mark_selected_read(),
+            (KeyCode::Char('j'), _) | (KeyCode::Down, _) => app.move_selection(true),
+            (KeyCode::Char('k'), _) | (KeyCode::Up, _) => app.move_selection(false),
+            _ => {}
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let server = std::env::var("SEYMOUR_SERVER").unwrap_or_else(|_| "localhost:2003".to_string());
+    let username =
+        std::env::var("SEYMOUR_USER").map_err(|_| format_err!("SEYMOUR_USER must be set"))?;
+
+    let runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()?;
+    let mut app = App::new(runtime, server, username);
+
+    let changes = match (
+        std::env::var("SEYMOUR_API_URL"),
+        std::env::var("SEYMOUR_TOKEN"),
+    ) {
+        (Ok(api_url), Ok(token)) => Some(events::watch(api_url, token)?),
+        _ => None,
+    };
+
+    // Put the terminal back before a panic's message is printed, or it's
+    // lost in the alternate screen and the shell is left in raw mode.
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        default_hook(info);
+    }));
+
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let result = run(&mut terminal, &mut app, changes);
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+
+    result
+}
@@ -0,0 +1,103 @@
+//! Draws the feed sidebar, the entry list or entry, and a status line.
+
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, Wrap};
+use ratatui::Frame;
+
+use crate::app::{App, Focus, Viewing};
+
+fn block(title: &str, focused: bool) -> Block<'_> {
+    let block = Block::default().borders(Borders::ALL).title(title);
+    if focused {
+        block.border_style(Style::default().add_modifier(Modifier::BOLD))
+    } else {
+        block
+    }
+}
+
+fn highlight() -> Style {
+    Style::default().add_modifier(Modifier::REVERSED)
+}
+
+pub fn draw(frame: &mut Frame<'_>, app: &mut App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)])
+        .split(frame.size());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(25), Constraint::Percentage(75)])
+        .split(rows[0]);
+
+    draw_feeds(frame, app, columns[0]);
+    match &app.viewing {
+        Some(viewing) => draw_entry(frame, viewing, app.scroll, columns[1]),
+        None => draw_entries(frame, app, columns[1]),
+    }
+
+    let status = if app.is_offline() {
+        format!("[offline] {}", app.status)
+    } else {
+        app.status.clone()
+    };
+    frame.render_widget(Paragraph::new(status), rows[1]);
+}
+
+fn draw_feeds(frame: &mut Frame<'_>, app: &mut App, area: Rect) {
+    let unread = &app.cache.unread;
+    let mut items = vec![ListItem::new(format!("All ({})", unread.len()))];
+    items.extend(app.cache.subscriptions.iter().map(|feed| {
+        let count = unread
+            .iter()
+            .filter(|entry| entry.feed_id == feed.id)
+            .count();
+        let title = feed.title.as_deref().unwrap_or(&feed.url);
+        ListItem::new(format!("{} ({})", title, count))
+    }));
+
+    let list = List::new(items)
+        .block(block("Feeds", app.focus == Focus::Feeds))
+        .highlight_style(highlight());
+    frame.render_stateful_widget(list, area, &mut app.feeds);
+}
+
+fn draw_entries(frame: &mut Frame<'_>, app: &mut App, area: Rect) {
+    let items: Vec<_> = app
+        .visible_entries()
+        .into_iter()
+        .map(|entry| ListItem::new(entry.title.clone()))
+        .collect();
+
+    let list = List::new(items)
+        .block(block("Unread", app.focus == Focus::Entries))
+        .highlight_style(highlight());
+    frame.render_stateful_widget(list, area, &mut app.entries);
+}
+
+fn draw_entry(frame: &mut Frame<'_>, viewing: &Viewing, scroll: u16, area: Rect) {
+    let mut lines = vec![
+        Line::styled(
+            viewing.entry.title.clone(),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Line::raw(viewing.entry.url.clone()),
+        Line::raw(""),
+    ];
+    match &viewing.content {
+        Some(content) => lines.extend(
+            content
+                .content
+                .lines()
+                .map(|line| Line::raw(line.to_string())),
+        ),
+        None => lines.push(Line::raw("No content was fetched for this entry.")),
+    }
+
+    let paragraph = Paragraph::new(lines)
+        .block(block("Entry", true))
+        .wrap(Wrap { trim: false })
+        .scroll((scroll, 0));
+    frame.render_widget(paragraph, area);
+}
@@ -0,0 +1,134 @@
+//! Decides which clients may connect, by network and by how many
+//! connections their address already has open.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{format_err, Result};
+use ipnet::IpNet;
+
+/// Parses a comma-separated list of CIDR blocks, e.g. `10.0.0.0/8,::1/128`.
+pub fn parse_networks(list: &str) -> Result<Vec<IpNet>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|network| !network.is_empty())
+        .map(|network| {
+            network
+                .parse()
+                .map_err(|e| format_err!("invalid network \"{}\": {}", network, e))
+        })
+        .collect()
+}
+
+/// IPv4 clients of a socket bound to `[::]` show up as IPv4-mapped IPv6
+/// addresses, which IPv4 networks wouldn't otherwise contain.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, _, _] => v6.to_ipv4().map_or(ip, IpAddr::V4),
+            _ => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// Networks clients may and may not connect from.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkAccess {
+    /// When not empty, only these networks may connect.
+    pub allowed: Vec<IpNet>,
+    /// Never allowed, even when also in `allowed`.
+    pub denied: Vec<IpNet>,
+}
+
+impl NetworkAccess {
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        let ip = canonical(ip);
+
+        if self.denied.iter().any(|network| network.contains(&ip)) {
+            return false;
+        }
+
+        self.allowed.is_empty() || self.allowed.iter().any(|network| network.contains(&ip))
+    }
+}
+
+/// A connection counted towards a limit until it's dropped, which happens
+/// even if the task handling it panics.
+pub struct CountedConnection(Arc<AtomicUsize>);
+
+impl CountedConnection {
+    /// Counts one more connection in `count`, unless `limit` are already
+    /// counted.
+    pub fn try_count(count: &Arc<AtomicUsize>, limit: usize) -> Option<Self> {
+        count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                Some(open + 1).filter(|_| open < limit)
+            })
+            .ok()
+            .map(|_| Self(count.clone()))
+    }
+}
+
+impl Drop for CountedConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The counts every listener's connections are held to, so clients can't
+/// get past the limits by connecting to another one.
+#[derive(Clone, Default)]
+pub struct ConnectionCounts {
+    pub open: Arc<AtomicUsize>,
+    pub per_ip: Arc<ConnectionsPerIp>,
+}
+
+/// Counts open connections per client address.
+#[derive(Default)]
+pub struct ConnectionsPerIp {
+    counts: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// A connection counted by `ConnectionsPerIp::try_open`, until it's dropped.
+pub struct IpConnection {
+    connections: Arc<ConnectionsPerIp>,
+    ip: IpAddr,
+}
+
+impl ConnectionsPerIp {
+    /// Counts a new connection from `ip` unless it already has `limit`
+    /// open.
+    pub fn try_open(self: &Arc<Self>, ip: IpAddr, limit: Option<usize>) -> Option<IpConnection> {
+        let mut counts = self.counts.lock().expect("connection counts lock poisoned");
+        let ip = canonical(ip);
+        let count = counts.entry(ip).or_insert(0);
+        if limit.is_some_and(|limit| *count >= limit) {
+            return None;
+        }
+
+        *count += 1;
+        Some(IpConnection {
+            connections: self.clone(),
+            ip,
+        })
+    }
+
+    fn close(&self, ip: IpAddr) {
+        let mut counts = self.counts.lock().expect("connection counts lock poisoned");
+        if let Some(count) = counts.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&ip);
+            }
+        }
+    }
+}
+
+impl Drop for IpConnection {
+    fn drop(&mut self) {
+        self.connections.close(self.ip);
+    }
+}
@@ -0,0 +1,405 @@
+//! A JSON HTTP API offering what the line protocol does for a single user,
+//! so web and mobile clients don't need to speak the protocol.
+//!
+//! Requests authenticate with `Authorization: Bearer <token>`, using a
+//! token made with `seymour addtoken`.
+//!
+//! * `GET /subscriptions`
+//! * `POST /subscriptions` with `{"url": ...}`
+//! * `DELETE /subscriptions/<feed id>`
+//! * `GET /unread`
+//! * `POST /entries/<entry id>/read`
+//! * `GET /search?q=<text>[&limit=<n>]`
+//! * `GET /events`, a stream of server-sent events for new entries
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::Utc;
+use futures::{stream, TryStreamExt};
+use hyper::body::HttpBody;
+use hyper::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use seymour_protocol::{FeedStatus, DEFAULT_SEARCH_LIMIT};
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast::{self, RecvError};
+use tracing::{error, info};
+use url::form_urlencoded;
+
+use crate::events::NewEntry;
+use crate::shutdown::Shutdown;
+use crate::store::Store;
+use crate::Config;
+
+/// Bodies are only ever a URL, so anything bigger is refused.
+const MAX_BODY_SIZE: usize = 16 * 1024;
+
+/// Returns a new random token, which is shown once and only its hash kept.
+pub fn new_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .collect()
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[derive(Serialize)]
+struct Subscription {
+    id: i64,
+    url: String,
+    status: String,
+    unread: i64,
+    title: Option<String>,
+    subtitle: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Subscribed {
+    id: i64,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct SubscribeRequest {
+    url: String,
+    /// Marks the feed's entries so far read.
+    #[serde(default)]
+    mark_old: bool,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    id: i64,
+    feed_id: i64,
+    feed_url: String,
+    url: String,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    feed_title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    read: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct Error<'a> {
+    error: &'a str,
+}
+
+fn json(status: StatusCode, value: &impl Serialize) -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(value)?))?)
+}
+
+fn error(status: StatusCode, message: &str) -> Result<Response<Body>> {
+    json(status, &Error { error: message })
+}
+
+fn no_content() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())?)
+}
+
+async fn authenticate(store: &dyn Store, request: &Request<Body>) -> Result<Option<i64>> {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match token {
+        Some(token) => store.user_for_token(&hash_token(token.trim())).await,
+        None => Ok(None),
+    }
+}
+
+async fn subscriptions(store: &dyn Store, user_id: i64) -> Result<Response<Body>> {
+    let unread = store.feed_unread_counts(user_id).await?;
+    let subscriptions: Vec<Subscription> = store
+        .subscriptions(user_id)
+        .await?
+        .into_iter()
+        .map(|subscription| Subscription {
+            id: subscription.feed_id,
+            url: subscription.url,
+            status: FeedStatus::new(subscription.gone, subscription.failing).to_string(),
+            unread: unread.get(&subscription.feed_id).copied().unwrap_or(0),
+            title: subscription.title,
+            subtitle: subscription.subtitle,
+        })
+        .collect();
+
+    json(StatusCode::OK, &subscriptions)
+}
+
+async fn subscribe(
+    store: &dyn Store,
+    config: &Config,
+    user_id: i64,
+    request: Request<Body>,
+) -> Result<Response<Body>> {
+    let mut chunks = request.into_body();
+    let mut body = Vec::new();
+    while let Some(chunk) = chunks.data().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > MAX_BODY_SIZE {
+            return error(StatusCode::PAYLOAD_TOO_LARGE, "request body too large");
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let request: SubscribeRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return error(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let (id, url) = crate::subscribe(store, config, user_id, request.url, request.mark_old).await?;
+
+    json(StatusCode::OK, &Subscribed { id, url })
+}
+
+async fn unsubscribe(store: &dyn Store, user_id: i64, feed_id: i64) -> Result<Response<Body>> {
+    if store.unsubscribe(user_id, feed_id).await? {
+        no_content()
+    } else {
+        error(StatusCode::NOT_FOUND, "no such subscription")
+    }
+}
+
+async fn unread(store: &dyn Store, user_id: i64) -> Result<Response<Body>> {
+    let entries: Vec<Entry> = store
+        .unread_entries(user_id)
+        .map_ok(|entry| Entry {
+            id: entry.id,
+            feed_id: entry.feed_id,
+            feed_url: entry.feed_url,
+            url: entry.url,
+            title: entry.title,
+            feed_title: entry.feed_title,
+            read: None,
+        })
+        .try_collect()
+        .await?;
+
+    json(StatusCode::OK, &entries)
+}
+
+async fn mark_read(store: &dyn Store, user_id: i64, entry_id: i64) -> Result<Response<Body>> {
+    if !store.can_access_entry(user_id, entry_id).await? {
+        return error(StatusCode::NOT_FOUND, "no such entry");
+    }
+
+    store
+        .mark_read(user_id, entry_id, Utc::now().naive_utc())
+        .await?;
+
+    no_content()
+}
+
+async fn search(store: &dyn Store, user_id: i64, query: Option<&str>) -> Result<Response<Body>> {
+    let mut text = None;
+    let mut limit = DEFAULT_SEARCH_LIMIT;
+    for (name, value) in form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        match name.as_ref() {
+            "q" => text = Some(value.into_owned()),
+            "limit" => match value.parse() {
+                Ok(value) => limit = value,
+                Err(_) => return error(StatusCode::BAD_REQUEST, "invalid limit"),
+            },
+            _ => {}
+        }
+    }
+    let text = match text {
+        Some(text) if !text.is_empty() => text,
+        _ => return error(StatusCode::BAD_REQUEST, "missing query parameter q"),
+    };
+
+    let entries: Vec<Entry> = store
+        .search_entries(user_id, &text, limit)
+        .await?
+        .into_iter()
+        .map(|entry| Entry {
+            id: entry.id,
+            feed_id: entry.feed_id,
+            feed_url: entry.feed_url,
+            url: entry.url,
+            title: entry.title,
+            feed_title: entry.feed_title,
+            read: Some(entry.read),
+        })
+        .collect();
+
+    json(StatusCode::OK, &entries)
+}
+
+fn server_sent_event(name: &str, value: &impl Serialize) -> Result<String> {
+    Ok(format!(
+        "event: {}\ndata: {}\n\n",
+        name,
+        serde_json::to_string(value)?
+    ))
+}
+
+/// Streams entries added to the user's subscriptions as `entry` events
+/// until the client goes away or the server shuts down. A `lagged` event
+/// means some were missed, and the client should fetch `/unread` again.
+fn events(
+    store: Arc<dyn Store>,
+    user_id: i64,
+    new_entries: broadcast::Receiver<NewEntry>,
+    shutdown: Shutdown,
+) -> Result<Response<Body>> {
+    let events = stream::unfold(
+        (store, new_entries, shutdown),
+        move |(store, mut new_entries, mut shutdown)| async move {
+            loop {
+                // Graceful shutdown waits for every response to finish, so
+                // the stream has to end by itself.
+                let received = tokio::select! {
+                    _ = shutdown.requested() => return None,
+                    received = new_entries.recv() => received,
+                };
+                let event = match received {
+                    Ok(entry) => match store.subscribed_feed(user_id, entry.feed_id).await {
+                        Ok(Some(feed)) => server_sent_event(
+                            "entry",
+                            &Entry {
+                                id: entry.id,
+                                feed_id: entry.feed_id,
+                                feed_url: entry.feed_url,
+                                url: entry.url,
+                                title: entry.title,
+                                feed_title: feed.title,
+                                read: None,
+                            },
+                        ),
+                        Ok(None) => continue,
+                        Err(e) => Err(e),
+                    },
+                    Err(RecvError::Lagged(missed)) => {
+                        server_sent_event("lagged", &serde_json::json!({ "missed": missed }))
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((event, (store, new_entries, shutdown)));
+            }
+        },
+    );
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(events))?)
+}
+
+async fn route(
+    store: &Arc<dyn Store>,
+    config: &Config,
+    new_entries: &broadcast::Sender<NewEntry>,
+    shutdown: &Shutdown,
+    request: Request<Body>,
+) -> Result<Response<Body>> {
+    let user_id = match authenticate(&**store, &request).await? {
+        Some(user_id) => user_id,
+        None => return error(StatusCode::UNAUTHORIZED, "missing or unknown token"),
+    };
+
+    // Owned, since subscribing consumes the request.
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (&method, segments.as_slice()) {
+        (&Method::GET, ["subscriptions"]) => subscriptions(&**store, user_id).await,
+        (&Method::POST, ["subscriptions"]) => subscribe(&**store, config, user_id, request).await,
+        (&Method::DELETE, ["subscriptions", feed_id]) => match feed_id.parse() {
+            Ok(feed_id) => unsubscribe(&**store, user_id, feed_id).await,
+            Err(_) => error(StatusCode::NOT_FOUND, "not found"),
+        },
+        (&Method::GET, ["unread"]) => unread(&**store, user_id).await,
+        (&Method::POST, ["entries", entry_id, "read"]) => match entry_id.parse() {
+            Ok(entry_id) => mark_read(&**store, user_id, entry_id).await,
+            Err(_) => error(StatusCode::NOT_FOUND, "not found"),
+        },
+        (&Method::GET, ["search"]) => search(&**store, user_id, request.uri().query()).await,
+        (&Method::GET, ["events"]) => events(
+            store.clone(),
+            user_id,
+            new_entries.subscribe(),
+            shutdown.clone(),
+        ),
+        _ => error(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+async fn handle(
+    store: &Arc<dyn Store>,
+    config: &Config,
+    new_entries: &broadcast::Sender<NewEntry>,
+    shutdown: &Shutdown,
+    request: Request<Body>,
+) -> Response<Body> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    match route(store, config, new_entries, shutdown, request).await {
+        Ok(response) => {
+            info!("{} {} {}", method, path, response.status().as_u16());
+            response
+        }
+        Err(e) => {
+            error!("{} {} failed: {:#}", method, path, e);
+            let mut response = Response::new(Body::from(r#"{"error":"internal error"}"#));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+/// Serves the API on `address` until shutdown is requested.
+pub(crate) async fn serve(
+    address: SocketAddr,
+    store: Arc<dyn Store>,
+    config: Arc<Config>,
+    new_entries: broadcast::Sender<NewEntry>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    let streams_shutdown = shutdown.clone();
+    let make_service = make_service_fn(move |_| {
+        let store = store.clone();
+        let config = config.clone();
+        let new_entries = new_entries.clone();
+        let shutdown = streams_shutdown.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let store = store.clone();
+                let config = config.clone();
+                let new_entries = new_entries.clone();
+                let shutdown = shutdown.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        handle(&store, &config, &new_entries, &shutdown, request).await,
+                    )
+                }
+            }))
+        }
+    });
+
+    info!("Serving HTTP API on: {}", address);
+    Server::try_bind(&address)?
+        .serve(make_service)
+        .with_graceful_shutdown(async move { shutdown.requested().await })
+        .await?;
+
+    Ok(())
+}
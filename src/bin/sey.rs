@@ -0,0 +1,161 @@
+//! A small reader for the shell, e.g. `sey unread` then `sey open 42`.
+//!
+//! Connects to `--server` as `--user` for each command, using
+//! `seymour-client`, so it doubles as an example of a protocol client.
+
+use std::process;
+
+use anyhow::{format_err, Context, Result};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use seymour_client::{Client, Entry};
+
+#[cfg(target_os = "macos")]
+const DEFAULT_OPENER: &str = "open";
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_OPENER: &str = "xdg-open";
+
+fn cli() -> App<'static, 'static> {
+    let entry_id = Arg::with_name("entry-id").required(true);
+
+    App::new("sey")
+        .about("Reads feeds from a seymour server")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("server")
+                .long("server")
+                .short("s")
+                .env("SEYMOUR_SERVER")
+                .default_value("localhost:2003")
+                .help("Address of the server"),
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .short("u")
+                .env("SEYMOUR_USER")
+                .required(true)
+                .help("User to read as"),
+        )
+        .arg(
+            Arg::with_name("opener")
+                .long("opener")
+                .env("SEY_OPENER")
+                .default_value(DEFAULT_OPENER)
+                .help("Program `open` passes entry URLs to"),
+        )
+        .subcommand(SubCommand::with_name("unread").about("Lists unread entries, oldest first"))
+        .subcommand(
+            SubCommand::with_name("open")
+                .about("Opens an unread entry and marks it read")
+                .arg(entry_id.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("read")
+                .about("Marks an entry read without opening it")
+                .arg(entry_id),
+        )
+        .subcommand(
+            SubCommand::with_name("subscribe")
+                .about("Subscribes to a feed or a capsule with one")
+                .arg(Arg::with_name("url").required(true))
+                .arg(
+                    Arg::with_name("mark-old")
+                        .long("mark-old")
+                        .help("Marks the feed's entries so far read"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("subscriptions").about("Lists subscribed feeds"))
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Lists entries whose titles contain the query, newest first")
+                .arg(Arg::with_name("query").required(true).multiple(true)),
+        )
+}
+
+fn print_entries(entries: &[Entry]) {
+    for entry in entries {
+        println!("{}\t{}\t{}", entry.id, entry.title, entry.url);
+    }
+}
+
+fn entry_id(args: &ArgMatches<'_>) -> Result<i64> {
+    let id = args.value_of("entry-id").unwrap_or_default();
+    id.parse()
+        .map_err(|_| format_err!("invalid entry ID \"{}\"", id))
+}
+
+async fn run(client: &mut Client, matches: &ArgMatches<'_>) -> Result<()> {
+    let (name, args) = matches.subcommand();
+    let args = args.ok_or_else(|| format_err!("missing subcommand"))?;
+
+    match name {
+        "unread" => print_entries(&client.list_unread().await?),
+        "open" => {
+            let id = entry_id(args)?;
+            let entry = client
+                .list_unread()
+                .await?
+                .into_iter()
+                .find(|entry| entry.id == id)
+                .ok_or_else(|| format_err!("no unread entry with ID {}", id))?;
+
+            let opener = matches.value_of("opener").unwrap_or(DEFAULT_OPENER);
+            let status = process::Command::new(opener)
+                .arg(&entry.url)
+                .status()
+                .with_context(|| format!("failed to run {}", opener))?;
+            if !status.success() {
+                return Err(format_err!("{} failed with {}", opener, status));
+            }
+
+            client.mark_read(id).await?;
+        }
+        "read" => client.mark_read(entry_id(args)?).await?,
+        "subscribe" => {
+            let (id, url) = client
+                .subscribe(
+                    args.value_of("url").unwrap_or_default(),
+                    args.is_present("mark-old"),
+                )
+                .await?;
+            println!("{}\t{}", id, url);
+        }
+        "subscriptions" => {
+            for subscription in client.subscriptions().await? {
+                println!(
+                    "{}\t{}\t{}",
+                    subscription.id,
+                    subscription.title.as_deref().unwrap_or(""),
+                    subscription.url
+                );
+            }
+        }
+        "search" => {
+            let query = args
+                .values_of("query")
+                .map(|words| words.collect::<Vec<_>>().join(" "))
+                .unwrap_or_default();
+            print_entries(&client.search(&query).await?);
+        }
+        _ => return Err(format_err!("unknown subcommand \"{}\"", name)),
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let matches = cli().get_matches();
+
+    let server = matches.value_of("server").unwrap_or_default();
+    let mut client = Client::connect(server)
+        .await
+        .with_context(|| format!("failed to connect to {}", server))?;
+    client
+        .login(matches.value_of("user").unwrap_or_default())
+        .await?;
+
+    run(&mut client, &matches).await
+}
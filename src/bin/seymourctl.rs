@@ -0,0 +1,376 @@
+//! Manages a running seymour instance from the shell by speaking the line
+//! protocol to it, e.g. `seymourctl --user admin refresh 12`.
+//!
+//! Each subcommand sends one command, after USER when `--user` is given
+//! and AUTH when `--token` is, and prints the response without its code.
+//! Error responses are printed to stderr and exit with a failure.
+
+use anyhow::{format_err, Context, Result};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use seymour_protocol::Response;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+
+const END_LIST: &str = "13";
+
+struct Client {
+    lines: Lines<BufReader<ReadHalf<TcpStream>>>,
+    writer: WriteHalf<TcpStream>,
+}
+
+impl Client {
+    async fn connect(server: &str) -> Result<Self> {
+        let stream = TcpStream::connect(server)
+            .await
+            .with_context(|| format!("failed to connect to {}", server))?;
+        let (reader, writer) = tokio::io::split(stream);
+
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        })
+    }
+
+    async fn next_line(&mut self) -> Result<String> {
+        self.lines
+            .next_line()
+            .await?
+            .ok_or_else(|| format_err!("server closed the connection"))
+    }
+
+    /// Sends `command` and returns its response lines, leaving out the
+    /// start and end of a list.
+    async fn send(&mut self, command: &str) -> Result<Vec<String>> {
+        self.writer
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await?;
+
+        let first = self.next_line().await?;
+        if is_error(&first) {
+            return Err(format_err!("{} failed: {}", command, first));
+        }
+
+        // A line that doesn't parse, e.g. from a newer server, doesn't
+        // start any list this client knows of.
+        let starts_list = first
+            .parse::<Response>()
+            .is_ok_and(|response| response.starts_list());
+        if !starts_list {
+            return Ok(vec![first]);
+        }
+
+        // A list the server fails partway through has the error just
+        // before its end.
+        let mut items = Vec::new();
+        let mut error = None;
+        loop {
+            let line = self.next_line().await?;
+            if line == END_LIST {
+                break;
+            }
+            if is_error(&line) {
+                error = Some(line);
+            } else {
+                items.push(line);
+            }
+        }
+
+        match error {
+            Some(error) => Err(format_err!("{} failed: {}", command, error)),
+            None => Ok(items),
+        }
+    }
+}
+
+/// Whether a line is a 4x or 5x response.
+fn is_error(line: &str) -> bool {
+    let code = line.split(' ').next().unwrap_or_default();
+    code.starts_with('4') || code.starts_with('5')
+}
+
+/// Strips the response code, leaving only the fields.
+fn fields(line: &str) -> &str {
+    line.split_once(' ').map_or("", |(_, fields)| fields)
+}
+
+fn cli() -> App<'static, 'static> {
+    let feed_id = Arg::with_name("feed-id").required(true);
+
+    App::new("seymourctl")
+        .about("Manages a running seymour instance")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("server")
+                .long("server")
+                .short("s")
+                .env("SEYMOUR_SERVER")
+                .default_value("localhost:2003")
+                .help("Address of the server"),
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .short("u")
+                .env("SEYMOUR_USER")
+                .takes_value(true)
+                .help("User to act as; admin commands need an admin"),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .short("t")
+                .env("SEYMOUR_TOKEN")
+                .hide_env_values(true)
+                .takes_value(true)
+                .requires("user")
+                .help("API token of the user, which admin commands need"),
+        )
+        .subcommand(
+            SubCommand::with_name("adduser")
+                .about("Creates a user, printing its ID")
+                .arg(Arg::with_name("username").required(true)),
+        )
+        .subcommand(SubCommand::with_name("users").about("Lists users (admin)"))
+        .subcommand(
+            SubCommand::with_name("allfeeds")
+                .about("Lists every feed with its subscribers and health (admin)"),
+        )
+        .subcommand(SubCommand::with_name("subscriptions").about("Lists the user's subscriptions"))
+        .subcommand(
+            SubCommand::with_name("subscribe")
+                .about("Subscribes the user to a feed")
+                .arg(Arg::with_name("url").required(true))
+                .arg(
+                    Arg::with_name("mark-old")
+                        .long("mark-old")
+                        .help("Marks the feed's entries so far read"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("unsubscribe")
+                .about("Unsubscribes the user from a feed")
+                .arg(feed_id.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("feedinfo")
+                .about("Shows a subscribed feed's fetch status and last error")
+                .arg(feed_id.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("feedlog")
+                .about("Shows a subscribed feed's most recent fetch attempts")
+                .arg(feed_id.clone())
+                .arg(Arg::with_name("limit").help("How many attempts to show")),
+        )
+        .subcommand(
+            SubCommand::with_name("feedhealth")
+                .about("Shows a subscribed feed's failures and pinned certificate expiry")
+                .arg(feed_id.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("notify")
+                .about("Turns chat announcements of a feed's new entries on or off")
+                .arg(feed_id.clone())
+                .arg(
+                    Arg::with_name("state")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("snooze")
+                .about("Hides a feed's entries from the unread list until a date")
+                .arg(feed_id.clone())
+                .arg(
+                    Arg::with_name("until")
+                        .required(true)
+                        .help("YYYY-MM-DD, in the user's timezone"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("snoozed").about("Lists the user's snoozed feeds"))
+        .subcommand(
+            SubCommand::with_name("keepunread")
+                .about("Keeps only a feed's newest unread entries, marking older ones read")
+                .arg(feed_id.clone())
+                .arg(
+                    Arg::with_name("limit")
+                        .required(true)
+                        .help("How many to keep, or `off` to keep them all"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("creategroup")
+                .about("Creates a group whose feeds all its members are subscribed to")
+                .arg(Arg::with_name("group").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("addmember")
+                .about("Adds a user to a group, subscribing them to its feeds")
+                .arg(Arg::with_name("group").required(true))
+                .arg(Arg::with_name("username").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("groupsubscribe")
+                .about("Subscribes every member of a group to a feed")
+                .arg(Arg::with_name("group").required(true))
+                .arg(Arg::with_name("url").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("groupunsubscribe")
+                .about("Removes a feed from a group; members stay subscribed")
+                .arg(Arg::with_name("group").required(true))
+                .arg(feed_id.clone()),
+        )
+        .subcommand(SubCommand::with_name("groups").about("Lists the groups the user is in"))
+        .subcommand(
+            SubCommand::with_name("groupfeeds")
+                .about("Lists a group's feeds")
+                .arg(Arg::with_name("group").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("discover")
+                .about("Lists feeds other users subscribe to")
+                .arg(Arg::with_name("query").help("Only feeds whose URL or title contains this")),
+        )
+        .subcommand(
+            SubCommand::with_name("recommend")
+                .about("Suggests feeds often subscribed to alongside the user's"),
+        )
+        .subcommand(
+            SubCommand::with_name("readstats")
+                .about("Shows how much the user has read lately, and their most read feeds"),
+        )
+        .subcommand(
+            SubCommand::with_name("setpref")
+                .about("Sets one of the user's preferences, e.g. `automarkread 14`")
+                .arg(Arg::with_name("name").required(true))
+                .arg(Arg::with_name("value").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("refresh")
+                .about("Fetches a feed on the next pass of the fetcher (admin)")
+                .arg(feed_id),
+        )
+        .subcommand(
+            SubCommand::with_name("refreshall")
+                .about("Fetches every feed on the next pass of the fetcher (admin)"),
+        )
+        .subcommand(
+            SubCommand::with_name("auditlog")
+                .about("Shows the admin commands most recently run (admin)")
+                .arg(Arg::with_name("limit").help("How many commands to show")),
+        )
+        .subcommand(
+            SubCommand::with_name("feedstats")
+                .about("Shows fetch statistics for every feed (admin)"),
+        )
+        .subcommand(
+            SubCommand::with_name("slowfeeds")
+                .about("Shows the feeds taking longest to fetch, with histograms (admin)")
+                .arg(Arg::with_name("limit").help("How many feeds to show")),
+        )
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about("Lists what changed since a token from the last sync, and a new token")
+                .arg(Arg::with_name("token").help("Token from the last sync")),
+        )
+        .subcommand(SubCommand::with_name("backup").about("Snapshots the database (admin)"))
+        .subcommand(
+            SubCommand::with_name("health")
+                .about("Shows how long ago the fetcher last finished checking feeds"),
+        )
+}
+
+/// Translates a subcommand into the protocol command it sends.
+fn command(matches: &ArgMatches<'_>) -> Result<String> {
+    let (name, args) = matches.subcommand();
+    let args = args.ok_or_else(|| format_err!("missing subcommand"))?;
+    // Required arguments are enforced by clap.
+    let argument = |name: &str| args.value_of(name).unwrap_or_default();
+
+    Ok(match name {
+        "adduser" => format!("USER {}", argument("username")),
+        "users" => "LISTUSERS".to_string(),
+        "allfeeds" => "LISTALLFEEDS".to_string(),
+        "subscriptions" => "LISTSUBSCRIPTIONS".to_string(),
+        "subscribe" if args.is_present("mark-old") => {
+            format!("SUBSCRIBE {} MARKOLD", argument("url"))
+        }
+        "subscribe" => format!("SUBSCRIBE {}", argument("url")),
+        "unsubscribe" => format!("UNSUBSCRIBE {}", argument("feed-id")),
+        "feedinfo" => format!("FEEDINFO {}", argument("feed-id")),
+        "feedlog" => match args.value_of("limit") {
+            Some(limit) => format!("FEEDLOG {} {}", argument("feed-id"), limit),
+            None => format!("FEEDLOG {}", argument("feed-id")),
+        },
+        "feedhealth" => format!("FEEDHEALTH {}", argument("feed-id")),
+        "notify" => format!("NOTIFY {} {}", argument("feed-id"), argument("state")),
+        "snooze" => format!("SNOOZEFEED {} {}", argument("feed-id"), argument("until")),
+        "snoozed" => "LISTSNOOZED".to_string(),
+        "keepunread" => format!("KEEPUNREAD {} {}", argument("feed-id"), argument("limit")),
+        "creategroup" => format!("CREATEGROUP {}", argument("group")),
+        "addmember" => format!("ADDMEMBER {} {}", argument("group"), argument("username")),
+        "groupsubscribe" => format!("GROUPSUBSCRIBE {} {}", argument("group"), argument("url")),
+        "groupunsubscribe" => format!(
+            "GROUPUNSUBSCRIBE {} {}",
+            argument("group"),
+            argument("feed-id")
+        ),
+        "groups" => "LISTGROUPS".to_string(),
+        "groupfeeds" => format!("GROUPFEEDS {}", argument("group")),
+        "discover" => match args.value_of("query") {
+            Some(query) => format!("DISCOVER {}", query),
+            None => "DISCOVER".to_string(),
+        },
+        "recommend" => "RECOMMEND".to_string(),
+        "readstats" => "READSTATS".to_string(),
+        "setpref" => format!("SETPREF {} {}", argument("name"), argument("value")),
+        "refresh" => format!("REFRESH {}", argument("feed-id")),
+        "refreshall" => "REFRESHALL".to_string(),
+        "auditlog" => match args.value_of("limit") {
+            Some(limit) => format!("AUDITLOG {}", limit),
+            None => "AUDITLOG".to_string(),
+        },
+        "feedstats" => "FEEDSTATS".to_string(),
+        "slowfeeds" => match args.value_of("limit") {
+            Some(limit) => format!("SLOWFEEDS {}", limit),
+            None => "SLOWFEEDS".to_string(),
+        },
+        "sync" => match args.value_of("token") {
+            Some(token) => format!("SYNC {}", token),
+            None => "SYNC".to_string(),
+        },
+        "backup" => "BACKUP".to_string(),
+        "health" => "HEALTH".to_string(),
+        _ => return Err(format_err!("unknown subcommand \"{}\"", name)),
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let matches = cli().get_matches();
+    let command = command(&matches)?;
+
+    let mut client = Client::connect(matches.value_of("server").unwrap_or_default()).await?;
+
+    if let Some(user) = matches.value_of("user") {
+        client.send(&format!("USER {}", user)).await?;
+    }
+    if let Some(token) = matches.value_of("token") {
+        client
+            .send(&format!("AUTH {}", token))
+            .await
+            .map_err(|_| format_err!("the token doesn't belong to the user"))?;
+    }
+
+    for line in client.send(&command).await? {
+        let fields = fields(&line);
+        if !fields.is_empty() {
+            println!("{}", fields);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,332 @@
+//! A read-only Gemini interface, so seymour can be used from any Gemini
+//! browser.
+//!
+//! Users are identified by client certificate. A certificate is linked to a
+//! user with `seymour linkcert`, and the capsule shows unknown certificates
+//! the fingerprint to link.
+
+use std::fs::File;
+use std::io::BufReader as StdBufReader;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{format_err, Context, Result};
+use chrono::Utc;
+use futures::TryStreamExt;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::time::timeout;
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::{
+    Certificate, ClientCertVerified, ClientCertVerifier, DistinguishedNames, ServerConfig, Session,
+    TLSError,
+};
+use tokio_rustls::webpki::DNSName;
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, info, info_span, Instrument};
+use url::Url;
+
+use crate::access::{ConnectionCounts, CountedConnection, NetworkAccess};
+use crate::reload::Tunables;
+use crate::shutdown::Shutdown;
+use crate::store::Store;
+
+/// A URL of up to 1024 bytes and CRLF.
+const MAX_REQUEST_LENGTH: u64 = 1024 + 2;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_PORT: u16 = 1965;
+
+#[derive(Debug, Clone)]
+pub struct CapsuleConfig {
+    pub host_port: String,
+    /// The lowercase host name requests must be for.
+    pub hostname: String,
+    /// The port requests must be for.
+    pub port: u16,
+    /// PEM certificate chain presented to browsers.
+    pub cert_path: PathBuf,
+    /// PEM private key, PKCS #8 or RSA.
+    pub key_path: PathBuf,
+}
+
+/// Client certificates are almost always self-signed, and only serve to
+/// tell users apart, so any certificate is accepted.
+struct AcceptAnyClientCertificate;
+
+impl ClientCertVerifier for AcceptAnyClientCertificate {
+    fn client_auth_mandatory(&self, _sni: Option<&DNSName>) -> Option<bool> {
+        // Browsers only offer a certificate once asked for one with 60.
+        Some(false)
+    }
+
+    fn client_auth_root_subjects(&self, _sni: Option<&DNSName>) -> Option<DistinguishedNames> {
+        Some(DistinguishedNames::new())
+    }
+
+    fn verify_client_cert(
+        &self,
+        _presented_certs: &[Certificate],
+        _sni: Option<&DNSName>,
+    ) -> Result<ClientCertVerified, TLSError> {
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+fn tls_config(config: &CapsuleConfig) -> Result<ServerConfig> {
+    let open = |path: &PathBuf| {
+        File::open(path)
+            .map(StdBufReader::new)
+            .with_context(|| format!("failed to open {}", path.display()))
+    };
+
+    let chain = certs(&mut open(&config.cert_path)?)
+        .map_err(|_| format_err!("invalid certificate {}", config.cert_path.display()))?;
+
+    let invalid_key = || format_err!("invalid private key {}", config.key_path.display());
+    let mut keys = pkcs8_private_keys(&mut open(&config.key_path)?).map_err(|_| invalid_key())?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(&config.key_path)?).map_err(|_| invalid_key())?;
+    }
+    let key = keys.into_iter().next().ok_or_else(invalid_key)?;
+
+    let mut tls = ServerConfig::new(Arc::new(AcceptAnyClientCertificate));
+    tls.set_single_cert(chain, key)?;
+
+    Ok(tls)
+}
+
+/// Identifies a client certificate by the SHA-256 of its DER encoding.
+fn fingerprint(certificate: &Certificate) -> String {
+    format!("{:x}", Sha256::digest(&certificate.0))
+}
+
+/// A response header and, for successes, its gemtext body.
+struct Response {
+    status: u8,
+    meta: String,
+    body: Option<String>,
+}
+
+impl Response {
+    fn new(status: u8, meta: impl Into<String>) -> Self {
+        Self {
+            status,
+            meta: meta.into(),
+            body: None,
+        }
+    }
+
+    fn gemtext(body: String) -> Self {
+        Self {
+            status: 20,
+            meta: "text/gemini; charset=utf-8".to_string(),
+            body: Some(body),
+        }
+    }
+}
+
+async fn unread_page(store: &dyn Store, user_id: i64) -> Result<Response> {
+    let entries: Vec<_> = store.unread_entries(user_id).try_collect().await?;
+
+    let mut page = String::from("# Unread\n\n");
+    if entries.is_empty() {
+        page.push_str("Nothing new.\n");
+    }
+    for entry in entries {
+        page.push_str(&format!("=> {} {}\n", entry.url, entry.title));
+        page.push_str(&format!("=> /read/{} Mark read\n\n", entry.id));
+    }
+
+    Ok(Response::gemtext(page))
+}
+
+async fn mark_read(store: &dyn Store, user_id: i64, entry_id: &str) -> Result<Response> {
+    let entry_id: i64 = match entry_id.parse() {
+        Ok(id) => id,
+        Err(_) => return Ok(Response::new(59, "invalid entry ID")),
+    };
+
+    // Only what the page could have linked to can be marked read.
+    let unread: Vec<_> = store.unread_entries(user_id).try_collect().await?;
+    if !unread.iter().any(|entry| entry.id == entry_id) {
+        return Ok(Response::new(51, "no such unread entry"));
+    }
+
+    store
+        .mark_read(user_id, entry_id, Utc::now().naive_utc())
+        .await?;
+
+    Ok(Response::new(30, "/"))
+}
+
+async fn respond(
+    store: &dyn Store,
+    config: &CapsuleConfig,
+    request: &str,
+    fingerprint: Option<String>,
+) -> Result<Response> {
+    let url = match Url::parse(request.trim_end()) {
+        Ok(url) if url.scheme() == "gemini" => url,
+        _ => return Ok(Response::new(59, "invalid request")),
+    };
+
+    // The capsule only serves itself, so anything else would be proxying.
+    if url.host_str() != Some(config.hostname.as_str())
+        || url.port().unwrap_or(DEFAULT_PORT) != config.port
+    {
+        return Ok(Response::new(53, "proxy request refused"));
+    }
+
+    let fingerprint = match fingerprint {
+        Some(fingerprint) => fingerprint,
+        None => {
+            return Ok(Response::new(
+                60,
+                "a client certificate is needed to see your unread entries",
+            ));
+        }
+    };
+    let user_id = match store.user_for_certificate(&fingerprint).await? {
+        Some(id) => id,
+        None => {
+            return Ok(Response::new(
+                61,
+                format!(
+                    "certificate isn't linked to a user; ask the operator to run `seymour linkcert <username> {}`",
+                    fingerprint
+                ),
+            ));
+        }
+    };
+
+    let path = url.path();
+    if path.is_empty() || path == "/" {
+        unread_page(store, user_id).await
+    } else if let Some(entry_id) = path.strip_prefix("/read/") {
+        mark_read(store, user_id, entry_id).await
+    } else {
+        Ok(Response::new(51, "not found"))
+    }
+}
+
+async fn handle_request(
+    stream: TcpStream,
+    acceptor: TlsAcceptor,
+    store: &dyn Store,
+    config: &CapsuleConfig,
+) -> Result<()> {
+    // Clients that never finish the handshake would otherwise hold their
+    // connection slots forever.
+    let mut stream = timeout(REQUEST_TIMEOUT, acceptor.accept(stream))
+        .await
+        .map_err(|_| format_err!("timed out during the TLS handshake"))??;
+
+    let fingerprint = stream
+        .get_ref()
+        .1
+        .get_peer_certificates()
+        .and_then(|chain| chain.first().map(fingerprint));
+
+    let mut request = Vec::new();
+    {
+        let mut reader = BufReader::new((&mut stream).take(MAX_REQUEST_LENGTH));
+        timeout(REQUEST_TIMEOUT, reader.read_until(b'\n', &mut request))
+            .await
+            .map_err(|_| format_err!("timed out reading request"))??;
+    }
+
+    let response = match String::from_utf8(request) {
+        Ok(request) if request.ends_with("\r\n") => respond(store, config, &request, fingerprint)
+            .await
+            .unwrap_or_else(|e| {
+                error!("failed to handle request: {:#}", e);
+                Response::new(40, "internal error")
+            }),
+        _ => Response::new(59, "invalid request"),
+    };
+
+    stream
+        .write_all(format!("{} {}\r\n", response.status, response.meta).as_bytes())
+        .await?;
+    if let Some(body) = response.body {
+        stream.write_all(body.as_bytes()).await?;
+    }
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// Serves the capsule until shutdown is requested. Clients are held to the
+/// same networks and connection limits as the line protocol's, counted
+/// together with its connections in `counts`.
+pub async fn serve(
+    config: CapsuleConfig,
+    store: Arc<dyn Store>,
+    network_access: NetworkAccess,
+    counts: ConnectionCounts,
+    max_connections_per_ip: Option<usize>,
+    tunables: watch::Receiver<Tunables>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    let acceptor = TlsAcceptor::from(Arc::new(tls_config(&config)?));
+    let mut listener = TcpListener::bind(&config.host_port).await?;
+    let config = Arc::new(config);
+    info!("Serving Gemini on: {}", config.host_port);
+
+    loop {
+        let (stream, address) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.requested() => break,
+        };
+
+        if !network_access.permits(address.ip()) {
+            info!(
+                "Refusing Gemini client {}, not in an allowed network",
+                address
+            );
+            continue;
+        }
+
+        // Clients over a limit are disconnected before the TLS handshake,
+        // which is the expensive part.
+        let max_connections = tunables.borrow().max_client_connections;
+        let counted = match CountedConnection::try_count(&counts.open, max_connections) {
+            Some(counted) => counted,
+            None => {
+                info!(
+                    "Turning away Gemini client {}, too many connections",
+                    address
+                );
+                continue;
+            }
+        };
+        let counted_for_ip = match counts.per_ip.try_open(address.ip(), max_connections_per_ip) {
+            Some(counted) => counted,
+            None => {
+                info!(
+                    "Turning away Gemini client {}, too many connections from it",
+                    address
+                );
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let store = store.clone();
+        let config = config.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = handle_request(stream, acceptor, &*store, &config).await {
+                    error!("Gemini request failed: {}", e);
+                }
+                drop((counted, counted_for_ip));
+            }
+            .instrument(info_span!("gemini", %address)),
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! Keeps the certificates pinned by `TLS_POLICY=tofu` in the database, so
+//! they survive restarts and are shared with a separate fetcher.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use sqlx::{Any, Pool, Row};
+use tracing::warn;
+
+use crate::feed::TIMESTAMP_FORMAT;
+use crate::fetch::{CertificateStore, PinnedCertificate};
+use crate::now_timestamp;
+
+/// How long before a pinned certificate expires operators are warned.
+const EXPIRY_WARNING_DAYS: i64 = 14;
+
+pub struct SqlCertificateStore {
+    pool: Pool<Any>,
+}
+
+impl SqlCertificateStore {
+    pub fn new(pool: Pool<Any>) -> Self {
+        Self { pool }
+    }
+}
+
+fn format_not_after(certificate: &PinnedCertificate) -> Option<String> {
+    certificate
+        .not_after
+        .map(|not_after| not_after.format(TIMESTAMP_FORMAT).to_string())
+}
+
+#[async_trait]
+impl CertificateStore for SqlCertificateStore {
+    async fn pinned(&self, host: &str, port: u16) -> Result<Option<PinnedCertificate>> {
+        let row = sqlx::query(
+            "SELECT fingerprint, not_after FROM known_certificates WHERE host = $1 AND port = $2",
+        )
+        .bind(host)
+        .bind(i64::from(port))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let not_after: Option<String> = row.try_get("not_after")?;
+        Ok(Some(PinnedCertificate {
+            fingerprint: row.try_get("fingerprint")?,
+            not_after: not_after
+                .map(|not_after| NaiveDateTime::parse_from_str(&not_after, TIMESTAMP_FORMAT))
+                .transpose()?,
+        }))
+    }
+
+    async fn remember(&self, host: &str, port: u16, certificate: &PinnedCertificate) -> Result<()> {
+        // Another fetch may have pinned the host meanwhile, and it stays
+        // pinned to whichever came first.
+        sqlx::query(
+            r#"INSERT INTO known_certificates (host, port, fingerprint, first_seen_at, not_after)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (host, port) DO UPDATE SET not_after = excluded.not_after
+                WHERE known_certificates.fingerprint = excluded.fingerprint"#,
+        )
+        .bind(host)
+        .bind(i64::from(port))
+        .bind(&certificate.fingerprint)
+        .bind(now_timestamp())
+        .bind(format_not_after(certificate))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn rotate(&self, host: &str, port: u16, certificate: &PinnedCertificate) -> Result<()> {
+        sqlx::query(
+            r#"UPDATE known_certificates
+                SET fingerprint = $1, not_after = $2, rotated_at = $3
+                WHERE host = $4 AND port = $5"#,
+        )
+        .bind(&certificate.fingerprint)
+        .bind(format_not_after(certificate))
+        .bind(now_timestamp())
+        .bind(host)
+        .bind(i64::from(port))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Logs pinned certificates expiring within `EXPIRY_WARNING_DAYS`, or
+/// already expired, so the change that follows isn't a surprise.
+pub async fn warn_about_expiring_certificates(pool: &Pool<Any>) -> Result<()> {
+    let soon = chrono::Utc::now().naive_utc() + chrono::Duration::days(EXPIRY_WARNING_DAYS);
+    let rows = sqlx::query(
+        r#"SELECT host, port, not_after FROM known_certificates
+            WHERE not_after < $1
+            ORDER BY not_after"#,
+    )
+    .bind(soon.format(TIMESTAMP_FORMAT).to_string())
+    .fetch_all(pool)
+    .await?;
+
+    for row in rows {
+        let host: String = row.try_get("host")?;
+        let port: i64 = row.try_get("port")?;
+        let not_after: String = row.try_get("not_after")?;
+        warn!(
+            "Pinned certificate for {}:{} expires {} UTC",
+            host, port, not_after
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,2025 @@
+//! Runs the line protocol's commands.
+//!
+//! Each command has a `Handler`, registered in `Commands` under its verb.
+//! What every command shares, namely logging, the per-connection rate limit
+//! and checking the user may run it, happens in `Commands::run` before the
+//! handler is called.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+use anyhow::{format_err, Context, Result};
+use async_trait::async_trait;
+use chrono::{Datelike, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use futures::TryStreamExt;
+use seymour_protocol::{
+    Command, ErrorCode, FeedStatus, Response, DEFAULT_DIRECTORY_LIMIT, DEFAULT_RECOMMEND_LIMIT,
+    DEFAULT_SEARCH_LIMIT, READ_STATS_FEEDS,
+};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::time::timeout;
+use tracing::{info, Span};
+use url::Url;
+
+use crate::api::hash_token;
+use crate::store::{Store, SyncChange, SyncPosition, UnreadEntry};
+use crate::{
+    backlog_from, database_busy_count, group_subscribe, in_timezone, seconds_since, subscribe,
+    username, Config,
+};
+
+/// Responses beyond this many bytes are written out before more are added.
+const RESPONSE_BUFFER_BYTES: usize = 16 * 1024;
+
+/// How long a client gets to read each write. A list streamed off the
+/// database holds a pooled connection until it's written out, so a client
+/// that stops reading can't be allowed to hold it indefinitely.
+const RESPONSE_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sends responses to a client, buffering them so that a command's
+/// responses go out in a few large writes rather than one per line.
+pub(crate) struct Responses<'a> {
+    writer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    buffer: String,
+    /// Whether a list has been started and not yet ended.
+    in_list: bool,
+}
+
+impl<'a> Responses<'a> {
+    pub(crate) fn new(writer: &'a mut (dyn AsyncWrite + Unpin + Send)) -> Self {
+        Self {
+            writer,
+            buffer: String::new(),
+            in_list: false,
+        }
+    }
+
+    pub(crate) async fn send(&mut self, response: Response) -> Result<()> {
+        if response.starts_list() {
+            self.in_list = true;
+        } else if response == Response::EndList {
+            self.in_list = false;
+        }
+        write!(self.buffer, "{}\r\n", response)?;
+        if self.buffer.len() >= RESPONSE_BUFFER_BYTES {
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes out everything sent so far.
+    pub(crate) async fn flush(&mut self) -> Result<()> {
+        timeout(
+            RESPONSE_WRITE_TIMEOUT,
+            self.writer.write_all(self.buffer.as_bytes()),
+        )
+        .await
+        .context("timed out writing to the client")??;
+        self.buffer.clear();
+
+        Ok(())
+    }
+
+    /// Sends `error` for a command that failed, then ends the list it was
+    /// partway through, if any, so the client still sees the list's end.
+    pub(crate) async fn fail(&mut self, error: Response) -> Result<()> {
+        let in_list = self.in_list;
+        self.send(error).await?;
+        if in_list {
+            self.send(Response::EndList).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Who may run a command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Access {
+    Anyone,
+    /// Only once a user is selected.
+    User,
+    /// Only admins, who are recorded in the audit log each time.
+    Admin,
+}
+
+/// How often the rate limit lets a connection run commands.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long an excerpt of an entry's content can be, in characters.
+const EXCERPT_MAX_CHARS: usize = 200;
+
+/// A client connection's state between commands.
+pub(crate) struct Session<'a> {
+    store: &'a dyn Store,
+    config: &'a Config,
+    /// The selected user's name and ID.
+    user: Option<(String, i64)>,
+    /// Whether the selected user has `users.is_admin` set or is in
+    /// `ADMIN_USERS`, and has proven who they are with AUTH.
+    is_admin: bool,
+    window_started: Instant,
+    commands_in_window: usize,
+}
+
+impl<'a> Session<'a> {
+    pub(crate) fn new(store: &'a dyn Store, config: &'a Config) -> Self {
+        Self {
+            store,
+            config,
+            user: None,
+            is_admin: false,
+            window_started: Instant::now(),
+            commands_in_window: 0,
+        }
+    }
+
+    /// The selected user's ID. Always there for handlers needing
+    /// `Access::User` or `Access::Admin`.
+    fn user_id(&self) -> Result<i64> {
+        self.user
+            .as_ref()
+            .map(|(_, id)| *id)
+            .ok_or_else(|| format_err!("no user is selected"))
+    }
+
+    /// Counts a command against the rate limit, returning whether it's
+    /// within it.
+    fn within_rate_limit(&mut self) -> bool {
+        let limit = match self.config.max_commands_per_minute {
+            Some(limit) => limit,
+            None => return true,
+        };
+
+        if self.window_started.elapsed() >= RATE_LIMIT_WINDOW {
+            self.window_started = Instant::now();
+            self.commands_in_window = 0;
+        }
+        self.commands_in_window += 1;
+
+        self.commands_in_window <= limit
+    }
+
+    /// The response to send instead of running `command` when the session
+    /// doesn't have `access`.
+    fn check_access(&self, access: Access, command: &str) -> Option<Response> {
+        match (access, &self.user) {
+            (Access::Anyone, _) => None,
+            (_, None) => Some(Response::error(
+                ErrorCode::UserRequired,
+                "must select a user",
+            )),
+            (Access::Admin, Some(_)) if !self.is_admin => Some(Response::error(
+                ErrorCode::AdminRequired,
+                format!("{} requires an admin user authenticated with AUTH", command),
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+pub(crate) trait Handler: Send + Sync {
+    fn access(&self) -> Access {
+        Access::User
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()>;
+}
+
+/// Takes the arguments out of the command a handler is registered for.
+macro_rules! arguments {
+    ($command:expr, $pattern:pat => $arguments:expr) => {
+        match $command {
+            $pattern => $arguments,
+            command => return Err(format_err!("\"{}\" sent to the wrong handler", command)),
+        }
+    };
+}
+
+/// Every command the server runs, by verb.
+pub(crate) struct Commands {
+    handlers: HashMap<&'static str, Box<dyn Handler>>,
+}
+
+impl Commands {
+    pub(crate) fn new() -> Self {
+        let mut commands = Self {
+            handlers: HashMap::new(),
+        };
+
+        commands.register("USER", SelectUser);
+        commands.register("AUTH", Authenticate);
+        commands.register("HEALTH", Health);
+        commands.register("LISTSUBSCRIPTIONS", ListSubscriptions);
+        commands.register("SUBSCRIBE", Subscribe);
+        commands.register("UNSUBSCRIBE", Unsubscribe);
+        commands.register("CREATEGROUP", CreateGroup);
+        commands.register("ADDMEMBER", AddMember);
+        commands.register("GROUPSUBSCRIBE", GroupSubscribe);
+        commands.register("GROUPUNSUBSCRIBE", GroupUnsubscribe);
+        commands.register("LISTGROUPS", ListGroups);
+        commands.register("GROUPFEEDS", GroupFeeds);
+        commands.register("NOTIFY", Notify);
+        commands.register("SHOWARCHIVE", ShowArchive);
+        commands.register("SNOOZEFEED", SnoozeFeed);
+        commands.register("LISTSNOOZED", ListSnoozed);
+        commands.register("KEEPUNREAD", KeepUnread);
+        commands.register("SYNC", SyncSince);
+        commands.register("FEEDINFO", FeedInfo);
+        commands.register("FEEDLOG", FeedLog);
+        commands.register("FEEDHEALTH", FeedHealth);
+        commands.register("LISTUNREAD", ListUnread);
+        commands.register("MARKREAD", MarkRead);
+        commands.register("GETENTRY", GetEntry);
+        commands.register("SEARCH", Search);
+        commands.register("HISTORY", ReadHistory);
+        commands.register("READSTATS", ReadStats);
+        commands.register("BACKUP", Backup);
+        commands.register("LISTUSERS", ListUsers);
+        commands.register("LISTALLFEEDS", ListAllFeeds);
+        commands.register("REFRESH", Refresh);
+        commands.register("FEEDSTATS", FeedStats);
+        commands.register("SLOWFEEDS", SlowFeeds);
+        commands.register("SETPREF", SetPref);
+        commands.register("DISCOVER", Discover);
+        commands.register("RECOMMEND", Recommend);
+        commands.register("REFRESHALL", RefreshAll);
+        commands.register("AUDITLOG", AuditLog);
+
+        commands
+    }
+
+    fn register(&mut self, verb: &'static str, handler: impl Handler + 'static) {
+        self.handlers.insert(verb, Box::new(handler));
+    }
+
+    pub(crate) async fn run(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        match &command {
+            // Tokens are secrets, so they stay out of the log.
+            Command::Auth { .. } => info!("< AUTH"),
+            command => info!("< {}", command),
+        }
+
+        if !session.within_rate_limit() {
+            return out
+                .send(Response::error(
+                    ErrorCode::RateLimited,
+                    "too many commands, slow down",
+                ))
+                .await;
+        }
+
+        let verb = command.verb();
+        let handler = match self.handlers.get(verb) {
+            Some(handler) => handler,
+            // Only reachable if the protocol crate is newer than the server.
+            None => {
+                return out
+                    .send(Response::error(
+                        ErrorCode::UnknownCommand,
+                        format!("unsupported command \"{}\"", command),
+                    ))
+                    .await
+            }
+        };
+
+        let access = handler.access();
+        if let Some(response) = session.check_access(access, verb) {
+            return out.send(response).await;
+        }
+
+        if access == Access::Admin {
+            if let Some((username, _)) = &session.user {
+                session
+                    .store
+                    .record_audit(username, &command.to_string())
+                    .await?;
+            }
+        }
+
+        handler.handle(session, command, out).await
+    }
+}
+
+struct SelectUser;
+
+#[async_trait]
+impl Handler for SelectUser {
+    fn access(&self) -> Access {
+        Access::Anyone
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let username = arguments!(command, Command::User { username } => username);
+        let username = match username::normalize(&username) {
+            Ok(username) => username,
+            Err(e) => {
+                return out
+                    .send(Response::error(ErrorCode::InvalidUsername, e.to_string()))
+                    .await
+            }
+        };
+        let id = session.store.user_id(&username).await?;
+
+        Span::current().record("user", &username.as_str());
+        // Anyone can claim any name, so admin rights wait for AUTH.
+        session.is_admin = false;
+        session.user = Some((username, id));
+
+        out.send(Response::AckUser { id }).await
+    }
+}
+
+/// Authenticates the selected user with one of their API tokens, which is
+/// what admin commands need.
+struct Authenticate;
+
+#[async_trait]
+impl Handler for Authenticate {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let token = arguments!(command, Command::Auth { token } => token);
+        let id = session.user_id()?;
+
+        if session.store.user_for_token(&hash_token(&token)).await? != Some(id) {
+            session.is_admin = false;
+            return out
+                .send(Response::error(
+                    ErrorCode::InvalidToken,
+                    "token doesn't belong to the selected user",
+                ))
+                .await;
+        }
+
+        let is_listed = match &session.user {
+            Some((username, _)) => session.config.admin_users.contains(username),
+            None => false,
+        };
+        session.is_admin = is_listed || session.store.is_admin(id).await?;
+
+        out.send(Response::AckUser { id }).await
+    }
+}
+
+/// Available without selecting a user so that liveness probes can use it.
+struct Health;
+
+#[async_trait]
+impl Handler for Health {
+    fn access(&self) -> Access {
+        Access::Anyone
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        _command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let last_fetch_cycle = session.store.last_fetch_cycle().await?;
+
+        out.send(Response::Health {
+            last_fetch_cycle_age_secs: last_fetch_cycle.map(seconds_since),
+            database_busy_count: database_busy_count(),
+        })
+        .await
+    }
+}
+
+struct Subscribe;
+
+#[async_trait]
+impl Handler for Subscribe {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let (url, mark_old) =
+            arguments!(command, Command::Subscribe { url, mark_old } => (url, mark_old));
+        let user_id = session.user_id()?;
+
+        let (feed_id, url) =
+            subscribe(session.store, session.config, user_id, url, mark_old).await?;
+
+        out.send(Response::AckSubscribe { id: feed_id, url }).await
+    }
+}
+
+struct ListSubscriptions;
+
+#[async_trait]
+impl Handler for ListSubscriptions {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        _command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let user_id = session.user_id()?;
+
+        out.send(Response::StartSubscriptionList).await?;
+
+        let unread = session.store.feed_unread_counts(user_id).await?;
+        for subscription in session.store.subscriptions(user_id).await? {
+            out.send(Response::Subscription {
+                id: subscription.feed_id,
+                url: subscription.url,
+                status: FeedStatus::new(subscription.gone, subscription.failing),
+                unread: unread.get(&subscription.feed_id).copied().unwrap_or(0),
+                title: subscription.title,
+                subtitle: subscription.subtitle,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+fn no_subscription(feed_id: i64) -> Response {
+    Response::error(
+        ErrorCode::FeedNotFound,
+        format!("no subscription with feed ID {} exists", feed_id),
+    )
+}
+
+/// Whether the user may see or change `entry_id`, answering as though it
+/// doesn't exist if not. Other feeds' entry IDs aren't revealed either way.
+async fn authorize_entry(
+    session: &Session<'_>,
+    user_id: i64,
+    entry_id: i64,
+    out: &mut Responses<'_>,
+) -> Result<bool> {
+    if session.store.can_access_entry(user_id, entry_id).await? {
+        return Ok(true);
+    }
+
+    out.send(Response::error(
+        ErrorCode::EntryNotFound,
+        format!("no entry with ID {} exists", entry_id),
+    ))
+    .await?;
+    Ok(false)
+}
+
+struct Unsubscribe;
+
+#[async_trait]
+impl Handler for Unsubscribe {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let feed_id = arguments!(command, Command::Unsubscribe { id } => id);
+        let user_id = session.user_id()?;
+
+        if session.store.unsubscribe(user_id, feed_id).await? {
+            out.send(Response::AckUnsubscribe).await
+        } else {
+            out.send(no_subscription(feed_id)).await
+        }
+    }
+}
+
+fn no_group(name: &str) -> Response {
+    Response::error(
+        ErrorCode::GroupNotFound,
+        format!("you're not in a group named \"{}\"", name),
+    )
+}
+
+/// Creates a group, e.g. for a household, whose feeds all its members are
+/// subscribed to. Each member still has their own read state.
+struct CreateGroup;
+
+#[async_trait]
+impl Handler for CreateGroup {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let name = arguments!(command, Command::CreateGroup { name } => name);
+        let user_id = session.user_id()?;
+
+        match session.store.create_group(user_id, &name).await? {
+            Some(id) => out.send(Response::AckCreateGroup { id }).await,
+            None => {
+                out.send(Response::error(
+                    ErrorCode::GroupExists,
+                    format!("a group named \"{}\" already exists", name),
+                ))
+                .await
+            }
+        }
+    }
+}
+
+/// Any member of a group may add others to it.
+struct AddMember;
+
+#[async_trait]
+impl Handler for AddMember {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let (group, username) =
+            arguments!(command, Command::AddMember { group, username } => (group, username));
+        let user_id = session.user_id()?;
+
+        let group_id = match session.store.group_id(user_id, &group).await? {
+            Some(group_id) => group_id,
+            None => return out.send(no_group(&group)).await,
+        };
+        let username = match username::normalize(&username) {
+            Ok(username) => username,
+            Err(e) => {
+                return out
+                    .send(Response::error(ErrorCode::InvalidUsername, e.to_string()))
+                    .await
+            }
+        };
+        let member_id = session.store.user_id(&username).await?;
+
+        session
+            .store
+            .add_group_member(group_id, member_id, backlog_from(session.config))
+            .await?;
+
+        out.send(Response::AckAddMember).await
+    }
+}
+
+struct GroupSubscribe;
+
+#[async_trait]
+impl Handler for GroupSubscribe {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let (group, url) =
+            arguments!(command, Command::GroupSubscribe { group, url } => (group, url));
+        let user_id = session.user_id()?;
+
+        let group_id = match session.store.group_id(user_id, &group).await? {
+            Some(group_id) => group_id,
+            None => return out.send(no_group(&group)).await,
+        };
+        let (feed_id, url) = group_subscribe(session.store, session.config, group_id, url).await?;
+
+        out.send(Response::AckSubscribe { id: feed_id, url }).await
+    }
+}
+
+struct GroupUnsubscribe;
+
+#[async_trait]
+impl Handler for GroupUnsubscribe {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let (group, feed_id) =
+            arguments!(command, Command::GroupUnsubscribe { group, id } => (group, id));
+        let user_id = session.user_id()?;
+
+        let group_id = match session.store.group_id(user_id, &group).await? {
+            Some(group_id) => group_id,
+            None => return out.send(no_group(&group)).await,
+        };
+
+        if session.store.group_unsubscribe(group_id, feed_id).await? {
+            out.send(Response::AckUnsubscribe).await
+        } else {
+            out.send(Response::error(
+                ErrorCode::FeedNotFound,
+                format!("group \"{}\" has no feed with ID {}", group, feed_id),
+            ))
+            .await
+        }
+    }
+}
+
+struct ListGroups;
+
+#[async_trait]
+impl Handler for ListGroups {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        _command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let user_id = session.user_id()?;
+
+        out.send(Response::StartGroupList).await?;
+
+        for group in session.store.groups(user_id).await? {
+            out.send(Response::Group {
+                id: group.id,
+                name: group.name,
+                members: group.members,
+                feeds: group.feeds,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+struct GroupFeeds;
+
+#[async_trait]
+impl Handler for GroupFeeds {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let group = arguments!(command, Command::GroupFeeds { group } => group);
+        let user_id = session.user_id()?;
+
+        let group_id = match session.store.group_id(user_id, &group).await? {
+            Some(group_id) => group_id,
+            None => return out.send(no_group(&group)).await,
+        };
+
+        out.send(Response::StartSubscriptionList).await?;
+
+        let unread = session.store.feed_unread_counts(user_id).await?;
+        for feed in session.store.group_feeds(group_id).await? {
+            out.send(Response::Subscription {
+                id: feed.feed_id,
+                url: feed.url,
+                status: FeedStatus::new(feed.gone, feed.failing),
+                unread: unread.get(&feed.feed_id).copied().unwrap_or(0),
+                title: feed.title,
+                subtitle: feed.subtitle,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+/// Turns chat announcements of a subscribed feed's new entries on or off.
+struct Notify;
+
+#[async_trait]
+impl Handler for Notify {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let (feed_id, enabled) =
+            arguments!(command, Command::Notify { id, enabled } => (id, enabled));
+        let user_id = session.user_id()?;
+
+        if session.store.set_notify(user_id, feed_id, enabled).await? {
+            out.send(Response::AckNotify).await
+        } else {
+            out.send(no_subscription(feed_id)).await
+        }
+    }
+}
+
+/// Lists a subscribed feed's entire archive as unread, not just the backlog
+/// it was subscribed with.
+struct ShowArchive;
+
+#[async_trait]
+impl Handler for ShowArchive {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let feed_id = arguments!(command, Command::ShowArchive { id } => id);
+        let user_id = session.user_id()?;
+
+        if session.store.show_archive(user_id, feed_id).await? {
+            out.send(Response::AckShowArchive).await
+        } else {
+            out.send(no_subscription(feed_id)).await
+        }
+    }
+}
+
+/// Hides a feed's entries from the unread list for a while, a gentler
+/// alternative to unsubscribing. A date in the past wakes the feed up.
+struct SnoozeFeed;
+
+#[async_trait]
+impl Handler for SnoozeFeed {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let (feed_id, until) =
+            arguments!(command, Command::SnoozeFeed { id, until } => (id, until));
+        let user_id = session.user_id()?;
+        let timezone = session.store.timezone(user_id).await?;
+
+        let until = local_midnight(until, timezone);
+        if session.store.snooze(user_id, feed_id, until).await? {
+            out.send(Response::AckSnoozeFeed).await
+        } else {
+            out.send(no_subscription(feed_id)).await
+        }
+    }
+}
+
+/// Caps how many of a feed's entries stay unread, for firehose feeds. The
+/// excess is marked read by the hourly maintenance pass.
+struct KeepUnread;
+
+#[async_trait]
+impl Handler for KeepUnread {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let (feed_id, limit) =
+            arguments!(command, Command::KeepUnread { id, limit } => (id, limit));
+        let user_id = session.user_id()?;
+
+        if session
+            .store
+            .set_max_unread(user_id, feed_id, limit)
+            .await?
+        {
+            out.send(Response::AckKeepUnread).await
+        } else {
+            out.send(no_subscription(feed_id)).await
+        }
+    }
+}
+
+struct ListSnoozed;
+
+#[async_trait]
+impl Handler for ListSnoozed {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        _command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let user_id = session.user_id()?;
+        let timezone = session.store.timezone(user_id).await?;
+
+        out.send(Response::StartSnoozedList).await?;
+
+        for feed in session.store.snoozed_feeds(user_id).await? {
+            out.send(Response::SnoozedFeed {
+                id: feed.feed_id,
+                url: feed.url,
+                until: in_timezone(feed.until, timezone),
+                title: feed.title,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+/// A SYNC token: where the last sync left off, and when, since changes
+/// are only kept for `Config::sync_retention`.
+struct SyncToken {
+    position: SyncPosition,
+    issued_at: i64,
+}
+
+impl SyncToken {
+    fn parse(token: &str) -> Option<Self> {
+        let mut parts = token.split('-');
+        let token = Self {
+            position: SyncPosition {
+                entry_id: parts.next()?.parse().ok()?,
+                change_id: parts.next()?.parse().ok()?,
+            },
+            issued_at: parts.next()?.parse().ok()?,
+        };
+
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(token)
+    }
+}
+
+impl std::fmt::Display for SyncToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}-{}",
+            self.position.entry_id, self.position.change_id, self.issued_at
+        )
+    }
+}
+
+/// Each entry's and feed's latest change, in the order of those changes.
+fn latest_changes(changes: Vec<SyncChange>) -> Vec<SyncChange> {
+    let mut seen = HashSet::new();
+    let mut latest: Vec<SyncChange> = changes
+        .into_iter()
+        .rev()
+        .filter(|change| {
+            seen.insert(match change {
+                SyncChange::Read(id) | SyncChange::Unread(id) => (true, *id),
+                SyncChange::Subscribed(id) | SyncChange::Unsubscribed(id) => (false, *id),
+            })
+        })
+        .collect();
+    latest.reverse();
+
+    latest
+}
+
+struct SyncSince;
+
+#[async_trait]
+impl Handler for SyncSince {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let token = arguments!(command, Command::Sync { token } => token);
+        let user_id = session.user_id()?;
+        let now = Utc::now();
+
+        let since = match token {
+            Some(token) => match SyncToken::parse(&token) {
+                Some(since) => Some(since),
+                None => {
+                    return out
+                        .send(Response::error(
+                            ErrorCode::InvalidSyncToken,
+                            format!("invalid sync token \"{}\"", token),
+                        ))
+                        .await
+                }
+            },
+            None => None,
+        };
+        if let Some(since) = &since {
+            if now.timestamp() - since.issued_at > session.config.sync_retention.num_seconds() {
+                return out
+                    .send(Response::error(
+                        ErrorCode::SyncTokenExpired,
+                        "sync token has expired, list everything and SYNC again",
+                    ))
+                    .await;
+            }
+        }
+
+        let position = session.store.sync_position().await?;
+        let token = SyncToken {
+            position,
+            issued_at: now.timestamp(),
+        };
+        out.send(Response::StartSync {
+            token: token.to_string(),
+        })
+        .await?;
+
+        if let Some(since) = since {
+            let entries = session
+                .store
+                .entries_between(user_id, since.position.entry_id, position.entry_id)
+                .await?;
+            for entry in entries {
+                out.send(Response::Entry {
+                    id: entry.id,
+                    feed_id: entry.feed_id,
+                    feed_url: entry.feed_url,
+                    url: entry.url,
+                    title: entry.title,
+                    feed_title: entry.feed_title,
+                })
+                .await?;
+            }
+
+            let changes = session
+                .store
+                .changes_between(user_id, since.position.change_id, position.change_id)
+                .await?;
+            for change in latest_changes(changes) {
+                out.send(match change {
+                    SyncChange::Read(id) => Response::ReadStateChange { id, read: true },
+                    SyncChange::Unread(id) => Response::ReadStateChange { id, read: false },
+                    SyncChange::Subscribed(id) => Response::SubscriptionChange {
+                        id,
+                        subscribed: true,
+                    },
+                    SyncChange::Unsubscribed(id) => Response::SubscriptionChange {
+                        id,
+                        subscribed: false,
+                    },
+                })
+                .await?;
+            }
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+struct FeedInfo;
+
+#[async_trait]
+impl Handler for FeedInfo {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let feed_id = arguments!(command, Command::FeedInfo { id } => id);
+        let user_id = session.user_id()?;
+
+        match session.store.subscribed_feed(user_id, feed_id).await? {
+            Some(feed) => {
+                out.send(Response::FeedInfo {
+                    id: feed_id,
+                    url: feed.url,
+                    status: FeedStatus::new(feed.gone, feed.failing),
+                    title: feed.title,
+                    subtitle: feed.subtitle,
+                    failure_count: feed.failure_count,
+                    last_error: feed.last_error,
+                })
+                .await
+            }
+            None => out.send(no_subscription(feed_id)).await,
+        }
+    }
+}
+
+struct FeedLog;
+
+#[async_trait]
+impl Handler for FeedLog {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let (feed_id, limit) = arguments!(command, Command::FeedLog { id, limit } => (id, limit));
+        let user_id = session.user_id()?;
+
+        if session
+            .store
+            .subscribed_feed(user_id, feed_id)
+            .await?
+            .is_none()
+        {
+            return out.send(no_subscription(feed_id)).await;
+        }
+        let timezone = session.store.timezone(user_id).await?;
+
+        out.send(Response::StartFeedLog).await?;
+
+        for attempt in session.store.fetch_attempts(feed_id, limit).await? {
+            out.send(Response::FetchAttempt {
+                fetched_at: in_timezone(attempt.fetched_at, timezone),
+                status: attempt.status,
+                duration_ms: attempt.duration_ms,
+                error: attempt.error,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+struct FeedHealth;
+
+#[async_trait]
+impl Handler for FeedHealth {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let feed_id = arguments!(command, Command::FeedHealth { id } => id);
+        let user_id = session.user_id()?;
+
+        let feed = match session.store.subscribed_feed(user_id, feed_id).await? {
+            Some(feed) => feed,
+            None => return out.send(no_subscription(feed_id)).await,
+        };
+
+        // A feed URL without a host can't have a pinned certificate.
+        let certificate = match Url::parse(&feed.url)
+            .ok()
+            .and_then(|url| crate::fetch::host_and_port(&url).ok())
+        {
+            Some((host, port)) => session.store.known_certificate(&host, port).await?,
+            None => None,
+        };
+        let timezone = session.store.timezone(user_id).await?;
+        let (expires_at, rotated_at) = match certificate {
+            Some(certificate) => (certificate.not_after, certificate.rotated_at),
+            None => (None, None),
+        };
+
+        out.send(Response::FeedHealth {
+            id: feed_id,
+            failure_count: feed.failure_count,
+            certificate_expires_at: expires_at.map(|at| in_timezone(at, timezone)),
+            certificate_rotated_at: rotated_at.map(|at| in_timezone(at, timezone)),
+            last_error: feed.last_error,
+        })
+        .await
+    }
+}
+
+struct ListUnread;
+
+#[async_trait]
+impl Handler for ListUnread {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let grouped = arguments!(command, Command::ListUnread { grouped } => grouped);
+        let user_id = session.user_id()?;
+        let excerpts = session.store.excerpts(user_id).await?;
+
+        out.send(Response::StartEntryList).await?;
+
+        if grouped || excerpts {
+            return list_unread_collected(session, user_id, grouped, excerpts, out).await;
+        }
+
+        // Entries are sent as they're read, so a long list doesn't have to
+        // fit in memory first.
+        let mut entries = session.store.unread_entries(user_id);
+        while let Some(entry) = entries.try_next().await? {
+            out.send(Response::Entry {
+                id: entry.id,
+                feed_id: entry.feed_id,
+                feed_url: entry.feed_url,
+                url: entry.url,
+                title: entry.title,
+                feed_title: entry.feed_title,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+/// Sends unread entries once all of them have been read. Grouping them by
+/// feed needs that, feeds coming in the order of their oldest unread entry,
+/// and so does looking up excerpts while no query is still in progress.
+async fn list_unread_collected(
+    session: &mut Session<'_>,
+    user_id: i64,
+    grouped: bool,
+    excerpts: bool,
+    out: &mut Responses<'_>,
+) -> Result<()> {
+    let entries: Vec<UnreadEntry> = session.store.unread_entries(user_id).try_collect().await?;
+
+    if !grouped {
+        for entry in entries {
+            send_unread_entry(session, user_id, entry, excerpts, out).await?;
+        }
+        return out.send(Response::EndList).await;
+    }
+
+    let mut feeds: Vec<(i64, String, Vec<UnreadEntry>)> = Vec::new();
+    for entry in entries {
+        match feeds.iter_mut().find(|(id, _, _)| *id == entry.feed_id) {
+            Some((_, _, feed_entries)) => feed_entries.push(entry),
+            None => feeds.push((entry.feed_id, entry.feed_url.clone(), vec![entry])),
+        }
+    }
+
+    let titles: HashMap<i64, Option<String>> = session
+        .store
+        .subscriptions(user_id)
+        .await?
+        .into_iter()
+        .map(|subscription| (subscription.feed_id, subscription.title))
+        .collect();
+
+    for (feed_id, url, feed_entries) in feeds {
+        out.send(Response::UnreadFeed {
+            id: feed_id,
+            url,
+            unread: feed_entries.len() as i64,
+            title: titles.get(&feed_id).cloned().flatten(),
+        })
+        .await?;
+
+        for entry in feed_entries {
+            send_unread_entry(session, user_id, entry, excerpts, out).await?;
+        }
+    }
+
+    out.send(Response::EndList).await
+}
+
+async fn send_unread_entry(
+    session: &Session<'_>,
+    user_id: i64,
+    entry: UnreadEntry,
+    excerpts: bool,
+    out: &mut Responses<'_>,
+) -> Result<()> {
+    let entry_id = entry.id;
+    out.send(Response::Entry {
+        id: entry.id,
+        feed_id: entry.feed_id,
+        feed_url: entry.feed_url,
+        url: entry.url,
+        title: entry.title,
+        feed_title: entry.feed_title,
+    })
+    .await?;
+
+    if excerpts {
+        send_excerpt(session, user_id, entry_id, out).await?;
+    }
+    Ok(())
+}
+
+/// Follows an entry with an excerpt of the summary its feed gave it or,
+/// failing that, its content, if either was kept.
+async fn send_excerpt(
+    session: &Session<'_>,
+    user_id: i64,
+    entry_id: i64,
+    out: &mut Responses<'_>,
+) -> Result<()> {
+    let text = match session.store.entry_summary(entry_id).await? {
+        Some(summary) => summary,
+        None => match session.store.entry_content(user_id, entry_id).await? {
+            Some(content) => content.content,
+            None => return Ok(()),
+        },
+    };
+
+    match excerpt(&text) {
+        Some(excerpt) => {
+            out.send(Response::Excerpt {
+                id: entry_id,
+                excerpt,
+            })
+            .await
+        }
+        None => Ok(()),
+    }
+}
+
+/// The first paragraph of gemtext, on one line and cut to
+/// `EXCERPT_MAX_CHARS`. Headings, links and preformatted text are skipped.
+fn excerpt(gemtext: &str) -> Option<String> {
+    let mut preformatted = false;
+    let paragraph = gemtext.lines().find(|line| {
+        if line.starts_with("```") {
+            preformatted = !preformatted;
+            return false;
+        }
+        !preformatted
+            && !line.starts_with('#')
+            && !line.starts_with("=>")
+            && !line.trim().is_empty()
+    })?;
+
+    let paragraph = paragraph
+        .trim_start_matches(['*', '>'])
+        .split(|c: char| c.is_whitespace() || c.is_control())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if paragraph.is_empty() {
+        return None;
+    }
+
+    match paragraph.char_indices().nth(EXCERPT_MAX_CHARS) {
+        Some((end, _)) => Some(format!("{}…", paragraph[..end].trim_end())),
+        None => Some(paragraph),
+    }
+}
+
+struct MarkRead;
+
+#[async_trait]
+impl Handler for MarkRead {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let entry_id = arguments!(command, Command::MarkRead { id } => id);
+        let user_id = session.user_id()?;
+        if !authorize_entry(session, user_id, entry_id, out).await? {
+            return Ok(());
+        }
+
+        session
+            .store
+            .mark_read(user_id, entry_id, Utc::now().naive_utc())
+            .await?;
+
+        out.send(Response::AckMarkRead).await
+    }
+}
+
+/// Sends the content fetched for an entry, a line at a time.
+struct GetEntry;
+
+#[async_trait]
+impl Handler for GetEntry {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let entry_id = arguments!(command, Command::GetEntry { id } => id);
+        let user_id = session.user_id()?;
+        if !authorize_entry(session, user_id, entry_id, out).await? {
+            return Ok(());
+        }
+
+        let content = match session.store.entry_content(user_id, entry_id).await? {
+            Some(content) => content,
+            None => {
+                return out
+                    .send(Response::error(
+                        ErrorCode::ContentNotFound,
+                        format!("no content for entry {} has been fetched", entry_id),
+                    ))
+                    .await;
+            }
+        };
+
+        out.send(Response::StartEntryContent {
+            id: entry_id,
+            mime_type: content.mime_type,
+        })
+        .await?;
+
+        for line in content.content.lines() {
+            out.send(Response::ContentLine(line.to_string())).await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+/// Lists entries of subscribed feeds by title, newest first.
+struct Search;
+
+#[async_trait]
+impl Handler for Search {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let query = arguments!(command, Command::Search { query } => query);
+        let user_id = session.user_id()?;
+
+        out.send(Response::StartEntryList).await?;
+
+        let excerpts = session.store.excerpts(user_id).await?;
+        for entry in session
+            .store
+            .search_entries(user_id, &query, DEFAULT_SEARCH_LIMIT)
+            .await?
+        {
+            let entry_id = entry.id;
+            out.send(Response::Entry {
+                id: entry.id,
+                feed_id: entry.feed_id,
+                feed_url: entry.feed_url,
+                url: entry.url,
+                title: entry.title,
+                feed_title: entry.feed_title,
+            })
+            .await?;
+
+            if excerpts {
+                send_excerpt(session, user_id, entry_id, out).await?;
+            }
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+/// Lists the entries the user most recently marked read, newest first.
+struct ReadHistory;
+
+#[async_trait]
+impl Handler for ReadHistory {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let limit = arguments!(command, Command::History { limit } => limit);
+        let user_id = session.user_id()?;
+        let timezone = session.store.timezone(user_id).await?;
+
+        out.send(Response::StartHistoryList).await?;
+
+        for entry in session.store.read_history(user_id, limit).await? {
+            out.send(Response::HistoryEntry {
+                id: entry.id,
+                feed_id: entry.feed_id,
+                feed_url: entry.feed_url,
+                url: entry.url,
+                viewed_at: in_timezone(entry.viewed_at, timezone),
+                title: entry.title,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+/// When midnight starting `date` in `timezone` is, in UTC.
+fn local_midnight(date: NaiveDate, timezone: Tz) -> NaiveDateTime {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+    // Some timezones skip midnight when daylight saving time starts.
+    timezone
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map_or(midnight, |midnight| midnight.naive_utc())
+}
+
+/// The most consecutive days in `timezone` with something read on each.
+fn longest_streak(read_times: &[NaiveDateTime], timezone: Tz) -> i64 {
+    let mut longest = 0;
+    let mut current = 0;
+    let mut last_day: Option<NaiveDate> = None;
+    for time in read_times {
+        let day = timezone.from_utc_datetime(time).naive_local().date();
+        match last_day {
+            Some(last_day) if last_day == day => continue,
+            Some(last_day) if last_day.succ_opt() == Some(day) => current += 1,
+            _ => current = 1,
+        }
+        last_day = Some(day);
+        longest = longest.max(current);
+    }
+
+    longest
+}
+
+/// Sends how much the user has been reading, and what.
+struct ReadStats;
+
+#[async_trait]
+impl Handler for ReadStats {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        _command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let user_id = session.user_id()?;
+        let store = session.store;
+        let timezone = store.timezone(user_id).await?;
+
+        let today = Utc::now().with_timezone(&timezone).naive_local().date();
+        let week_start =
+            today - chrono::Duration::days(today.weekday().num_days_from_monday().into());
+        let month_start = today.with_day(1).unwrap_or(today);
+
+        out.send(Response::StartReadStats {
+            read_this_week: store
+                .read_count_since(user_id, local_midnight(week_start, timezone))
+                .await?,
+            read_this_month: store
+                .read_count_since(user_id, local_midnight(month_start, timezone))
+                .await?,
+            unread: store.unread_count(user_id).await?,
+            longest_streak_days: longest_streak(&store.read_times(user_id).await?, timezone),
+        })
+        .await?;
+
+        for feed in store.most_read_feeds(user_id, READ_STATS_FEEDS).await? {
+            out.send(Response::FeedReads {
+                id: feed.feed_id,
+                url: feed.url,
+                reads: feed.reads,
+                title: feed.title,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+struct Backup;
+
+#[async_trait]
+impl Handler for Backup {
+    fn access(&self) -> Access {
+        Access::Admin
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        _command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let path = session
+            .config
+            .backup_dir
+            .join(format!("seymour-{}.db", Utc::now().format("%Y%m%d%H%M%S")));
+        session.store.backup(&path).await?;
+
+        out.send(Response::AckBackup {
+            path: path.display().to_string(),
+        })
+        .await
+    }
+}
+
+struct ListUsers;
+
+#[async_trait]
+impl Handler for ListUsers {
+    fn access(&self) -> Access {
+        Access::Admin
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        _command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        out.send(Response::StartUserList).await?;
+
+        for user in session.store.users().await? {
+            out.send(Response::User {
+                id: user.id,
+                username: user.username,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+struct ListAllFeeds;
+
+#[async_trait]
+impl Handler for ListAllFeeds {
+    fn access(&self) -> Access {
+        Access::Admin
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        _command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        out.send(Response::StartAllFeedsList).await?;
+
+        for feed in session.store.all_feeds().await? {
+            out.send(Response::ServerFeed {
+                id: feed.feed_id,
+                url: feed.url,
+                status: FeedStatus::new(feed.gone, feed.failing),
+                subscribers: feed.subscribers,
+                failure_count: feed.failure_count,
+                title: feed.title,
+                last_error: feed.last_error,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+/// Fetches a feed on the fetcher's next pass instead of waiting for its
+/// schedule.
+struct Refresh;
+
+#[async_trait]
+impl Handler for Refresh {
+    fn access(&self) -> Access {
+        Access::Admin
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let feed_id = arguments!(command, Command::Refresh { id } => id);
+
+        if session.store.refresh_feed(feed_id).await? {
+            out.send(Response::AckRefresh).await
+        } else {
+            out.send(Response::error(
+                ErrorCode::FeedNotFound,
+                format!("no feed with ID {} exists", feed_id),
+            ))
+            .await
+        }
+    }
+}
+
+/// Fetches every feed on the fetcher's next pass.
+struct RefreshAll;
+
+#[async_trait]
+impl Handler for RefreshAll {
+    fn access(&self) -> Access {
+        Access::Admin
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        _command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let feeds = session.store.refresh_all_feeds().await?;
+        info!("Refreshing all {} feeds", feeds);
+
+        out.send(Response::AckRefresh).await
+    }
+}
+
+struct AuditLog;
+
+#[async_trait]
+impl Handler for AuditLog {
+    fn access(&self) -> Access {
+        Access::Admin
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let limit = arguments!(command, Command::AuditLog { limit } => limit);
+
+        out.send(Response::StartAuditLog).await?;
+
+        for entry in session.store.audit_log(limit).await? {
+            out.send(Response::AuditEntry {
+                at: Utc.from_utc_datetime(&entry.created_at),
+                username: entry.username,
+                command: entry.command,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+struct FeedStats;
+
+#[async_trait]
+impl Handler for FeedStats {
+    fn access(&self) -> Access {
+        Access::Admin
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        _command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        out.send(Response::StartFeedStatsList).await?;
+
+        for feed in session.store.feed_stats().await? {
+            out.send(Response::FeedStats {
+                id: feed.feed_id,
+                url: feed.url,
+                fetches: feed.fetches,
+                failures: feed.failures,
+                avg_duration_ms: feed.avg_duration_ms,
+                max_duration_ms: feed.max_duration_ms,
+                bytes: feed.bytes,
+                entries_inserted: feed.entries_inserted,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+struct SlowFeeds;
+
+#[async_trait]
+impl Handler for SlowFeeds {
+    fn access(&self) -> Access {
+        Access::Admin
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let limit = arguments!(command, Command::SlowFeeds { limit } => limit);
+
+        out.send(Response::StartSlowFeedList).await?;
+
+        for feed in session.store.slow_feeds(limit).await? {
+            out.send(Response::SlowFeed {
+                id: feed.feed_id,
+                url: feed.url,
+                fetches: feed.fetches,
+                total_duration_ms: feed.total_duration_ms,
+                durations: feed.durations,
+                sizes: feed.sizes,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+/// Parses a number of days, where `off` or `0` means never.
+fn parse_days(value: &str) -> Option<Option<i64>> {
+    match value {
+        "off" | "0" => Some(None),
+        days => days.parse().ok().filter(|days| *days > 0).map(Some),
+    }
+}
+
+/// Sets one of the user's preferences:
+///
+/// * `automarkread <days|off>`: marks entries read once they've been unread
+///   for that many days.
+/// * `timezone <name>`: the IANA timezone, e.g. `Europe/Berlin`, dates are
+///   shown in. `UTC` by default.
+/// * `directory <on|off>`: whether the user's subscriptions count towards
+///   `DISCOVER` and `RECOMMEND`.
+struct SetPref;
+
+#[async_trait]
+impl Handler for SetPref {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let (name, value) = arguments!(command, Command::SetPref { name, value } => (name, value));
+        let user_id = session.user_id()?;
+
+        match name.as_str() {
+            "automarkread" => match parse_days(&value) {
+                Some(days) => session.store.set_auto_mark_read(user_id, days).await?,
+                None => {
+                    return out
+                        .send(Response::error(
+                            ErrorCode::InvalidPreferenceValue,
+                            format!("invalid number of days \"{}\"", value),
+                        ))
+                        .await
+                }
+            },
+            "timezone" => match value.parse::<Tz>() {
+                Ok(timezone) => session.store.set_timezone(user_id, timezone).await?,
+                Err(_) => {
+                    return out
+                        .send(Response::error(
+                            ErrorCode::InvalidPreferenceValue,
+                            format!("unknown timezone \"{}\"", value),
+                        ))
+                        .await
+                }
+            },
+            "excerpts" => match value.to_lowercase().as_str() {
+                "on" => session.store.set_excerpts(user_id, true).await?,
+                "off" => session.store.set_excerpts(user_id, false).await?,
+                _ => {
+                    return out
+                        .send(Response::error(
+                            ErrorCode::InvalidPreferenceValue,
+                            format!("invalid value \"{}\", expected on or off", value),
+                        ))
+                        .await
+                }
+            },
+            "dedupe" => match value.to_lowercase().as_str() {
+                "on" => session.store.set_dedupe(user_id, true).await?,
+                "off" => session.store.set_dedupe(user_id, false).await?,
+                _ => {
+                    return out
+                        .send(Response::error(
+                            ErrorCode::InvalidPreferenceValue,
+                            format!("invalid value \"{}\", expected on or off", value),
+                        ))
+                        .await
+                }
+            },
+            "directory" => match value.to_lowercase().as_str() {
+                "on" => {
+                    session
+                        .store
+                        .set_hide_from_directory(user_id, false)
+                        .await?
+                }
+                "off" => session.store.set_hide_from_directory(user_id, true).await?,
+                _ => {
+                    return out
+                        .send(Response::error(
+                            ErrorCode::InvalidPreferenceValue,
+                            format!("invalid value \"{}\", expected on or off", value),
+                        ))
+                        .await
+                }
+            },
+            _ => {
+                return out
+                    .send(Response::error(
+                        ErrorCode::UnknownPreference,
+                        format!("unknown preference \"{}\"", name),
+                    ))
+                    .await
+            }
+        }
+
+        out.send(Response::AckSetPref).await
+    }
+}
+
+/// Lists feeds other users subscribe to, so joining one doesn't mean hunting
+/// for its URL.
+struct Discover;
+
+#[async_trait]
+impl Handler for Discover {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let query = arguments!(command, Command::Discover { query } => query);
+
+        out.send(Response::StartDirectoryList).await?;
+
+        for feed in session
+            .store
+            .directory(query.as_deref(), DEFAULT_DIRECTORY_LIMIT)
+            .await?
+        {
+            out.send(Response::DirectoryFeed {
+                id: feed.feed_id,
+                url: feed.url,
+                subscribers: feed.subscribers,
+                title: feed.title,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+/// Suggests feeds that users sharing the user's subscriptions also read.
+struct Recommend;
+
+#[async_trait]
+impl Handler for Recommend {
+    async fn handle(
+        &self,
+        session: &mut Session<'_>,
+        _command: Command,
+        out: &mut Responses<'_>,
+    ) -> Result<()> {
+        let user_id = session.user_id()?;
+
+        out.send(Response::StartRecommendationList).await?;
+
+        for feed in session
+            .store
+            .recommendations(user_id, DEFAULT_RECOMMEND_LIMIT)
+            .await?
+        {
+            out.send(Response::Recommendation {
+                id: feed.feed_id,
+                url: feed.url,
+                score: feed.score,
+                title: feed.title,
+            })
+            .await?;
+        }
+
+        out.send(Response::EndList).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use seymour_protocol::Response;
+
+    use super::{Commands, Responses, Session};
+    use crate::store::memory::MemoryStore;
+    use crate::test_config;
+
+    const FEED_URL: &str = "gemini://example.com/gemlog/";
+
+    /// Runs each line as a command of one connection, returning every
+    /// response line.
+    async fn run(store: &MemoryStore, lines: &[&str]) -> Vec<Response> {
+        let config = test_config();
+        let commands = Commands::new();
+        let mut session = Session::new(store, &config);
+
+        let mut output = Vec::new();
+        let mut out = Responses::new(&mut output);
+        for line in lines {
+            commands
+                .run(&mut session, line.parse().unwrap(), &mut out)
+                .await
+                .unwrap();
+        }
+        out.flush().await.unwrap();
+        drop(out);
+
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| line.parse().unwrap())
+            .collect()
+    }
+
+    fn entry_ids(responses: &[Response]) -> Vec<i64> {
+        responses
+            .iter()
+            .filter_map(|response| match response {
+                Response::Entry { id, .. } => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Subscribes alice to the feed, returning its ID.
+    async fn subscribe_alice(store: &MemoryStore) -> i64 {
+        let subscribe = format!("SUBSCRIBE {}", FEED_URL);
+        match &run(store, &["USER alice", &subscribe]).await[1] {
+            Response::AckSubscribe { id, url } => {
+                assert_eq!(url, FEED_URL);
+                *id
+            }
+            response => panic!("unexpected response {}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_need_a_user() {
+        let store = MemoryStore::new();
+        let responses = run(&store, &["LISTSUBSCRIPTIONS"]).await;
+        assert!(matches!(responses[..], [Response::NeedUser(_)]));
+    }
+
+    #[tokio::test]
+    async fn subscribe_and_unsubscribe() {
+        let store = MemoryStore::new();
+        let feed_id = subscribe_alice(&store).await;
+
+        let responses = run(&store, &["USER alice", "LISTSUBSCRIPTIONS"]).await;
+        assert_eq!(responses.len(), 4);
+        assert!(matches!(responses[2], Response::Subscription { id, .. } if id == feed_id));
+
+        let unsubscribe = format!("UNSUBSCRIBE {}", feed_id);
+        let responses = run(
+            &store,
+            &[
+                "USER alice",
+                &unsubscribe,
+                &unsubscribe,
+                "LISTSUBSCRIPTIONS",
+            ],
+        )
+        .await;
+        assert_eq!(responses[1], Response::AckUnsubscribe);
+        assert!(matches!(responses[2], Response::ResourceNotFound(_)));
+        assert_eq!(
+            responses[3..],
+            [Response::StartSubscriptionList, Response::EndList]
+        );
+    }
+
+    #[tokio::test]
+    async fn marked_entries_leave_the_unread_list() {
+        let store = MemoryStore::new();
+        let feed_id = subscribe_alice(&store).await;
+        let now = Utc::now().naive_utc();
+        let first = store.add_entry(feed_id, "First", "gemini://example.com/1", now);
+        let second = store.add_entry(feed_id, "Second", "gemini://example.com/2", now);
+
+        let responses = run(&store, &["USER alice", "LISTUNREAD"]).await;
+        assert_eq!(responses[1], Response::StartEntryList);
+        assert_eq!(entry_ids(&responses), vec![first, second]);
+        assert_eq!(responses.last(), Some(&Response::EndList));
+
+        let mark_read = format!("MARKREAD {}", first);
+        let responses = run(&store, &["USER alice", &mark_read, "LISTUNREAD"]).await;
+        assert_eq!(responses[1], Response::AckMarkRead);
+        assert_eq!(entry_ids(&responses), vec![second]);
+    }
+
+    #[tokio::test]
+    async fn entries_before_the_backlog_are_not_listed() {
+        let store = MemoryStore::new();
+        let feed_id = subscribe_alice(&store).await;
+        // With no SUBSCRIPTION_BACKLOG_DAYS, only today's entries are unread.
+        let yesterday = Utc::now().naive_utc() - Duration::days(1);
+        store.add_entry(feed_id, "Old", "gemini://example.com/1", yesterday);
+        let new = store.add_entry(
+            feed_id,
+            "New",
+            "gemini://example.com/2",
+            Utc::now().naive_utc(),
+        );
+
+        let responses = run(&store, &["USER alice", "LISTUNREAD"]).await;
+        assert_eq!(entry_ids(&responses), vec![new]);
+    }
+}
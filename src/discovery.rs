@@ -0,0 +1,105 @@
+use std::convert::TryFrom;
+
+use anyhow::{Context, Result};
+use tracing::{debug, info};
+use url::Url;
+
+use crate::feed::Feed;
+use crate::fetch::{fetch, FetchConfig, Page};
+
+/// Places capsules commonly keep their feeds, relative to the root.
+const COMMON_FEED_PATHS: &[&str] = &["/gemlog/", "/atom.xml", "/index.gmi"];
+
+/// Words that mark a link on a capsule's root page as pointing at a feed.
+const FEED_LINK_HINTS: &[&str] = &["gemlog", "feed", "atom", "journal", "blog", "posts"];
+
+/// Whether `url` points at a capsule root rather than a specific page.
+pub fn is_capsule_root(url: &Url) -> bool {
+    matches!(url.path(), "" | "/") && url.query().is_none()
+}
+
+/// Links on the root page whose label suggests they lead to a feed.
+fn hinted_links(root: &Url, body: &str) -> Vec<Url> {
+    body.lines()
+        .filter_map(|line| line.strip_prefix("=>"))
+        .filter_map(|link| {
+            let mut parts = link.trim_start().splitn(2, char::is_whitespace);
+            let url = parts.next()?;
+            let label = parts.next().unwrap_or("").to_lowercase();
+
+            if FEED_LINK_HINTS.iter().any(|hint| label.contains(hint)) {
+                root.join(url).ok()
+            } else {
+                None
+            }
+        })
+        .filter(|url| url.scheme() == "gemini" && url.host_str() == root.host_str())
+        .collect()
+}
+
+/// The number of entries in `page` if it parses as a feed.
+fn count_entries(page: Page) -> Option<usize> {
+    Feed::try_from(page)
+        .ok()
+        .map(|feed| feed.entries.len())
+        .filter(|entries| *entries > 0)
+}
+
+/// Looks for the best feed on the capsule rooted at `root`.
+///
+/// The root page itself, links on it that look like feeds, and a handful of
+/// well-known locations are probed, and whichever has the most entries wins.
+/// Returns `None` if nothing that looks like a feed was found.
+pub async fn discover_feed(root: &Url, config: &FetchConfig) -> Result<Option<Url>> {
+    let mut best: Option<(Url, usize)> = None;
+    let mut candidates = Vec::new();
+
+    let page = fetch(root.as_str(), config)
+        .await
+        .with_context(|| format!("failed to fetch capsule root \"{}\"", root))?;
+    if let Some(body) = page.body.as_deref() {
+        candidates.extend(hinted_links(&page.url, body));
+    }
+    if let Some(entries) = count_entries(page) {
+        best = Some((root.clone(), entries));
+    }
+
+    for path in COMMON_FEED_PATHS {
+        if let Ok(url) = root.join(path) {
+            candidates.push(url);
+        }
+    }
+
+    let mut probed = vec![root.clone()];
+    for candidate in candidates {
+        if probed.contains(&candidate) {
+            continue;
+        }
+        probed.push(candidate.clone());
+
+        let entries = match fetch(candidate.as_str(), config).await {
+            Ok(page) => count_entries(page),
+            Err(e) => {
+                debug!("Feed discovery probe of \"{}\" failed: {:#}", candidate, e);
+                None
+            }
+        };
+
+        if let Some(entries) = entries {
+            debug!(
+                "Feed discovery found {} entries at \"{}\"",
+                entries, candidate
+            );
+
+            if best.as_ref().is_none_or(|(_, most)| entries > *most) {
+                best = Some((candidate, entries));
+            }
+        }
+    }
+
+    if let Some((url, _)) = &best {
+        info!("Discovered feed \"{}\" for \"{}\"", url, root);
+    }
+
+    Ok(best.map(|(url, _)| url))
+}
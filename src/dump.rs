@@ -0,0 +1,995 @@
+//! Dumps every table to newline-delimited JSON and restores it, so data
+//! can be moved between database backends or recovered from a file. `copy`
+//! does both at once between two databases. Only `unread_counts`, which
+//! triggers rebuild as rows are loaded, and `data_fixes`, which the target's
+//! own migrations fill in, are left out.
+//!
+//! Records are written parents first (users and feeds before the entries,
+//! subscriptions and views that refer to them), and restored in file order.
+
+use anyhow::{format_err, Context, Result};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::any::AnyRow;
+use sqlx::{Any, Pool, Row, Transaction};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tracing::info;
+
+use crate::feed_url::url_key;
+
+/// Bumped whenever the shape of a record changes incompatibly.
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    Header {
+        version: u32,
+    },
+    User {
+        id: i64,
+        username: String,
+        #[serde(default)]
+        auto_mark_read_days: Option<i64>,
+        #[serde(default)]
+        hide_from_directory: i64,
+        #[serde(default)]
+        is_admin: i64,
+        #[serde(default)]
+        timezone: Option<String>,
+        #[serde(default)]
+        excerpts: i64,
+        #[serde(default)]
+        dedupe: i64,
+    },
+    Feed {
+        id: i64,
+        url: String,
+        content_hash: Option<String>,
+        fetch_after: Option<String>,
+        failure_count: i64,
+        last_error: Option<String>,
+        feed_kind: Option<String>,
+        proxy: Option<String>,
+        #[serde(default)]
+        feed_format: Option<String>,
+        apply_title_edits: i64,
+        gone_at: Option<String>,
+        title: Option<String>,
+        subtitle: Option<String>,
+        unsubscribed_at: Option<String>,
+        #[serde(default)]
+        last_seen_published_at: Option<String>,
+        #[serde(default)]
+        last_seen_url: Option<String>,
+        #[serde(default)]
+        failing_since: Option<String>,
+    },
+    Entry {
+        id: i64,
+        feed_id: i64,
+        title: String,
+        published_at: String,
+        url: String,
+        #[serde(default)]
+        entry_key: Option<String>,
+        #[serde(default)]
+        summary: Option<String>,
+        #[serde(default)]
+        guid: Option<String>,
+    },
+    EntryContent {
+        feed_entry_id: i64,
+        mime_type: String,
+        content: String,
+        fetched_at: String,
+    },
+    Subscription {
+        user_id: i64,
+        feed_id: i64,
+        created_at: Option<String>,
+        backlog_from: Option<String>,
+        #[serde(default)]
+        notify: i64,
+        #[serde(default)]
+        snoozed_until: Option<String>,
+        #[serde(default)]
+        max_unread: Option<i64>,
+    },
+    View {
+        user_id: i64,
+        feed_entry_id: i64,
+        #[serde(default)]
+        viewed_at: Option<String>,
+    },
+    PendingView {
+        user_id: i64,
+        feed_id: i64,
+        entry_url: String,
+        viewed_at: Option<String>,
+    },
+    ClientCertificate {
+        fingerprint: String,
+        user_id: i64,
+        created_at: String,
+    },
+    ApiToken {
+        token_hash: String,
+        user_id: i64,
+        created_at: String,
+    },
+    KnownCertificate {
+        host: String,
+        port: i64,
+        fingerprint: String,
+        first_seen_at: String,
+        not_after: Option<String>,
+        rotated_at: Option<String>,
+    },
+    AuditEntry {
+        id: i64,
+        username: String,
+        command: String,
+        created_at: String,
+    },
+    FetchAttempt {
+        id: i64,
+        feed_id: i64,
+        fetched_at: String,
+        duration_ms: i64,
+        status: Option<i64>,
+        bytes: i64,
+        entries_inserted: i64,
+        error: Option<String>,
+    },
+    FetchCycle {
+        completed_at: String,
+    },
+    SyncChange {
+        id: i64,
+        user_id: i64,
+        kind: String,
+        feed_id: Option<i64>,
+        feed_entry_id: Option<i64>,
+        changed_at: String,
+    },
+    Group {
+        id: i64,
+        name: String,
+        created_at: String,
+    },
+    GroupMember {
+        group_id: i64,
+        user_id: i64,
+    },
+    GroupFeed {
+        group_id: i64,
+        feed_id: i64,
+    },
+}
+
+impl Record {
+    fn user(row: AnyRow) -> Result<Self> {
+        Ok(Record::User {
+            id: row.try_get("id")?,
+            username: row.try_get("username")?,
+            auto_mark_read_days: row.try_get("auto_mark_read_days")?,
+            hide_from_directory: row.try_get("hide_from_directory")?,
+            is_admin: row.try_get("is_admin")?,
+            timezone: row.try_get("timezone")?,
+            excerpts: row.try_get("excerpts")?,
+            dedupe: row.try_get("dedupe")?,
+        })
+    }
+
+    fn feed(row: AnyRow) -> Result<Self> {
+        Ok(Record::Feed {
+            id: row.try_get("id")?,
+            url: row.try_get("url")?,
+            content_hash: row.try_get("content_hash")?,
+            fetch_after: row.try_get("fetch_after")?,
+            failure_count: row.try_get("failure_count")?,
+            last_error: row.try_get("last_error")?,
+            feed_kind: row.try_get("feed_kind")?,
+            proxy: row.try_get("proxy")?,
+            feed_format: row.try_get("feed_format")?,
+            apply_title_edits: row.try_get("apply_title_edits")?,
+            gone_at: row.try_get("gone_at")?,
+            title: row.try_get("title")?,
+            subtitle: row.try_get("subtitle")?,
+            unsubscribed_at: row.try_get("unsubscribed_at")?,
+            last_seen_published_at: row.try_get("last_seen_published_at")?,
+            last_seen_url: row.try_get("last_seen_url")?,
+            failing_since: row.try_get("failing_since")?,
+        })
+    }
+
+    fn entry(row: AnyRow) -> Result<Self> {
+        Ok(Record::Entry {
+            id: row.try_get("id")?,
+            feed_id: row.try_get("feed_id")?,
+            title: row.try_get("title")?,
+            published_at: row.try_get("published_at")?,
+            url: row.try_get("url")?,
+            entry_key: row.try_get("entry_key")?,
+            summary: row.try_get("summary")?,
+            guid: row.try_get("guid")?,
+        })
+    }
+
+    fn entry_content(row: AnyRow) -> Result<Self> {
+        Ok(Record::EntryContent {
+            feed_entry_id: row.try_get("feed_entry_id")?,
+            mime_type: row.try_get("mime_type")?,
+            content: row.try_get("content")?,
+            fetched_at: row.try_get("fetched_at")?,
+        })
+    }
+
+    fn subscription(row: AnyRow) -> Result<Self> {
+        Ok(Record::Subscription {
+            user_id: row.try_get("user_id")?,
+            feed_id: row.try_get("feed_id")?,
+            created_at: row.try_get("created_at")?,
+            backlog_from: row.try_get("backlog_from")?,
+            notify: row.try_get("notify")?,
+            snoozed_until: row.try_get("snoozed_until")?,
+            max_unread: row.try_get("max_unread")?,
+        })
+    }
+
+    fn view(row: AnyRow) -> Result<Self> {
+        Ok(Record::View {
+            user_id: row.try_get("user_id")?,
+            feed_entry_id: row.try_get("feed_entry_id")?,
+            viewed_at: row.try_get("viewed_at")?,
+        })
+    }
+
+    fn pending_view(row: AnyRow) -> Result<Self> {
+        Ok(Record::PendingView {
+            user_id: row.try_get("user_id")?,
+            feed_id: row.try_get("feed_id")?,
+            entry_url: row.try_get("entry_url")?,
+            viewed_at: row.try_get("viewed_at")?,
+        })
+    }
+
+    fn client_certificate(row: AnyRow) -> Result<Self> {
+        Ok(Record::ClientCertificate {
+            fingerprint: row.try_get("fingerprint")?,
+            user_id: row.try_get("user_id")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+
+    fn api_token(row: AnyRow) -> Result<Self> {
+        Ok(Record::ApiToken {
+            token_hash: row.try_get("token_hash")?,
+            user_id: row.try_get("user_id")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+
+    fn known_certificate(row: AnyRow) -> Result<Self> {
+        Ok(Record::KnownCertificate {
+            host: row.try_get("host")?,
+            port: row.try_get("port")?,
+            fingerprint: row.try_get("fingerprint")?,
+            first_seen_at: row.try_get("first_seen_at")?,
+            not_after: row.try_get("not_after")?,
+            rotated_at: row.try_get("rotated_at")?,
+        })
+    }
+
+    fn audit_entry(row: AnyRow) -> Result<Self> {
+        Ok(Record::AuditEntry {
+            id: row.try_get("id")?,
+            username: row.try_get("username")?,
+            command: row.try_get("command")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+
+    fn fetch_attempt(row: AnyRow) -> Result<Self> {
+        Ok(Record::FetchAttempt {
+            id: row.try_get("id")?,
+            feed_id: row.try_get("feed_id")?,
+            fetched_at: row.try_get("fetched_at")?,
+            duration_ms: row.try_get("duration_ms")?,
+            status: row.try_get("status")?,
+            bytes: row.try_get("bytes")?,
+            entries_inserted: row.try_get("entries_inserted")?,
+            error: row.try_get("error")?,
+        })
+    }
+
+    fn fetch_cycle(row: AnyRow) -> Result<Self> {
+        Ok(Record::FetchCycle {
+            completed_at: row.try_get("completed_at")?,
+        })
+    }
+
+    fn sync_change(row: AnyRow) -> Result<Self> {
+        Ok(Record::SyncChange {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            kind: row.try_get("kind")?,
+            feed_id: row.try_get("feed_id")?,
+            feed_entry_id: row.try_get("feed_entry_id")?,
+            changed_at: row.try_get("changed_at")?,
+        })
+    }
+
+    fn group(row: AnyRow) -> Result<Self> {
+        Ok(Record::Group {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+
+    fn group_member(row: AnyRow) -> Result<Self> {
+        Ok(Record::GroupMember {
+            group_id: row.try_get("group_id")?,
+            user_id: row.try_get("user_id")?,
+        })
+    }
+
+    fn group_feed(row: AnyRow) -> Result<Self> {
+        Ok(Record::GroupFeed {
+            group_id: row.try_get("group_id")?,
+            feed_id: row.try_get("feed_id")?,
+        })
+    }
+}
+
+/// Converts a table's row into a record.
+type ToRecord = fn(AnyRow) -> Result<Record>;
+
+/// Tables in dump order, with the conversion from each of their rows.
+const TABLES: &[(&str, &str, ToRecord)] = &[
+    ("users", "SELECT * FROM users ORDER BY id", Record::user),
+    ("feeds", "SELECT * FROM feeds ORDER BY id", Record::feed),
+    (
+        "feed_entries",
+        "SELECT * FROM feed_entries ORDER BY id",
+        Record::entry,
+    ),
+    (
+        "entry_contents",
+        "SELECT * FROM entry_contents ORDER BY feed_entry_id",
+        Record::entry_content,
+    ),
+    (
+        "subscriptions",
+        "SELECT * FROM subscriptions ORDER BY user_id, feed_id",
+        Record::subscription,
+    ),
+    (
+        "views",
+        "SELECT * FROM views ORDER BY user_id, feed_entry_id",
+        Record::view,
+    ),
+    (
+        "pending_views",
+        "SELECT * FROM pending_views ORDER BY user_id, feed_id, entry_url",
+        Record::pending_view,
+    ),
+    (
+        "client_certificates",
+        "SELECT * FROM client_certificates ORDER BY fingerprint",
+        Record::client_certificate,
+    ),
+    (
+        "api_tokens",
+        "SELECT * FROM api_tokens ORDER BY token_hash",
+        Record::api_token,
+    ),
+    (
+        "known_certificates",
+        "SELECT * FROM known_certificates ORDER BY host, port",
+        Record::known_certificate,
+    ),
+    (
+        "user_groups",
+        "SELECT * FROM user_groups ORDER BY id",
+        Record::group,
+    ),
+    (
+        "group_members",
+        "SELECT * FROM group_members ORDER BY group_id, user_id",
+        Record::group_member,
+    ),
+    (
+        "group_feeds",
+        "SELECT * FROM group_feeds ORDER BY group_id, feed_id",
+        Record::group_feed,
+    ),
+    (
+        "audit_log",
+        "SELECT * FROM audit_log ORDER BY id",
+        Record::audit_entry,
+    ),
+    (
+        "fetch_log",
+        "SELECT * FROM fetch_log ORDER BY id",
+        Record::fetch_attempt,
+    ),
+    // The ID is always 1, and an INTEGER that PostgreSQL won't read as i64.
+    (
+        "fetch_cycles",
+        "SELECT completed_at FROM fetch_cycles",
+        Record::fetch_cycle,
+    ),
+    // After views and subscriptions, whose triggers record changes of their
+    // own as they're loaded. See `replace_sync_changes`.
+    (
+        "sync_changes",
+        "SELECT * FROM sync_changes ORDER BY id",
+        Record::sync_change,
+    ),
+];
+
+/// Tables that aren't dumped: the migrations sqlx and `run_data_fixes`
+/// applied, which the target records itself when it's migrated, and what
+/// triggers rebuild from the dumped tables.
+const SKIPPED_TABLES: &[&str] = &["_sqlx_migrations", "data_fixes", "unread_counts"];
+
+async fn write_record(writer: &mut BufWriter<File>, record: &Record) -> Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+
+    Ok(())
+}
+
+/// Writes every table to `path`.
+pub async fn dump(pool: &Pool<Any>, path: &str) -> Result<()> {
+    let file = File::create(path)
+        .await
+        .with_context(|| format!("failed to create {}", path))?;
+    let mut writer = BufWriter::new(file);
+
+    write_record(
+        &mut writer,
+        &Record::Header {
+            version: FORMAT_VERSION,
+        },
+    )
+    .await?;
+
+    // Read everything in one transaction so the dump is consistent even
+    // while the server is writing.
+    let mut tx = pool.begin().await?;
+
+    for (table, query, to_record) in TABLES {
+        let mut count = 0;
+        let mut rows = sqlx::query(query).fetch(&mut tx);
+        while let Some(row) = rows.try_next().await? {
+            write_record(&mut writer, &to_record(row)?)
+                .await
+                .with_context(|| format!("failed to write to {}", path))?;
+            count += 1;
+        }
+
+        info!("Dumped {} rows from {}", count, table);
+    }
+
+    tx.commit().await?;
+    writer.flush().await?;
+
+    Ok(())
+}
+
+async fn insert_record(tx: &mut Transaction<'_, Any>, record: Record) -> Result<()> {
+    match record {
+        Record::Header { .. } => {
+            return Err(format_err!("unexpected header in the middle of the dump"));
+        }
+        Record::User {
+            id,
+            username,
+            auto_mark_read_days,
+            hide_from_directory,
+            is_admin,
+            timezone,
+            excerpts,
+            dedupe,
+        } => {
+            sqlx::query(
+                r#"INSERT INTO users
+                    (id, username, auto_mark_read_days, hide_from_directory, is_admin, timezone,
+                        excerpts, dedupe)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+            )
+            .bind(id)
+            .bind(username)
+            .bind(auto_mark_read_days)
+            .bind(hide_from_directory)
+            .bind(is_admin)
+            .bind(timezone)
+            .bind(excerpts)
+            .bind(dedupe)
+            .execute(tx)
+            .await?;
+        }
+        Record::Feed {
+            id,
+            url,
+            content_hash,
+            fetch_after,
+            failure_count,
+            last_error,
+            feed_kind,
+            proxy,
+            feed_format,
+            apply_title_edits,
+            gone_at,
+            title,
+            subtitle,
+            unsubscribed_at,
+            last_seen_published_at,
+            last_seen_url,
+            failing_since,
+        } => {
+            // The key is derived from the URL, so it isn't dumped.
+            sqlx::query(
+                r#"INSERT INTO feeds
+                    (id, url, content_hash, fetch_after, failure_count, last_error, feed_kind,
+                        proxy, feed_format, apply_title_edits, gone_at, title, subtitle,
+                        unsubscribed_at, last_seen_published_at, last_seen_url, failing_since,
+                        url_key)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15,
+                        $16, $17, $18)"#,
+            )
+            .bind(id)
+            .bind(&url)
+            .bind(content_hash)
+            .bind(fetch_after)
+            .bind(failure_count)
+            .bind(last_error)
+            .bind(feed_kind)
+            .bind(proxy)
+            .bind(feed_format)
+            .bind(apply_title_edits)
+            .bind(gone_at)
+            .bind(title)
+            .bind(subtitle)
+            .bind(unsubscribed_at)
+            .bind(last_seen_published_at)
+            .bind(last_seen_url)
+            .bind(failing_since)
+            .bind(url_key(&url))
+            .execute(tx)
+            .await?;
+        }
+        Record::Entry {
+            id,
+            feed_id,
+            title,
+            published_at,
+            url,
+            entry_key,
+            summary,
+            guid,
+        } => {
+            sqlx::query(
+                r#"INSERT INTO feed_entries
+                    (id, feed_id, title, published_at, url, entry_key, summary, guid)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+            )
+            .bind(id)
+            .bind(feed_id)
+            .bind(title)
+            .bind(published_at)
+            .bind(url)
+            .bind(entry_key)
+            .bind(summary)
+            .bind(guid)
+            .execute(tx)
+            .await?;
+        }
+        Record::EntryContent {
+            feed_entry_id,
+            mime_type,
+            content,
+            fetched_at,
+        } => {
+            sqlx::query(
+                r#"INSERT INTO entry_contents (feed_entry_id, mime_type, content, fetched_at)
+                    VALUES ($1, $2, $3, $4)"#,
+            )
+            .bind(feed_entry_id)
+            .bind(mime_type)
+            .bind(content)
+            .bind(fetched_at)
+            .execute(tx)
+            .await?;
+        }
+        Record::Subscription {
+            user_id,
+            feed_id,
+            created_at,
+            backlog_from,
+            notify,
+            snoozed_until,
+            max_unread,
+        } => {
+            sqlx::query(
+                r#"INSERT INTO subscriptions
+                    (user_id, feed_id, created_at, backlog_from, notify, snoozed_until, max_unread)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            )
+            .bind(user_id)
+            .bind(feed_id)
+            .bind(created_at)
+            .bind(backlog_from)
+            .bind(notify)
+            .bind(snoozed_until)
+            .bind(max_unread)
+            .execute(tx)
+            .await?;
+        }
+        Record::View {
+            user_id,
+            feed_entry_id,
+            viewed_at,
+        } => {
+            sqlx::query(
+                "INSERT INTO views (user_id, feed_entry_id, viewed_at) VALUES ($1, $2, $3)",
+            )
+            .bind(user_id)
+            .bind(feed_entry_id)
+            .bind(viewed_at)
+            .execute(tx)
+            .await?;
+        }
+        Record::PendingView {
+            user_id,
+            feed_id,
+            entry_url,
+            viewed_at,
+        } => {
+            sqlx::query(
+                r#"INSERT INTO pending_views (user_id, feed_id, entry_url, viewed_at)
+                    VALUES ($1, $2, $3, $4)"#,
+            )
+            .bind(user_id)
+            .bind(feed_id)
+            .bind(entry_url)
+            .bind(viewed_at)
+            .execute(tx)
+            .await?;
+        }
+        Record::ClientCertificate {
+            fingerprint,
+            user_id,
+            created_at,
+        } => {
+            sqlx::query(
+                r#"INSERT INTO client_certificates (fingerprint, user_id, created_at)
+                    VALUES ($1, $2, $3)"#,
+            )
+            .bind(fingerprint)
+            .bind(user_id)
+            .bind(created_at)
+            .execute(tx)
+            .await?;
+        }
+        Record::ApiToken {
+            token_hash,
+            user_id,
+            created_at,
+        } => {
+            sqlx::query(
+                r#"INSERT INTO api_tokens (token_hash, user_id, created_at)
+                    VALUES ($1, $2, $3)"#,
+            )
+            .bind(token_hash)
+            .bind(user_id)
+            .bind(created_at)
+            .execute(tx)
+            .await?;
+        }
+        Record::KnownCertificate {
+            host,
+            port,
+            fingerprint,
+            first_seen_at,
+            not_after,
+            rotated_at,
+        } => {
+            sqlx::query(
+                r#"INSERT INTO known_certificates
+                    (host, port, fingerprint, first_seen_at, not_after, rotated_at)
+                    VALUES ($1, $2, $3, $4, $5, $6)"#,
+            )
+            .bind(host)
+            .bind(port)
+            .bind(fingerprint)
+            .bind(first_seen_at)
+            .bind(not_after)
+            .bind(rotated_at)
+            .execute(tx)
+            .await?;
+        }
+        Record::AuditEntry {
+            id,
+            username,
+            command,
+            created_at,
+        } => {
+            sqlx::query(
+                r#"INSERT INTO audit_log (id, username, command, created_at)
+                    VALUES ($1, $2, $3, $4)"#,
+            )
+            .bind(id)
+            .bind(username)
+            .bind(command)
+            .bind(created_at)
+            .execute(tx)
+            .await?;
+        }
+        Record::FetchAttempt {
+            id,
+            feed_id,
+            fetched_at,
+            duration_ms,
+            status,
+            bytes,
+            entries_inserted,
+            error,
+        } => {
+            sqlx::query(
+                r#"INSERT INTO fetch_log
+                    (id, feed_id, fetched_at, duration_ms, status, bytes, entries_inserted, error)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+            )
+            .bind(id)
+            .bind(feed_id)
+            .bind(fetched_at)
+            .bind(duration_ms)
+            .bind(status)
+            .bind(bytes)
+            .bind(entries_inserted)
+            .bind(error)
+            .execute(tx)
+            .await?;
+        }
+        Record::FetchCycle { completed_at } => {
+            sqlx::query("INSERT INTO fetch_cycles (id, completed_at) VALUES (1, $1)")
+                .bind(completed_at)
+                .execute(tx)
+                .await?;
+        }
+        Record::SyncChange {
+            id,
+            user_id,
+            kind,
+            feed_id,
+            feed_entry_id,
+            changed_at,
+        } => {
+            sqlx::query(
+                r#"INSERT INTO sync_changes (id, user_id, kind, feed_id, feed_entry_id, changed_at)
+                    VALUES ($1, $2, $3, $4, $5, $6)"#,
+            )
+            .bind(id)
+            .bind(user_id)
+            .bind(kind)
+            .bind(feed_id)
+            .bind(feed_entry_id)
+            .bind(changed_at)
+            .execute(tx)
+            .await?;
+        }
+        Record::Group {
+            id,
+            name,
+            created_at,
+        } => {
+            sqlx::query("INSERT INTO user_groups (id, name, created_at) VALUES ($1, $2, $3)")
+                .bind(id)
+                .bind(name)
+                .bind(created_at)
+                .execute(tx)
+                .await?;
+        }
+        Record::GroupMember { group_id, user_id } => {
+            sqlx::query("INSERT INTO group_members (group_id, user_id) VALUES ($1, $2)")
+                .bind(group_id)
+                .bind(user_id)
+                .execute(tx)
+                .await?;
+        }
+        Record::GroupFeed { group_id, feed_id } => {
+            sqlx::query("INSERT INTO group_feeds (group_id, feed_id) VALUES ($1, $2)")
+                .bind(group_id)
+                .bind(feed_id)
+                .execute(tx)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn count_rows(tx: &mut Transaction<'_, Any>, table: &str) -> Result<i64> {
+    let count = sqlx::query(&format!("SELECT COUNT(*) AS count FROM {}", table))
+        .fetch_one(tx)
+        .await?
+        .try_get("count")?;
+
+    Ok(count)
+}
+
+async fn ensure_empty(tx: &mut Transaction<'_, Any>) -> Result<()> {
+    for table in &["users", "feeds"] {
+        if count_rows(tx, table).await? > 0 {
+            return Err(format_err!(
+                "refusing to load into a database that already has {}",
+                table
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Loading views and subscriptions makes their triggers record changes of
+/// their own. Those are dropped before the dumped changes are loaded, so
+/// that SYNC tokens handed out before keep pointing at the same changes.
+async fn replace_sync_changes(tx: &mut Transaction<'_, Any>) -> Result<()> {
+    sqlx::query("DELETE FROM sync_changes").execute(tx).await?;
+
+    Ok(())
+}
+
+/// Fails if the source has tables that are neither dumped nor known to be
+/// safe to skip, so a table added later can't be silently left behind.
+async fn ensure_all_tables_known(tx: &mut Transaction<'_, Any>, database_url: &str) -> Result<()> {
+    let query = if database_url.starts_with("postgres") {
+        "SELECT tablename AS name FROM pg_tables WHERE schemaname = current_schema()"
+    } else {
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'"
+    };
+
+    for row in sqlx::query(query).fetch_all(tx).await? {
+        let name: String = row.try_get("name")?;
+        let known = TABLES.iter().any(|(table, _, _)| *table == name)
+            || SKIPPED_TABLES.contains(&name.as_str());
+        if !known {
+            return Err(format_err!("don't know how to copy table {}", name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Rows are inserted with their original IDs, which doesn't advance
+/// PostgreSQL's sequences the way SQLite's AUTOINCREMENT follows MAX(id).
+async fn reset_sequences(tx: &mut Transaction<'_, Any>, database_url: &str) -> Result<()> {
+    if database_url.starts_with("postgres") {
+        for table in &[
+            "users",
+            "feeds",
+            "feed_entries",
+            "user_groups",
+            "audit_log",
+            "fetch_log",
+            "sync_changes",
+        ] {
+            sqlx::query(&format!(
+                "SELECT setval(pg_get_serial_sequence('{0}', 'id'), COALESCE(MAX(id), 1)) FROM {0}",
+                table
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads a dump made by [`dump`] into an empty, fully migrated database.
+pub async fn restore(pool: &Pool<Any>, database_url: &str, path: &str) -> Result<()> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("failed to open {}", path))?;
+    let mut lines = BufReader::new(file).lines();
+
+    let mut tx = pool.begin().await?;
+    ensure_empty(&mut tx).await?;
+
+    match lines.next_line().await? {
+        Some(line) => match serde_json::from_str(&line)? {
+            Record::Header { version } if version == FORMAT_VERSION => {}
+            Record::Header { version } => {
+                return Err(format_err!("unsupported dump format version {}", version));
+            }
+            _ => return Err(format_err!("{} is missing its header", path)),
+        },
+        None => return Err(format_err!("{} is empty", path)),
+    }
+
+    let mut count = 0;
+    let mut line_number = 1;
+    let mut sync_changes_replaced = false;
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: Record = serde_json::from_str(&line)
+            .with_context(|| format!("invalid record on line {} of {}", line_number, path))?;
+        if let Record::SyncChange { .. } = record {
+            if !sync_changes_replaced {
+                replace_sync_changes(&mut tx).await?;
+                sync_changes_replaced = true;
+            }
+        }
+        insert_record(&mut tx, record)
+            .await
+            .with_context(|| format!("failed to restore line {} of {}", line_number, path))?;
+        count += 1;
+    }
+
+    reset_sequences(&mut tx, database_url).await?;
+
+    tx.commit().await?;
+
+    info!("Restored {} records from {}", count, path);
+
+    Ok(())
+}
+
+/// Copies every table `dump` covers from `from` into `to`, an empty, fully
+/// migrated database, keeping IDs. Fails if `from` has a table `dump`
+/// doesn't know of. The copy is only committed once each table has as many
+/// rows in `to` as in `from`.
+pub async fn copy(from: &Pool<Any>, from_url: &str, to: &Pool<Any>, to_url: &str) -> Result<()> {
+    // As with dump, one transaction keeps the source consistent.
+    let mut source = from.begin().await?;
+    let mut target = to.begin().await?;
+    ensure_all_tables_known(&mut source, from_url).await?;
+    ensure_empty(&mut target).await?;
+
+    for (table, query, to_record) in TABLES {
+        if *table == "sync_changes" {
+            replace_sync_changes(&mut target).await?;
+        }
+
+        let mut count = 0;
+        let mut rows = sqlx::query(query).fetch(&mut source);
+        while let Some(row) = rows.try_next().await? {
+            insert_record(&mut target, to_record(row)?)
+                .await
+                .with_context(|| format!("failed to copy a row of {}", table))?;
+            count += 1;
+        }
+
+        info!("Copied {} rows of {}", count, table);
+    }
+
+    for (table, _, _) in TABLES {
+        let copied = count_rows(&mut target, table).await?;
+        let original = count_rows(&mut source, table).await?;
+        if copied != original {
+            return Err(format_err!(
+                "{} has {} rows after copying, but {} in the source",
+                table,
+                copied,
+                original
+            ));
+        }
+    }
+
+    reset_sequences(&mut target, to_url).await?;
+    target.commit().await?;
+    source.commit().await?;
+
+    Ok(())
+}
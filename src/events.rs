@@ -0,0 +1,23 @@
+//! Tells interested clients about entries as the fetcher adds them.
+
+use tokio::sync::broadcast;
+
+/// How many events a slow listener can fall behind by before it misses
+/// some and has to catch up by listing unread entries again.
+const CAPACITY: usize = 1024;
+
+/// An entry the fetcher just added.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewEntry {
+    pub id: i64,
+    pub feed_id: i64,
+    pub feed_url: String,
+    pub url: String,
+    pub title: String,
+}
+
+/// Returns the sender the fetcher publishes new entries on. Listeners call
+/// `subscribe` on it.
+pub fn channel() -> broadcast::Sender<NewEntry> {
+    broadcast::channel(CAPACITY).0
+}
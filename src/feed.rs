@@ -0,0 +1,571 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use anyhow::{format_err, Error, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use url::Url;
+
+use crate::fetch::{MimeType, Page, Status};
+
+/// How the fetcher turns a subscribed page into entries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeedKind {
+    /// Entries are the page's dated links.
+    Gemfeed,
+    /// An arbitrary page that generates an entry whenever it changes.
+    Page,
+}
+
+impl FeedKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FeedKind::Gemfeed => "gemfeed",
+            FeedKind::Page => "page",
+        }
+    }
+}
+
+impl FromStr for FeedKind {
+    type Err = Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        match kind {
+            "gemfeed" => Ok(FeedKind::Gemfeed),
+            "page" => Ok(FeedKind::Page),
+            _ => Err(format_err!("unknown feed kind \"{}\"", kind)),
+        }
+    }
+}
+
+/// How timestamps are stored in the database. Stored as text, they sort
+/// chronologically on every backend.
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug)]
+pub struct Entry {
+    pub published_at: NaiveDateTime,
+    pub title: String,
+    pub url: String,
+    /// What the feed says about the entry: for gemfeeds, the text right
+    /// after its link, and for Atom, its summary.
+    pub summary: Option<String>,
+    /// The Atom entry's ID, which stays the same when its link changes.
+    pub guid: Option<String>,
+}
+
+/// What identifies an entry, along with its publication time, when a feed
+/// moves: its URL's path and query. Moving to another host or following a
+/// redirect keeps them, so read state can follow the entry.
+pub fn entry_key(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        },
+        Err(_) => url.to_string(),
+    }
+}
+
+/// A feed as described by the Gemini companion specification
+/// "Subscribing to Gemini pages" (gemfeed).
+#[derive(Debug)]
+pub struct Feed {
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub entries: Vec<Entry>,
+    /// Link lines that weren't taken as entries, for finding out why a
+    /// feed's entries are missing.
+    pub skipped: Vec<SkippedLine>,
+}
+
+#[derive(Debug)]
+pub struct SkippedLine {
+    pub line: String,
+    pub reason: String,
+}
+
+/// Date formats gemlogs use besides the specification's YYYY-MM-DD, all
+/// ten characters long.
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d"];
+
+/// Parses a date in any of `DATE_FORMATS` as midnight UTC.
+fn parse_date(date: &str) -> Option<NaiveDateTime> {
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(date, format).ok())
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+}
+
+/// Parses the timestamp at the start of an entry's link label, returning it
+/// along with the remainder of the label.
+///
+/// Full RFC 3339 timestamps are used when present; plain dates are treated
+/// as midnight UTC.
+fn parse_timestamp(label: &str) -> Option<(NaiveDateTime, &str)> {
+    let token = label.split(char::is_whitespace).next()?;
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(token) {
+        return Some((timestamp.naive_utc(), &label[token.len()..]));
+    }
+
+    let date = parse_date(label.get(..10)?)?;
+
+    Some((date, &label[10..]))
+}
+
+/// Parses a date at the end of a link label, as in "Title (2021/03/01)",
+/// returning it along with the label before it.
+fn parse_trailing_date(label: &str) -> Option<(NaiveDateTime, &str)> {
+    let label = label.trim_end_matches(|c: char| c.is_whitespace() || c == ')' || c == ']');
+    let start = label.len().checked_sub(10)?;
+    let date = parse_date(label.get(start..)?)?;
+
+    // The date has to be a word of its own.
+    let rest = &label[..start];
+    match rest.chars().last() {
+        Some(c) if c.is_whitespace() || c == '(' || c == '[' => {
+            Some((date, rest.trim_end_matches(['(', '['])))
+        }
+        _ => None,
+    }
+}
+
+/// Whether a gemtext line is plain text, or a quote or list item, rather
+/// than blank, a link, a heading or a preformatting toggle.
+fn is_summary_line(line: &str) -> bool {
+    !line.trim().is_empty()
+        && !line.starts_with("=>")
+        && !line.starts_with('#')
+        && !line.starts_with("```")
+}
+
+/// The text of a heading line of the given level. As in the specification,
+/// the space after the `#`s is optional.
+fn heading(line: &str, level: usize) -> Option<&str> {
+    let text = line.trim_start_matches('#');
+    if line.len() - text.len() != level {
+        return None;
+    }
+
+    Some(text.trim()).filter(|text| !text.is_empty())
+}
+
+/// What separates an entry's date from its title.
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || c == '-' || c == ':'
+}
+
+impl FromStr for Entry {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let line = line
+            .strip_prefix("=>")
+            .ok_or_else(|| format_err!("not a link line"))?
+            .trim_start();
+
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let url = parts
+            .next()
+            .filter(|url| !url.is_empty())
+            .ok_or_else(|| format_err!("link line has no URL"))?;
+        let label = parts
+            .next()
+            .ok_or_else(|| format_err!("link line has no label"))?
+            .trim_start();
+
+        // Entries are links whose label starts with a date, or failing
+        // that, ends with one.
+        let (published_at, title) = if let Some((published_at, rest)) = parse_timestamp(label) {
+            (
+                published_at,
+                rest.trim_start_matches(is_separator).trim_end(),
+            )
+        } else if let Some((published_at, rest)) = parse_trailing_date(label) {
+            (
+                published_at,
+                rest.trim_end_matches(is_separator).trim_start(),
+            )
+        } else {
+            return Err(format_err!("link label has no date"));
+        };
+
+        // Entries with only a date are titled with it.
+        let title = if title.is_empty() {
+            published_at.format("%Y-%m-%d").to_string()
+        } else {
+            title.to_string()
+        };
+
+        Ok(Entry {
+            published_at,
+            title,
+            url: url.to_string(),
+            summary: None,
+            guid: None,
+        })
+    }
+}
+
+impl Feed {
+    /// Parses a gemfeed, resolving entry links against `base`, the URL the
+    /// feed was fetched from.
+    pub fn parse(body: &str, base: &Url) -> Self {
+        let mut title = None;
+        let mut subtitle = None;
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut skipped = Vec::new();
+
+        // The subtitle is a level 2 heading immediately after the title,
+        // ignoring blank lines.
+        let mut expecting_subtitle = false;
+        // Text lines right after an entry's link, up to the next blank
+        // line, are its summary.
+        let mut summarizing: Option<usize> = None;
+        // Lines between preformatting toggles are never entries.
+        let mut preformatted = false;
+
+        for line in body.lines() {
+            if line.starts_with("```") {
+                preformatted = !preformatted;
+                summarizing = None;
+                continue;
+            }
+            if preformatted {
+                continue;
+            }
+
+            if title.is_none() {
+                if let Some(text) = heading(line, 1) {
+                    title = Some(text.to_string());
+                    expecting_subtitle = true;
+                    continue;
+                }
+            }
+
+            if expecting_subtitle && !line.trim().is_empty() {
+                expecting_subtitle = false;
+                if let Some(text) = heading(line, 2) {
+                    subtitle = Some(text.to_string());
+                    continue;
+                }
+            }
+
+            if let Some(index) = summarizing {
+                if is_summary_line(line) {
+                    let text = line.trim_start_matches(['>', '*']).trim();
+                    let summary = entries[index].summary.get_or_insert_with(String::new);
+                    if !summary.is_empty() {
+                        summary.push(' ');
+                    }
+                    summary.push_str(text);
+                    continue;
+                }
+                summarizing = None;
+            }
+
+            if !line.starts_with("=>") {
+                continue;
+            }
+            let mut skip = |reason: String| {
+                skipped.push(SkippedLine {
+                    line: line.to_string(),
+                    reason,
+                })
+            };
+            match line.parse::<Entry>() {
+                Ok(mut entry) => match base.join(&entry.url) {
+                    Ok(url) => {
+                        entry.url = url.to_string();
+                        entries.push(entry);
+                        summarizing = Some(entries.len() - 1);
+                    }
+                    Err(e) => skip(format!("invalid URL: {}", e)),
+                },
+                Err(e) => skip(e.to_string()),
+            }
+        }
+
+        Self {
+            title,
+            subtitle,
+            entries,
+            skipped,
+        }
+    }
+
+    /// Parses an Atom feed, resolving entry links against `base`.
+    pub fn parse_atom(body: &str, base: &Url) -> Result<Self> {
+        let feed = atom_syndication::Feed::from_str(body)?;
+
+        let entries = feed
+            .entries()
+            .iter()
+            .filter_map(|entry| {
+                let link = entry
+                    .links()
+                    .iter()
+                    .find(|link| link.rel() == "alternate")
+                    .or_else(|| entry.links().first())?;
+                let url = base.join(link.href()).ok()?;
+                let published_at = entry.published().unwrap_or_else(|| entry.updated());
+
+                Some(Entry {
+                    published_at: published_at.naive_utc(),
+                    title: entry.title().to_string(),
+                    url: url.to_string(),
+                    summary: entry.summary().map(|summary| summary.to_string()),
+                    guid: Some(entry.id())
+                        .filter(|id| !id.is_empty())
+                        .map(str::to_string),
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            title: Some(feed.title().to_string()),
+            subtitle: feed.subtitle().map(str::to_string),
+            entries,
+            skipped: Vec::new(),
+        })
+    }
+}
+
+/// Turns a fetched page in one format into a `Feed`. Supporting another
+/// format, or a particular site's pages, means implementing this and adding
+/// it to `PARSERS`.
+pub trait FeedParser: Sync {
+    /// What `feeds.feed_format` is set to so a feed always uses this parser.
+    fn name(&self) -> &'static str;
+
+    /// Whether a page looks like it's in this parser's format.
+    fn sniff(&self, mime: &MimeType, body: &str) -> bool;
+
+    /// Parses a page, resolving links against `base`, the URL it was
+    /// fetched from.
+    fn parse(&self, body: &str, base: &Url) -> Result<Feed>;
+}
+
+struct GemfeedParser;
+
+impl FeedParser for GemfeedParser {
+    fn name(&self) -> &'static str {
+        "gemfeed"
+    }
+
+    fn sniff(&self, mime: &MimeType, _body: &str) -> bool {
+        mime.is_gemtext()
+    }
+
+    fn parse(&self, body: &str, base: &Url) -> Result<Feed> {
+        Ok(Feed::parse(body, base))
+    }
+}
+
+struct AtomParser;
+
+impl FeedParser for AtomParser {
+    fn name(&self) -> &'static str {
+        "atom"
+    }
+
+    fn sniff(&self, mime: &MimeType, body: &str) -> bool {
+        mime.is_xml() || body.trim_start().starts_with("<?xml")
+    }
+
+    fn parse(&self, body: &str, base: &Url) -> Result<Feed> {
+        Feed::parse_atom(body, base)
+    }
+}
+
+/// Every parser, in the order they're tried when sniffing a page's format.
+static PARSERS: &[&dyn FeedParser] = &[&GemfeedParser, &AtomParser];
+
+/// The parser `feeds.feed_format` names.
+pub fn parser(name: &str) -> Option<&'static dyn FeedParser> {
+    PARSERS.iter().copied().find(|parser| parser.name() == name)
+}
+
+impl Feed {
+    /// Parses a successful response with the parser named `format`, or
+    /// without one, whichever parser's format the page looks like.
+    pub fn from_page(page: Page, format: Option<&str>) -> Result<Self> {
+        if page.header.status != Status::Success {
+            return Err(format_err!(
+                "unexpected response {:?} \"{}\"",
+                page.header.status,
+                page.header.meta
+            ));
+        }
+
+        let mime = page.header.mime();
+        let body = page.body.as_deref().unwrap_or("");
+
+        let parser = match format {
+            Some(format) => {
+                parser(format).ok_or_else(|| format_err!("unknown feed format \"{}\"", format))?
+            }
+            None => PARSERS
+                .iter()
+                .copied()
+                .find(|parser| parser.sniff(&mime, body))
+                .ok_or_else(|| format_err!("unsupported MIME type \"{}\"", mime))?,
+        };
+
+        parser.parse(body, &page.url)
+    }
+}
+
+impl TryFrom<Page> for Feed {
+    type Error = Error;
+
+    fn try_from(page: Page) -> Result<Self> {
+        Feed::from_page(page, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::{Entry, Feed};
+
+    #[test]
+    fn entries_parse_from_link_lines() {
+        let cases = [
+            ("=> a.gmi 2021-03-01 Title", "2021-03-01 00:00:00", "Title"),
+            ("=>a.gmi 2021-03-01 - Title", "2021-03-01 00:00:00", "Title"),
+            ("=> a.gmi 2021/03/01: Title", "2021-03-01 00:00:00", "Title"),
+            ("=> a.gmi 2021.03.01 Title", "2021-03-01 00:00:00", "Title"),
+            ("=> a.gmi 2021-03-01", "2021-03-01 00:00:00", "2021-03-01"),
+            (
+                "=> a.gmi 2021-03-01T12:30:00+02:00 Title",
+                "2021-03-01 10:30:00",
+                "Title",
+            ),
+            (
+                "=> a.gmi Title (2021/03/01)",
+                "2021-03-01 00:00:00",
+                "Title",
+            ),
+            (
+                "=> a.gmi Title [2021.03.01]",
+                "2021-03-01 00:00:00",
+                "Title",
+            ),
+            (
+                "=> a.gmi Title - 2021-03-01",
+                "2021-03-01 00:00:00",
+                "Title",
+            ),
+        ];
+
+        for (line, published_at, title) in &cases {
+            let entry: Entry = line.parse().unwrap_or_else(|e| panic!("{}: {}", line, e));
+            assert_eq!(entry.published_at.to_string(), *published_at, "{}", line);
+            assert_eq!(entry.title, *title, "{}", line);
+            assert_eq!(entry.url, "a.gmi", "{}", line);
+        }
+    }
+
+    #[test]
+    fn other_lines_are_not_entries() {
+        let cases = [
+            ("# 2021-03-01 Heading", "not a link line"),
+            ("=>", "link line has no URL"),
+            ("=> a.gmi", "link line has no label"),
+            ("=> a.gmi About", "link label has no date"),
+            ("=> a.gmi 2021-13-01 Title", "link label has no date"),
+            ("=> a.gmi Title2021-03-01", "link label has no date"),
+        ];
+
+        for (line, reason) in &cases {
+            match line.parse::<Entry>() {
+                Ok(entry) => panic!("{} parsed as {:?}", line, entry),
+                Err(e) => assert_eq!(e.to_string(), *reason, "{}", line),
+            }
+        }
+    }
+
+    #[test]
+    fn skipped_link_lines_are_reported() {
+        let feed = parse(
+            "=> about.gmi About\n\
+             => first.gmi 2021-03-01 First\n\
+             => http://[::1 2021-03-02 Broken\n\
+             Not a link.\n",
+        );
+
+        let skipped: Vec<_> = feed
+            .skipped
+            .iter()
+            .map(|skipped| (skipped.line.as_str(), skipped.reason.as_str()))
+            .collect();
+        assert_eq!(feed.entries.len(), 1);
+        assert_eq!(skipped.len(), 2);
+        assert_eq!(skipped[0], ("=> about.gmi About", "link label has no date"));
+        assert_eq!(skipped[1].0, "=> http://[::1 2021-03-02 Broken");
+        assert!(skipped[1].1.starts_with("invalid URL"), "{}", skipped[1].1);
+    }
+
+    fn parse(body: &str) -> Feed {
+        Feed::parse(body, &Url::parse("gemini://example.com/gemlog/").unwrap())
+    }
+
+    fn summaries(feed: &Feed) -> Vec<Option<&str>> {
+        feed.entries
+            .iter()
+            .map(|entry| entry.summary.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn summaries_are_the_text_right_after_an_entry() {
+        let feed = parse(
+            "# Gemlog\n\
+             => first.gmi 2021-03-01 First\n\
+             The first post.\n\
+             > Quoted,\n\
+             * and listed.\n\
+             \n\
+             Not part of it.\n\
+             => second.gmi 2021-03-02 Second\n",
+        );
+
+        assert_eq!(
+            summaries(&feed),
+            vec![Some("The first post. Quoted, and listed."), None]
+        );
+    }
+
+    #[test]
+    fn summaries_end_at_links_headings_and_preformatting() {
+        let feed = parse(
+            "=> first.gmi 2021-03-01 First\n\
+             => second.gmi 2021-03-02 Second\n\
+             Second's summary.\n\
+             ## Older\n\
+             => third.gmi 2021-03-03 Third\n\
+             ```\n\
+             => fourth.gmi 2021-03-04 Preformatted\n\
+             ```\n\
+             After the block.\n",
+        );
+
+        assert_eq!(
+            summaries(&feed),
+            vec![None, Some("Second's summary."), None]
+        );
+    }
+
+    #[test]
+    fn skipped_links_have_no_summary() {
+        let feed = parse(
+            "=> about.gmi About\n\
+             Not a summary.\n\
+             => first.gmi 2021-03-01 First\n",
+        );
+
+        assert_eq!(summaries(&feed), vec![None]);
+        assert_eq!(feed.skipped.len(), 1);
+    }
+}
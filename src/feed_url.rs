@@ -0,0 +1,368 @@
+//! What makes two feed URLs the same feed, so that spelling a URL
+//! differently doesn't add a second feed row that's fetched separately.
+//!
+//! URLs are canonicalized before they're stored: the scheme and host are
+//! lowercased, Gemini's default port and any fragment are dropped, an empty
+//! path becomes `/`, and percent-encoding is normalized. Whether a path ends
+//! in a slash can change what a server returns, so it's left as given, but
+//! a feed differing from an existing one only by its trailing slash is
+//! treated as that feed: both have the same `url_key`, which is unique.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use sqlx::{Any, Row, Transaction};
+use tracing::info;
+use url::Url;
+
+const GEMINI_DEFAULT_PORT: u16 = 1965;
+
+/// Decodes percent-encoded unreserved characters, which never need
+/// escaping, and uppercases the hex digits of the escapes left.
+fn normalize_percent_encoding(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut normalized = String::with_capacity(path.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                normalized.push(byte as char);
+                i += 3;
+            }
+            Some(byte) => {
+                normalized.push_str(&format!("%{:02X}", byte));
+                i += 3;
+            }
+            // Parsed URLs are ASCII, anything else being percent-encoded.
+            None => {
+                normalized.push(bytes[i] as char);
+                i += 1;
+            }
+        }
+    }
+
+    normalized
+}
+
+/// The canonical spelling of a feed URL. Anything that doesn't parse as a
+/// URL is returned as is, and fails when it's fetched.
+pub fn canonicalize(url: &str) -> String {
+    let mut parsed = match Url::parse(url.trim()) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_string(),
+    };
+
+    parsed.set_fragment(None);
+
+    // Hosts of schemes the URL standard doesn't know, like gemini, keep
+    // their case when parsed.
+    if let Some(host) = parsed.host_str() {
+        let lowercase = host.to_ascii_lowercase();
+        if lowercase != host {
+            let _ = parsed.set_host(Some(&lowercase));
+        }
+    }
+
+    if parsed.scheme() == "gemini" && parsed.port() == Some(GEMINI_DEFAULT_PORT) {
+        let _ = parsed.set_port(None);
+    }
+
+    let path = match parsed.path() {
+        "" => "/".to_string(),
+        path => normalize_percent_encoding(path),
+    };
+    parsed.set_path(&path);
+
+    parsed.to_string()
+}
+
+/// The same URL with its trailing slash added or removed, for a path other
+/// than the root.
+pub fn slash_variant(url: &str) -> Option<String> {
+    let parsed = Url::parse(url).ok()?;
+    let path = parsed.path();
+    if matches!(path, "" | "/") {
+        return None;
+    }
+
+    let mut variant = parsed.clone();
+    match path.strip_suffix('/') {
+        Some(path) => variant.set_path(path),
+        None => variant.set_path(&format!("{}/", path)),
+    }
+
+    Some(variant.to_string())
+}
+
+/// What identifies a feed, stored in `feeds.url_key`: its canonical URL,
+/// without a trailing slash unless the path is the root.
+pub fn url_key(url: &str) -> String {
+    let canonical = canonicalize(url);
+    match canonical.strip_suffix('/') {
+        Some(stripped) if slash_variant(&canonical).is_some() => stripped.to_string(),
+        _ => canonical,
+    }
+}
+
+/// Folds feed `duplicate` into `feed`: subscriptions, group feeds, pending
+/// views, fetch attempts and sync changes move over, entries `feed` doesn't
+/// have move with their read state, read state of entries both have is
+/// copied, and `feed` keeps whichever newest entry was seen last, before
+/// `duplicate` is deleted.
+async fn merge_feed(tx: &mut Transaction<'_, Any>, feed: i64, duplicate: i64) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO subscriptions
+            (user_id, feed_id, created_at, backlog_from, notify, snoozed_until, max_unread)
+        SELECT user_id, $1, created_at, backlog_from, notify, snoozed_until, max_unread
+        FROM subscriptions
+        WHERE feed_id = $2
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(feed)
+    .bind(duplicate)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO group_feeds (group_id, feed_id)
+        SELECT group_id, $1 FROM group_feeds WHERE feed_id = $2
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(feed)
+    .bind(duplicate)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE feed_entries SET feed_id = $1
+        WHERE feed_id = $2
+            AND NOT EXISTS (
+                SELECT 1 FROM feed_entries AS existing
+                WHERE existing.feed_id = $1
+                    AND existing.published_at = feed_entries.published_at
+                    AND (
+                        existing.url = feed_entries.url
+                        OR existing.entry_key = feed_entries.entry_key
+                    )
+            )
+        "#,
+    )
+    .bind(feed)
+    .bind(duplicate)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO views (user_id, feed_entry_id, viewed_at)
+        SELECT views.user_id, kept.id, views.viewed_at
+        FROM views
+        INNER JOIN feed_entries AS merged ON views.feed_entry_id = merged.id
+        INNER JOIN feed_entries AS kept
+            ON kept.feed_id = $1
+            AND kept.published_at = merged.published_at
+            AND (kept.url = merged.url OR kept.entry_key = merged.entry_key)
+        WHERE merged.feed_id = $2
+        ON CONFLICT (user_id, feed_entry_id) DO NOTHING
+        "#,
+    )
+    .bind(feed)
+    .bind(duplicate)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO pending_views (user_id, feed_id, entry_url, viewed_at)
+        SELECT user_id, $1, entry_url, viewed_at FROM pending_views WHERE feed_id = $2
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(feed)
+    .bind(duplicate)
+    .execute(&mut *tx)
+    .await?;
+
+    // Nothing else refers to these by feed, so they aren't deleted along
+    // with it.
+    sqlx::query("DELETE FROM pending_views WHERE feed_id = $1")
+        .bind(duplicate)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE fetch_log SET feed_id = $1 WHERE feed_id = $2")
+        .bind(feed)
+        .bind(duplicate)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE sync_changes SET feed_id = $1 WHERE feed_id = $2")
+        .bind(feed)
+        .bind(duplicate)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE feeds SET
+            last_seen_published_at = (
+                SELECT last_seen_published_at FROM feeds AS duplicate WHERE id = $2
+            ),
+            last_seen_url = (SELECT last_seen_url FROM feeds AS duplicate WHERE id = $2)
+        WHERE id = $1
+            AND EXISTS (
+                SELECT 1 FROM feeds AS duplicate
+                WHERE duplicate.id = $2
+                    AND duplicate.last_seen_published_at
+                        > COALESCE(feeds.last_seen_published_at, '')
+            )
+        "#,
+    )
+    .bind(feed)
+    .bind(duplicate)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM feeds WHERE id = $1")
+        .bind(duplicate)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Canonicalizes the URLs of feeds added before they were canonicalized,
+/// merging feeds that turn out to be the same into the oldest of them, and
+/// fills in every feed's `url_key`.
+pub async fn merge_duplicate_feeds(tx: &mut Transaction<'_, Any>) -> Result<()> {
+    let feeds = sqlx::query("SELECT id, url FROM feeds ORDER BY id")
+        .fetch_all(&mut *tx)
+        .await?;
+
+    // Feeds by their key, oldest first.
+    let mut same: HashMap<String, Vec<(i64, String)>> = HashMap::new();
+    for feed in feeds {
+        let id: i64 = feed.try_get("id")?;
+        let url: String = feed.try_get("url")?;
+        same.entry(url_key(&url)).or_default().push((id, url));
+    }
+
+    let mut merged = 0;
+    for (key, feeds) in &same {
+        let (feed, url) = &feeds[0];
+        for (duplicate, duplicate_url) in &feeds[1..] {
+            info!(
+                "Merging feed {} \"{}\" into feed {} \"{}\"",
+                duplicate, duplicate_url, feed, url
+            );
+            merge_feed(tx, *feed, *duplicate).await?;
+            merged += 1;
+        }
+
+        sqlx::query("UPDATE feeds SET url = $1, url_key = $2 WHERE id = $3")
+            .bind(canonicalize(url))
+            .bind(key)
+            .bind(feed)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    if merged > 0 {
+        info!("Merged {} duplicate feeds", merged);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonicalize, slash_variant, url_key};
+
+    #[test]
+    fn canonical_urls_ignore_case_default_ports_and_fragments() {
+        let cases = [
+            (
+                "GEMINI://Example.COM/Gemlog/",
+                "gemini://example.com/Gemlog/",
+            ),
+            (
+                "gemini://example.com:1965/gemlog/",
+                "gemini://example.com/gemlog/",
+            ),
+            (
+                "gemini://example.com:1966/gemlog/",
+                "gemini://example.com:1966/gemlog/",
+            ),
+            (
+                "gemini://example.com/gemlog/#top",
+                "gemini://example.com/gemlog/",
+            ),
+            ("gemini://example.com", "gemini://example.com/"),
+            (" gemini://example.com/ ", "gemini://example.com/"),
+            ("not a url", "not a url"),
+        ];
+
+        for (url, canonical) in &cases {
+            assert_eq!(canonicalize(url), *canonical, "{}", url);
+        }
+    }
+
+    #[test]
+    fn percent_encoding_is_normalized() {
+        let cases = [
+            (
+                "gemini://example.com/%7euser/",
+                "gemini://example.com/~user/",
+            ),
+            ("gemini://example.com/%41%2d%5F", "gemini://example.com/A-_"),
+            ("gemini://example.com/a%2fb", "gemini://example.com/a%2Fb"),
+            ("gemini://example.com/a%20b", "gemini://example.com/a%20b"),
+            // Not escapes, so left alone.
+            ("gemini://example.com/%+f", "gemini://example.com/%+f"),
+            ("gemini://example.com/%zz%4", "gemini://example.com/%zz%4"),
+            ("gemini://example.com/100%", "gemini://example.com/100%"),
+        ];
+
+        for (url, canonical) in &cases {
+            assert_eq!(canonicalize(url), *canonical, "{}", url);
+        }
+    }
+
+    #[test]
+    fn trailing_slashes_share_a_key() {
+        assert_eq!(
+            url_key("gemini://example.com/gemlog/"),
+            "gemini://example.com/gemlog"
+        );
+        assert_eq!(
+            url_key("GEMINI://example.com:1965/gemlog"),
+            "gemini://example.com/gemlog"
+        );
+        assert_eq!(url_key("gemini://example.com"), "gemini://example.com/");
+        assert_eq!(url_key("gemini://example.com/"), "gemini://example.com/");
+    }
+
+    #[test]
+    fn slash_variants_toggle_the_trailing_slash() {
+        assert_eq!(
+            slash_variant("gemini://example.com/gemlog/").as_deref(),
+            Some("gemini://example.com/gemlog")
+        );
+        assert_eq!(
+            slash_variant("gemini://example.com/gemlog?page=2").as_deref(),
+            Some("gemini://example.com/gemlog/?page=2")
+        );
+        assert_eq!(slash_variant("gemini://example.com/"), None);
+        assert_eq!(slash_variant("not a url"), None);
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::{delay_until, Instant};
+
+pub use seymour_gemini::{
+    fetch, host_and_port, CertificateStore, DnsCache, FetchConfig, MemoryCertificateStore,
+    MimeType, Page, PinnedCertificate, Status, TlsPolicy,
+};
+
+/// Spaces out requests to the same host by at least `delay` so that we
+/// don't hammer a single capsule with requests.
+pub struct HostRateLimiter {
+    delay: Mutex<Duration>,
+    next_allowed: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostRateLimiter {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay: Mutex::new(delay),
+            next_allowed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Changes the delay for requests reserved from now on.
+    pub fn set_delay(&self, delay: Duration) {
+        *self.delay.lock().expect("host rate limiter lock poisoned") = delay;
+    }
+
+    /// Waits until a request to `host` is allowed and reserves the slot.
+    pub async fn wait(&self, host: &str) {
+        let delay = *self.delay.lock().expect("host rate limiter lock poisoned");
+        let slot = {
+            let mut next_allowed = self
+                .next_allowed
+                .lock()
+                .expect("host rate limiter lock poisoned");
+            let now = Instant::now();
+            let slot = match next_allowed.get(host) {
+                Some(next) if *next > now => *next,
+                _ => now,
+            };
+            next_allowed.insert(host.to_string(), slot + delay);
+            slot
+        };
+
+        delay_until(slot).await;
+    }
+}
@@ -0,0 +1,267 @@
+//! Serves the operations in `proto/seymour.proto` over gRPC, for clients
+//! generated from the definition. Built with the `grpc` feature.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+use futures::TryStreamExt;
+use seymour_protocol::{DEFAULT_HISTORY_LIMIT, DEFAULT_SEARCH_LIMIT};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::{error, info};
+
+use crate::api::hash_token;
+use crate::shutdown::Shutdown;
+use crate::store::Store;
+use crate::{in_timezone, Config};
+
+pub mod pb {
+    tonic::include_proto!("seymour");
+}
+
+use pb::seymour_server::{Seymour, SeymourServer};
+
+fn internal(e: anyhow::Error) -> Status {
+    error!("gRPC call failed: {:#}", e);
+    Status::internal("internal error")
+}
+
+struct Service {
+    store: Arc<dyn Store>,
+    config: Arc<Config>,
+}
+
+impl Service {
+    async fn authenticate<T>(&self, request: &Request<T>) -> Result<i64, Status> {
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        let user_id = match token {
+            Some(token) => self
+                .store
+                .user_for_token(&hash_token(token.trim()))
+                .await
+                .map_err(internal)?,
+            None => None,
+        };
+
+        user_id.ok_or_else(|| Status::unauthenticated("missing or unknown token"))
+    }
+}
+
+#[tonic::async_trait]
+impl Seymour for Service {
+    async fn list_subscriptions(
+        &self,
+        request: Request<pb::ListSubscriptionsRequest>,
+    ) -> Result<Response<pb::ListSubscriptionsResponse>, Status> {
+        let user_id = self.authenticate(&request).await?;
+
+        let unread = self
+            .store
+            .feed_unread_counts(user_id)
+            .await
+            .map_err(internal)?;
+        let subscriptions = self
+            .store
+            .subscriptions(user_id)
+            .await
+            .map_err(internal)?
+            .into_iter()
+            .map(|subscription| pb::Subscription {
+                id: subscription.feed_id,
+                url: subscription.url,
+                gone: subscription.gone,
+                failing: subscription.failing,
+                unread: unread.get(&subscription.feed_id).copied().unwrap_or(0),
+                title: subscription.title.unwrap_or_default(),
+                subtitle: subscription.subtitle.unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(Response::new(pb::ListSubscriptionsResponse {
+            subscriptions,
+        }))
+    }
+
+    async fn subscribe(
+        &self,
+        request: Request<pb::SubscribeRequest>,
+    ) -> Result<Response<pb::SubscribeResponse>, Status> {
+        let user_id = self.authenticate(&request).await?;
+
+        let request = request.into_inner();
+        let (id, url) = crate::subscribe(
+            &*self.store,
+            &self.config,
+            user_id,
+            request.url,
+            request.mark_old,
+        )
+        .await
+        .map_err(internal)?;
+
+        Ok(Response::new(pb::SubscribeResponse { id, url }))
+    }
+
+    async fn unsubscribe(
+        &self,
+        request: Request<pb::UnsubscribeRequest>,
+    ) -> Result<Response<pb::UnsubscribeResponse>, Status> {
+        let user_id = self.authenticate(&request).await?;
+        let feed_id = request.into_inner().id;
+
+        if self
+            .store
+            .unsubscribe(user_id, feed_id)
+            .await
+            .map_err(internal)?
+        {
+            Ok(Response::new(pb::UnsubscribeResponse {}))
+        } else {
+            Err(Status::not_found(format!(
+                "no subscription with feed ID {} exists",
+                feed_id
+            )))
+        }
+    }
+
+    async fn list_unread(
+        &self,
+        request: Request<pb::ListUnreadRequest>,
+    ) -> Result<Response<pb::ListUnreadResponse>, Status> {
+        let user_id = self.authenticate(&request).await?;
+
+        let entries = self
+            .store
+            .unread_entries(user_id)
+            .map_ok(|entry| pb::Entry {
+                id: entry.id,
+                feed_id: entry.feed_id,
+                feed_url: entry.feed_url,
+                url: entry.url,
+                title: entry.title,
+            })
+            .try_collect()
+            .await
+            .map_err(internal)?;
+
+        Ok(Response::new(pb::ListUnreadResponse { entries }))
+    }
+
+    async fn mark_read(
+        &self,
+        request: Request<pb::MarkReadRequest>,
+    ) -> Result<Response<pb::MarkReadResponse>, Status> {
+        let user_id = self.authenticate(&request).await?;
+        let entry_id = request.into_inner().id;
+
+        if !self
+            .store
+            .can_access_entry(user_id, entry_id)
+            .await
+            .map_err(internal)?
+        {
+            return Err(Status::not_found(format!(
+                "no entry with ID {} exists",
+                entry_id
+            )));
+        }
+
+        self.store
+            .mark_read(user_id, entry_id, Utc::now().naive_utc())
+            .await
+            .map_err(internal)?;
+
+        Ok(Response::new(pb::MarkReadResponse {}))
+    }
+
+    async fn history(
+        &self,
+        request: Request<pb::HistoryRequest>,
+    ) -> Result<Response<pb::HistoryResponse>, Status> {
+        let user_id = self.authenticate(&request).await?;
+        let limit = match request.into_inner().limit {
+            0 => DEFAULT_HISTORY_LIMIT,
+            limit => limit,
+        };
+
+        let timezone = self.store.timezone(user_id).await.map_err(internal)?;
+        let entries = self
+            .store
+            .read_history(user_id, limit)
+            .await
+            .map_err(internal)?
+            .into_iter()
+            .map(|entry| pb::HistoryEntry {
+                entry: Some(pb::Entry {
+                    id: entry.id,
+                    feed_id: entry.feed_id,
+                    feed_url: entry.feed_url,
+                    url: entry.url,
+                    title: entry.title,
+                }),
+                viewed_at: in_timezone(entry.viewed_at, timezone)
+                    .to_rfc3339_opts(SecondsFormat::Secs, true),
+            })
+            .collect();
+
+        Ok(Response::new(pb::HistoryResponse { entries }))
+    }
+
+    async fn search(
+        &self,
+        request: Request<pb::SearchRequest>,
+    ) -> Result<Response<pb::SearchResponse>, Status> {
+        let user_id = self.authenticate(&request).await?;
+        let request = request.into_inner();
+        if request.query.is_empty() {
+            return Err(Status::invalid_argument("query is empty"));
+        }
+        let limit = match request.limit {
+            0 => DEFAULT_SEARCH_LIMIT,
+            limit => limit,
+        };
+
+        let results = self
+            .store
+            .search_entries(user_id, &request.query, limit)
+            .await
+            .map_err(internal)?
+            .into_iter()
+            .map(|entry| pb::SearchResult {
+                entry: Some(pb::Entry {
+                    id: entry.id,
+                    feed_id: entry.feed_id,
+                    feed_url: entry.feed_url,
+                    url: entry.url,
+                    title: entry.title,
+                }),
+                read: entry.read,
+            })
+            .collect();
+
+        Ok(Response::new(pb::SearchResponse { results }))
+    }
+}
+
+/// Serves gRPC on `address` until shutdown is requested.
+pub(crate) async fn serve(
+    address: SocketAddr,
+    store: Arc<dyn Store>,
+    config: Arc<Config>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    info!("Serving gRPC on: {}", address);
+    Server::builder()
+        .add_service(SeymourServer::new(Service { store, config }))
+        .serve_with_shutdown(address, async move { shutdown.requested().await })
+        .await?;
+
+    Ok(())
+}
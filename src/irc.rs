@@ -0,0 +1,143 @@
+//! Announces new entries in an IRC channel, for users who'd rather hear
+//! about them in chat than poll a reader. Users opt in per feed with
+//! `NOTIFY <feed id> on`, and are mentioned by username.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{format_err, Result};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::{self, RecvError};
+use tokio::time::delay_for;
+use tracing::{error, info, warn};
+
+use crate::events::NewEntry;
+use crate::shutdown::Shutdown;
+use crate::store::Store;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// IRC lines are at most 512 bytes, CRLF included.
+const MAX_LINE_LENGTH: usize = 510;
+
+#[derive(Debug, Clone)]
+pub struct IrcConfig {
+    /// `host:port` of the server. Only plaintext connections are supported.
+    pub server: String,
+    pub nick: String,
+    pub channel: String,
+}
+
+async fn send(writer: &mut WriteHalf<TcpStream>, line: &str) -> Result<()> {
+    let mut end = line.len().min(MAX_LINE_LENGTH);
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    writer
+        .write_all(format!("{}\r\n", &line[..end]).as_bytes())
+        .await?;
+
+    Ok(())
+}
+
+async fn announce(
+    writer: &mut WriteHalf<TcpStream>,
+    config: &IrcConfig,
+    store: &dyn Store,
+    entry: NewEntry,
+) -> Result<()> {
+    let usernames = store.notified_usernames(entry.feed_id).await?;
+    if usernames.is_empty() {
+        return Ok(());
+    }
+
+    let title = entry.title.replace(&['\r', '\n'][..], " ");
+    send(
+        writer,
+        &format!(
+            "PRIVMSG {} :{}: {} {}",
+            config.channel,
+            usernames.join(", "),
+            title,
+            entry.url
+        ),
+    )
+    .await
+}
+
+/// Stays connected and announces entries until shutdown, returning an
+/// error when the connection is lost.
+async fn run_session(
+    config: &IrcConfig,
+    store: &dyn Store,
+    new_entries: &mut broadcast::Receiver<NewEntry>,
+    shutdown: &mut Shutdown,
+) -> Result<()> {
+    let stream = TcpStream::connect(&config.server).await?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    send(&mut writer, &format!("NICK {}", config.nick)).await?;
+    send(&mut writer, &format!("USER {} 0 * :seymour", config.nick)).await?;
+
+    let mut joined = false;
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let line = line?.ok_or_else(|| format_err!("server closed the connection"))?;
+                if let Some(token) = line.strip_prefix("PING ") {
+                    send(&mut writer, &format!("PONG {}", token)).await?;
+                    continue;
+                }
+
+                match line.split(' ').nth(1) {
+                    // Welcome, so registration is done.
+                    Some("001") if !joined => {
+                        send(&mut writer, &format!("JOIN {}", config.channel)).await?;
+                        info!("Joined {} on {}", config.channel, config.server);
+                        joined = true;
+                    }
+                    Some("433") => return Err(format_err!("nick \"{}\" is in use", config.nick)),
+                    _ => {}
+                }
+            }
+            entry = new_entries.recv() => match entry {
+                Ok(entry) if joined => {
+                    if let Err(e) = announce(&mut writer, config, store, entry).await {
+                        error!("failed to announce entry: {:#}", e);
+                    }
+                }
+                // Entries found before joining aren't worth announcing late.
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => warn!("Missed announcing {} entries", missed),
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = shutdown.requested() => {
+                send(&mut writer, "QUIT :shutting down").await?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Announces entries until shutdown is requested, reconnecting whenever
+/// the connection is lost.
+pub async fn serve(
+    config: IrcConfig,
+    store: Arc<dyn Store>,
+    mut new_entries: broadcast::Receiver<NewEntry>,
+    mut shutdown: Shutdown,
+) {
+    while !shutdown.is_requested() {
+        match run_session(&config, &*store, &mut new_entries, &mut shutdown).await {
+            Ok(()) => return,
+            Err(e) => error!("IRC connection to {} failed: {:#}", config.server, e),
+        }
+
+        tokio::select! {
+            _ = delay_for(RECONNECT_DELAY) => {}
+            _ = shutdown.requested() => return,
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//! Decides where log lines go: stderr by default, or with `LOG_FILE` set, a
+//! file that's rotated by time, keeping the last few. `LOG_TARGET` sends
+//! them to the systemd journal or syslog instead.
+
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{format_err, Context, Result};
+use syslog::{Facility, Formatter3164, Logger, LoggerBackend};
+use tokio::time::interval;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{warn, Event, Level, Subscriber};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context as LayerContext, Layer, Layered, SubscriberExt};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter};
+
+use crate::reload::SetLogLevel;
+use crate::{env_var_or, DEFAULT_LOG_LEVEL};
+
+/// How often old log files are looked for.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How log lines are written.
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format_err!("expected \"text\" or \"json\"")),
+        }
+    }
+}
+
+/// Where log lines are sent.
+enum LogTarget {
+    /// Stderr, or `LOG_FILE` when it's set.
+    Stderr,
+    Journald,
+    Syslog,
+}
+
+impl FromStr for LogTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stderr" => Ok(LogTarget::Stderr),
+            "journald" => Ok(LogTarget::Journald),
+            "syslog" => Ok(LogTarget::Syslog),
+            _ => Err(format_err!(
+                "expected \"stderr\", \"journald\" or \"syslog\""
+            )),
+        }
+    }
+}
+
+/// How often `LOG_FILE` is rotated. tracing-appender only rotates by time,
+/// so there's no size limit.
+struct LogRotation(Rotation);
+
+impl FromStr for LogRotation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "minutely" => Ok(LogRotation(Rotation::MINUTELY)),
+            "hourly" => Ok(LogRotation(Rotation::HOURLY)),
+            "daily" => Ok(LogRotation(Rotation::DAILY)),
+            "never" => Ok(LogRotation(Rotation::NEVER)),
+            _ => Err(format_err!(
+                "expected \"minutely\", \"hourly\", \"daily\" or \"never\""
+            )),
+        }
+    }
+}
+
+/// Where log lines go.
+#[derive(Clone)]
+enum LogWriter {
+    Stderr,
+    /// Written to from a background thread, so logging never waits on disk.
+    File(NonBlocking),
+}
+
+impl MakeWriter for LogWriter {
+    type Writer = Box<dyn Write>;
+
+    fn make_writer(&self) -> Self::Writer {
+        match self {
+            LogWriter::Stderr => Box::new(io::stderr()),
+            LogWriter::File(writer) => Box::new(writer.clone()),
+        }
+    }
+}
+
+/// The log file as set up from `LOG_FILE`.
+struct LogFile {
+    writer: NonBlocking,
+    guard: WorkerGuard,
+    directory: PathBuf,
+    prefix: String,
+    keep: usize,
+}
+
+impl LogFile {
+    fn open(path: &Path) -> Result<Self> {
+        let rotation: LogRotation = env_var_or("LOG_FILE_ROTATION", LogRotation(Rotation::DAILY))?;
+        let keep = env_var_or("LOG_FILE_KEEP", 7)?;
+
+        let directory = match path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let prefix = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format_err!("invalid $LOG_FILE \"{}\"", path.display()))?
+            .to_string();
+        fs::create_dir_all(&directory)
+            .with_context(|| format!("failed to create {}", directory.display()))?;
+
+        let appender = RollingFileAppender::new(rotation.0, &directory, &prefix);
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+
+        Ok(Self {
+            writer,
+            guard,
+            directory,
+            prefix,
+            keep,
+        })
+    }
+}
+
+/// Deletes all but the newest `keep` files rotated out of the log file, now
+/// and every so often, unless `keep` is 0.
+fn prune_periodically(directory: PathBuf, prefix: String, keep: usize) {
+    if keep == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut timer = interval(PRUNE_INTERVAL);
+        loop {
+            timer.tick().await;
+            if let Err(e) = prune(&directory, &prefix, keep) {
+                warn!("failed to delete old log files: {}", e);
+            }
+        }
+    });
+}
+
+fn prune(directory: &Path, prefix: &str, keep: usize) -> io::Result<()> {
+    // Rotated files are named after the prefix and the time they start,
+    // which sorts oldest first.
+    let rotated = format!("{}.", prefix);
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        if entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(&rotated))
+        {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+
+    let excess = files.len().saturating_sub(keep);
+    for file in &files[..excess] {
+        fs::remove_file(file)?;
+    }
+
+    Ok(())
+}
+
+/// Fields of events and the spans they happen in, formatted as
+/// ` key=value`, with the message kept apart.
+#[derive(Default)]
+struct SyslogFields {
+    message: String,
+    fields: String,
+}
+
+impl Visit for SyslogFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else if !field.name().starts_with("log.") {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else if !field.name().starts_with("log.") {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// A span's formatted fields, kept in its extensions.
+struct SpanFields(String);
+
+/// Sends events to the local syslog daemon, followed by the fields of the
+/// spans they happened in, like the client's address and username or the
+/// feed being fetched.
+struct SyslogLayer {
+    logger: Mutex<Logger<LoggerBackend, Formatter3164>>,
+}
+
+impl SyslogLayer {
+    fn connect() -> Result<Self> {
+        let formatter = Formatter3164 {
+            facility: Facility::LOG_DAEMON,
+            hostname: None,
+            process: "seymour".to_string(),
+            pid: std::process::id() as i32,
+        };
+        let logger = syslog::unix(formatter)
+            .map_err(|e| format_err!("failed to connect to syslog: {}", e))?;
+
+        Ok(Self {
+            logger: Mutex::new(logger),
+        })
+    }
+}
+
+impl<S> Layer<S> for SyslogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: LayerContext<'_, S>) {
+        let mut fields = SyslogFields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields.fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: LayerContext<'_, S>) {
+        let mut fields = SyslogFields::default();
+        values.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            if let Some(SpanFields(existing)) = span.extensions_mut().get_mut::<SpanFields>() {
+                existing.push_str(&fields.fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: LayerContext<'_, S>) {
+        let mut fields = SyslogFields::default();
+        event.record(&mut fields);
+        let mut line = fields.message;
+        line.push_str(&fields.fields);
+
+        if let Some(current) = ctx.lookup_current() {
+            // The current span comes first, then the ones it's inside.
+            for span in current.scope() {
+                if let Some(SpanFields(span_fields)) = span.extensions().get::<SpanFields>() {
+                    line.push_str(span_fields);
+                }
+            }
+        }
+
+        // There's nowhere left to report failing to log.
+        let mut logger = match self.logger.lock() {
+            Ok(logger) => logger,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let _ = match *event.metadata().level() {
+            Level::ERROR => logger.err(line),
+            Level::WARN => logger.warning(line),
+            Level::INFO => logger.info(line),
+            // Debug and trace.
+            _ => logger.debug(line),
+        };
+    }
+}
+
+/// Installs `layer` as the only place log lines go, behind a filter that can
+/// be changed later.
+fn init_layer<L>(filter: EnvFilter, layer: L) -> SetLogLevel
+where
+    L: Layer<Layered<reload::Layer<EnvFilter, Registry>, Registry>> + Send + Sync + 'static,
+{
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .init();
+
+    Box::new(move |level: &str| Ok(handle.reload(EnvFilter::try_new(level)?)?))
+}
+
+/// Sends log lines to stderr, leaving stdout for subcommands' output, or to
+/// `LOG_FILE`, journald or syslog. Records from libraries that use `log`,
+/// like sqlx, are included.
+///
+/// Returns a way to change the log level later, and when logging to a file,
+/// a guard that writes out what's left when dropped.
+pub fn init() -> Result<(SetLogLevel, Option<WorkerGuard>)> {
+    let log_level = env_var_or("LOG_LEVEL", DEFAULT_LOG_LEVEL.to_string())?;
+    let filter = EnvFilter::try_new(&log_level)
+        .map_err(|e| format_err!("invalid $LOG_LEVEL \"{}\": {}", log_level, e))?;
+
+    let log_file = dotenv::var("LOG_FILE").ok().filter(|path| !path.is_empty());
+    let target = env_var_or("LOG_TARGET", LogTarget::Stderr)?;
+    if log_file.is_some() && !matches!(target, LogTarget::Stderr) {
+        return Err(format_err!(
+            "$LOG_FILE only applies when $LOG_TARGET is stderr"
+        ));
+    }
+
+    // The journal and syslog lay lines out their own way, so LOG_FORMAT
+    // doesn't apply.
+    match target {
+        LogTarget::Stderr => {}
+        LogTarget::Journald => {
+            let layer = tracing_journald::layer().context("failed to connect to journald")?;
+            return Ok((init_layer(filter, layer), None));
+        }
+        LogTarget::Syslog => return Ok((init_layer(filter, SyslogLayer::connect()?), None)),
+    }
+
+    let file = match log_file {
+        Some(path) => Some(LogFile::open(Path::new(&path))?),
+        None => None,
+    };
+    let writer = match &file {
+        Some(file) => LogWriter::File(file.writer.clone()),
+        None => LogWriter::Stderr,
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(file.is_none())
+        .with_writer(writer);
+
+    // The handle's type depends on the formatter, so each is boxed up.
+    let set_log_level: SetLogLevel = match env_var_or("LOG_FORMAT", LogFormat::Text)? {
+        LogFormat::Text => {
+            let subscriber = subscriber.with_filter_reloading();
+            let handle = subscriber.reload_handle();
+            subscriber.init();
+            Box::new(move |level: &str| Ok(handle.reload(EnvFilter::try_new(level)?)?))
+        }
+        LogFormat::Json => {
+            let subscriber = subscriber.json().with_filter_reloading();
+            let handle = subscriber.reload_handle();
+            subscriber.init();
+            Box::new(move |level: &str| Ok(handle.reload(EnvFilter::try_new(level)?)?))
+        }
+    };
+
+    let guard = file.map(|file| {
+        prune_periodically(file.directory, file.prefix, file.keep);
+        file.guard
+    });
+
+    Ok((set_log_level, guard))
+}
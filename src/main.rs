@@ -1,301 +1,695 @@
-use std::convert::TryInto;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{format_err, Context, Result};
-use env_logger::Builder;
-use futures::future::join_all;
-use futures::TryStreamExt;
-use log::{error, info, LevelFilter};
-use sqlx::sqlite::SqlitePool;
-use sqlx::{Done, Pool, Row, Sqlite};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use futures::{stream, StreamExt};
+use seymour_protocol::{ErrorCode, Response};
+use sha2::{Digest, Sha256};
+use sqlx::any::AnyPoolOptions;
+use sqlx::migrate::MigrateDatabase;
+use sqlx::{Any, Done, Executor, Pool, Row, Transaction};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::time::interval;
-
-use gemini_feed::Feed;
-use gemini_fetch::Page;
-use seymour_protocol::{Command, Response};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::time::{interval, timeout};
+use tracing::field::Empty;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use tracing_subscriber::EnvFilter;
+use url::{Host, Url};
+
+mod access;
+mod api;
+mod capsule;
+mod certificates;
+mod commands;
+mod discovery;
+mod dump;
+mod events;
+mod feed;
+mod feed_url;
+mod fetch;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod irc;
+mod logging;
+mod nntp;
+mod reload;
+mod robots;
+mod shutdown;
+mod store;
+mod user_transfer;
+mod username;
+mod wake;
+
+use access::{ConnectionCounts, CountedConnection, NetworkAccess};
+use capsule::CapsuleConfig;
+use commands::{Commands, Responses, Session};
+use discovery::{discover_feed, is_capsule_root};
+use events::NewEntry;
+use feed::{entry_key, Entry, Feed, FeedKind, TIMESTAMP_FORMAT};
+use fetch::{fetch, CertificateStore, DnsCache, FetchConfig, HostRateLimiter, Status, TlsPolicy};
+use irc::IrcConfig;
+use reload::{SetLogLevel, Tunables};
+use robots::RobotsCache;
+use shutdown::Shutdown;
+use store::{SqlStore, Store};
+use wake::Wake;
+
+/// Swaps a capsule root for the best feed found on the capsule, if any.
+async fn discover(url: String, fetch: &FetchConfig) -> String {
+    let root = match Url::parse(&url) {
+        Ok(root) if is_capsule_root(&root) => root,
+        _ => return url,
+    };
 
-enum ConnectedUser {
-    NoUser,
-    User { username: String, id: i64 },
+    match discover_feed(&root, fetch).await {
+        Ok(Some(feed_url)) => feed_url.to_string(),
+        Ok(None) => url,
+        Err(e) => {
+            error!("feed discovery for \"{}\" failed: {:#}", url, e);
+            url
+        }
+    }
 }
 
-struct Connection<'a> {
-    address: SocketAddr,
-    user: ConnectedUser,
-    pool: &'a Pool<Sqlite>,
+/// Where a subscription made now starts listing entries as unread: only the
+/// configured backlog of a feed's history, counted in whole days so undated
+/// times don't hide today's entries.
+fn backlog_from(config: &Config) -> NaiveDateTime {
+    (Utc::now() - config.subscription_backlog)
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time")
 }
 
-impl<'a> Connection<'a> {
-    fn new(address: SocketAddr, pool: &'a Pool<Sqlite>) -> Self {
-        Self {
-            address,
-            user: ConnectedUser::NoUser,
-            pool,
-        }
+/// Subscribes the user to the feed at `url`, or the one discovered there.
+/// With `mark_old`, the feed's entries so far are marked read, and only
+/// today's are listed from its first fetch. Returns the feed's ID and URL.
+async fn subscribe(
+    store: &dyn Store,
+    config: &Config,
+    user_id: i64,
+    url: String,
+    mark_old: bool,
+) -> Result<(i64, String)> {
+    let url = feed_url::canonicalize(&discover(url, &config.fetch).await);
+
+    let backlog_from = if mark_old {
+        Utc::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight is a valid time")
+    } else {
+        backlog_from(config)
+    };
+    let feed_id = store.subscribe(user_id, &url, backlog_from).await?;
+
+    if mark_old {
+        store
+            .mark_feed_read(user_id, feed_id, Utc::now().naive_utc())
+            .await?;
     }
 
-    async fn select_user(&mut self, username: String) -> Result<Vec<Response>> {
-        let id = match sqlx::query!("SELECT id FROM users WHERE username = ?1", username)
-            .fetch_one(self.pool)
-            .await
-        {
-            Ok(user) => user
-                .id
-                .ok_or_else(|| format_err!("database entry for user \"{}\" has no ID", username))?,
-            Err(_) => {
-                let mut conn = self.pool.acquire().await?;
-
-                sqlx::query!("INSERT INTO users (username) VALUES (?1)", username)
-                    .execute(&mut conn)
-                    .await?
-                    .last_insert_rowid()
-            }
-        };
+    Ok((feed_id, url))
+}
+
+/// Like `subscribe`, for every member of a group.
+async fn group_subscribe(
+    store: &dyn Store,
+    config: &Config,
+    group_id: i64,
+    url: String,
+) -> Result<(i64, String)> {
+    let url = feed_url::canonicalize(&discover(url, &config.fetch).await);
+
+    let feed_id = store
+        .group_subscribe(group_id, &url, backlog_from(config))
+        .await?;
+
+    Ok((feed_id, url))
+}
 
-        self.user = ConnectedUser::User { username, id };
+/// A line read from a client.
+enum ClientLine {
+    Line(String),
+    /// Longer than allowed. It's been skipped without being buffered.
+    TooLong,
+}
 
-        Ok(vec![Response::AckUser { id }])
+/// How much of a line that's too long is read at a time while skipping it.
+const SKIP_CHUNK_LEN: u64 = 4096;
+
+/// Reads a line of at most `max_len` bytes, not counting its line ending,
+/// never buffering much more than that of a longer one. `None` at the end
+/// of the stream.
+async fn read_line<R>(reader: &mut R, max_len: usize) -> Result<Option<ClientLine>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    // Leaves room for a "\r\n" after a line of the longest length.
+    let limit = max_len as u64 + 2;
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(limit)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.is_empty() {
+        return Ok(None);
     }
 
-    async fn subscribe(&self, url: String) -> Result<Vec<Response>> {
-        let user_id = match self.user {
-            ConnectedUser::NoUser => {
-                return Ok(vec![Response::NeedUser("must select a user".to_string())]);
+    if line.ends_with(b"\n") {
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+    } else if line.len() as u64 == limit {
+        // Hit the limit partway through, so skip to the end of the line.
+        let mut skipped = Vec::new();
+        loop {
+            skipped.clear();
+            (&mut *reader)
+                .take(SKIP_CHUNK_LEN)
+                .read_until(b'\n', &mut skipped)
+                .await?;
+            if skipped.is_empty() || skipped.ends_with(b"\n") {
+                break;
             }
-            ConnectedUser::User { id, .. } => id,
-        };
+        }
+        return Ok(Some(ClientLine::TooLong));
+    }
 
-        let feed_id = match sqlx::query!("SELECT id FROM feeds WHERE url = ?1", url)
-            .fetch_one(self.pool)
-            .await
-        {
-            Ok(feed) => feed
-                .id
-                .ok_or_else(|| format_err!("database entry for feed \"{}\" has no ID", url))?,
-            Err(_) => {
-                let mut conn = self.pool.acquire().await?;
-
-                sqlx::query!("INSERT INTO feeds (url) VALUES (?1)", url)
-                    .execute(&mut conn)
-                    .await?
-                    .last_insert_rowid()
-            }
-        };
+    if line.len() > max_len {
+        return Ok(Some(ClientLine::TooLong));
+    }
 
-        let mut conn = self.pool.acquire().await?;
-        sqlx::query!(
-            "INSERT OR IGNORE INTO subscriptions (user_id, feed_id) VALUES (?1, ?2)",
-            user_id,
-            feed_id
-        )
-        .execute(&mut conn)
-        .await?;
+    let line = String::from_utf8(line).context("command line isn't valid UTF-8")?;
+    Ok(Some(ClientLine::Line(line)))
+}
 
-        Ok(vec![Response::AckSubscribe])
-    }
+async fn handle_connection(
+    stream: TcpStream,
+    commands: &Commands,
+    store: &dyn Store,
+    config: &Config,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    let mut session = Session::new(store, config);
+
+    info!("Client connected");
 
-    async fn list_subscriptions(&self) -> Result<Vec<Response>> {
-        let user_id = match self.user {
-            ConnectedUser::NoUser => {
-                return Ok(vec![Response::NeedUser("must select a user".to_string())]);
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut responses = Responses::new(&mut writer);
+
+    let mut server_reader = BufReader::new(reader);
+    loop {
+        // Commands already being handled run to completion, but once the
+        // server is shutting down no new ones are read.
+        let line = tokio::select! {
+            line = read_line(&mut server_reader, config.max_line_length) => line?,
+            _ = shutdown.requested() => break,
+        };
+        let line = match line {
+            Some(ClientLine::Line(line)) => line,
+            Some(ClientLine::TooLong) => {
+                debug!(
+                    "Skipped a line longer than {} bytes",
+                    config.max_line_length
+                );
+                responses
+                    .send(Response::error(
+                        ErrorCode::LineTooLong,
+                        format!("line longer than {} bytes", config.max_line_length),
+                    ))
+                    .await?;
+                responses.flush().await?;
+                if config.disconnect_on_long_line {
+                    break;
+                }
+                continue;
             }
-            ConnectedUser::User { id, .. } => id,
+            None => break,
         };
 
-        let subscriptions = sqlx::query!(
-            r#"
-            SELECT subscriptions.feed_id, feeds.url
-            FROM subscriptions
-            LEFT JOIN feeds ON subscriptions.feed_id = feeds.id
-            WHERE subscriptions.user_id = ?1
-            "#,
-            user_id
-        )
-        .fetch_all(self.pool)
-        .await?;
+        let result = match line.parse() {
+            Ok(command) => commands.run(&mut session, command, &mut responses).await,
+            Err(e) => responses.send(e.into()).await,
+        };
+        if let Err(e) = result {
+            responses.fail(error_response(&e)).await?;
+        }
+        responses.flush().await?;
+    }
 
-        let mut responses = vec![Response::StartSubscriptionList];
+    info!("Client closed");
 
-        for subscription in subscriptions {
-            responses.push(Response::Subscription {
-                id: subscription.feed_id,
-                url: subscription.url,
-            });
-        }
+    Ok(())
+}
 
-        responses.push(Response::EndList);
+/// How many commands have failed waiting for a database connection since
+/// startup, reported by HEALTH.
+static DATABASE_BUSY_COUNT: AtomicI64 = AtomicI64::new(0);
 
-        Ok(responses)
+fn database_busy_count() -> i64 {
+    DATABASE_BUSY_COUNT.load(Ordering::Relaxed)
+}
+
+/// The response for a command that failed, telling an exhausted connection
+/// pool apart from other errors so clients know to retry.
+fn error_response(e: &anyhow::Error) -> Response {
+    match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::PoolTimedOut) => {
+            DATABASE_BUSY_COUNT.fetch_add(1, Ordering::Relaxed);
+            error!("Timed out waiting for a database connection");
+            Response::error(
+                ErrorCode::DatabaseBusy,
+                "no database connection available, try again later",
+            )
+        }
+        _ => Response::error(ErrorCode::Internal, e.to_string()),
     }
+}
 
-    async fn unsubscribe(&self, feed_id: i64) -> Result<Vec<Response>> {
-        let user_id = match self.user {
-            ConnectedUser::NoUser => {
-                return Ok(vec![Response::NeedUser("must select a user".to_string())]);
-            }
-            ConnectedUser::User { id, .. } => id,
-        };
+/// How long a client turned away for being over the connection limit gets
+/// to read the response before the connection is closed.
+const BUSY_RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many clients turned away may be waiting to read why at once. Past
+/// that, they're disconnected without being told.
+const MAX_REJECTING_CONNECTIONS: usize = 64;
+
+/// Tells a client the server is busy and closes the connection, in the
+/// background unless too many are already being turned away.
+fn reject_connection(mut stream: TcpStream, rejecting: &Arc<AtomicUsize>) {
+    let counted = match CountedConnection::try_count(rejecting, MAX_REJECTING_CONNECTIONS) {
+        Some(counted) => counted,
+        None => return,
+    };
 
-        let affected_rows = sqlx::query!(
-            "DELETE FROM subscriptions WHERE user_id = ?1 AND feed_id = ?2",
-            user_id,
-            feed_id
+    tokio::spawn(async move {
+        let response = Response::error(
+            ErrorCode::TooManyConnections,
+            "too many connections, try again later",
+        );
+        let _ = timeout(
+            BUSY_RESPONSE_TIMEOUT,
+            stream.write_all(format!("{}\r\n", response).as_bytes()),
         )
-        .execute(self.pool)
-        .await?
-        .rows_affected();
+        .await;
+        drop(counted);
+    });
+}
+
+struct DatabaseConfig {
+    max_connections: u32,
+    min_connections: u32,
+    /// How long SQLite waits on a locked database before giving up.
+    busy_timeout: Duration,
+    /// How long a query waits for a connection from the pool.
+    acquire_timeout: Duration,
+    /// Idle connections are closed after this long. Never when `None`.
+    idle_timeout: Option<Duration>,
+    /// Connections are replaced after this long. Never when `None`.
+    max_lifetime: Option<Duration>,
+}
+
+struct Config {
+    /// Only optional when systemd passes in the listening socket.
+    host_port: Option<String>,
+    database_url: String,
+    database: DatabaseConfig,
+    run_migrations: bool,
+    /// Users allowed to run administrative commands.
+    admin_users: Vec<String>,
+    /// Where snapshots made with the BACKUP command are written.
+    backup_dir: PathBuf,
+    /// How long clients get to finish their current command on shutdown.
+    shutdown_grace_period: Duration,
+    /// Connections from one address beyond this many are turned away as
+    /// busy. Unlimited when `None`.
+    max_connections_per_ip: Option<usize>,
+    /// Commands a connection may run each minute before being told to slow
+    /// down. Unlimited when `None`.
+    max_commands_per_minute: Option<usize>,
+    /// Longest command line accepted, in bytes, not counting its line
+    /// ending. Longer ones are answered with `BadCommand` and skipped.
+    max_line_length: usize,
+    /// Whether to close the connection after a line that's too long.
+    disconnect_on_long_line: bool,
+    /// Which networks may connect at all.
+    network_access: NetworkAccess,
+    /// Where to serve the Gemini interface. Disabled when `None`.
+    capsule: Option<CapsuleConfig>,
+    /// Where to serve the HTTP API. Disabled when `None`.
+    api_address: Option<SocketAddr>,
+    /// Where to serve gRPC, with the `grpc` feature. Disabled when `None`.
+    grpc_address: Option<SocketAddr>,
+    /// Where to serve the NNTP gateway. Disabled when `None`.
+    nntp_host_port: Option<String>,
+    /// Where to announce new entries in chat. Disabled when `None`.
+    irc: Option<IrcConfig>,
+    /// When to optimize and vacuum the database. Disabled when `None`.
+    maintenance_window: Option<QuietWindow>,
+    /// How far back new subscriptions list existing entries as unread.
+    subscription_backlog: chrono::Duration,
+    /// How long feeds nobody subscribes to are kept before being purged.
+    unsubscribed_feed_grace_period: chrono::Duration,
+    /// How long fetch attempts are kept in `fetch_log`.
+    fetch_log_retention: chrono::Duration,
+    /// How long changes are kept for SYNC, and so how long its tokens last.
+    sync_retention: chrono::Duration,
+    fetch: FetchConfig,
+    fetch_entry_contents: bool,
+    entry_content_fetch: FetchConfig,
+    /// Whether entries keep the summaries their feeds give them.
+    entry_summaries: bool,
+    /// Settings as of startup. The running server follows the copy that's
+    /// updated on SIGHUP instead.
+    tunables: Tunables,
+}
+
+/// A daily UTC time range, e.g. `03:00-05:00`, that may wrap past midnight.
+#[derive(Debug, Clone, Copy)]
+struct QuietWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
 
-        if affected_rows > 0 {
-            Ok(vec![Response::AckUnsubscribe])
+impl QuietWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
         } else {
-            Ok(vec![Response::ResourceNotFound(format!(
-                "no subscription with feed ID {} exists",
-                feed_id
-            ))])
+            time >= self.start || time < self.end
         }
     }
+}
 
-    async fn list_unread(&self) -> Result<Vec<Response>> {
-        let user_id = match self.user {
-            ConnectedUser::NoUser => {
-                return Ok(vec![Response::NeedUser("must select a user".to_string())]);
-            }
-            ConnectedUser::User { id, .. } => id,
-        };
+impl FromStr for QuietWindow {
+    type Err = anyhow::Error;
 
-        let mut conn = self.pool.acquire().await?;
-        // I would love to use sqlx::query!() here but it hard hangs rustc
-        // for some reason.
-        let mut rows = sqlx::query(
-            r#"
-            SELECT
-                feed_entries.id, feed_entries.feed_id, feeds.url AS feed_url, feed_entries.url, feed_entries.title
-            FROM feed_entries
-            LEFT JOIN feeds ON feed_entries.feed_id = feeds.id
-                WHERE feed_entries.id NOT IN (
-                    SELECT feed_entry_id FROM views WHERE user_id = ?
-                )
-                AND feed_entries.feed_id IN (
-                    SELECT feed_id FROM subscriptions WHERE user_id = ?
-                )
-            "#).bind(user_id).bind(user_id).fetch(&mut conn);
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '-');
+        let (start, end) = match (parts.next(), parts.next()) {
+            (Some(start), Some(end)) => (start.trim(), end.trim()),
+            _ => return Err(format_err!("expected HH:MM-HH:MM")),
+        };
 
-        let mut responses = vec![Response::StartEntryList];
+        let window = Self {
+            start: NaiveTime::parse_from_str(start, "%H:%M")?,
+            end: NaiveTime::parse_from_str(end, "%H:%M")?,
+        };
 
-        while let Some(row) = rows.try_next().await? {
-            responses.push(Response::Entry {
-                id: row.try_get("id")?,
-                feed_id: row.try_get("feed_id")?,
-                feed_url: row.try_get("feed_url")?,
-                url: row.try_get("url")?,
-                title: row.try_get("title")?,
-            });
+        // Maintenance only checks the time once an hour, so it could miss a
+        // shorter window altogether.
+        let mut length = window.end - window.start;
+        if length < chrono::Duration::zero() {
+            length += chrono::Duration::days(1);
+        }
+        if length.to_std()? < MAINTENANCE_INTERVAL {
+            return Err(format_err!("the window must be at least an hour long"));
         }
 
-        responses.push(Response::EndList);
-
-        Ok(responses)
+        Ok(window)
     }
+}
 
-    async fn mark_read(&self, feed_entry_id: i64) -> Result<Vec<Response>> {
-        let user_id = match self.user {
-            ConnectedUser::NoUser => {
-                return Ok(vec![Response::NeedUser("must select a user".to_string())]);
-            }
-            ConnectedUser::User { id, .. } => id,
-        };
+/// How often the fetcher checks for feeds that are due to be fetched.
+const SCHEDULER_TICK: Duration = Duration::from_secs(30);
 
-        let mut conn = self.pool.acquire().await?;
+/// How often database maintenance runs.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
-        sqlx::query!(
-            "INSERT INTO views (user_id, feed_entry_id) VALUES (?1, ?2)",
-            user_id,
-            feed_entry_id
-        )
-        .execute(&mut conn)
-        .await?;
+/// How long to back off when a server responds with 44 (SLOW DOWN) but
+/// doesn't give a usable number of seconds in the meta.
+const DEFAULT_SLOW_DOWN_SECS: i64 = 60;
 
-        Ok(vec![Response::AckMarkRead])
-    }
+/// The longest a server may ask us to back off for with 44.
+const MAX_SLOW_DOWN_SECS: i64 = 7 * 24 * 60 * 60;
 
-    async fn consume_command(&mut self, command: Command) -> Result<Vec<Response>> {
-        info!("< {}", command);
+/// The seconds a 44 response's meta asks us to wait, when that's a sane
+/// number.
+fn slow_down_secs(meta: &str) -> i64 {
+    meta.trim()
+        .parse()
+        .ok()
+        .filter(|secs| (0..=MAX_SLOW_DOWN_SECS).contains(secs))
+        .unwrap_or(DEFAULT_SLOW_DOWN_SECS)
+}
 
-        match command {
-            Command::User { username } => self.select_user(username).await,
-            Command::ListSubscriptions => self.list_subscriptions().await,
-            Command::Subscribe { url } => self.subscribe(url).await,
-            Command::Unsubscribe { id } => self.unsubscribe(id).await,
-            Command::ListUnread => self.list_unread().await,
-            Command::MarkRead { id } => self.mark_read(id).await,
-        }
-    }
+/// Strips trailing whitespace and line ending differences so that
+/// cosmetic changes to a page aren't reported as updates.
+fn normalize_body(body: &str) -> String {
+    body.lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
 }
 
-async fn handle_connection(
-    stream: TcpStream,
-    address: SocketAddr,
-    pool: &Pool<Sqlite>,
+fn seconds_since(timestamp: NaiveDateTime) -> i64 {
+    (Utc::now().naive_utc() - timestamp).num_seconds()
+}
+
+/// The current time in the format timestamps are stored in.
+fn now_timestamp() -> String {
+    Utc::now().naive_utc().format(TIMESTAMP_FORMAT).to_string()
+}
+
+/// A stored UTC timestamp as local time in a user's timezone.
+fn in_timezone(timestamp: NaiveDateTime, timezone: Tz) -> DateTime<FixedOffset> {
+    let local = timezone.from_utc_datetime(&timestamp);
+    local.with_timezone(&local.offset().fix())
+}
+
+fn hash_body(body: &str) -> String {
+    format!("{:x}", Sha256::digest(body.as_bytes()))
+}
+
+/// Downloads the gemtext an entry links to and caches it in `entry_contents`.
+async fn fetch_entry_content(
+    pool: &Pool<Any>,
+    config: &Config,
+    state: &FetcherState,
+    feed_entry_id: i64,
+    entry_url: &str,
 ) -> Result<()> {
-    let mut connection = Connection::new(address, pool);
+    let url = Url::parse(entry_url).with_context(|| format!("invalid URL \"{}\"", entry_url))?;
+    if url.scheme() != "gemini" {
+        debug!(
+            "Not fetching content for non-gemini entry \"{}\"",
+            entry_url
+        );
+        return Ok(());
+    }
 
-    info!("Client connected from {}", connection.address);
+    if let Some(host) = url.host_str() {
+        state.entry_limiter.wait(host).await;
+    }
 
-    let (reader, mut writer) = tokio::io::split(stream);
+    let page = fetch(entry_url, &config.entry_content_fetch)
+        .await
+        .with_context(|| format!("failed to fetch entry \"{}\"", entry_url))?;
+
+    if page.header.status != Status::Success {
+        return Err(format_err!(
+            "unexpected response {:?} \"{}\" for entry \"{}\"",
+            page.header.status,
+            page.header.meta,
+            entry_url
+        ));
+    }
 
-    let server_reader = BufReader::new(reader);
-    let mut lines = server_reader.lines();
-    while let Some(line) = lines.next_line().await? {
-        match line.parse() {
-            Ok(command) => match connection.consume_command(command).await {
-                Ok(responses) => {
-                    for response in responses.into_iter() {
-                        writer
-                            .write_all(format!("{}\r\n", response).as_bytes())
-                            .await?;
-                    }
-                }
-                Err(e) => {
-                    writer
-                        .write_all(
-                            format!("{}\r\n", Response::InternalError(e.to_string())).as_bytes(),
-                        )
-                        .await?;
-                }
-            },
-            Err(e) => {
-                let response: Response = e.into();
-                writer
-                    .write_all(format!("{}\r\n", response).as_bytes())
-                    .await?;
-            }
-        }
+    let mime = page.header.mime();
+    if !mime.is_gemtext() {
+        debug!(
+            "Not caching entry \"{}\" with MIME type \"{}\"",
+            entry_url, mime
+        );
+        return Ok(());
     }
 
-    info!("Client closed");
+    // The body has already been decoded from its declared charset.
+    let mime_type = mime.essence;
+    let content = page.body.unwrap_or_default();
+    sqlx::query(
+        r#"INSERT INTO entry_contents
+            (feed_entry_id, mime_type, content, fetched_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (feed_entry_id) DO UPDATE SET
+                mime_type = excluded.mime_type,
+                content = excluded.content,
+                fetched_at = excluded.fetched_at"#,
+    )
+    .bind(feed_entry_id)
+    .bind(mime_type)
+    .bind(content)
+    .bind(now_timestamp())
+    .execute(pool)
+    .await
+    .with_context(|| format!("failed to store content for entry \"{}\"", entry_url))?;
 
     Ok(())
 }
 
-struct Config {
-    host_port: String,
-    database_url: String,
-    feed_fetch_interval: Duration,
+/// State the fetcher keeps across fetch cycles.
+struct FetcherState {
+    entry_limiter: HostRateLimiter,
+    feed_limiter: HostRateLimiter,
+    robots: RobotsCache,
+    /// Where inserted entries are announced.
+    new_entries: broadcast::Sender<NewEntry>,
+}
+
+/// What a successful fetch did, for the fetch log.
+struct FetchStats {
+    status: Status,
+    bytes: usize,
+    entries_inserted: usize,
+}
+
+/// The state of a feed the fetcher needs to decide what to do with a fetch.
+struct FeedToCheck {
+    id: i64,
+    url: String,
+    content_hash: Option<String>,
+    /// The kind found by the last successful fetch, if any. Only a gemfeed
+    /// stays one; pages are detected again.
+    kind: Option<FeedKind>,
+    /// Overrides the globally configured SOCKS5 proxy for this feed.
+    proxy: Option<String>,
+    /// The parser to use instead of sniffing the format.
+    format: Option<String>,
+    /// Whether changed titles of existing entries are written back.
+    apply_title_edits: bool,
+    /// When the newest entry seen last time was published, and its URL.
+    last_seen: Option<(NaiveDateTime, String)>,
 }
 
-async fn check_feed(pool: &Pool<Sqlite>, feed_id: i64, feed_url: String) -> Result<()> {
-    let contents = Page::fetch_and_handle_redirects(feed_url.clone())
+async fn check_feed(
+    pool: &Pool<Any>,
+    config: &Config,
+    state: &FetcherState,
+    feed: FeedToCheck,
+) -> Result<FetchStats> {
+    let FeedToCheck {
+        id: feed_id,
+        url: feed_url,
+        content_hash: previous_hash,
+        kind,
+        proxy,
+        format,
+        apply_title_edits,
+        last_seen,
+    } = feed;
+
+    let fetch_config = match proxy {
+        Some(proxy) => FetchConfig {
+            proxy: Some(proxy),
+            ..config.fetch.clone()
+        },
+        None => config.fetch.clone(),
+    };
+
+    let url = Url::parse(&feed_url).with_context(|| format!("invalid URL \"{}\"", feed_url))?;
+    if !state.robots.allows(&url, &fetch_config).await {
+        return Err(format_err!(
+            "fetching \"{}\" is disallowed by robots.txt",
+            feed_url
+        ));
+    }
+
+    let contents = fetch(&feed_url, &fetch_config)
         .await
         .with_context(|| format!("failed to fetch page \"{}\"", &feed_url))?;
-    let feed: Feed = contents
-        .try_into()
-        .with_context(|| format!("failed to parse \"{}\" as a gemfeed", &feed_url))?;
 
-    if feed.entries.is_empty() {
-        return Ok(());
+    let mut stats = FetchStats {
+        status: contents.header.status,
+        bytes: contents.body.as_ref().map_or(0, String::len),
+        entries_inserted: 0,
+    };
+
+    if let Status::Gone = contents.header.status {
+        info!("Feed \"{}\" is gone, retiring it", &feed_url);
+
+        sqlx::query("UPDATE feeds SET gone_at = $1 WHERE id = $2")
+            .bind(now_timestamp())
+            .bind(feed_id)
+            .execute(pool)
+            .await
+            .with_context(|| format!("failed to retire \"{}\"", &feed_url))?;
+
+        return Ok(stats);
+    }
+
+    if let Status::SlowDown = contents.header.status {
+        let retry_after = slow_down_secs(&contents.header.meta);
+
+        info!(
+            "Feed \"{}\" asked us to slow down, not fetching for {} seconds",
+            &feed_url, retry_after
+        );
+
+        let fetch_after = (Utc::now() + chrono::Duration::seconds(retry_after))
+            .naive_utc()
+            .format(TIMESTAMP_FORMAT)
+            .to_string();
+        sqlx::query("UPDATE feeds SET fetch_after = $1 WHERE id = $2")
+            .bind(fetch_after)
+            .bind(feed_id)
+            .execute(pool)
+            .await
+            .with_context(|| format!("failed to record slow down for \"{}\"", &feed_url))?;
+
+        return Ok(stats);
+    }
+
+    // Anything else that isn't a success is a failure, even if its body
+    // happens to hash like the feed's last one.
+    if contents.header.status != Status::Success {
+        return Err(format_err!(
+            "unexpected response {:?} \"{}\" from \"{}\"",
+            contents.header.status,
+            contents.header.meta,
+            &feed_url
+        ));
+    }
+
+    // Most feeds don't change between fetches, so skip parsing and the
+    // insert transaction entirely when the body is unchanged.
+    let content_hash = hash_body(&normalize_body(contents.body.as_deref().unwrap_or("")));
+    if previous_hash.as_deref() == Some(content_hash.as_str()) {
+        debug!("Feed \"{}\" unchanged since last fetch", &feed_url);
+
+        sqlx::query(
+            r#"UPDATE feeds
+                SET failure_count = 0, last_error = NULL, failing_since = NULL
+                WHERE id = $1"#,
+        )
+        .bind(feed_id)
+        .execute(pool)
+        .await
+        .with_context(|| format!("failed to record fetch of \"{}\"", &feed_url))?;
+
+        return Ok(stats);
+    }
+
+    let feed = Feed::from_page(contents, format.as_deref())
+        .with_context(|| format!("failed to parse \"{}\" as a feed", &feed_url))?;
+    for skipped in &feed.skipped {
+        debug!(
+            "Skipped line of \"{}\" ({}): {}",
+            &feed_url, skipped.reason, skipped.line
+        );
     }
 
     let mut tx = pool.begin().await.with_context(|| {
@@ -305,22 +699,292 @@ async fn check_feed(pool: &Pool<Sqlite>, feed_id: i64, feed_url: String) -> Resu
         )
     })?;
 
-    for entry in feed.entries {
-        let published_at = entry.published_at.to_string();
-        sqlx::query!(
-            r#"INSERT OR IGNORE INTO feed_entries
-                (feed_id, title, published_at, url)
-                VALUES (?1, ?2, ?3, ?4)"#,
-            feed_id,
-            entry.title,
-            published_at,
-            entry.url,
+    let title = feed.title;
+    let subtitle = feed.subtitle;
+
+    // Pages without any dated links aren't gemfeeds, so we watch them for
+    // changes instead. A gemlog with no posts yet looks the same, so only
+    // being a gemfeed sticks, and a page is checked for dated links again
+    // on every fetch.
+    let kind = match kind {
+        Some(FeedKind::Gemfeed) => FeedKind::Gemfeed,
+        _ if feed.entries.is_empty() => FeedKind::Page,
+        _ => FeedKind::Gemfeed,
+    };
+
+    let entries = match kind {
+        FeedKind::Gemfeed => feed.entries,
+        // The first fetch only establishes the baseline hash.
+        FeedKind::Page if previous_hash.is_none() => Vec::new(),
+        FeedKind::Page => {
+            let published_at = Utc::now().naive_utc();
+            vec![Entry {
+                title: format!("Page changed on {}", published_at.date()),
+                published_at,
+                url: feed_url.clone(),
+                summary: None,
+                guid: None,
+            }]
+        }
+    };
+
+    // Entries older than the newest one seen last time were usually dealt
+    // with then, as long as that one is still here unchanged, so look up
+    // which of them are stored all at once instead of one by one. One can
+    // still be new, e.g. if it was backdated, so only stored ones are
+    // skipped. Otherwise the feed may have moved, and every entry is checked
+    // for a changed URL. Title edits can be to any entry, so nothing is
+    // skipped for them either.
+    let newest = entries
+        .iter()
+        .map(|entry| (entry.published_at, entry.url.clone()))
+        .max();
+    let entries: Vec<Entry> = match last_seen {
+        Some((published_at, url))
+            if !apply_title_edits
+                && entries
+                    .iter()
+                    .any(|entry| entry.published_at == published_at && entry.url == url) =>
+        {
+            let stored = sqlx::query(
+                r#"SELECT published_at, url FROM feed_entries
+                    WHERE feed_id = $1 AND published_at < $2"#,
+            )
+            .bind(feed_id)
+            .bind(published_at.format(TIMESTAMP_FORMAT).to_string())
+            .fetch_all(&mut tx)
+            .await
+            .with_context(|| format!("failed to look up stored entries of \"{}\"", &feed_url))?
+            .into_iter()
+            .map(|row| -> Result<(String, String)> {
+                Ok((row.try_get("published_at")?, row.try_get("url")?))
+            })
+            .collect::<Result<HashSet<_>>>()?;
+
+            entries
+                .into_iter()
+                .filter(|entry| {
+                    entry.published_at >= published_at
+                        || !stored.contains(&(
+                            entry.published_at.format(TIMESTAMP_FORMAT).to_string(),
+                            entry.url.clone(),
+                        ))
+                })
+                .collect()
+        }
+        _ => entries,
+    };
+
+    let mut new_entries = Vec::new();
+    for entry in entries {
+        let published_at = entry.published_at.format(TIMESTAMP_FORMAT).to_string();
+        let key = entry_key(&entry.url);
+
+        if let Some(guid) = &entry.guid {
+            // Atom entries are identified by their IDs, so one whose link
+            // changed is moved to the new one, keeping whether it has been
+            // read.
+            let moved = sqlx::query(
+                r#"UPDATE feed_entries
+                    SET url = $1, entry_key = $2
+                    WHERE feed_id = $3 AND guid = $4 AND url != $1
+                        AND NOT EXISTS (
+                            SELECT 1 FROM feed_entries AS existing
+                            WHERE existing.feed_id = $3
+                                AND existing.published_at = feed_entries.published_at
+                                AND existing.url = $1
+                        )"#,
+            )
+            .bind(&entry.url)
+            .bind(&key)
+            .bind(feed_id)
+            .bind(guid)
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("failed to move entry for \"{}\"", &feed_url))?
+            .rows_affected();
+            if moved > 0 {
+                debug!("Moved entry of \"{}\" to \"{}\"", feed_url, entry.url);
+            }
+
+            // Entries stored before IDs were kept get theirs.
+            sqlx::query(
+                r#"UPDATE feed_entries
+                    SET guid = $1
+                    WHERE feed_id = $2 AND published_at = $3 AND url = $4 AND guid IS NULL
+                        AND NOT EXISTS (
+                            SELECT 1 FROM feed_entries AS existing
+                            WHERE existing.feed_id = $2 AND existing.guid = $1
+                        )"#,
+            )
+            .bind(guid)
+            .bind(feed_id)
+            .bind(&published_at)
+            .bind(&entry.url)
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("failed to store entry ID for \"{}\"", &entry.url))?;
+        }
+
+        // An entry whose URL changed, e.g. because the feed now redirects
+        // to another host, is moved rather than inserted again, keeping its
+        // ID and so whether it has been read.
+        let moved = sqlx::query(
+            r#"UPDATE feed_entries
+                SET url = $1, entry_key = $4
+                WHERE feed_id = $2 AND published_at = $3 AND entry_key = $4 AND url != $1
+                    AND NOT EXISTS (
+                        SELECT 1 FROM feed_entries AS existing
+                        WHERE existing.feed_id = $2
+                            AND existing.published_at = $3
+                            AND existing.url = $1
+                    )"#,
+        )
+        .bind(&entry.url)
+        .bind(feed_id)
+        .bind(&published_at)
+        .bind(&key)
+        .execute(&mut tx)
+        .await
+        .with_context(|| format!("failed to move entry for \"{}\"", &feed_url))?
+        .rows_affected();
+        if moved > 0 {
+            debug!("Moved entry of \"{}\" to \"{}\"", feed_url, entry.url);
+        }
+
+        let summary = entry.summary.as_deref().filter(|_| config.entry_summaries);
+        let inserted = sqlx::query(
+            r#"INSERT INTO feed_entries
+                (feed_id, title, published_at, url, entry_key, summary, guid)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT DO NOTHING"#,
         )
+        .bind(feed_id)
+        .bind(&entry.title)
+        .bind(&published_at)
+        .bind(&entry.url)
+        .bind(&key)
+        .bind(summary)
+        .bind(&entry.guid)
         .execute(&mut tx)
         .await
-        .with_context(|| format!("failed to insert entry for \"{}\" into database", &feed_url))?;
+        .with_context(|| format!("failed to insert entry for \"{}\" into database", &feed_url))?
+        .rows_affected();
+
+        if inserted > 0 {
+            let feed_entry_id: i64 = sqlx::query(
+                "SELECT id FROM feed_entries WHERE feed_id = $1 AND published_at = $2 AND url = $3",
+            )
+            .bind(feed_id)
+            .bind(&published_at)
+            .bind(&entry.url)
+            .fetch_one(&mut tx)
+            .await?
+            .try_get("id")?;
+
+            // Subscribers who read the same entry under another feed, such
+            // as the one this feed moved from, have read it here too.
+            sqlx::query(
+                r#"
+                INSERT INTO views (user_id, feed_entry_id, viewed_at)
+                SELECT views.user_id, $1, MIN(views.viewed_at)
+                FROM feed_entries AS other
+                INNER JOIN views ON views.feed_entry_id = other.id
+                INNER JOIN subscriptions
+                    ON subscriptions.user_id = views.user_id AND subscriptions.feed_id = $2
+                WHERE other.entry_key = $3 AND other.published_at = $4 AND other.feed_id != $2
+                GROUP BY views.user_id
+                "#,
+            )
+            .bind(feed_entry_id)
+            .bind(feed_id)
+            .bind(&key)
+            .bind(&published_at)
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("failed to carry over read state for \"{}\"", &entry.url))?;
+
+            // As have users who read it on the instance they imported from.
+            sqlx::query(
+                r#"
+                INSERT INTO views (user_id, feed_entry_id, viewed_at)
+                SELECT user_id, $1, viewed_at
+                FROM pending_views
+                WHERE feed_id = $2 AND entry_url = $3
+                    AND NOT EXISTS (
+                        SELECT 1 FROM views
+                        WHERE views.user_id = pending_views.user_id AND views.feed_entry_id = $1
+                    )
+                "#,
+            )
+            .bind(feed_entry_id)
+            .bind(feed_id)
+            .bind(&entry.url)
+            .execute(&mut tx)
+            .await
+            .with_context(|| {
+                format!("failed to apply imported read state for \"{}\"", &entry.url)
+            })?;
+            sqlx::query("DELETE FROM pending_views WHERE feed_id = $1 AND entry_url = $2")
+                .bind(feed_id)
+                .bind(&entry.url)
+                .execute(&mut tx)
+                .await?;
+
+            new_entries.push((feed_entry_id, entry.url, entry.title));
+        } else if apply_title_edits {
+            // Editing in place keeps the entry's ID, so whether it has been
+            // read is unaffected.
+            let edited = sqlx::query(
+                r#"UPDATE feed_entries
+                    SET title = $1
+                    WHERE feed_id = $2 AND published_at = $3 AND url = $4 AND title != $1"#,
+            )
+            .bind(&entry.title)
+            .bind(feed_id)
+            .bind(&published_at)
+            .bind(&entry.url)
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("failed to update entry title for \"{}\"", &feed_url))?
+            .rows_affected();
+
+            if edited > 0 {
+                debug!("Updated title of \"{}\" to \"{}\"", entry.url, entry.title);
+            }
+        }
     }
 
+    let feed_kind = kind.as_str();
+    sqlx::query(
+        r#"UPDATE feeds
+            SET
+                content_hash = $1,
+                feed_kind = $2,
+                title = $3,
+                subtitle = $4,
+                failure_count = 0,
+                last_error = NULL,
+                failing_since = NULL,
+                last_seen_published_at = $5,
+                last_seen_url = $6
+            WHERE id = $7"#,
+    )
+    .bind(content_hash)
+    .bind(feed_kind)
+    .bind(title)
+    .bind(subtitle)
+    .bind(
+        newest
+            .as_ref()
+            .map(|(published_at, _)| published_at.format(TIMESTAMP_FORMAT).to_string()),
+    )
+    .bind(newest.map(|(_, url)| url))
+    .bind(feed_id)
+    .execute(&mut tx)
+    .await
+    .with_context(|| format!("failed to store content hash for \"{}\"", &feed_url))?;
+
     tx.commit().await.with_context(|| {
         format!(
             "failed to commit transaction while inserting feed entries for \"{}\"",
@@ -328,112 +992,2065 @@ async fn check_feed(pool: &Pool<Sqlite>, feed_id: i64, feed_url: String) -> Resu
         )
     })?;
 
-    Ok(())
-}
-
-async fn check_feeds(pool: &Pool<Sqlite>) -> Result<()> {
-    let feeds = sqlx::query!("SELECT id, url FROM feeds")
-        .fetch_all(pool)
-        .await?;
+    stats.entries_inserted = new_entries.len();
 
-    let mut futures = Vec::new();
-    for feed in feeds {
-        futures.push(check_feed(
-            pool,
-            feed.id.ok_or_else(|| format_err!("feed missing ID"))?,
-            feed.url.clone(),
-        ));
+    for (feed_entry_id, entry_url, entry_title) in &new_entries {
+        // Nobody listening is fine.
+        let _ = state.new_entries.send(NewEntry {
+            id: *feed_entry_id,
+            feed_id,
+            feed_url: feed_url.clone(),
+            url: entry_url.clone(),
+            title: entry_title.clone(),
+        });
     }
 
-    for result in join_all(futures).await {
-        if let Err(e) = result {
-            error!("failed to check feed: {:?}", e);
+    if config.fetch_entry_contents {
+        for (feed_entry_id, entry_url, _) in new_entries {
+            if let Err(e) =
+                fetch_entry_content(pool, config, state, feed_entry_id, &entry_url).await
+            {
+                error!("failed to fetch entry content: {:?}", e);
+            }
         }
     }
 
-    let mut conn = pool.acquire().await?;
-    let mut rows = sqlx::query(
-        r#"
-        SELECT id
-        FROM feeds
-        WHERE id NOT IN (
-            SELECT feed_id FROM subscriptions GROUP BY feed_id
-        )
-        "#,
+    Ok(stats)
+}
+
+async fn log_fetch(
+    pool: &Pool<Any>,
+    feed_id: i64,
+    duration: Duration,
+    result: &Result<FetchStats>,
+) -> Result<()> {
+    let (status, bytes, entries_inserted, error) = match result {
+        Ok(stats) => (
+            Some(stats.status.code() as i64),
+            stats.bytes as i64,
+            stats.entries_inserted as i64,
+            None,
+        ),
+        Err(e) => (None, 0, 0, Some(format!("{:#}", e))),
+    };
+
+    sqlx::query(
+        r#"INSERT INTO fetch_log
+            (feed_id, fetched_at, duration_ms, status, bytes, entries_inserted, error)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
     )
-    .fetch(&mut conn);
+    .bind(feed_id)
+    .bind(now_timestamp())
+    .bind(duration.as_millis() as i64)
+    .bind(status)
+    .bind(bytes)
+    .bind(entries_inserted)
+    .bind(error)
+    .execute(pool)
+    .await?;
 
-    let mut tx = pool.begin().await?;
+    Ok(())
+}
 
-    while let Some(row) = rows.try_next().await? {
-        let feed_id: i64 = row.try_get("id")?;
+/// Counts a failed fetch. Once `dead_after` failures in a row have piled
+/// up (0 never), the feed is flagged as failing until it next succeeds.
+async fn record_fetch_failure(
+    pool: &Pool<Any>,
+    feed_id: i64,
+    error: &anyhow::Error,
+    dead_after: i64,
+) -> Result<()> {
+    let last_error = format!("{:#}", error);
+    sqlx::query(
+        "UPDATE feeds SET failure_count = failure_count + 1, last_error = $1 WHERE id = $2",
+    )
+    .bind(last_error)
+    .bind(feed_id)
+    .execute(pool)
+    .await?;
 
-        info!("Deleting fully unsubscribed feed {}", feed_id);
+    if dead_after == 0 {
+        return Ok(());
+    }
 
-        sqlx::query!("DELETE FROM feeds WHERE id = ?1", feed_id)
-            .execute(&mut tx)
-            .await?;
+    let flagged = sqlx::query(
+        r#"UPDATE feeds
+            SET failing_since = $1
+            WHERE id = $2 AND failing_since IS NULL AND failure_count >= $3"#,
+    )
+    .bind(Utc::now().naive_utc().format(TIMESTAMP_FORMAT).to_string())
+    .bind(feed_id)
+    .bind(dead_after)
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    if flagged > 0 {
+        warn!(
+            "Feed {} has failed {} times in a row and is likely dead",
+            feed_id, dead_after
+        );
     }
-    tx.commit().await?;
 
     Ok(())
 }
 
-async fn check_feeds_task(pool: &Pool<Sqlite>, config: &Config) -> Result<()> {
-    let mut timer = interval(config.feed_fetch_interval);
-    timer.tick().await;
+/// Returns when a feed should next be fetched.
+///
+/// Each feed gets a fixed offset into the fetch interval derived from its ID,
+/// so fetches are spread across the interval instead of all happening at
+/// once.
+fn next_fetch_at(feed_id: i64, fetch_interval: Duration, now: DateTime<Utc>) -> NaiveDateTime {
+    let interval = fetch_interval.as_secs().max(1) as i64;
+    // Multiplying by a large odd constant scatters consecutive IDs.
+    let offset = ((feed_id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) % interval as u64) as i64;
+
+    let now = now.timestamp();
+    let next = ((now - offset).div_euclid(interval) + 1) * interval + offset;
+
+    DateTime::from_timestamp(next, 0)
+        .expect("the next fetch is a representable time")
+        .naive_utc()
+}
 
-    loop {
-        if let Err(e) = check_feeds(pool).await {
-            error!("failed to check feeds: {}", e);
-        }
+async fn schedule_next_fetch(
+    pool: &Pool<Any>,
+    fetch_interval: Duration,
+    feed_id: i64,
+) -> Result<()> {
+    let fetch_after = next_fetch_at(feed_id, fetch_interval, Utc::now())
+        .format(TIMESTAMP_FORMAT)
+        .to_string();
+
+    // A 44 (SLOW DOWN) may already have pushed the next fetch further out.
+    sqlx::query(
+        r#"UPDATE feeds
+            SET fetch_after = $1
+            WHERE id = $2 AND (fetch_after IS NULL OR fetch_after < $1)"#,
+    )
+    .bind(fetch_after)
+    .bind(feed_id)
+    .execute(pool)
+    .await?;
 
-        timer.tick().await;
-    }
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    Builder::new().filter_level(LevelFilter::Info).init();
+/// Checks the feeds that are due, or every feed with `all`.
+async fn check_feeds(
+    pool: &Pool<Any>,
+    config: &Config,
+    tunables: &Tunables,
+    state: &FetcherState,
+    shutdown: &Shutdown,
+    all: bool,
+) -> Result<()> {
+    let due_by = if all { None } else { Some(now_timestamp()) };
+    // Each cycle looks hosts up afresh, once. Entry contents share the
+    // cache.
+    config.fetch.dns_cache.clear();
 
-    let feed_fetch_interval_min =
-        dotenv::var("FEED_FETCH_INTERVAL_MIN").unwrap_or_else(|_| "60".to_string());
-    let feed_fetch_interval_min: u64 = feed_fetch_interval_min.parse().with_context(|| {
-        format!(
-            "invalid $FEED_FETCH_INTERVAL_MIN \"{}\"",
-            feed_fetch_interval_min
-        )
-    })?;
+    let feeds = sqlx::query(
+        r#"
+        SELECT id, url, content_hash, feed_kind, proxy, feed_format, apply_title_edits,
+            last_seen_published_at, last_seen_url
+        FROM feeds
+        WHERE gone_at IS NULL
+            AND unsubscribed_at IS NULL
+            AND ($1 IS NULL OR fetch_after IS NULL OR fetch_after <= $1)
+        "#,
+    )
+    .bind(due_by)
+    .fetch_all(pool)
+    .await?;
 
-    let config = Config {
-        database_url: dotenv::var("DATABASE_URL").context("Missing env var $DATABASE_URL")?,
-        host_port: dotenv::var("HOST_PORT").context("Missing env var $HOST_PORT")?,
-        feed_fetch_interval: Duration::from_secs(feed_fetch_interval_min * 60),
-    };
+    let mut due = Vec::new();
+    for feed in feeds {
+        let feed_id: i64 = feed.try_get("id")?;
+        let kind: Option<String> = feed.try_get("feed_kind")?;
+        let kind = kind
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .with_context(|| format!("feed {} has an invalid kind", feed_id))?;
+        let apply_title_edits: i64 = feed.try_get("apply_title_edits")?;
+        let last_seen_published_at: Option<String> = feed.try_get("last_seen_published_at")?;
+        let last_seen_url: Option<String> = feed.try_get("last_seen_url")?;
+        let last_seen = match (last_seen_published_at, last_seen_url) {
+            (Some(published_at), Some(url)) => Some((
+                NaiveDateTime::parse_from_str(&published_at, TIMESTAMP_FORMAT)
+                    .with_context(|| format!("feed {} has an invalid last seen entry", feed_id))?,
+                url,
+            )),
+            _ => None,
+        };
+        let feed = FeedToCheck {
+            id: feed_id,
+            url: feed.try_get("url")?,
+            content_hash: feed.try_get("content_hash")?,
+            kind,
+            proxy: feed.try_get("proxy")?,
+            format: feed.try_get("feed_format")?,
+            apply_title_edits: apply_title_edits != 0,
+            last_seen,
+        };
+        due.push(feed);
+    }
 
-    let pool = SqlitePool::connect(&config.database_url).await?;
+    let mut checks = Vec::new();
+    for feed in interleave_by_host(due) {
+        let feed_id = feed.id;
+        let mut shutdown = shutdown.clone();
+        checks.push(async move {
+            // Feeds already being fetched are finished when shutting down,
+            // but no more are started, or waited for.
+            if shutdown.is_requested() {
+                return None;
+            }
 
-    let mut listener = TcpListener::bind(&config.host_port).await?;
-    info!("Listening on: {}", config.host_port);
+            // Waiting out the host's delay isn't part of the fetch's
+            // duration.
+            let host = host_of(&feed.url);
+            tokio::select! {
+                _ = state.feed_limiter.wait(&host) => {}
+                _ = shutdown.requested() => return None,
+            }
+
+            let span = info_span!("fetch", feed_id, url = %feed.url, duration_ms = Empty);
+            let started = Instant::now();
+            let result = check_feed(pool, config, state, feed)
+                .instrument(span.clone())
+                .await;
+            let duration = started.elapsed();
+            span.record("duration_ms", &(duration.as_millis() as u64));
+            Some((feed_id, duration, result, span))
+        });
+    }
+
+    let mut results = stream::iter(checks).buffer_unordered(tunables.max_concurrent_fetches);
+    while let Some(outcome) = results.next().await {
+        // Checks skipped for shutdown have nothing to record.
+        let (feed_id, duration, result, span) = match outcome {
+            Some(outcome) => outcome,
+            None => continue,
+        };
+        async {
+            if let Err(e) = log_fetch(pool, feed_id, duration, &result).await {
+                error!("failed to log fetch: {}", e);
+            }
+
+            match result {
+                Ok(_) => debug!("Checked feed"),
+                Err(e) => {
+                    error!("failed to check feed: {:?}", e);
+
+                    if let Err(e) =
+                        record_fetch_failure(pool, feed_id, &e, tunables.dead_feed_failures).await
+                    {
+                        error!("failed to record failure: {}", e);
+                    }
+                }
+            }
+
+            if let Err(e) = schedule_next_fetch(pool, tunables.feed_fetch_interval, feed_id).await {
+                error!("failed to schedule next fetch: {}", e);
+            }
+        }
+        .instrument(span)
+        .await;
+    }
+
+    retire_unsubscribed_feeds(pool, config.unsubscribed_feed_grace_period).await?;
+    record_fetch_cycle(pool).await?;
+
+    Ok(())
+}
+
+/// The host of a feed's URL, or nothing if it doesn't parse.
+fn host_of(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Orders feeds so those on the same host are spread through the cycle
+/// rather than bunched together, where waiting out the host's politeness
+/// delay would hold up fetches from other hosts.
+fn interleave_by_host(feeds: Vec<FeedToCheck>) -> Vec<FeedToCheck> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut ranked: Vec<(usize, FeedToCheck)> = feeds
+        .into_iter()
+        .map(|feed| {
+            let count = seen.entry(host_of(&feed.url)).or_insert(0);
+            *count += 1;
+            (*count, feed)
+        })
+        .collect();
+    // The sort is stable, so feeds otherwise keep their order.
+    ranked.sort_by_key(|(rank, _)| *rank);
+
+    ranked.into_iter().map(|(_, feed)| feed).collect()
+}
+
+/// Notes that every due feed has been checked, for health checks.
+async fn record_fetch_cycle(pool: &Pool<Any>) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO fetch_cycles (id, completed_at) VALUES (1, $1)
+        ON CONFLICT (id) DO UPDATE SET completed_at = excluded.completed_at
+        "#,
+    )
+    .bind(now_timestamp())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Stops fetching feeds nobody subscribes to any more, but only deletes
+/// them and their entries after `grace_period` so that resubscribing by
+/// accident doesn't lose history and read state.
+async fn retire_unsubscribed_feeds(pool: &Pool<Any>, grace_period: chrono::Duration) -> Result<()> {
+    let retired = sqlx::query(
+        r#"
+        UPDATE feeds
+        SET unsubscribed_at = $1
+        WHERE unsubscribed_at IS NULL
+            AND id NOT IN (SELECT feed_id FROM subscriptions)
+        "#,
+    )
+    .bind(now_timestamp())
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    if retired > 0 {
+        info!("Stopped fetching {} fully unsubscribed feeds", retired);
+    }
+
+    let cutoff = (Utc::now() - grace_period)
+        .naive_utc()
+        .format(TIMESTAMP_FORMAT)
+        .to_string();
+    let purged = sqlx::query("DELETE FROM feeds WHERE unsubscribed_at < $1")
+        .bind(cutoff)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    if purged > 0 {
+        info!(
+            "Deleted {} feeds unsubscribed for longer than the grace period",
+            purged
+        );
+    }
+
+    Ok(())
+}
+
+/// Entries used to be stored with their links exactly as written in the
+/// feed, so resolve any relative ones left over against their feed's URL.
+async fn resolve_relative_entry_urls(tx: &mut Transaction<'_, Any>) -> Result<()> {
+    let entries = sqlx::query(
+        r#"
+        SELECT
+            feed_entries.id, feed_entries.feed_id, feed_entries.published_at,
+            feed_entries.url, feeds.url AS feed_url
+        FROM feed_entries
+        INNER JOIN feeds ON feed_entries.feed_id = feeds.id
+        WHERE feed_entries.url NOT LIKE '%://%'
+        "#,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for entry in entries {
+        let id: i64 = entry.try_get("id")?;
+        let feed_id: i64 = entry.try_get("feed_id")?;
+        let published_at: String = entry.try_get("published_at")?;
+        let url: String = entry.try_get("url")?;
+        let feed_url: String = entry.try_get("feed_url")?;
+
+        if Url::parse(&url).is_ok() {
+            continue;
+        }
+
+        let resolved = match Url::parse(&feed_url).and_then(|base| base.join(&url)) {
+            Ok(url) => url.to_string(),
+            Err(e) => {
+                error!("failed to resolve entry URL \"{}\": {}", url, e);
+                continue;
+            }
+        };
+
+        info!("Resolved entry URL \"{}\" to \"{}\"", url, resolved);
+
+        // If the resolved entry was already inserted by a newer fetch, the
+        // relative copy is a duplicate and can go once its views are moved
+        // over, since deleting it deletes them too.
+        let duplicate = sqlx::query(
+            "SELECT id FROM feed_entries WHERE feed_id = $1 AND published_at = $2 AND url = $3",
+        )
+        .bind(feed_id)
+        .bind(&published_at)
+        .bind(&resolved)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        match duplicate {
+            Some(kept) => {
+                let kept: i64 = kept.try_get("id")?;
+                sqlx::query(
+                    r#"
+                    INSERT INTO views (user_id, feed_entry_id, viewed_at)
+                    SELECT user_id, $1, viewed_at FROM views WHERE feed_entry_id = $2
+                    ON CONFLICT (user_id, feed_entry_id) DO NOTHING
+                    "#,
+                )
+                .bind(kept)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+
+                sqlx::query("DELETE FROM feed_entries WHERE id = $1")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            None => {
+                sqlx::query("UPDATE feed_entries SET url = $1 WHERE id = $2")
+                    .bind(&resolved)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn check_feeds_task(
+    pool: &Pool<Any>,
+    config: &Config,
+    tunables: watch::Receiver<Tunables>,
+    new_entries: broadcast::Sender<NewEntry>,
+    mut wake: mpsc::Receiver<Wake>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    // Feeds are fetched on their own schedules, so wake up often to pick up
+    // whichever are due.
+    let mut timer = interval(SCHEDULER_TICK);
+    timer.tick().await;
+
+    let state = FetcherState {
+        entry_limiter: HostRateLimiter::new(config.tunables.entry_content_host_delay),
+        feed_limiter: HostRateLimiter::new(config.tunables.feed_host_delay),
+        robots: RobotsCache::new(),
+        new_entries,
+    };
+
+    let mut all = false;
+    while !shutdown.is_requested() {
+        // Settings reloaded mid-cycle apply from the next one.
+        let current = tunables.borrow().clone();
+        state
+            .entry_limiter
+            .set_delay(current.entry_content_host_delay);
+        state.feed_limiter.set_delay(current.feed_host_delay);
+
+        if let Err(e) = check_feeds(pool, config, &current, &state, &shutdown, all).await {
+            error!("failed to check feeds: {}", e);
+        }
+
+        all = tokio::select! {
+            _ = timer.tick() => false,
+            Some(requested) = wake.recv() => requested == Wake::All,
+            _ = shutdown.requested() => false,
+        };
+    }
+
+    Ok(())
+}
+
+/// Connects to the database, picking the driver from the URL's scheme:
+/// sqlite:// or, when built with the `postgres` feature, postgres://.
+async fn connect(database_url: &str, config: &DatabaseConfig) -> Result<Pool<Any>> {
+    let mut options = AnyPoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .connect_timeout(config.acquire_timeout)
+        .idle_timeout(config.idle_timeout)
+        .max_lifetime(config.max_lifetime);
+
+    if database_url.starts_with("sqlite") {
+        // WAL lets readers proceed while the fetcher writes, and the busy
+        // timeout makes writers wait on each other instead of failing.
+        let pragmas = format!(
+            r#"
+            PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = NORMAL;
+            PRAGMA foreign_keys = ON;
+            PRAGMA busy_timeout = {};
+            "#,
+            config.busy_timeout.as_millis()
+        );
+
+        options = options.after_connect(move |conn| {
+            let pragmas = pragmas.clone();
+            Box::pin(async move {
+                conn.execute(pragmas.as_str()).await?;
+                Ok(())
+            })
+        });
+    }
+
+    options
+        .connect(database_url)
+        .await
+        .with_context(|| format!("failed to connect to {}", database_url))
+}
+
+async fn run_migrations(pool: &Pool<Any>, database_url: &str) -> Result<()> {
+    let migrator = if database_url.starts_with("postgres") {
+        sqlx::migrate!("migrations/postgres")
+    } else {
+        sqlx::migrate!("migrations/sqlite")
+    };
 
+    migrator
+        .run(pool)
+        .await
+        .context("failed to run database migrations")?;
+
+    run_data_fixes(pool).await?;
+
+    info!("Database migrations are up to date");
+
+    Ok(())
+}
+
+/// Whether the data fix `name` has already run against this database.
+async fn data_fix_applied(tx: &mut Transaction<'_, Any>, name: &str) -> Result<bool> {
+    Ok(sqlx::query("SELECT 1 FROM data_fixes WHERE name = $1")
+        .bind(name)
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some())
+}
+
+/// Runs the fixes to existing data that SQL migrations can't express, each
+/// only the first time the database is migrated after it was added.
+async fn run_data_fixes(pool: &Pool<Any>) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    let mut applied = Vec::new();
+    if !data_fix_applied(&mut tx, "resolve_relative_entry_urls").await? {
+        resolve_relative_entry_urls(&mut tx).await?;
+        applied.push("resolve_relative_entry_urls");
+    }
+    if !data_fix_applied(&mut tx, "merge_duplicate_feeds").await? {
+        feed_url::merge_duplicate_feeds(&mut tx).await?;
+        applied.push("merge_duplicate_feeds");
+    }
+
+    for name in applied {
+        sqlx::query("INSERT INTO data_fixes (name, applied_at) VALUES ($1, $2)")
+            .bind(name)
+            .bind(now_timestamp())
+            .execute(&mut tx)
+            .await?;
+        info!("Applied data fix {}", name);
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Deletes views of entries or by users that no longer exist.
+async fn prune_orphaned_views(pool: &Pool<Any>) -> Result<()> {
+    let pruned = sqlx::query(
+        r#"
+        DELETE FROM views
+        WHERE feed_entry_id NOT IN (SELECT id FROM feed_entries)
+            OR user_id NOT IN (SELECT id FROM users)
+        "#,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    if pruned > 0 {
+        info!("Pruned {} orphaned views", pruned);
+    }
+
+    Ok(())
+}
+
+/// Refreshes query planner statistics and returns free pages to the
+/// filesystem, which deleting rows alone never does.
+async fn optimize_database(pool: &Pool<Any>, database_url: &str) -> Result<()> {
+    if !database_url.starts_with("sqlite") {
+        // PostgreSQL's autovacuum daemon already takes care of this.
+        sqlx::query("ANALYZE").execute(pool).await?;
+        return Ok(());
+    }
+
+    let mut conn = pool.acquire().await?;
+
+    // Incremental vacuum only works once auto_vacuum is enabled, and
+    // switching an existing database over takes one full VACUUM.
+    let auto_vacuum: i64 = sqlx::query("PRAGMA auto_vacuum")
+        .fetch_one(&mut conn)
+        .await?
+        .try_get(0)?;
+    if auto_vacuum != 2 {
+        info!("Enabling incremental vacuum, this may take a while");
+        conn.execute("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;")
+            .await?;
+    }
+
+    conn.execute("ANALYZE; PRAGMA optimize; PRAGMA incremental_vacuum;")
+        .await?;
+
+    info!("Optimized database");
+
+    Ok(())
+}
+
+async fn prune_fetch_log(pool: &Pool<Any>, retention: chrono::Duration) -> Result<()> {
+    let cutoff = (Utc::now() - retention)
+        .naive_utc()
+        .format(TIMESTAMP_FORMAT)
+        .to_string();
+
+    sqlx::query("DELETE FROM fetch_log WHERE fetched_at < $1")
+        .bind(cutoff)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn prune_sync_changes(pool: &Pool<Any>, retention: chrono::Duration) -> Result<()> {
+    let cutoff = (Utc::now() - retention)
+        .naive_utc()
+        .format(TIMESTAMP_FORMAT)
+        .to_string();
+
+    sqlx::query("DELETE FROM sync_changes WHERE changed_at < $1")
+        .bind(cutoff)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Fills in `feed_entries.entry_key` for entries stored before it existed.
+async fn backfill_entry_keys(pool: &Pool<Any>) -> Result<()> {
+    let entries = sqlx::query("SELECT id, url FROM feed_entries WHERE entry_key IS NULL")
+        .fetch_all(pool)
+        .await?;
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    for entry in &entries {
+        let id: i64 = entry.try_get("id")?;
+        let url: String = entry.try_get("url")?;
+        sqlx::query("UPDATE feed_entries SET entry_key = $1 WHERE id = $2")
+            .bind(entry_key(&url))
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+    }
+    tx.commit().await?;
+
+    info!("Filled in entry keys for {} entries", entries.len());
+
+    Ok(())
+}
+
+/// Marks entries read for users who set `SETPREF automarkread`, once
+/// they've been unread that many days. Entries count as unread from when
+/// they were published, or from when the user subscribed for older ones.
+/// Snoozed feeds are left alone until they wake up.
+async fn auto_mark_read(pool: &Pool<Any>) -> Result<()> {
+    let users = sqlx::query(
+        "SELECT id, auto_mark_read_days FROM users WHERE auto_mark_read_days IS NOT NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let now = now_timestamp();
+    for user in users {
+        let user_id: i64 = user.try_get("id")?;
+        let days: i64 = user.try_get("auto_mark_read_days")?;
+        let cutoff = (Utc::now() - chrono::Duration::days(days))
+            .naive_utc()
+            .format(TIMESTAMP_FORMAT)
+            .to_string();
+
+        let marked = sqlx::query(
+            r#"
+            INSERT INTO views (user_id, feed_entry_id, viewed_at)
+            SELECT subscriptions.user_id, feed_entries.id, $1
+            FROM subscriptions
+            INNER JOIN feed_entries ON feed_entries.feed_id = subscriptions.feed_id
+            LEFT JOIN views
+                ON views.feed_entry_id = feed_entries.id AND views.user_id = subscriptions.user_id
+            WHERE subscriptions.user_id = $2
+                AND views.feed_entry_id IS NULL
+                AND feed_entries.published_at < $3
+                AND (subscriptions.created_at IS NULL OR subscriptions.created_at < $3)
+                AND (subscriptions.snoozed_until IS NULL OR subscriptions.snoozed_until <= $1)
+                AND (
+                    subscriptions.backlog_from IS NULL
+                    OR feed_entries.published_at >= subscriptions.backlog_from
+                )
+            "#,
+        )
+        .bind(&now)
+        .bind(user_id)
+        .bind(cutoff)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+        if marked > 0 {
+            info!(
+                "Marked {} entries read for user {} after {} days unread",
+                marked, user_id, days
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Marks read all but the newest unread entries of feeds the user set
+/// `KEEPUNREAD` on, so firehose feeds don't pile up.
+async fn trim_unread(pool: &Pool<Any>) -> Result<()> {
+    let subscriptions = sqlx::query(
+        "SELECT user_id, feed_id, max_unread FROM subscriptions WHERE max_unread IS NOT NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let now = now_timestamp();
+    for subscription in subscriptions {
+        let user_id: i64 = subscription.try_get("user_id")?;
+        let feed_id: i64 = subscription.try_get("feed_id")?;
+        let max_unread: i64 = subscription.try_get("max_unread")?;
+
+        let marked = sqlx::query(
+            r#"
+            INSERT INTO views (user_id, feed_entry_id, viewed_at)
+            SELECT subscriptions.user_id, feed_entries.id, $1
+            FROM subscriptions
+            INNER JOIN feed_entries ON feed_entries.feed_id = subscriptions.feed_id
+            LEFT JOIN views
+                ON views.feed_entry_id = feed_entries.id AND views.user_id = subscriptions.user_id
+            WHERE subscriptions.user_id = $2
+                AND subscriptions.feed_id = $3
+                AND views.feed_entry_id IS NULL
+                AND (
+                    subscriptions.backlog_from IS NULL
+                    OR feed_entries.published_at >= subscriptions.backlog_from
+                )
+                AND feed_entries.id NOT IN (
+                    SELECT newest.id
+                    FROM feed_entries AS newest
+                    LEFT JOIN views AS newest_views
+                        ON newest_views.feed_entry_id = newest.id AND newest_views.user_id = $2
+                    WHERE newest.feed_id = $3
+                        AND newest_views.feed_entry_id IS NULL
+                        AND (
+                            subscriptions.backlog_from IS NULL
+                            OR newest.published_at >= subscriptions.backlog_from
+                        )
+                    ORDER BY newest.published_at DESC, newest.id DESC
+                    LIMIT $4
+                )
+            "#,
+        )
+        .bind(&now)
+        .bind(user_id)
+        .bind(feed_id)
+        .bind(max_unread)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+        if marked > 0 {
+            info!(
+                "Marked {} entries of feed {} read for user {}, keeping the newest {}",
+                marked, feed_id, user_id, max_unread
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs database maintenance every `MAINTENANCE_INTERVAL` until shutdown is
+/// requested. A pass that's already started is finished first.
+async fn maintenance_task(pool: &Pool<Any>, config: &Config, mut shutdown: Shutdown) -> Result<()> {
+    let mut timer = interval(MAINTENANCE_INTERVAL);
+    let mut last_optimized: Option<NaiveDate> = None;
+    let mut last_certificate_check: Option<NaiveDate> = None;
+
+    loop {
+        tokio::select! {
+            _ = timer.tick() => {}
+            _ = shutdown.requested() => return Ok(()),
+        }
+
+        if let Err(e) = prune_orphaned_views(pool).await {
+            error!("failed to prune orphaned views: {}", e);
+        }
+
+        if let Err(e) = prune_fetch_log(pool, config.fetch_log_retention).await {
+            error!("failed to prune fetch log: {}", e);
+        }
+
+        if let Err(e) = prune_sync_changes(pool, config.sync_retention).await {
+            error!("failed to prune sync changes: {}", e);
+        }
+
+        if let Err(e) = auto_mark_read(pool).await {
+            error!("failed to mark old entries read: {}", e);
+        }
+
+        if let Err(e) = trim_unread(pool).await {
+            error!("failed to trim unread entries: {}", e);
+        }
+
+        let now = Utc::now().naive_utc();
+        if last_certificate_check != Some(now.date()) {
+            last_certificate_check = Some(now.date());
+
+            if let Err(e) = certificates::warn_about_expiring_certificates(pool).await {
+                error!("failed to check pinned certificates: {}", e);
+            }
+        }
+
+        let due = match config.maintenance_window {
+            Some(window) => window.contains(now.time()) && last_optimized != Some(now.date()),
+            None => false,
+        };
+        if due {
+            last_optimized = Some(now.date());
+
+            if let Err(e) = optimize_database(pool, &config.database_url).await {
+                error!("failed to optimize database: {}", e);
+            }
+        }
+    }
+}
+
+/// The first file descriptor systemd passes to socket-activated services.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: std::os::unix::io::RawFd = 3;
+
+/// Takes the listening socket systemd passed in, if the process was socket
+/// activated. This is `sd_listen_fds(3)` without linking to libsystemd.
+#[cfg(unix)]
+fn systemd_listener() -> Result<Option<std::net::TcpListener>> {
+    use std::os::unix::io::FromRawFd;
+
+    let activated = dotenv::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let fds: i32 = match dotenv::var("LISTEN_FDS") {
+        Ok(fds) if activated => fds
+            .parse()
+            .map_err(|e| format_err!("invalid $LISTEN_FDS \"{}\": {}", fds, e))?,
+        _ => return Ok(None),
+    };
+
+    // Children shouldn't think the sockets are meant for them.
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    if fds != 1 {
+        return Err(format_err!("expected 1 socket from systemd, got {}", fds));
+    }
+
+    // Safety: systemd hands over ownership of the descriptors it passes, and
+    // nothing else in the process uses this one.
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+
+    Ok(Some(listener))
+}
+
+#[cfg(not(unix))]
+fn systemd_listener() -> Result<Option<std::net::TcpListener>> {
+    Ok(None)
+}
+
+/// Uses the socket systemd passed in when socket activated, and otherwise
+/// binds `$HOST_PORT`.
+async fn listen(config: &Config) -> Result<TcpListener> {
+    if let Some(listener) = systemd_listener()? {
+        let listener = TcpListener::from_std(listener)?;
+        info!("Listening on: {} (from systemd)", listener.local_addr()?);
+        return Ok(listener);
+    }
+
+    let host_port = config
+        .host_port
+        .as_deref()
+        .ok_or_else(|| format_err!("Missing env var $HOST_PORT"))?;
+    let listener = TcpListener::bind(host_port).await?;
+    info!("Listening on: {}", host_port);
+
+    Ok(listener)
+}
+
+/// Reads and parses an optional environment variable, falling back to
+/// `default` when it isn't set.
+fn env_var_or<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    var_or(&|name| dotenv::var(name).ok(), name, default)
+}
+
+/// Like `env_var_or`, but looking variables up with `var`.
+fn var_or<T>(var: &dyn Fn(&str) -> Option<String>, name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match var(name) {
+        Some(value) => value
+            .parse()
+            .map_err(|e| format_err!("invalid ${} \"{}\": {}", name, value, e)),
+        None => Ok(default),
+    }
+}
+
+/// A duration in seconds, where `0` means none.
+fn optional_secs(secs: u64) -> Option<Duration> {
+    if secs == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(secs))
+    }
+}
+
+fn database_config() -> Result<DatabaseConfig> {
+    Ok(DatabaseConfig {
+        max_connections: env_var_or("DATABASE_MAX_CONNECTIONS", 10)?,
+        min_connections: env_var_or("DATABASE_MIN_CONNECTIONS", 0)?,
+        busy_timeout: Duration::from_millis(env_var_or("DATABASE_BUSY_TIMEOUT_MS", 5000)?),
+        acquire_timeout: Duration::from_millis(env_var_or("DATABASE_ACQUIRE_TIMEOUT_MS", 30_000)?),
+        idle_timeout: optional_secs(env_var_or("DATABASE_IDLE_TIMEOUT_SECS", 600)?),
+        max_lifetime: optional_secs(env_var_or("DATABASE_MAX_LIFETIME_SECS", 1800)?),
+    })
+}
+
+fn database_url() -> Result<String> {
+    dotenv::var("DATABASE_URL").context("Missing env var $DATABASE_URL")
+}
+
+/// Connects to the database, first creating it and bringing its schema up
+/// to date if `migrate` is set.
+async fn open_database(
+    database_url: &str,
+    config: &DatabaseConfig,
+    migrate: bool,
+) -> Result<Pool<Any>> {
+    if migrate && !Any::database_exists(database_url).await? {
+        info!("Creating database {}", database_url);
+        Any::create_database(database_url).await?;
+    }
+
+    let pool = connect(database_url, config).await?;
+
+    if migrate {
+        run_migrations(&pool, database_url).await?;
+    }
+
+    Ok(pool)
+}
+
+/// The host name and port the capsule answers for: `$GEMINI_HOSTNAME`, or
+/// else the host it listens on, and the port from `$GEMINI_HOST_PORT`.
+fn capsule_address(host_port: &str) -> Result<(String, u16)> {
+    let listening = Url::parse(&format!("gemini://{}/", host_port))
+        .ok()
+        .filter(|url| url.port().is_some())
+        .ok_or_else(|| format_err!("invalid $GEMINI_HOST_PORT \"{}\"", host_port))?;
+    let port = listening.port().unwrap_or_default();
+
+    if let Ok(hostname) = dotenv::var("GEMINI_HOSTNAME") {
+        return Ok((hostname.to_lowercase(), port));
+    }
+
+    let listens_everywhere = match listening.host() {
+        Some(Host::Ipv4(ip)) => ip.is_unspecified(),
+        Some(Host::Ipv6(ip)) => ip.is_unspecified(),
+        _ => false,
+    };
+    if listens_everywhere {
+        return Err(format_err!(
+            "$GEMINI_HOSTNAME is needed when $GEMINI_HOST_PORT listens on every address"
+        ));
+    }
+
+    Ok((listening.host_str().unwrap_or_default().to_string(), port))
+}
+
+/// The log filter used when `$LOG_LEVEL` isn't set.
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Reads the tunables, looking variables up with `var`.
+fn load_tunables(var: &dyn Fn(&str) -> Option<String>) -> Result<Tunables> {
+    let feed_fetch_interval_min: u64 = var_or(var, "FEED_FETCH_INTERVAL_MIN", 60)?;
+    let tunables = Tunables {
+        feed_fetch_interval: Duration::from_secs(feed_fetch_interval_min * 60),
+        max_concurrent_fetches: var_or(var, "MAX_CONCURRENT_FETCHES", 8)?,
+        max_client_connections: var_or(var, "MAX_CLIENT_CONNECTIONS", 256)?,
+        entry_content_host_delay: Duration::from_millis(var_or(
+            var,
+            "ENTRY_CONTENT_HOST_DELAY_MS",
+            1000,
+        )?),
+        feed_host_delay: Duration::from_millis(var_or(var, "FEED_HOST_DELAY_MS", 1000)?),
+        dead_feed_failures: var_or(var, "DEAD_FEED_FAILURES", 10)?,
+        log_level: var_or(var, "LOG_LEVEL", DEFAULT_LOG_LEVEL.to_string())?,
+    };
+
+    if tunables.max_concurrent_fetches == 0 {
+        return Err(format_err!("$MAX_CONCURRENT_FETCHES must be at least 1"));
+    }
+
+    if tunables.max_client_connections == 0 {
+        return Err(format_err!("$MAX_CLIENT_CONNECTIONS must be at least 1"));
+    }
+
+    if tunables.dead_feed_failures < 0 {
+        return Err(format_err!("$DEAD_FEED_FAILURES can't be negative"));
+    }
+
+    EnvFilter::try_new(&tunables.log_level)
+        .map_err(|e| format_err!("invalid $LOG_LEVEL \"{}\": {}", tunables.log_level, e))?;
+
+    Ok(tunables)
+}
+
+/// `.env`, as it was when last read. Variables in the process environment
+/// take precedence over it, as at startup.
+#[derive(Debug)]
+struct EnvFile {
+    /// Where `.env` was found at startup, if it was.
+    path: Option<PathBuf>,
+    /// Variables the process started with, which the file can't override.
+    inherited: HashSet<String>,
+    variables: HashMap<String, String>,
+}
+
+impl EnvFile {
+    /// Loads `.env` before anything reads the environment. Like `dotenv`, a
+    /// missing or unreadable file is ignored here.
+    fn load() -> Self {
+        let inherited = std::env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .collect();
+        let mut env_file = Self {
+            path: dotenv::dotenv().ok(),
+            inherited,
+            variables: HashMap::new(),
+        };
+        let _ = env_file.reload();
+        env_file
+    }
+
+    /// Re-reads `.env`, for SIGHUP. This leaves the process environment
+    /// alone, since other threads may be reading it.
+    fn reload(&mut self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        // Reading the file without setting its variables is deprecated in
+        // favour of `from_path`, which sets them.
+        #[allow(deprecated)]
+        let variables = match dotenv::from_path_iter(path) {
+            Ok(variables) => variables.collect::<Result<_, _>>()?,
+            // Without the file, every variable it set goes back to its
+            // default.
+            Err(_) => HashMap::new(),
+        };
+        self.variables = variables;
+
+        Ok(())
+    }
+
+    /// The value of `name`, from the process environment if it was set
+    /// there at startup and otherwise from the file.
+    fn var(&self, name: &str) -> Option<String> {
+        if self.inherited.contains(name) {
+            std::env::var(name).ok()
+        } else {
+            self.variables.get(name).cloned()
+        }
+    }
+}
+
+/// Reads the tunables again for SIGHUP. The process environment can't be
+/// changed from outside, so only `.env` is re-read.
+fn reload_tunables(env_file: &mut EnvFile) -> Result<Tunables> {
+    env_file.reload()?;
+    load_tunables(&|name| env_file.var(name))
+}
+
+/// How fetches trust server certificates. TOFU starts out remembering
+/// certificates in memory, until `pin_certificates_in` has a database.
+fn tls_policy() -> Result<TlsPolicy> {
+    match dotenv::var("TLS_POLICY")
+        .unwrap_or_else(|_| "self-signed".to_string())
+        .as_str()
     {
+        "self-signed" => Ok(TlsPolicy::AllowSelfSigned),
+        "webpki" => Ok(TlsPolicy::WebPki),
+        "tofu" => Ok(TlsPolicy::Tofu(Arc::new(
+            fetch::MemoryCertificateStore::default(),
+        ))),
+        policy => Err(format_err!(
+            "invalid $TLS_POLICY \"{}\", expected self-signed, webpki or tofu",
+            policy
+        )),
+    }
+}
+
+/// Keeps the certificates TOFU pins in the database.
+fn pin_certificates_in(config: &mut Config, pool: &Pool<Any>) {
+    let store: Arc<dyn CertificateStore> =
+        Arc::new(certificates::SqlCertificateStore::new(pool.clone()));
+    for fetch_config in [&mut config.fetch, &mut config.entry_content_fetch] {
+        if let TlsPolicy::Tofu(_) = fetch_config.tls {
+            fetch_config.tls = TlsPolicy::Tofu(store.clone());
+        }
+    }
+}
+
+fn load_config(run_migrations: bool) -> Result<Config> {
+    let tls_policy = tls_policy()?;
+    let resolve_timeout = Duration::from_secs(env_var_or("FETCH_RESOLVE_TIMEOUT_SECS", 5)?);
+    let dns_cache = Arc::new(DnsCache::default());
+    let same_host_redirects = env_var_or("FETCH_SAME_HOST_REDIRECTS", false)?;
+    let allow_private_addresses = env_var_or("FETCH_ALLOW_PRIVATE_ADDRESSES", false)?;
+
+    Ok(Config {
+        database_url: database_url()?,
+        host_port: dotenv::var("HOST_PORT").ok(),
+        database: database_config()?,
+        run_migrations,
+        admin_users: dotenv::var("ADMIN_USERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|user| !user.is_empty())
+            .map(str::to_ascii_lowercase)
+            .collect(),
+        backup_dir: PathBuf::from(env_var_or("BACKUP_DIR", ".".to_string())?),
+        shutdown_grace_period: Duration::from_secs(env_var_or("SHUTDOWN_GRACE_SECS", 10)?),
+        max_connections_per_ip: match env_var_or("MAX_CONNECTIONS_PER_IP", 0)? {
+            0 => None,
+            limit => Some(limit),
+        },
+        max_commands_per_minute: match env_var_or("MAX_COMMANDS_PER_MINUTE", 0)? {
+            0 => None,
+            limit => Some(limit),
+        },
+        max_line_length: env_var_or("MAX_LINE_LENGTH", 4096)?,
+        disconnect_on_long_line: env_var_or("DISCONNECT_ON_LONG_LINE", false)?,
+        network_access: NetworkAccess {
+            allowed: access::parse_networks(&dotenv::var("ALLOWED_NETWORKS").unwrap_or_default())
+                .context("invalid $ALLOWED_NETWORKS")?,
+            denied: access::parse_networks(&dotenv::var("DENIED_NETWORKS").unwrap_or_default())
+                .context("invalid $DENIED_NETWORKS")?,
+        },
+        maintenance_window: dotenv::var("DATABASE_MAINTENANCE_WINDOW")
+            .ok()
+            .map(|window| {
+                window.parse().map_err(|e| {
+                    format_err!("invalid $DATABASE_MAINTENANCE_WINDOW \"{}\": {}", window, e)
+                })
+            })
+            .transpose()?,
+        subscription_backlog: chrono::Duration::days(env_var_or("SUBSCRIPTION_BACKLOG_DAYS", 0)?),
+        unsubscribed_feed_grace_period: chrono::Duration::days(env_var_or(
+            "UNSUBSCRIBED_FEED_GRACE_DAYS",
+            30,
+        )?),
+        fetch_log_retention: chrono::Duration::days(env_var_or("FETCH_LOG_RETENTION_DAYS", 30)?),
+        sync_retention: chrono::Duration::days(env_var_or("SYNC_RETENTION_DAYS", 30)?),
+        fetch: FetchConfig {
+            connect_timeout: Duration::from_secs(env_var_or("FETCH_CONNECT_TIMEOUT_SECS", 10)?),
+            read_timeout: Duration::from_secs(env_var_or("FETCH_READ_TIMEOUT_SECS", 30)?),
+            max_body_size: Some(env_var_or("FETCH_MAX_RESPONSE_BYTES", 4 * 1024 * 1024)?),
+            proxy: dotenv::var("FETCH_SOCKS5_PROXY").ok(),
+            tls: tls_policy.clone(),
+            resolve_timeout,
+            dns_cache: dns_cache.clone(),
+            same_host_redirects,
+            allow_private_addresses,
+        },
+        fetch_entry_contents: env_var_or("FETCH_ENTRY_CONTENTS", false)?,
+        entry_summaries: env_var_or("ENTRY_SUMMARIES", false)?,
+        entry_content_fetch: FetchConfig {
+            connect_timeout: Duration::from_secs(env_var_or("FETCH_CONNECT_TIMEOUT_SECS", 10)?),
+            read_timeout: Duration::from_secs(env_var_or("FETCH_READ_TIMEOUT_SECS", 30)?),
+            max_body_size: Some(env_var_or("ENTRY_CONTENT_MAX_BYTES", 256 * 1024)?),
+            proxy: dotenv::var("FETCH_SOCKS5_PROXY").ok(),
+            tls: tls_policy.clone(),
+            resolve_timeout,
+            dns_cache: dns_cache.clone(),
+            same_host_redirects,
+            allow_private_addresses,
+        },
+        capsule: dotenv::var("GEMINI_HOST_PORT")
+            .ok()
+            .map(|host_port| -> Result<_> {
+                let (hostname, port) = capsule_address(&host_port)?;
+                Ok(CapsuleConfig {
+                    host_port,
+                    hostname,
+                    port,
+                    cert_path: dotenv::var("GEMINI_CERT")
+                        .context("$GEMINI_CERT is needed with $GEMINI_HOST_PORT")?
+                        .into(),
+                    key_path: dotenv::var("GEMINI_KEY")
+                        .context("$GEMINI_KEY is needed with $GEMINI_HOST_PORT")?
+                        .into(),
+                })
+            })
+            .transpose()?,
+        api_address: dotenv::var("API_HOST_PORT")
+            .ok()
+            .map(|address| {
+                address
+                    .parse()
+                    .map_err(|e| format_err!("invalid $API_HOST_PORT \"{}\": {}", address, e))
+            })
+            .transpose()?,
+        grpc_address: dotenv::var("GRPC_HOST_PORT")
+            .ok()
+            .map(|address| {
+                address
+                    .parse()
+                    .map_err(|e| format_err!("invalid $GRPC_HOST_PORT \"{}\": {}", address, e))
+            })
+            .transpose()?,
+        nntp_host_port: dotenv::var("NNTP_HOST_PORT").ok(),
+        irc: dotenv::var("IRC_SERVER")
+            .ok()
+            .map(|server| -> Result<_> {
+                Ok(IrcConfig {
+                    server,
+                    nick: env_var_or("IRC_NICK", "seymour".to_string())?,
+                    channel: dotenv::var("IRC_CHANNEL")
+                        .context("$IRC_CHANNEL is needed with $IRC_SERVER")?,
+                })
+            })
+            .transpose()?,
+        tunables: load_tunables(&|name| dotenv::var(name).ok())?,
+    })
+}
+
+/// The configuration when nothing but the database is set, for tests that
+/// don't use the database.
+#[cfg(test)]
+fn test_config() -> Config {
+    std::env::set_var("DATABASE_URL", "sqlite::memory:");
+    load_config(false).expect("the default configuration is valid")
+}
+
+/// Checks every feed that's due once, without starting the server.
+async fn fetch_once(mut config: Config) -> Result<()> {
+    let pool = open_database(
+        &config.database_url,
+        &config.database,
+        config.run_migrations,
+    )
+    .await?;
+    pin_certificates_in(&mut config, &pool);
+
+    let state = FetcherState {
+        entry_limiter: HostRateLimiter::new(config.tunables.entry_content_host_delay),
+        feed_limiter: HostRateLimiter::new(config.tunables.feed_host_delay),
+        robots: RobotsCache::new(),
+        new_entries: events::channel(),
+    };
+    // Nothing asks a one-off fetch to stop early, but the sender has to
+    // outlive it.
+    let (_request_shutdown, shutdown) = Shutdown::new();
+
+    check_feeds(&pool, &config, &config.tunables, &state, &shutdown, false).await?;
+    pool.close().await;
+
+    Ok(())
+}
+
+/// Fetches and parses a feed without storing anything, printing its
+/// entries and the link lines that weren't taken as entries, to debug
+/// feeds whose entries go missing.
+async fn show_parsed_feed(config: Config, url: &str) -> Result<()> {
+    let page = fetch(url, &config.fetch)
+        .await
+        .with_context(|| format!("failed to fetch page \"{}\"", url))?;
+    let feed = Feed::from_page(page, None)
+        .with_context(|| format!("failed to parse \"{}\" as a feed", url))?;
+
+    for entry in &feed.entries {
+        println!(
+            "{} {} {}",
+            entry.published_at.format(TIMESTAMP_FORMAT),
+            entry.url,
+            entry.title
+        );
+    }
+    for skipped in &feed.skipped {
+        println!("skipped ({}): {}", skipped.reason, skipped.line);
+    }
+
+    Ok(())
+}
+
+/// Follows SIGHUP with `update_tunables`, where that exists.
+fn spawn_reload(
+    update_tunables: watch::Sender<Tunables>,
+    initial: Tunables,
+    env_file: EnvFile,
+    set_log_level: SetLogLevel,
+) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        let mut env_file = env_file;
+        let load = move || reload_tunables(&mut env_file);
+        if let Err(e) =
+            reload::reload_on_hangup(update_tunables, initial, load, set_log_level).await
+        {
+            error!("configuration reloading failed: {}", e);
+        }
+    });
+    #[cfg(not(unix))]
+    drop((update_tunables, initial, env_file, set_log_level));
+}
+
+/// Follows SIGUSR1 and SIGUSR2 with the returned receiver, where those exist.
+fn spawn_wake() -> mpsc::Receiver<Wake> {
+    // Holds one request, so signals sent during a cycle start one more.
+    let (sender, receiver) = mpsc::channel(1);
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        if let Err(e) = wake::wake_on_user_signals(sender).await {
+            error!("waiting for fetch signals failed: {}", e);
+        }
+    });
+    #[cfg(not(unix))]
+    drop(sender);
+
+    receiver
+}
+
+/// Runs only the fetcher, for deploying it separately from a server
+/// started with `serve --no-fetch` against the same database.
+async fn run_fetcher(
+    mut config: Config,
+    env_file: EnvFile,
+    set_log_level: SetLogLevel,
+) -> Result<()> {
+    let pool = open_database(
+        &config.database_url,
+        &config.database,
+        config.run_migrations,
+    )
+    .await?;
+    pin_certificates_in(&mut config, &pool);
+
+    let (update_tunables, tunables) = watch::channel(config.tunables.clone());
+    spawn_reload(
+        update_tunables,
+        config.tunables.clone(),
+        env_file,
+        set_log_level,
+    );
+
+    let (request_shutdown, shutdown) = Shutdown::new();
+    tokio::spawn(async move {
+        if let Err(e) = shutdown::signal().await {
+            error!("failed to wait for signals: {}", e);
+        }
+        info!("Shutting down");
+        let _ = request_shutdown.broadcast(true);
+    });
+
+    // A separate fetcher has no one to announce new entries to.
+    check_feeds_task(
+        &pool,
+        &config,
+        tunables,
+        events::channel(),
+        spawn_wake(),
+        shutdown,
+    )
+    .await?;
+    pool.close().await;
+
+    Ok(())
+}
+
+/// Runs the server, along with the fetcher unless `fetch` is off.
+async fn serve(
+    mut config: Config,
+    env_file: EnvFile,
+    set_log_level: SetLogLevel,
+    fetch: bool,
+) -> Result<()> {
+    #[cfg(not(feature = "grpc"))]
+    {
+        if config.grpc_address.is_some() {
+            return Err(format_err!(
+                "$GRPC_HOST_PORT is set, but seymour was built without the grpc feature"
+            ));
+        }
+    }
+
+    let pool = open_database(
+        &config.database_url,
+        &config.database,
+        config.run_migrations,
+    )
+    .await?;
+    pin_certificates_in(&mut config, &pool);
+
+    username::warn_about_legacy_usernames(&pool).await?;
+    backfill_entry_keys(&pool).await?;
+
+    let mut listener = listen(&config).await?;
+
+    let store = Arc::new(SqlStore::new(pool.clone(), config.database_url.clone()));
+    let (update_tunables, tunables) = watch::channel(config.tunables.clone());
+    let config = Arc::new(config);
+    let (request_shutdown, shutdown) = Shutdown::new();
+    let new_entries = events::channel();
+
+    spawn_reload(
+        update_tunables,
+        config.tunables.clone(),
+        env_file,
+        set_log_level,
+    );
+
+    let fetcher = if fetch {
         let pool = pool.clone();
-        tokio::spawn(async move {
-            check_feeds_task(&pool, &config)
+        let config = config.clone();
+        let tunables = tunables.clone();
+        let new_entries = new_entries.clone();
+        let wake = spawn_wake();
+        let shutdown = shutdown.clone();
+        Some(tokio::spawn(async move {
+            check_feeds_task(&pool, &config, tunables, new_entries, wake, shutdown)
                 .await
                 .expect("feed manager failed");
+        }))
+    } else {
+        info!("Not fetching feeds, leaving that to `seymour fetcher`");
+        None
+    };
+
+    let maintenance = {
+        let pool = pool.clone();
+        let config = config.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            maintenance_task(&pool, &config, shutdown)
+                .await
+                .expect("database maintenance failed");
+        })
+    };
+
+    if let Some(address) = config.api_address {
+        let store = store.clone();
+        let config = config.clone();
+        let new_entries = new_entries.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(e) = api::serve(address, store, config, new_entries, shutdown).await {
+                error!("HTTP API failed: {:#}", e);
+            }
         });
     }
 
-    loop {
-        let (stream, address) = listener.accept().await?;
+    #[cfg(feature = "grpc")]
+    {
+        if let Some(address) = config.grpc_address {
+            let store = store.clone();
+            let config = config.clone();
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                if let Err(e) = grpc::serve(address, store, config, shutdown).await {
+                    error!("gRPC failed: {:#}", e);
+                }
+            });
+        }
+    }
 
-        let pool = pool.clone();
+    if let Some(host_port) = config.nntp_host_port.clone() {
+        let store = store.clone();
+        let network_access = config.network_access.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(e) = nntp::serve(host_port, store, network_access, shutdown).await {
+                error!("NNTP gateway failed: {:#}", e);
+            }
+        });
+    }
 
+    if let Some(irc_config) = config.irc.clone() {
+        tokio::spawn(irc::serve(
+            irc_config,
+            store.clone(),
+            new_entries.subscribe(),
+            shutdown.clone(),
+        ));
+    }
+
+    let counts = ConnectionCounts::default();
+
+    if let Some(capsule_config) = config.capsule.clone() {
+        let store = store.clone();
+        let network_access = config.network_access.clone();
+        let counts = counts.clone();
+        let max_connections_per_ip = config.max_connections_per_ip;
+        let tunables = tunables.clone();
+        let shutdown = shutdown.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, address, &pool).await {
-                error!("client handler failed: {}", e);
+            if let Err(e) = capsule::serve(
+                capsule_config,
+                store,
+                network_access,
+                counts,
+                max_connections_per_ip,
+                tunables,
+                shutdown,
+            )
+            .await
+            {
+                error!("Gemini interface failed: {:#}", e);
             }
         });
     }
+
+    // Every connection holds a sender, so the receiver sees the channel
+    // close once they've all finished.
+    let (connection_open, mut connections_closed) = mpsc::channel::<()>(1);
+
+    let commands = Arc::new(Commands::new());
+    let rejecting = Arc::new(AtomicUsize::new(0));
+
+    let signal = shutdown::signal();
+    tokio::pin!(signal);
+
+    loop {
+        let (stream, address) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            result = &mut signal => {
+                result?;
+                break;
+            }
+        };
+
+        // Clients outside the allowed networks aren't told anything.
+        if !config.network_access.permits(address.ip()) {
+            info!("Refusing {}, not in an allowed network", address);
+            continue;
+        }
+
+        // Both counts are held until the connection's task ends, however
+        // it ends.
+        let max_connections = tunables.borrow().max_client_connections;
+        let counted = match CountedConnection::try_count(&counts.open, max_connections) {
+            Some(counted) => counted,
+            None => {
+                info!("Turning away {}, too many connections", address);
+                reject_connection(stream, &rejecting);
+                continue;
+            }
+        };
+
+        let counted_for_ip = match counts
+            .per_ip
+            .try_open(address.ip(), config.max_connections_per_ip)
+        {
+            Some(counted) => counted,
+            None => {
+                info!("Turning away {}, too many connections from it", address);
+                reject_connection(stream, &rejecting);
+                continue;
+            }
+        };
+
+        let commands = commands.clone();
+        let store = store.clone();
+        let config = config.clone();
+        let shutdown = shutdown.clone();
+        let connection_open = connection_open.clone();
+
+        let span = info_span!("connection", %address, user = Empty);
+        tokio::spawn(
+            async move {
+                if let Err(e) =
+                    handle_connection(stream, &commands, &*store, &config, shutdown).await
+                {
+                    error!("client handler failed: {}", e);
+                }
+                drop((counted, counted_for_ip));
+                drop(connection_open);
+            }
+            .instrument(span),
+        );
+    }
+
+    info!("Shutting down");
+    drop(listener);
+    let _ = request_shutdown.broadcast(true);
+
+    drop(connection_open);
+    if timeout(config.shutdown_grace_period, connections_closed.recv())
+        .await
+        .is_err()
+    {
+        info!("Closing client connections still busy after the grace period");
+    }
+
+    if let Some(fetcher) = fetcher {
+        if let Err(e) = fetcher.await {
+            error!("feed manager failed to stop: {}", e);
+        }
+    }
+
+    if let Err(e) = maintenance.await {
+        error!("database maintenance failed to stop: {}", e);
+    }
+
+    pool.close().await;
+
+    Ok(())
+}
+
+fn cli() -> App<'static, 'static> {
+    let no_migrate = Arg::with_name("no-migrate")
+        .long("no-migrate")
+        .help("Don't create the database or apply migrations at startup");
+    let path = Arg::with_name("path").required(true);
+    let username = Arg::with_name("username").required(true);
+
+    App::new("seymour")
+        .about("A headless feed aggregator for Gemini")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::VersionlessSubcommands)
+        // Running with no subcommand serves, as it always has.
+        .arg(no_migrate.clone())
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Runs the server and feed fetcher (the default)")
+                .arg(no_migrate.clone())
+                .arg(
+                    Arg::with_name("no-fetch")
+                        .long("no-fetch")
+                        .help("Leaves fetching feeds to a separate `seymour fetcher`"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fetcher")
+                .about("Runs only the feed fetcher, until interrupted")
+                .arg(no_migrate.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Creates the database if needed and applies migrations"),
+        )
+        .subcommand(
+            SubCommand::with_name("fetch-once")
+                .about("Checks every feed that's due once, then exits")
+                .arg(no_migrate),
+        )
+        .subcommand(
+            SubCommand::with_name("check-feed")
+                .about("Fetches and parses a feed, showing its entries and skipped lines")
+                .arg(Arg::with_name("url").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("healthcheck")
+                .about("Checks the database is reachable and the fetcher is running")
+                .arg(
+                    Arg::with_name("max-fetch-age")
+                        .long("max-fetch-age")
+                        .value_name("SECONDS")
+                        .help("Fails if no fetch cycle has finished in this long"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("adduser")
+                .about("Creates a user if it doesn't already exist")
+                .arg(username.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("grantadmin")
+                .about("Lets a user run admin commands, creating it if needed")
+                .arg(username.clone())
+                .arg(
+                    Arg::with_name("revoke")
+                        .long("revoke")
+                        .help("Takes admin commands away from the user instead"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("addtoken")
+                .about("Creates an HTTP API token for a user and prints it")
+                .arg(username.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("linkcert")
+                .about("Lets a Gemini client certificate sign in as a user")
+                .arg(username.clone())
+                .arg(Arg::with_name("fingerprint").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("Snapshots a SQLite database, even while the server is running")
+                .arg(path.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("dump")
+                .about("Writes every table to a newline-delimited JSON file")
+                .arg(path.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Loads a dump into an empty database")
+                .arg(path.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate-db")
+                .about("Copies everything from one database into another, empty one")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .value_name("URL")
+                        .required(true)
+                        .help("The database to copy, e.g. sqlite://seymour.db"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("URL")
+                        .required(true)
+                        .help("The database to copy into, e.g. postgres://localhost/seymour"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-user")
+                .about("Prints a user's subscriptions and read entries as JSON")
+                .arg(username.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("import-user")
+                .about("Merges in a user written by export-user")
+                .arg(path),
+        )
+        .subcommand(
+            SubCommand::with_name("import-instance")
+                .about("Copies a user's subscriptions and read entries from another instance")
+                .arg(
+                    Arg::with_name("address")
+                        .required(true)
+                        .help("The other instance's host:port"),
+                )
+                .arg(username),
+        )
+}
+
+/// Prints how long ago the last fetch cycle finished, failing if that's
+/// longer than `--max-fetch-age` allows. Connecting to the database at all
+/// is the rest of the check.
+async fn healthcheck(store: &dyn Store, args: &ArgMatches<'_>) -> Result<()> {
+    let max_age: Option<i64> = args
+        .value_of("max-fetch-age")
+        .map(str::parse)
+        .transpose()
+        .context("invalid --max-fetch-age")?;
+    let age = store.last_fetch_cycle().await?.map(seconds_since);
+
+    match age {
+        Some(age) => println!("ok, last fetch cycle finished {}s ago", age),
+        None => println!("ok, no fetch cycle has finished yet"),
+    }
+
+    match (max_age, age) {
+        (Some(max_age), Some(age)) if age > max_age => Err(format_err!(
+            "last fetch cycle finished {}s ago, more than {}s",
+            age,
+            max_age
+        )),
+        (Some(_), None) => Err(format_err!("no fetch cycle has finished yet")),
+        _ => Ok(()),
+    }
+}
+
+/// Copies every table from one database into another, e.g. to move from
+/// SQLite to PostgreSQL. Both are migrated first, and the target created if
+/// needed.
+async fn migrate_db(from: &str, to: &str) -> Result<()> {
+    let config = database_config()?;
+    let source = open_database(from, &config, true).await?;
+    let target = open_database(to, &config, true).await?;
+
+    let result = dump::copy(&source, from, &target, to).await;
+
+    source.close().await;
+    target.close().await;
+
+    result
+}
+
+/// Runs any subcommand other than `serve`, `fetch-once`, `check-feed` and
+/// `migrate-db`, none of which need more configuration than the database's.
+async fn run_subcommand(subcommand: &str, args: &ArgMatches<'_>) -> Result<()> {
+    let database_url = database_url()?;
+    let creates_database =
+        ["migrate", "restore", "import-user", "import-instance"].contains(&subcommand);
+    let pool = open_database(&database_url, &database_config()?, creates_database).await?;
+
+    // Required arguments are enforced by clap.
+    let path = args.value_of("path").unwrap_or_default();
+    let username = match args.value_of("username") {
+        Some(username) => username::normalize(username)?,
+        None => String::new(),
+    };
+    let username = username.as_str();
+
+    let result = match subcommand {
+        "migrate" => Ok(()),
+        "healthcheck" => healthcheck(&SqlStore::new(pool.clone(), database_url), args).await,
+        "adduser" => {
+            let store = SqlStore::new(pool.clone(), database_url);
+            store
+                .user_id(username)
+                .await
+                .map(|id| info!("User \"{}\" has ID {}", username, id))
+        }
+        "grantadmin" => {
+            let store = SqlStore::new(pool.clone(), database_url);
+            let user_id = store.user_id(username).await?;
+            let admin = !args.is_present("revoke");
+            store.set_admin(user_id, admin).await?;
+            if admin {
+                info!("\"{}\" is now an admin", username);
+            } else {
+                info!("\"{}\" is no longer an admin", username);
+            }
+            Ok(())
+        }
+        "addtoken" => {
+            let store = SqlStore::new(pool.clone(), database_url);
+            let user_id = store.user_id(username).await?;
+            let token = api::new_token();
+            store.add_token(user_id, &api::hash_token(&token)).await?;
+            // The token can't be recovered later, only its hash is kept.
+            println!("{}", token);
+            Ok(())
+        }
+        "linkcert" => {
+            let fingerprint = args.value_of("fingerprint").unwrap_or_default();
+            let store = SqlStore::new(pool.clone(), database_url);
+            let user_id = store.user_id(username).await?;
+            store
+                .link_certificate(user_id, &fingerprint.to_lowercase())
+                .await
+                .map(|_| info!("Linked certificate to \"{}\"", username))
+        }
+        // Snapshots the database without stopping a running server.
+        "backup" => {
+            SqlStore::new(pool.clone(), database_url)
+                .backup(Path::new(path))
+                .await
+        }
+        "dump" => dump::dump(&pool, path).await,
+        "restore" => dump::restore(&pool, &database_url, path).await,
+        "export-user" => user_transfer::export_user(&pool, username).await,
+        "import-user" => user_transfer::import_user(&pool, path).await,
+        "import-instance" => {
+            let address = args.value_of("address").unwrap_or_default();
+            user_transfer::import_instance(&pool, address, username).await
+        }
+        _ => Err(format_err!("unknown subcommand \"{}\"", subcommand)),
+    };
+
+    pool.close().await;
+
+    result
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let env_file = EnvFile::load();
+    let (set_log_level, _log_guard) = logging::init()?;
+
+    let matches = cli().get_matches();
+    // `--no-migrate` counts whether it comes before or after the subcommand.
+    let migrate =
+        |args: &ArgMatches<'_>| !matches.is_present("no-migrate") && !args.is_present("no-migrate");
+
+    match matches.subcommand() {
+        ("serve", Some(args)) => {
+            serve(
+                load_config(migrate(args))?,
+                env_file,
+                set_log_level,
+                !args.is_present("no-fetch"),
+            )
+            .await
+        }
+        ("fetcher", Some(args)) => {
+            run_fetcher(load_config(migrate(args))?, env_file, set_log_level).await
+        }
+        ("fetch-once", Some(args)) => fetch_once(load_config(migrate(args))?).await,
+        ("migrate-db", Some(args)) => {
+            migrate_db(
+                args.value_of("from").unwrap_or_default(),
+                args.value_of("to").unwrap_or_default(),
+            )
+            .await
+        }
+        ("check-feed", Some(args)) => {
+            show_parsed_feed(
+                load_config(false)?,
+                args.value_of("url").unwrap_or_default(),
+            )
+            .await
+        }
+        (subcommand, Some(args)) => run_subcommand(subcommand, args).await,
+        _ => {
+            serve(
+                load_config(migrate(&matches))?,
+                env_file,
+                set_log_level,
+                true,
+            )
+            .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{
+        read_line, slow_down_secs, ClientLine, EnvFile, DEFAULT_SLOW_DOWN_SECS, MAX_SLOW_DOWN_SECS,
+    };
+
+    /// Reads every line of `input`, with `None` standing in for lines that are too long.
+    async fn read_lines(mut input: &[u8], max_len: usize) -> Vec<Option<String>> {
+        let mut lines = Vec::new();
+        while let Some(line) = read_line(&mut input, max_len).await.unwrap() {
+            lines.push(match line {
+                ClientLine::Line(line) => Some(line),
+                ClientLine::TooLong => None,
+            });
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn lines_of_the_longest_length_are_read() {
+        assert_eq!(
+            read_lines(b"abcd\r\nefgh\n", 4).await,
+            vec![Some("abcd".to_string()), Some("efgh".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn longer_lines_are_too_long() {
+        assert_eq!(read_lines(b"abcde\r\n", 4).await, vec![None]);
+        assert_eq!(read_lines(b"abcde\n", 4).await, vec![None]);
+    }
+
+    #[tokio::test]
+    async fn reading_resumes_after_a_line_that_is_too_long() {
+        let mut input = vec![b'x'; 10_000];
+        input.extend_from_slice(b"\r\nLIST\r\n");
+
+        assert_eq!(
+            read_lines(&input, 16).await,
+            vec![None, Some("LIST".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_line_cut_off_by_eof_is_still_read() {
+        assert_eq!(
+            read_lines(b"LIST\r\nQUIT", 16).await,
+            vec![Some("LIST".to_string()), Some("QUIT".to_string())]
+        );
+        assert_eq!(read_lines(b"", 16).await, Vec::<Option<String>>::new());
+    }
+
+    #[test]
+    fn env_file_never_overrides_the_process_environment() {
+        let mut variables = HashMap::new();
+        variables.insert("PATH".to_string(), "file".to_string());
+        variables.insert("SEYMOUR_TEST_FROM_FILE".to_string(), "file".to_string());
+        let env_file = EnvFile {
+            path: None,
+            inherited: vec!["PATH".to_string()].into_iter().collect(),
+            variables,
+        };
+
+        assert_eq!(env_file.var("PATH"), std::env::var("PATH").ok());
+        assert_eq!(
+            env_file.var("SEYMOUR_TEST_FROM_FILE").as_deref(),
+            Some("file")
+        );
+        assert_eq!(env_file.var("SEYMOUR_TEST_NOWHERE"), None);
+    }
+
+    #[test]
+    fn slow_down_waits_as_long_as_asked() {
+        assert_eq!(slow_down_secs("120"), 120);
+        assert_eq!(slow_down_secs(" 0 "), 0);
+        assert_eq!(
+            slow_down_secs(&MAX_SLOW_DOWN_SECS.to_string()),
+            MAX_SLOW_DOWN_SECS
+        );
+    }
+
+    #[test]
+    fn slow_down_falls_back_on_unusable_meta() {
+        for meta in &[
+            "",
+            "soon",
+            "-5",
+            "604801",
+            "99999999999999999",
+            "99999999999999999999",
+        ] {
+            assert_eq!(slow_down_secs(meta), DEFAULT_SLOW_DOWN_SECS, "{:?}", meta);
+        }
+    }
 }
@@ -0,0 +1,427 @@
+//! A read-only NNTP gateway, so newsreaders like slrn and tin can be used
+//! as seymour clients.
+//!
+//! Each subscribed feed is the newsgroup `seymour.feed.<feed id>`, and each
+//! of its entries an article numbered by the entry's ID. Users sign in with
+//! `AUTHINFO USER <username>` and `AUTHINFO PASS <token>`, using a token
+//! made with `seymour addtoken`. Retrieving an article or its body marks
+//! the entry read.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::Utc;
+use chrono_tz::Tz;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::field::Empty;
+use tracing::{error, info, info_span, Instrument, Span};
+
+use crate::access::NetworkAccess;
+use crate::api::hash_token;
+use crate::shutdown::Shutdown;
+use crate::store::{FeedEntry, Store};
+use crate::{in_timezone, username};
+
+const GROUP_PREFIX: &str = "seymour.feed.";
+/// Message-IDs need a domain, and seymour doesn't know its own.
+const MESSAGE_ID_DOMAIN: &str = "seymour.invalid";
+
+fn group_name(feed_id: i64) -> String {
+    format!("{}{}", GROUP_PREFIX, feed_id)
+}
+
+fn message_id(entry_id: i64) -> String {
+    format!("<{}@{}>", entry_id, MESSAGE_ID_DOMAIN)
+}
+
+/// Keeps titles from breaking out of their header line.
+fn header_value(value: &str) -> String {
+    value.replace(&['\r', '\n'][..], " ")
+}
+
+/// The group the client selected with GROUP or LISTGROUP.
+struct Group {
+    feed_id: i64,
+    entries: Vec<FeedEntry>,
+    /// The article STAT, HEAD, BODY and ARTICLE use without an argument.
+    current: Option<i64>,
+}
+
+impl Group {
+    fn summary(&self) -> String {
+        match (self.entries.first(), self.entries.last()) {
+            (Some(first), Some(last)) => format!(
+                "{} {} {} {}",
+                self.entries.len(),
+                first.id,
+                last.id,
+                group_name(self.feed_id)
+            ),
+            _ => format!("0 1 0 {}", group_name(self.feed_id)),
+        }
+    }
+}
+
+/// How much of an article a retrieval command wants.
+#[derive(Clone, Copy, PartialEq)]
+enum Part {
+    Article,
+    Head,
+    Body,
+    Stat,
+}
+
+struct Session<'a> {
+    store: &'a dyn Store,
+    /// Given by AUTHINFO USER, awaiting AUTHINFO PASS.
+    username: Option<String>,
+    user_id: Option<i64>,
+    group: Option<Group>,
+}
+
+impl<'a> Session<'a> {
+    fn new(store: &'a dyn Store) -> Self {
+        Self {
+            store,
+            username: None,
+            user_id: None,
+            group: None,
+        }
+    }
+
+    /// Answers one command with the lines to send, including the `.` ending
+    /// multi-line responses.
+    async fn respond(&mut self, line: &str) -> Result<Vec<String>> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default().to_uppercase();
+        let arguments: Vec<&str> = words.collect();
+
+        match (command.as_str(), arguments.as_slice()) {
+            ("CAPABILITIES", _) => Ok(vec![
+                "101 Capability list:".to_string(),
+                "VERSION 2".to_string(),
+                "READER".to_string(),
+                "LIST ACTIVE NEWSGROUPS".to_string(),
+                "AUTHINFO USER".to_string(),
+                ".".to_string(),
+            ]),
+            ("MODE", [mode]) if mode.eq_ignore_ascii_case("READER") => {
+                Ok(vec!["201 Posting prohibited".to_string()])
+            }
+            ("AUTHINFO", [kind, value]) if kind.eq_ignore_ascii_case("USER") => {
+                self.username = Some(value.to_string());
+                Ok(vec!["381 Password required".to_string()])
+            }
+            ("AUTHINFO", [kind, value]) if kind.eq_ignore_ascii_case("PASS") => {
+                self.authenticate(value).await
+            }
+            ("QUIT", _) => Ok(vec!["205 Bye".to_string()]),
+            _ => match self.user_id {
+                Some(user_id) => self.respond_as(user_id, &command, &arguments).await,
+                None => Ok(vec!["480 Authentication required".to_string()]),
+            },
+        }
+    }
+
+    async fn authenticate(&mut self, token: &str) -> Result<Vec<String>> {
+        let username = match self.username.take() {
+            Some(username) => username,
+            None => return Ok(vec!["482 AUTHINFO USER first".to_string()]),
+        };
+
+        let user_id = match self.store.user_for_token(&hash_token(token)).await? {
+            Some(user_id) => user_id,
+            None => return Ok(vec!["481 Authentication failed".to_string()]),
+        };
+        let username = match username::normalize(&username) {
+            Ok(username) => username,
+            Err(_) => return Ok(vec!["481 Authentication failed".to_string()]),
+        };
+        let users = self.store.users().await?;
+        if !users
+            .iter()
+            .any(|user| user.id == user_id && user.username == username)
+        {
+            return Ok(vec!["481 Authentication failed".to_string()]);
+        }
+
+        Span::current().record("user", &username.as_str());
+        self.user_id = Some(user_id);
+        Ok(vec!["281 Authentication accepted".to_string()])
+    }
+
+    async fn respond_as(
+        &mut self,
+        user_id: i64,
+        command: &str,
+        arguments: &[&str],
+    ) -> Result<Vec<String>> {
+        let argument = arguments.first().copied();
+        match (command, arguments) {
+            ("LIST", []) => self.list_active(user_id).await,
+            ("LIST", [keyword]) if keyword.eq_ignore_ascii_case("ACTIVE") => {
+                self.list_active(user_id).await
+            }
+            ("LIST", [keyword]) if keyword.eq_ignore_ascii_case("NEWSGROUPS") => {
+                self.list_newsgroups(user_id).await
+            }
+            ("GROUP", [name]) => self.select_group(user_id, name, false).await,
+            ("LISTGROUP", [name]) => self.select_group(user_id, name, true).await,
+            ("LISTGROUP", []) => match self.group.as_ref().map(|group| group_name(group.feed_id)) {
+                Some(name) => self.select_group(user_id, &name, true).await,
+                None => Ok(vec!["412 No newsgroup selected".to_string()]),
+            },
+            ("ARTICLE", _) => self.retrieve(user_id, Part::Article, argument).await,
+            ("HEAD", _) => self.retrieve(user_id, Part::Head, argument).await,
+            ("BODY", _) => self.retrieve(user_id, Part::Body, argument).await,
+            ("STAT", _) => self.retrieve(user_id, Part::Stat, argument).await,
+            _ => Ok(vec!["500 Unknown command".to_string()]),
+        }
+    }
+
+    async fn list_active(&self, user_id: i64) -> Result<Vec<String>> {
+        let mut lines = vec!["215 List of newsgroups follows".to_string()];
+        for subscription in self.store.subscriptions(user_id).await? {
+            let entries = self
+                .store
+                .feed_entries(user_id, subscription.feed_id)
+                .await?;
+            let (low, high) = match (entries.first(), entries.last()) {
+                (Some(first), Some(last)) => (first.id, last.id),
+                _ => (1, 0),
+            };
+            lines.push(format!(
+                "{} {} {} n",
+                group_name(subscription.feed_id),
+                high,
+                low
+            ));
+        }
+        lines.push(".".to_string());
+
+        Ok(lines)
+    }
+
+    async fn list_newsgroups(&self, user_id: i64) -> Result<Vec<String>> {
+        let mut lines = vec!["215 List of newsgroups follows".to_string()];
+        for subscription in self.store.subscriptions(user_id).await? {
+            let description = subscription.title.unwrap_or(subscription.url);
+            lines.push(format!(
+                "{}\t{}",
+                group_name(subscription.feed_id),
+                header_value(&description)
+            ));
+        }
+        lines.push(".".to_string());
+
+        Ok(lines)
+    }
+
+    async fn select_group(
+        &mut self,
+        user_id: i64,
+        name: &str,
+        list_articles: bool,
+    ) -> Result<Vec<String>> {
+        let feed_id = match name.strip_prefix(GROUP_PREFIX).map(str::parse) {
+            Some(Ok(feed_id)) => feed_id,
+            _ => return Ok(vec!["411 No such newsgroup".to_string()]),
+        };
+        if self
+            .store
+            .subscribed_feed(user_id, feed_id)
+            .await?
+            .is_none()
+        {
+            return Ok(vec!["411 No such newsgroup".to_string()]);
+        }
+
+        let entries = self.store.feed_entries(user_id, feed_id).await?;
+        let group = Group {
+            feed_id,
+            current: entries.first().map(|entry| entry.id),
+            entries,
+        };
+
+        let mut lines = vec![format!("211 {}", group.summary())];
+        if list_articles {
+            lines.extend(group.entries.iter().map(|entry| entry.id.to_string()));
+            lines.push(".".to_string());
+        }
+        self.group = Some(group);
+
+        Ok(lines)
+    }
+
+    async fn retrieve(
+        &mut self,
+        user_id: i64,
+        part: Part,
+        argument: Option<&str>,
+    ) -> Result<Vec<String>> {
+        let group = match &mut self.group {
+            Some(group) => group,
+            None if argument.is_some_and(|argument| argument.starts_with('<')) => {
+                return Ok(vec!["430 No such article".to_string()]);
+            }
+            None => return Ok(vec!["412 No newsgroup selected".to_string()]),
+        };
+
+        // Message-IDs can only be looked up in the selected group.
+        let (id, by_number) = match argument {
+            None => match group.current {
+                Some(id) => (id, true),
+                None => return Ok(vec!["420 No current article".to_string()]),
+            },
+            Some(argument) if argument.starts_with('<') => {
+                match group
+                    .entries
+                    .iter()
+                    .find(|entry| message_id(entry.id) == argument)
+                {
+                    Some(entry) => (entry.id, false),
+                    None => return Ok(vec!["430 No such article".to_string()]),
+                }
+            }
+            Some(argument) => match argument.parse() {
+                Ok(id) => (id, true),
+                Err(_) => return Ok(vec!["501 Invalid article number".to_string()]),
+            },
+        };
+
+        let entry = match group.entries.iter_mut().find(|entry| entry.id == id) {
+            Some(entry) => entry,
+            None if by_number => return Ok(vec!["423 No such article number".to_string()]),
+            None => return Ok(vec!["430 No such article".to_string()]),
+        };
+        if by_number {
+            group.current = Some(id);
+        }
+
+        let number = if by_number { id } else { 0 };
+        let (code, description) = match part {
+            Part::Article => (220, "article follows"),
+            Part::Head => (221, "headers follow"),
+            Part::Body => (222, "body follows"),
+            Part::Stat => (223, "article exists"),
+        };
+        let mut lines = vec![format!(
+            "{} {} {} {}",
+            code,
+            number,
+            message_id(entry.id),
+            description
+        )];
+        if part == Part::Stat {
+            return Ok(lines);
+        }
+
+        if part != Part::Body {
+            let timezone = self.store.timezone(user_id).await?;
+            lines.extend(headers(group.feed_id, entry, timezone));
+        }
+        if part == Part::Article {
+            lines.push(String::new());
+        }
+        if part != Part::Head {
+            lines.push(dot_stuffed(&entry.url));
+
+            if !entry.read {
+                self.store
+                    .mark_read(user_id, entry.id, Utc::now().naive_utc())
+                    .await?;
+                entry.read = true;
+            }
+        }
+        lines.push(".".to_string());
+
+        Ok(lines)
+    }
+}
+
+fn headers(feed_id: i64, entry: &FeedEntry, timezone: Tz) -> Vec<String> {
+    let published_at = in_timezone(entry.published_at, timezone);
+    vec![
+        "Path: seymour".to_string(),
+        format!("From: seymour@{}", MESSAGE_ID_DOMAIN),
+        format!("Newsgroups: {}", group_name(feed_id)),
+        format!("Subject: {}", header_value(&entry.title)),
+        format!("Date: {}", published_at.to_rfc2822()),
+        format!("Message-ID: {}", message_id(entry.id)),
+        "Content-Type: text/plain; charset=utf-8".to_string(),
+    ]
+}
+
+/// Doubles a leading `.` so the line isn't taken for the end of the body.
+fn dot_stuffed(line: &str) -> String {
+    if line.starts_with('.') {
+        format!(".{}", line)
+    } else {
+        line.to_string()
+    }
+}
+
+async fn handle_connection(stream: TcpStream, store: &dyn Store) -> Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut session = Session::new(store);
+
+    writer
+        .write_all(b"201 seymour NNTP gateway ready, posting prohibited\r\n")
+        .await?;
+
+    while let Some(line) = lines.next_line().await? {
+        let response = session.respond(&line).await.unwrap_or_else(|e| {
+            error!("failed to handle NNTP command: {:#}", e);
+            vec!["403 Internal error".to_string()]
+        });
+        for line in &response {
+            writer.write_all(format!("{}\r\n", line).as_bytes()).await?;
+        }
+
+        if response.first().is_some_and(|line| line.starts_with("205")) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Serves the gateway on `host_port` until shutdown is requested.
+pub async fn serve(
+    host_port: String,
+    store: Arc<dyn Store>,
+    network_access: NetworkAccess,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    let mut listener = TcpListener::bind(&host_port).await?;
+    info!("Serving NNTP on: {}", host_port);
+
+    loop {
+        let (stream, address) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.requested() => break,
+        };
+
+        if !network_access.permits(address.ip()) {
+            info!(
+                "Refusing NNTP client {}, not in an allowed network",
+                address
+            );
+            continue;
+        }
+
+        let store = store.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = handle_connection(stream, &*store).await {
+                    error!("NNTP connection failed: {}", e);
+                }
+            }
+            .instrument(info_span!("nntp", %address, user = Empty)),
+        );
+    }
+
+    Ok(())
+}
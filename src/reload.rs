@@ -0,0 +1,67 @@
+//! Applies changes to runtime-tunable settings on SIGHUP, without dropping
+//! client connections or restarting the fetcher.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+/// The settings that take effect without a restart. Tasks read the latest
+/// values from a `watch::Receiver<Tunables>` whenever they next need them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tunables {
+    pub feed_fetch_interval: Duration,
+    pub max_concurrent_fetches: usize,
+    /// Connections beyond this many are turned away as busy.
+    pub max_client_connections: usize,
+    pub entry_content_host_delay: Duration,
+    /// The least time between fetches of feeds on the same host.
+    pub feed_host_delay: Duration,
+    /// Consecutive fetch failures after which a feed is flagged as likely
+    /// dead, or 0 to never flag feeds.
+    pub dead_feed_failures: i64,
+    /// An `EnvFilter` directive such as `info` or `seymour=debug,sqlx=warn`.
+    pub log_level: String,
+}
+
+/// Replaces the filter of the installed log subscriber.
+pub type SetLogLevel = Box<dyn Fn(&str) -> Result<()> + Send + Sync>;
+
+/// Re-reads the tunables with `load` on every SIGHUP and hands them to
+/// whoever's watching. Invalid settings are logged and the old ones kept.
+#[cfg(unix)]
+pub async fn reload_on_hangup(
+    sender: watch::Sender<Tunables>,
+    mut current: Tunables,
+    mut load: impl FnMut() -> Result<Tunables>,
+    set_log_level: SetLogLevel,
+) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup())?;
+    while hangups.recv().await.is_some() {
+        let tunables = match load() {
+            Ok(tunables) => tunables,
+            Err(e) => {
+                error!("not reloading configuration: {:#}", e);
+                continue;
+            }
+        };
+
+        if tunables.log_level != current.log_level {
+            if let Err(e) = set_log_level(&tunables.log_level) {
+                error!("not reloading configuration: {:#}", e);
+                continue;
+            }
+        }
+
+        info!("Reloaded configuration: {:?}", tunables);
+        current = tunables.clone();
+        if sender.broadcast(tunables).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
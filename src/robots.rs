@@ -0,0 +1,201 @@
+//! Support for the Gemini robots.txt companion specification.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+use url::Url;
+
+use crate::fetch::{fetch, FetchConfig, Status};
+
+/// User agents whose rules apply to the fetcher, most specific first.
+const USER_AGENTS: &[&str] = &["seymour", "*"];
+
+/// How long a host's robots.txt is trusted before it's fetched again.
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Default, Clone)]
+struct Robots {
+    /// Disallowed path prefixes for each user agent, keyed by lowercase name.
+    disallow: HashMap<String, Vec<String>>,
+}
+
+impl Robots {
+    fn parse(body: &str) -> Self {
+        let mut disallow: HashMap<String, Vec<String>> = HashMap::new();
+        let mut agents: Vec<String> = Vec::new();
+        let mut in_rules = false;
+
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut parts = line.splitn(2, ':');
+            let (field, value) = match (parts.next(), parts.next()) {
+                (Some(field), Some(value)) => (field.trim().to_lowercase(), value.trim()),
+                _ => continue,
+            };
+
+            match field.as_str() {
+                "user-agent" => {
+                    // A user-agent line after rules starts a new group.
+                    if in_rules {
+                        agents.clear();
+                        in_rules = false;
+                    }
+                    agents.push(value.to_lowercase());
+                    disallow.entry(value.to_lowercase()).or_default();
+                }
+                "disallow" => {
+                    in_rules = true;
+                    if value.is_empty() {
+                        continue;
+                    }
+                    for agent in &agents {
+                        disallow
+                            .entry(agent.clone())
+                            .or_default()
+                            .push(value.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self { disallow }
+    }
+
+    fn allows(&self, path: &str) -> bool {
+        let rules = USER_AGENTS
+            .iter()
+            .find_map(|agent| self.disallow.get(*agent));
+
+        match rules {
+            Some(rules) => !rules.iter().any(|prefix| path.starts_with(prefix.as_str())),
+            None => true,
+        }
+    }
+}
+
+/// Per-host cache of robots.txt rules.
+#[derive(Default)]
+pub struct RobotsCache {
+    hosts: Mutex<HashMap<String, (Instant, Robots)>>,
+}
+
+impl RobotsCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn cached(&self, authority: &str) -> Option<Robots> {
+        let hosts = self.hosts.lock().expect("robots cache lock poisoned");
+        hosts
+            .get(authority)
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < CACHE_TTL)
+            .map(|(_, robots)| robots.clone())
+    }
+
+    /// Whether robots.txt on the URL's host allows the fetcher to fetch it.
+    ///
+    /// Hosts without a usable robots.txt allow everything.
+    pub async fn allows(&self, url: &Url, config: &FetchConfig) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host,
+            None => return true,
+        };
+        let authority = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+
+        let robots = match self.cached(&authority) {
+            Some(robots) => robots,
+            None => {
+                let robots_url = format!("gemini://{}/robots.txt", authority);
+                let robots = match fetch(&robots_url, config).await {
+                    Ok(page)
+                        if page.header.status == Status::Success
+                            && page.header.mime().essence == "text/plain" =>
+                    {
+                        Robots::parse(page.body.as_deref().unwrap_or(""))
+                    }
+                    Ok(_) => Robots::default(),
+                    Err(e) => {
+                        debug!("failed to fetch \"{}\": {:#}", robots_url, e);
+                        Robots::default()
+                    }
+                };
+
+                self.hosts
+                    .lock()
+                    .expect("robots cache lock poisoned")
+                    .insert(authority, (Instant::now(), robots.clone()));
+
+                robots
+            }
+        };
+
+        robots.allows(url.path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Robots;
+
+    #[test]
+    fn disallowed_prefixes_are_blocked() {
+        let robots = Robots::parse("User-agent: *\nDisallow: /private\n");
+
+        assert!(!robots.allows("/private"));
+        assert!(!robots.allows("/private/notes.gmi"));
+        assert!(robots.allows("/"));
+        assert!(robots.allows("/public/private"));
+    }
+
+    #[test]
+    fn seymour_rules_take_precedence_over_the_wildcard() {
+        let robots = Robots::parse(
+            "User-agent: *\nDisallow: /\n\nUser-agent: Seymour\nDisallow: /cgi-bin\n",
+        );
+
+        assert!(robots.allows("/gemlog/"));
+        assert!(!robots.allows("/cgi-bin/search"));
+    }
+
+    #[test]
+    fn empty_disallow_allows_everything() {
+        let robots =
+            Robots::parse("User-agent: *\nDisallow: /\n\nUser-agent: seymour\nDisallow:\n");
+
+        assert!(robots.allows("/"));
+        assert!(robots.allows("/anything"));
+    }
+
+    #[test]
+    fn user_agent_after_rules_starts_a_new_group() {
+        let robots = Robots::parse(
+            "User-agent: archiver\nUser-agent: seymour\nDisallow: /a\nUser-agent: *\nDisallow: /b\n",
+        );
+
+        // Both agents of the first group share its rules, but not the next group's.
+        assert!(!robots.allows("/a"));
+        assert!(robots.allows("/b"));
+    }
+
+    #[test]
+    fn comments_and_unknown_fields_are_ignored() {
+        let robots = Robots::parse(
+            "# Rules for everyone\nUser-agent: * # all of them\nCrawl-delay: 10\nDisallow: /tmp # scratch\nnonsense\n",
+        );
+
+        assert!(!robots.allows("/tmp/file"));
+        assert!(robots.allows("/scratch"));
+    }
+
+    #[test]
+    fn no_matching_group_allows_everything() {
+        assert!(Robots::parse("User-agent: archiver\nDisallow: /\n").allows("/"));
+        assert!(Robots::parse("").allows("/"));
+    }
+}
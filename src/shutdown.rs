@@ -0,0 +1,54 @@
+//! Coordinates stopping the server without cutting off clients mid-response
+//! or the fetcher mid-transaction.
+
+use anyhow::Result;
+use tokio::sync::watch;
+
+/// Lets long-running tasks find out that the server is shutting down.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Returns the handle tasks watch, along with the sender that starts
+    /// the shutdown by sending `true`.
+    pub fn new() -> (watch::Sender<bool>, Self) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Self { receiver })
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Waits until shutdown is requested.
+    pub async fn requested(&mut self) {
+        while !self.is_requested() {
+            // The sender going away means nobody is left to request a
+            // shutdown, which only happens when the process is exiting.
+            if self.receiver.recv().await.is_none() {
+                return;
+            }
+        }
+    }
+}
+
+/// Waits for SIGINT or, on Unix, SIGTERM.
+pub async fn signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
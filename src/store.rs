@@ -0,0 +1,2245 @@
+//! Storage for everything clients can see and change, kept behind a trait so
+//! protocol handling doesn't depend on a particular database.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{format_err, Context, Result};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use chrono_tz::Tz;
+use futures::stream::{BoxStream, StreamExt};
+use seymour_protocol::{FETCH_DURATION_BUCKETS_MS, FETCH_SIZE_BUCKETS_BYTES};
+use sqlx::{Any, Done, Pool, Row};
+use tracing::info;
+
+use crate::feed::TIMESTAMP_FORMAT;
+use crate::feed_url::url_key;
+
+// Only used by tests, to exercise commands without a database.
+#[cfg(test)]
+pub mod memory;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+}
+
+/// A feed a user is subscribed to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subscription {
+    pub feed_id: i64,
+    pub url: String,
+    pub gone: bool,
+    /// The feed has failed to fetch too many times in a row.
+    pub failing: bool,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+}
+
+/// A feed a user has snoozed, and until when.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnoozedFeed {
+    pub feed_id: i64,
+    pub url: String,
+    pub title: Option<String>,
+    pub until: NaiveDateTime,
+}
+
+/// A group a user belongs to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub id: i64,
+    pub name: String,
+    pub members: i64,
+    pub feeds: i64,
+}
+
+/// A subscribed feed along with its fetch health.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedDetails {
+    pub url: String,
+    pub gone: bool,
+    pub failing: bool,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub failure_count: i64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnreadEntry {
+    pub id: i64,
+    pub feed_id: i64,
+    pub feed_url: String,
+    pub feed_title: Option<String>,
+    pub url: String,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadEntry {
+    pub id: i64,
+    pub feed_id: i64,
+    pub feed_url: String,
+    pub url: String,
+    pub title: String,
+    pub viewed_at: NaiveDateTime,
+}
+
+/// A feed suggested to a user, scored by how many of their feeds its
+/// subscribers share.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recommendation {
+    pub feed_id: i64,
+    pub url: String,
+    pub title: Option<String>,
+    pub score: i64,
+}
+
+/// A feed and how many of its entries a user has read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedReads {
+    pub feed_id: i64,
+    pub url: String,
+    pub title: Option<String>,
+    pub reads: i64,
+}
+
+/// An admin command someone ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub created_at: NaiveDateTime,
+    pub username: String,
+    pub command: String,
+}
+
+/// A feed listed by DISCOVER.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectoryFeed {
+    pub feed_id: i64,
+    pub url: String,
+    pub title: Option<String>,
+    pub subscribers: i64,
+}
+
+/// An entry of a subscribed feed matching a search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoundEntry {
+    pub id: i64,
+    pub feed_id: i64,
+    pub feed_url: String,
+    pub feed_title: Option<String>,
+    pub url: String,
+    pub title: String,
+    pub read: bool,
+}
+
+/// An entry of a subscribed feed, read or not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedEntry {
+    pub id: i64,
+    pub url: String,
+    pub title: String,
+    pub published_at: NaiveDateTime,
+    pub read: bool,
+}
+
+/// What an entry links to, as fetched with `FETCH_ENTRY_CONTENTS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryContent {
+    pub mime_type: String,
+    pub content: String,
+}
+
+/// How long a feed's fetches still in the fetch log took and how big they
+/// were, bucketed by `FETCH_DURATION_BUCKETS_MS` and
+/// `FETCH_SIZE_BUCKETS_BYTES`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowFeed {
+    pub feed_id: i64,
+    pub url: String,
+    pub fetches: i64,
+    pub total_duration_ms: i64,
+    pub durations: Vec<i64>,
+    pub sizes: Vec<i64>,
+}
+
+/// A feed listed by LISTALLFEEDS, whether or not anyone subscribes to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerFeed {
+    pub feed_id: i64,
+    pub url: String,
+    pub gone: bool,
+    pub failing: bool,
+    pub title: Option<String>,
+    pub subscribers: i64,
+    pub failure_count: i64,
+    pub last_error: Option<String>,
+}
+
+/// Where a SYNC token picks up from: the newest entry and the newest
+/// journaled change when it was issued.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncPosition {
+    pub entry_id: i64,
+    pub change_id: i64,
+}
+
+/// A change to a user's read state or subscriptions, journaled for SYNC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncChange {
+    Read(i64),
+    Unread(i64),
+    Subscribed(i64),
+    Unsubscribed(i64),
+}
+
+/// A fetch of a feed recorded in the fetch log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchAttempt {
+    pub fetched_at: NaiveDateTime,
+    /// Missing when the fetch failed.
+    pub status: Option<i64>,
+    pub duration_ms: i64,
+    pub error: Option<String>,
+}
+
+/// What's known of the certificate TOFU pinned for a host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnownCertificate {
+    pub not_after: Option<NaiveDateTime>,
+    /// When it last replaced an expired certificate.
+    pub rotated_at: Option<NaiveDateTime>,
+}
+
+/// Aggregates over a feed's fetches still in the fetch log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedFetchStats {
+    pub feed_id: i64,
+    pub url: String,
+    pub fetches: i64,
+    pub failures: i64,
+    pub avg_duration_ms: i64,
+    pub max_duration_ms: i64,
+    pub bytes: i64,
+    pub entries_inserted: i64,
+}
+
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Returns the ID of the user, creating them if they don't exist. The
+    /// username must already be normalized.
+    async fn user_id(&self, username: &str) -> Result<i64>;
+
+    /// Subscribes the user to the feed at `url`, which must be canonical,
+    /// adding the feed if nobody else subscribes to it. A feed differing
+    /// only by a trailing slash is the same feed. Entries published before
+    /// `backlog_from` aren't listed as unread. Returns the feed's ID.
+    async fn subscribe(&self, user_id: i64, url: &str, backlog_from: NaiveDateTime) -> Result<i64>;
+
+    async fn subscriptions(&self, user_id: i64) -> Result<Vec<Subscription>>;
+
+    /// Creates a group with the user as its only member. Returns `None` if
+    /// the name is taken.
+    async fn create_group(&self, user_id: i64, name: &str) -> Result<Option<i64>>;
+
+    /// The ID of the group named `name`, if the user belongs to it.
+    async fn group_id(&self, user_id: i64, name: &str) -> Result<Option<i64>>;
+
+    /// Adds the user to the group and subscribes them to its feeds, like
+    /// `subscribe`.
+    async fn add_group_member(
+        &self,
+        group_id: i64,
+        user_id: i64,
+        backlog_from: NaiveDateTime,
+    ) -> Result<()>;
+
+    /// Adds the feed at `url` to the group and subscribes every member to
+    /// it, like `subscribe`. Returns the feed's ID.
+    async fn group_subscribe(
+        &self,
+        group_id: i64,
+        url: &str,
+        backlog_from: NaiveDateTime,
+    ) -> Result<i64>;
+
+    /// Removes the feed from the group, leaving members' subscriptions
+    /// alone. Returns whether it was a group feed.
+    async fn group_unsubscribe(&self, group_id: i64, feed_id: i64) -> Result<bool>;
+
+    /// The groups the user belongs to.
+    async fn groups(&self, user_id: i64) -> Result<Vec<Group>>;
+
+    async fn group_feeds(&self, group_id: i64) -> Result<Vec<Subscription>>;
+
+    /// Returns whether the user was subscribed to the feed.
+    async fn unsubscribe(&self, user_id: i64, feed_id: i64) -> Result<bool>;
+
+    /// Lists the feed's whole archive as unread. Returns whether the user
+    /// is subscribed to the feed.
+    async fn show_archive(&self, user_id: i64, feed_id: i64) -> Result<bool>;
+
+    /// Leaves the feed's entries out of `unread_entries` until `until`.
+    /// Returns whether the user is subscribed to the feed.
+    async fn snooze(&self, user_id: i64, feed_id: i64, until: NaiveDateTime) -> Result<bool>;
+
+    /// Keeps only the feed's `limit` newest unread entries for the user, or
+    /// all of them with `None`. Returns whether the user is subscribed.
+    async fn set_max_unread(&self, user_id: i64, feed_id: i64, limit: Option<i64>) -> Result<bool>;
+
+    /// The user's feeds that are still snoozed, soonest back first.
+    async fn snoozed_feeds(&self, user_id: i64) -> Result<Vec<SnoozedFeed>>;
+
+    /// Turns chat announcements of the feed's new entries on or off for the
+    /// user. Returns whether the user is subscribed to the feed.
+    async fn set_notify(&self, user_id: i64, feed_id: i64, notify: bool) -> Result<bool>;
+
+    /// Users who want the feed's new entries announced in chat.
+    async fn notified_usernames(&self, feed_id: i64) -> Result<Vec<String>>;
+
+    /// Returns the feed if the user is subscribed to it.
+    async fn subscribed_feed(&self, user_id: i64, feed_id: i64) -> Result<Option<FeedDetails>>;
+
+    /// Entries of subscribed feeds the user hasn't read, oldest first and
+    /// then by ID, produced as they're read from the database. Snoozed
+    /// feeds are left out. With `dedupe` set, entries are left out when the
+    /// user has read another with the same URL, or another subscribed feed
+    /// has one first, so aggregators don't repeat what they link to.
+    fn unread_entries(&self, user_id: i64) -> BoxStream<'_, Result<UnreadEntry>>;
+
+    /// How many entries `unread_entries` would produce.
+    async fn unread_count(&self, user_id: i64) -> Result<i64>;
+
+    /// How many entries `unread_entries` would produce from each feed, by
+    /// feed ID. Feeds without any may be left out.
+    async fn feed_unread_counts(&self, user_id: i64) -> Result<HashMap<i64, i64>>;
+
+    async fn mark_read(
+        &self,
+        user_id: i64,
+        feed_entry_id: i64,
+        viewed_at: NaiveDateTime,
+    ) -> Result<()>;
+
+    /// Marks every entry of the feed the user hasn't read as read.
+    async fn mark_feed_read(
+        &self,
+        user_id: i64,
+        feed_id: i64,
+        viewed_at: NaiveDateTime,
+    ) -> Result<()>;
+
+    /// The newest entry and journaled change.
+    async fn sync_position(&self) -> Result<SyncPosition>;
+
+    /// Entries of the user's subscriptions with IDs after `from`, up to and
+    /// including `to`, by ID.
+    async fn entries_between(&self, user_id: i64, from: i64, to: i64) -> Result<Vec<UnreadEntry>>;
+
+    /// The user's journaled changes with IDs after `from`, up to and
+    /// including `to`, oldest first. Those of entries since deleted are
+    /// left out.
+    async fn changes_between(&self, user_id: i64, from: i64, to: i64) -> Result<Vec<SyncChange>>;
+
+    /// Has the user's entries marked read once they've been unread for
+    /// `days`, or never with `None`.
+    async fn set_auto_mark_read(&self, user_id: i64, days: Option<i64>) -> Result<()>;
+
+    /// The timezone dates are shown to the user in, UTC unless they've set
+    /// one.
+    async fn timezone(&self, user_id: i64) -> Result<Tz>;
+
+    async fn set_timezone(&self, user_id: i64, timezone: Tz) -> Result<()>;
+
+    /// Whether the user wants excerpts of fetched content with entries.
+    async fn excerpts(&self, user_id: i64) -> Result<bool>;
+
+    async fn set_excerpts(&self, user_id: i64, excerpts: bool) -> Result<()>;
+
+    /// Sets whether entries with the same URL as one the user has read, or
+    /// one another subscribed feed has first, are left out of their unread
+    /// entries.
+    async fn set_dedupe(&self, user_id: i64, dedupe: bool) -> Result<()>;
+
+    /// Leaves the user's subscriptions out of the directory, or puts them
+    /// back.
+    async fn set_hide_from_directory(&self, user_id: i64, hide: bool) -> Result<()>;
+
+    /// Feeds with subscribers who haven't hidden themselves from the
+    /// directory, optionally only those whose URL or title contains `query`,
+    /// ignoring case. Most subscribed first.
+    async fn directory(&self, query: Option<&str>, limit: i64) -> Result<Vec<DirectoryFeed>>;
+
+    /// The `limit` feeds the user doesn't subscribe to that are most often
+    /// subscribed to alongside theirs, by users who haven't hidden
+    /// themselves from the directory. Highest scoring first.
+    async fn recommendations(&self, user_id: i64, limit: i64) -> Result<Vec<Recommendation>>;
+
+    /// How many entries the user has read since `since`.
+    async fn read_count_since(&self, user_id: i64, since: NaiveDateTime) -> Result<i64>;
+
+    /// When the user read each entry, oldest first. Entries read before
+    /// this was recorded are left out.
+    async fn read_times(&self, user_id: i64) -> Result<Vec<NaiveDateTime>>;
+
+    /// The `limit` feeds the user has read the most entries of, most read
+    /// first.
+    async fn most_read_feeds(&self, user_id: i64, limit: i64) -> Result<Vec<FeedReads>>;
+
+    /// The entries the user most recently read, newest first.
+    async fn read_history(&self, user_id: i64, limit: i64) -> Result<Vec<ReadEntry>>;
+
+    /// Entries of subscribed feeds whose titles contain `query`, ignoring
+    /// case, newest first.
+    async fn search_entries(
+        &self,
+        user_id: i64,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<FoundEntry>>;
+
+    /// Every entry of a subscribed feed, in the order they were added.
+    /// Empty when the user isn't subscribed.
+    async fn feed_entries(&self, user_id: i64, feed_id: i64) -> Result<Vec<FeedEntry>>;
+
+    /// Whether the entry belongs to a feed the user subscribes to, or one
+    /// they subscribed to before and read entries of.
+    async fn can_access_entry(&self, user_id: i64, entry_id: i64) -> Result<bool>;
+
+    /// The fetched content of an entry, if any.
+    async fn entry_content(&self, user_id: i64, entry_id: i64) -> Result<Option<EntryContent>>;
+
+    /// The summary the entry's feed gave it, kept with `ENTRY_SUMMARIES`.
+    async fn entry_summary(&self, entry_id: i64) -> Result<Option<String>>;
+
+    async fn feed_stats(&self) -> Result<Vec<FeedFetchStats>>;
+
+    /// The `limit` feeds whose fetches took longest in total, slowest
+    /// first.
+    async fn slow_feeds(&self, limit: i64) -> Result<Vec<SlowFeed>>;
+
+    /// Every feed, by ID.
+    async fn all_feeds(&self) -> Result<Vec<ServerFeed>>;
+
+    /// The feed's `limit` most recent fetches, newest first.
+    async fn fetch_attempts(&self, feed_id: i64, limit: i64) -> Result<Vec<FetchAttempt>>;
+
+    /// The certificate pinned for `host` and `port`, if TOFU has pinned one.
+    async fn known_certificate(&self, host: &str, port: u16) -> Result<Option<KnownCertificate>>;
+
+    async fn users(&self) -> Result<Vec<User>>;
+
+    async fn is_admin(&self, user_id: i64) -> Result<bool>;
+
+    async fn set_admin(&self, user_id: i64, admin: bool) -> Result<()>;
+
+    /// Records an admin command being run.
+    async fn record_audit(&self, username: &str, command: &str) -> Result<()>;
+
+    /// The admin commands most recently run, newest first.
+    async fn audit_log(&self, limit: i64) -> Result<Vec<AuditEntry>>;
+
+    /// Makes the feed due for fetching right away. Returns whether the
+    /// feed exists.
+    async fn refresh_feed(&self, feed_id: i64) -> Result<bool>;
+
+    /// Makes every feed that isn't gone due for fetching right away,
+    /// returning how many there are.
+    async fn refresh_all_feeds(&self) -> Result<u64>;
+
+    /// When the fetcher last finished checking feeds, if it ever has.
+    async fn last_fetch_cycle(&self) -> Result<Option<NaiveDateTime>>;
+
+    /// The user a Gemini client certificate is linked to.
+    async fn user_for_certificate(&self, fingerprint: &str) -> Result<Option<i64>>;
+
+    /// Links a client certificate to the user, replacing any earlier link.
+    async fn link_certificate(&self, user_id: i64, fingerprint: &str) -> Result<()>;
+
+    /// The user an API token, identified by its hash, belongs to.
+    async fn user_for_token(&self, token_hash: &str) -> Result<Option<i64>>;
+
+    async fn add_token(&self, user_id: i64, token_hash: &str) -> Result<()>;
+
+    /// Writes a consistent snapshot of the store to `path`.
+    async fn backup(&self, path: &Path) -> Result<()>;
+}
+
+fn format_timestamp(timestamp: NaiveDateTime) -> String {
+    timestamp.format(TIMESTAMP_FORMAT).to_string()
+}
+
+/// A `LIKE` pattern, escaped with `\`, matching lowercase text containing
+/// `query`.
+fn contains_pattern(query: &str) -> String {
+    format!(
+        "%{}%",
+        query
+            .to_lowercase()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    )
+}
+
+/// Selects `<name>_<i>`, the number of rows whose `column` falls in bucket
+/// `i` of those ending at `bounds`, plus one bucket for the rest.
+fn histogram_columns(column: &str, name: &str, bounds: &[i64]) -> String {
+    let mut lower = None;
+    let mut columns = Vec::new();
+    for (i, upper) in bounds.iter().map(Some).chain(Some(None)).enumerate() {
+        let condition = match (lower, upper) {
+            (None, Some(upper)) => format!("{} < {}", column, upper),
+            (Some(lower), Some(upper)) => {
+                format!("{} >= {} AND {} < {}", column, lower, column, upper)
+            }
+            (Some(lower), None) => format!("{} >= {}", column, lower),
+            (None, None) => "1 = 1".to_string(),
+        };
+        columns.push(format!(
+            "CAST(SUM(CASE WHEN {} THEN 1 ELSE 0 END) AS BIGINT) AS {}_{}",
+            condition, name, i
+        ));
+        lower = upper;
+    }
+
+    columns.join(", ")
+}
+
+fn histogram(row: &sqlx::any::AnyRow, name: &str, bounds: &[i64]) -> Result<Vec<i64>> {
+    (0..=bounds.len())
+        .map(|i| Ok(row.try_get(format!("{}_{}", name, i).as_str())?))
+        .collect()
+}
+
+/// A store backed by SQLite or, with the `postgres` feature, PostgreSQL.
+pub struct SqlStore {
+    pool: Pool<Any>,
+    database_url: String,
+}
+
+impl SqlStore {
+    pub fn new(pool: Pool<Any>, database_url: String) -> Self {
+        Self { pool, database_url }
+    }
+}
+
+#[async_trait]
+impl Store for SqlStore {
+    async fn user_id(&self, username: &str) -> Result<i64> {
+        // Inserting first means concurrent sign-ins as a new user can't both
+        // miss the lookup and create it twice.
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("INSERT INTO users (username) VALUES ($1) ON CONFLICT DO NOTHING")
+            .bind(username)
+            .execute(&mut conn)
+            .await?;
+
+        Ok(sqlx::query("SELECT id FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_one(&mut conn)
+            .await?
+            .try_get("id")?)
+    }
+
+    async fn subscribe(&self, user_id: i64, url: &str, backlog_from: NaiveDateTime) -> Result<i64> {
+        let mut conn = self.pool.acquire().await?;
+        let key = url_key(url);
+
+        // Inserting and then looking the feed up leaves the unique key to
+        // settle a race with another connection adding the same feed, under
+        // either spelling.
+        sqlx::query("INSERT INTO feeds (url, url_key) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(url)
+            .bind(&key)
+            .execute(&mut conn)
+            .await?;
+
+        let feed_id: i64 = sqlx::query("SELECT id FROM feeds WHERE url_key = $1")
+            .bind(&key)
+            .fetch_one(&mut conn)
+            .await?
+            .try_get("id")?;
+
+        sqlx::query(
+            r#"INSERT INTO subscriptions (user_id, feed_id, created_at, backlog_from)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT DO NOTHING"#,
+        )
+        .bind(user_id)
+        .bind(feed_id)
+        .bind(format_timestamp(chrono::Utc::now().naive_utc()))
+        .bind(format_timestamp(backlog_from))
+        .execute(&mut conn)
+        .await?;
+
+        // Resubscribing within the grace period brings the feed back.
+        sqlx::query("UPDATE feeds SET unsubscribed_at = NULL WHERE id = $1")
+            .bind(feed_id)
+            .execute(&mut conn)
+            .await?;
+
+        Ok(feed_id)
+    }
+
+    async fn subscriptions(&self, user_id: i64) -> Result<Vec<Subscription>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                subscriptions.feed_id, feeds.url, feeds.gone_at, feeds.failing_since,
+                feeds.title, feeds.subtitle
+            FROM subscriptions
+            INNER JOIN feeds ON subscriptions.feed_id = feeds.id
+            WHERE subscriptions.user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut subscriptions = Vec::new();
+        for row in rows {
+            let gone_at: Option<String> = row.try_get("gone_at")?;
+            let failing_since: Option<String> = row.try_get("failing_since")?;
+            subscriptions.push(Subscription {
+                feed_id: row.try_get("feed_id")?,
+                url: row.try_get("url")?,
+                gone: gone_at.is_some(),
+                failing: failing_since.is_some(),
+                title: row.try_get("title")?,
+                subtitle: row.try_get("subtitle")?,
+            });
+        }
+
+        Ok(subscriptions)
+    }
+
+    async fn create_group(&self, user_id: i64, name: &str) -> Result<Option<i64>> {
+        let mut tx = self.pool.begin().await?;
+
+        let affected_rows = sqlx::query(
+            "INSERT INTO user_groups (name, created_at) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(name)
+        .bind(format_timestamp(chrono::Utc::now().naive_utc()))
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+        if affected_rows == 0 {
+            return Ok(None);
+        }
+
+        let group_id: i64 = sqlx::query("SELECT id FROM user_groups WHERE name = $1")
+            .bind(name)
+            .fetch_one(&mut tx)
+            .await?
+            .try_get("id")?;
+
+        sqlx::query("INSERT INTO group_members (group_id, user_id) VALUES ($1, $2)")
+            .bind(group_id)
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(group_id))
+    }
+
+    async fn group_id(&self, user_id: i64, name: &str) -> Result<Option<i64>> {
+        let group = sqlx::query(
+            r#"
+            SELECT user_groups.id
+            FROM user_groups
+            INNER JOIN group_members ON user_groups.id = group_members.group_id
+            WHERE user_groups.name = $1 AND group_members.user_id = $2
+            "#,
+        )
+        .bind(name)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(match group {
+            Some(group) => Some(group.try_get("id")?),
+            None => None,
+        })
+    }
+
+    async fn add_group_member(
+        &self,
+        group_id: i64,
+        user_id: i64,
+        backlog_from: NaiveDateTime,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO group_members (group_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        for feed in self.group_feeds(group_id).await? {
+            self.subscribe(user_id, &feed.url, backlog_from).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn group_subscribe(
+        &self,
+        group_id: i64,
+        url: &str,
+        backlog_from: NaiveDateTime,
+    ) -> Result<i64> {
+        let members = sqlx::query("SELECT user_id FROM group_members WHERE group_id = $1")
+            .bind(group_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut feed_id = None;
+        for member in members {
+            feed_id = Some(
+                self.subscribe(member.try_get("user_id")?, url, backlog_from)
+                    .await?,
+            );
+        }
+        let feed_id = feed_id.ok_or_else(|| format_err!("group {} has no members", group_id))?;
+
+        sqlx::query(
+            "INSERT INTO group_feeds (group_id, feed_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(group_id)
+        .bind(feed_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(feed_id)
+    }
+
+    async fn group_unsubscribe(&self, group_id: i64, feed_id: i64) -> Result<bool> {
+        let affected_rows =
+            sqlx::query("DELETE FROM group_feeds WHERE group_id = $1 AND feed_id = $2")
+                .bind(group_id)
+                .bind(feed_id)
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+
+        Ok(affected_rows > 0)
+    }
+
+    async fn groups(&self, user_id: i64) -> Result<Vec<Group>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT user_groups.id, user_groups.name,
+                (SELECT CAST(COUNT(*) AS BIGINT) FROM group_members AS members
+                    WHERE members.group_id = user_groups.id) AS members,
+                (SELECT CAST(COUNT(*) AS BIGINT) FROM group_feeds
+                    WHERE group_feeds.group_id = user_groups.id) AS feeds
+            FROM user_groups
+            INNER JOIN group_members ON user_groups.id = group_members.group_id
+            WHERE group_members.user_id = $1
+            ORDER BY user_groups.name
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut groups = Vec::new();
+        for row in rows {
+            groups.push(Group {
+                id: row.try_get("id")?,
+                name: row.try_get("name")?,
+                members: row.try_get("members")?,
+                feeds: row.try_get("feeds")?,
+            });
+        }
+
+        Ok(groups)
+    }
+
+    async fn group_feeds(&self, group_id: i64) -> Result<Vec<Subscription>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                group_feeds.feed_id, feeds.url, feeds.gone_at, feeds.failing_since,
+                feeds.title, feeds.subtitle
+            FROM group_feeds
+            INNER JOIN feeds ON group_feeds.feed_id = feeds.id
+            WHERE group_feeds.group_id = $1
+            "#,
+        )
+        .bind(group_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut feeds = Vec::new();
+        for row in rows {
+            let gone_at: Option<String> = row.try_get("gone_at")?;
+            let failing_since: Option<String> = row.try_get("failing_since")?;
+            feeds.push(Subscription {
+                feed_id: row.try_get("feed_id")?,
+                url: row.try_get("url")?,
+                gone: gone_at.is_some(),
+                failing: failing_since.is_some(),
+                title: row.try_get("title")?,
+                subtitle: row.try_get("subtitle")?,
+            });
+        }
+
+        Ok(feeds)
+    }
+
+    async fn unsubscribe(&self, user_id: i64, feed_id: i64) -> Result<bool> {
+        let affected_rows =
+            sqlx::query("DELETE FROM subscriptions WHERE user_id = $1 AND feed_id = $2")
+                .bind(user_id)
+                .bind(feed_id)
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+
+        Ok(affected_rows > 0)
+    }
+
+    async fn show_archive(&self, user_id: i64, feed_id: i64) -> Result<bool> {
+        let affected_rows = sqlx::query(
+            "UPDATE subscriptions SET backlog_from = NULL WHERE user_id = $1 AND feed_id = $2",
+        )
+        .bind(user_id)
+        .bind(feed_id)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(affected_rows > 0)
+    }
+
+    async fn snooze(&self, user_id: i64, feed_id: i64, until: NaiveDateTime) -> Result<bool> {
+        let affected_rows = sqlx::query(
+            "UPDATE subscriptions SET snoozed_until = $1 WHERE user_id = $2 AND feed_id = $3",
+        )
+        .bind(format_timestamp(until))
+        .bind(user_id)
+        .bind(feed_id)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(affected_rows > 0)
+    }
+
+    async fn set_max_unread(&self, user_id: i64, feed_id: i64, limit: Option<i64>) -> Result<bool> {
+        let affected_rows = sqlx::query(
+            "UPDATE subscriptions SET max_unread = $1 WHERE user_id = $2 AND feed_id = $3",
+        )
+        .bind(limit)
+        .bind(user_id)
+        .bind(feed_id)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(affected_rows > 0)
+    }
+
+    async fn snoozed_feeds(&self, user_id: i64) -> Result<Vec<SnoozedFeed>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT subscriptions.feed_id, feeds.url, feeds.title, subscriptions.snoozed_until
+            FROM subscriptions
+            INNER JOIN feeds ON subscriptions.feed_id = feeds.id
+            WHERE subscriptions.user_id = $1 AND subscriptions.snoozed_until > $2
+            ORDER BY subscriptions.snoozed_until
+            "#,
+        )
+        .bind(user_id)
+        .bind(format_timestamp(chrono::Utc::now().naive_utc()))
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut feeds = Vec::new();
+        for row in rows {
+            let until: String = row.try_get("snoozed_until")?;
+            feeds.push(SnoozedFeed {
+                feed_id: row.try_get("feed_id")?,
+                url: row.try_get("url")?,
+                title: row.try_get("title")?,
+                until: NaiveDateTime::parse_from_str(&until, TIMESTAMP_FORMAT)?,
+            });
+        }
+
+        Ok(feeds)
+    }
+
+    async fn set_notify(&self, user_id: i64, feed_id: i64, notify: bool) -> Result<bool> {
+        let affected_rows =
+            sqlx::query("UPDATE subscriptions SET notify = $1 WHERE user_id = $2 AND feed_id = $3")
+                .bind(notify as i64)
+                .bind(user_id)
+                .bind(feed_id)
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+
+        Ok(affected_rows > 0)
+    }
+
+    async fn notified_usernames(&self, feed_id: i64) -> Result<Vec<String>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT users.username
+            FROM subscriptions
+            INNER JOIN users ON subscriptions.user_id = users.id
+            WHERE subscriptions.feed_id = $1 AND subscriptions.notify != 0
+            ORDER BY users.username
+            "#,
+        )
+        .bind(feed_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut usernames = Vec::new();
+        for row in rows {
+            usernames.push(row.try_get("username")?);
+        }
+
+        Ok(usernames)
+    }
+
+    async fn subscribed_feed(&self, user_id: i64, feed_id: i64) -> Result<Option<FeedDetails>> {
+        let feed = sqlx::query(
+            r#"
+            SELECT
+                feeds.url, feeds.gone_at, feeds.failing_since, feeds.title, feeds.subtitle,
+                feeds.failure_count, feeds.last_error
+            FROM subscriptions
+            INNER JOIN feeds ON subscriptions.feed_id = feeds.id
+            WHERE subscriptions.user_id = $1 AND subscriptions.feed_id = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(feed_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let feed = match feed {
+            Some(feed) => feed,
+            None => return Ok(None),
+        };
+
+        let gone_at: Option<String> = feed.try_get("gone_at")?;
+        let failing_since: Option<String> = feed.try_get("failing_since")?;
+        Ok(Some(FeedDetails {
+            url: feed.try_get("url")?,
+            gone: gone_at.is_some(),
+            failing: failing_since.is_some(),
+            title: feed.try_get("title")?,
+            subtitle: feed.try_get("subtitle")?,
+            failure_count: feed.try_get("failure_count")?,
+            last_error: feed.try_get("last_error")?,
+        }))
+    }
+
+    fn unread_entries(&self, user_id: i64) -> BoxStream<'_, Result<UnreadEntry>> {
+        sqlx::query(
+            r#"
+            SELECT
+                feed_entries.id, feed_entries.feed_id, feeds.url AS feed_url,
+                feeds.title AS feed_title, feed_entries.url, feed_entries.title
+            FROM subscriptions
+            INNER JOIN feeds ON subscriptions.feed_id = feeds.id
+            INNER JOIN feed_entries ON feed_entries.feed_id = subscriptions.feed_id
+            LEFT JOIN views
+                ON views.feed_entry_id = feed_entries.id AND views.user_id = subscriptions.user_id
+            WHERE subscriptions.user_id = $1
+                AND views.feed_entry_id IS NULL
+                AND (
+                    subscriptions.backlog_from IS NULL
+                    OR feed_entries.published_at >= subscriptions.backlog_from
+                )
+                AND (subscriptions.snoozed_until IS NULL OR subscriptions.snoozed_until <= $2)
+                AND NOT (
+                    (SELECT dedupe FROM users WHERE id = $1) <> 0
+                    AND EXISTS (
+                        SELECT 1 FROM feed_entries AS duplicates
+                        WHERE duplicates.url = feed_entries.url
+                            AND duplicates.id <> feed_entries.id
+                            AND (
+                                duplicates.id IN (
+                                    SELECT feed_entry_id FROM views WHERE user_id = $1
+                                )
+                                OR (
+                                    duplicates.id < feed_entries.id
+                                    AND duplicates.feed_id IN (
+                                        SELECT feed_id FROM subscriptions WHERE user_id = $1
+                                    )
+                                )
+                            )
+                    )
+                )
+            ORDER BY feed_entries.published_at, feed_entries.id
+            "#,
+        )
+        .bind(user_id)
+        .bind(format_timestamp(chrono::Utc::now().naive_utc()))
+        .fetch(&self.pool)
+        .map(|row| {
+            let row = row?;
+            Ok(UnreadEntry {
+                id: row.try_get("id")?,
+                feed_id: row.try_get("feed_id")?,
+                feed_url: row.try_get("feed_url")?,
+                feed_title: row.try_get("feed_title")?,
+                url: row.try_get("url")?,
+                title: row.try_get("title")?,
+            })
+        })
+        .boxed()
+    }
+
+    async fn unread_count(&self, user_id: i64) -> Result<i64> {
+        Ok(self.feed_unread_counts(user_id).await?.values().sum())
+    }
+
+    async fn feed_unread_counts(&self, user_id: i64) -> Result<HashMap<i64, i64>> {
+        let dedupe: Option<i64> = sqlx::query("SELECT dedupe FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| row.try_get("dedupe"))
+            .transpose()?;
+
+        // unread_counts is kept up to date by triggers, but can't tell which
+        // entries dedupe would hide, so those users are counted the slow way.
+        let query = if dedupe.unwrap_or(0) == 0 {
+            r#"
+            SELECT unread_counts.feed_id, unread_counts.count
+            FROM unread_counts
+            INNER JOIN subscriptions
+                ON subscriptions.user_id = unread_counts.user_id
+                AND subscriptions.feed_id = unread_counts.feed_id
+            WHERE unread_counts.user_id = $1
+                AND (subscriptions.snoozed_until IS NULL OR subscriptions.snoozed_until <= $2)
+            "#
+        } else {
+            // The count is cast so SQLite and PostgreSQL both return BIGINT.
+            r#"
+            SELECT subscriptions.feed_id, CAST(COUNT(*) AS BIGINT) AS count
+            FROM subscriptions
+            INNER JOIN feed_entries ON feed_entries.feed_id = subscriptions.feed_id
+            LEFT JOIN views
+                ON views.feed_entry_id = feed_entries.id AND views.user_id = subscriptions.user_id
+            WHERE subscriptions.user_id = $1
+                AND views.feed_entry_id IS NULL
+                AND (
+                    subscriptions.backlog_from IS NULL
+                    OR feed_entries.published_at >= subscriptions.backlog_from
+                )
+                AND (subscriptions.snoozed_until IS NULL OR subscriptions.snoozed_until <= $2)
+                AND NOT EXISTS (
+                    SELECT 1 FROM feed_entries AS duplicates
+                    WHERE duplicates.url = feed_entries.url
+                        AND duplicates.id <> feed_entries.id
+                        AND (
+                            duplicates.id IN (
+                                SELECT feed_entry_id FROM views WHERE user_id = $1
+                            )
+                            OR (
+                                duplicates.id < feed_entries.id
+                                AND duplicates.feed_id IN (
+                                    SELECT feed_id FROM subscriptions WHERE user_id = $1
+                                )
+                            )
+                        )
+                )
+            GROUP BY subscriptions.feed_id
+            "#
+        };
+
+        let rows = sqlx::query(query)
+            .bind(user_id)
+            .bind(format_timestamp(chrono::Utc::now().naive_utc()))
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut counts = HashMap::new();
+        for row in rows {
+            counts.insert(row.try_get("feed_id")?, row.try_get("count")?);
+        }
+
+        Ok(counts)
+    }
+
+    async fn mark_read(
+        &self,
+        user_id: i64,
+        feed_entry_id: i64,
+        viewed_at: NaiveDateTime,
+    ) -> Result<()> {
+        // Reading an entry again keeps when it was first read.
+        sqlx::query(
+            r#"INSERT INTO views (user_id, feed_entry_id, viewed_at) VALUES ($1, $2, $3)
+                ON CONFLICT (user_id, feed_entry_id) DO NOTHING"#,
+        )
+        .bind(user_id)
+        .bind(feed_entry_id)
+        .bind(format_timestamp(viewed_at))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn mark_feed_read(
+        &self,
+        user_id: i64,
+        feed_id: i64,
+        viewed_at: NaiveDateTime,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO views (user_id, feed_entry_id, viewed_at)
+            SELECT $1, feed_entries.id, $3
+            FROM feed_entries
+            WHERE feed_entries.feed_id = $2
+            ON CONFLICT (user_id, feed_entry_id) DO NOTHING
+            "#,
+        )
+        .bind(user_id)
+        .bind(feed_id)
+        .bind(format_timestamp(viewed_at))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn sync_position(&self) -> Result<SyncPosition> {
+        let row = sqlx::query(
+            r#"
+            SELECT
+                (SELECT CAST(COALESCE(MAX(id), 0) AS BIGINT) FROM feed_entries) AS entry_id,
+                (SELECT CAST(COALESCE(MAX(id), 0) AS BIGINT) FROM sync_changes) AS change_id
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(SyncPosition {
+            entry_id: row.try_get("entry_id")?,
+            change_id: row.try_get("change_id")?,
+        })
+    }
+
+    async fn entries_between(&self, user_id: i64, from: i64, to: i64) -> Result<Vec<UnreadEntry>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                feed_entries.id, feed_entries.feed_id, feeds.url AS feed_url,
+                feeds.title AS feed_title, feed_entries.url, feed_entries.title
+            FROM subscriptions
+            INNER JOIN feeds ON subscriptions.feed_id = feeds.id
+            INNER JOIN feed_entries ON feed_entries.feed_id = subscriptions.feed_id
+            WHERE subscriptions.user_id = $1
+                AND feed_entries.id > $2
+                AND feed_entries.id <= $3
+                AND (
+                    subscriptions.backlog_from IS NULL
+                    OR feed_entries.published_at >= subscriptions.backlog_from
+                )
+            ORDER BY feed_entries.id
+            "#,
+        )
+        .bind(user_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(UnreadEntry {
+                id: row.try_get("id")?,
+                feed_id: row.try_get("feed_id")?,
+                feed_url: row.try_get("feed_url")?,
+                feed_title: row.try_get("feed_title")?,
+                url: row.try_get("url")?,
+                title: row.try_get("title")?,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    async fn changes_between(&self, user_id: i64, from: i64, to: i64) -> Result<Vec<SyncChange>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT kind, feed_id, feed_entry_id
+            FROM sync_changes
+            WHERE user_id = $1
+                AND id > $2
+                AND id <= $3
+                AND (
+                    feed_entry_id IS NULL
+                    OR EXISTS (
+                        SELECT 1 FROM feed_entries
+                        WHERE feed_entries.id = sync_changes.feed_entry_id
+                    )
+                )
+            ORDER BY id
+            "#,
+        )
+        .bind(user_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut changes = Vec::new();
+        for row in rows {
+            let kind: String = row.try_get("kind")?;
+            let feed_id: Option<i64> = row.try_get("feed_id")?;
+            let feed_entry_id: Option<i64> = row.try_get("feed_entry_id")?;
+            let change = match (kind.as_str(), feed_entry_id, feed_id) {
+                ("read", Some(id), _) => SyncChange::Read(id),
+                ("unread", Some(id), _) => SyncChange::Unread(id),
+                ("subscribe", _, Some(id)) => SyncChange::Subscribed(id),
+                ("unsubscribe", _, Some(id)) => SyncChange::Unsubscribed(id),
+                _ => return Err(format_err!("invalid sync change \"{}\"", kind)),
+            };
+            changes.push(change);
+        }
+
+        Ok(changes)
+    }
+
+    async fn set_auto_mark_read(&self, user_id: i64, days: Option<i64>) -> Result<()> {
+        sqlx::query("UPDATE users SET auto_mark_read_days = $1 WHERE id = $2")
+            .bind(days)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn timezone(&self, user_id: i64) -> Result<Tz> {
+        let timezone: Option<String> = sqlx::query("SELECT timezone FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| row.try_get("timezone"))
+            .transpose()?
+            .flatten();
+
+        match timezone {
+            Some(timezone) => timezone
+                .parse()
+                .map_err(|e| format_err!("invalid timezone \"{}\": {}", timezone, e)),
+            None => Ok(Tz::UTC),
+        }
+    }
+
+    async fn set_timezone(&self, user_id: i64, timezone: Tz) -> Result<()> {
+        sqlx::query("UPDATE users SET timezone = $1 WHERE id = $2")
+            .bind(timezone.name())
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn excerpts(&self, user_id: i64) -> Result<bool> {
+        let excerpts: Option<i64> = sqlx::query("SELECT excerpts FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| row.try_get("excerpts"))
+            .transpose()?;
+
+        Ok(excerpts.unwrap_or(0) != 0)
+    }
+
+    async fn set_excerpts(&self, user_id: i64, excerpts: bool) -> Result<()> {
+        sqlx::query("UPDATE users SET excerpts = $1 WHERE id = $2")
+            .bind(excerpts as i64)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_dedupe(&self, user_id: i64, dedupe: bool) -> Result<()> {
+        sqlx::query("UPDATE users SET dedupe = $1 WHERE id = $2")
+            .bind(dedupe as i64)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_hide_from_directory(&self, user_id: i64, hide: bool) -> Result<()> {
+        sqlx::query("UPDATE users SET hide_from_directory = $1 WHERE id = $2")
+            .bind(hide as i64)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn directory(&self, query: Option<&str>, limit: i64) -> Result<Vec<DirectoryFeed>> {
+        let pattern = query.map(contains_pattern);
+
+        // The count is cast so SQLite and PostgreSQL both return BIGINT.
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                feeds.id, feeds.url, feeds.title,
+                CAST(COUNT(*) AS BIGINT) AS subscribers
+            FROM subscriptions
+            INNER JOIN feeds ON subscriptions.feed_id = feeds.id
+            INNER JOIN users ON subscriptions.user_id = users.id
+            WHERE users.hide_from_directory = 0
+                AND feeds.gone_at IS NULL
+                AND (
+                    $1 IS NULL
+                    OR LOWER(feeds.url) LIKE $1 ESCAPE '\'
+                    OR LOWER(feeds.title) LIKE $1 ESCAPE '\'
+                )
+            GROUP BY feeds.id, feeds.url, feeds.title
+            ORDER BY subscribers DESC, feeds.id
+            LIMIT $2
+            "#,
+        )
+        .bind(pattern)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut feeds = Vec::new();
+        for row in rows {
+            feeds.push(DirectoryFeed {
+                feed_id: row.try_get("id")?,
+                url: row.try_get("url")?,
+                title: row.try_get("title")?,
+                subscribers: row.try_get("subscribers")?,
+            });
+        }
+
+        Ok(feeds)
+    }
+
+    async fn recommendations(&self, user_id: i64, limit: i64) -> Result<Vec<Recommendation>> {
+        // Every (other user, shared feed, candidate feed) triple counts once
+        // towards the candidate's score.
+        let rows = sqlx::query(
+            r#"
+            SELECT feeds.id, feeds.url, feeds.title, CAST(COUNT(*) AS BIGINT) AS score
+            FROM subscriptions AS mine
+            INNER JOIN subscriptions AS shared
+                ON shared.feed_id = mine.feed_id AND shared.user_id != mine.user_id
+            INNER JOIN users ON shared.user_id = users.id
+            INNER JOIN subscriptions AS theirs ON theirs.user_id = shared.user_id
+            INNER JOIN feeds ON theirs.feed_id = feeds.id
+            WHERE mine.user_id = $1
+                AND users.hide_from_directory = 0
+                AND feeds.gone_at IS NULL
+                AND theirs.feed_id NOT IN (SELECT feed_id FROM subscriptions WHERE user_id = $1)
+            GROUP BY feeds.id, feeds.url, feeds.title
+            ORDER BY score DESC, feeds.id
+            LIMIT $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut recommendations = Vec::new();
+        for row in rows {
+            recommendations.push(Recommendation {
+                feed_id: row.try_get("id")?,
+                url: row.try_get("url")?,
+                title: row.try_get("title")?,
+                score: row.try_get("score")?,
+            });
+        }
+
+        Ok(recommendations)
+    }
+
+    async fn read_count_since(&self, user_id: i64, since: NaiveDateTime) -> Result<i64> {
+        let count = sqlx::query(
+            r#"
+            SELECT CAST(COUNT(*) AS BIGINT) AS count
+            FROM views
+            WHERE user_id = $1 AND viewed_at >= $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(format_timestamp(since))
+        .fetch_one(&self.pool)
+        .await?
+        .try_get("count")?;
+
+        Ok(count)
+    }
+
+    async fn read_times(&self, user_id: i64) -> Result<Vec<NaiveDateTime>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT viewed_at
+            FROM views
+            WHERE user_id = $1 AND viewed_at IS NOT NULL
+            ORDER BY viewed_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut times = Vec::new();
+        for row in rows {
+            let viewed_at: String = row.try_get("viewed_at")?;
+            times.push(
+                NaiveDateTime::parse_from_str(&viewed_at, TIMESTAMP_FORMAT)
+                    .with_context(|| format!("invalid view timestamp \"{}\"", viewed_at))?,
+            );
+        }
+
+        Ok(times)
+    }
+
+    async fn most_read_feeds(&self, user_id: i64, limit: i64) -> Result<Vec<FeedReads>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT feeds.id, feeds.url, feeds.title, CAST(COUNT(*) AS BIGINT) AS reads
+            FROM views
+            INNER JOIN feed_entries ON views.feed_entry_id = feed_entries.id
+            INNER JOIN feeds ON feed_entries.feed_id = feeds.id
+            WHERE views.user_id = $1
+            GROUP BY feeds.id, feeds.url, feeds.title
+            ORDER BY reads DESC, feeds.id
+            LIMIT $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut feeds = Vec::new();
+        for row in rows {
+            feeds.push(FeedReads {
+                feed_id: row.try_get("id")?,
+                url: row.try_get("url")?,
+                title: row.try_get("title")?,
+                reads: row.try_get("reads")?,
+            });
+        }
+
+        Ok(feeds)
+    }
+
+    async fn read_history(&self, user_id: i64, limit: i64) -> Result<Vec<ReadEntry>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                feed_entries.id, feed_entries.feed_id, feeds.url AS feed_url,
+                feed_entries.url, feed_entries.title, views.viewed_at
+            FROM views
+            INNER JOIN feed_entries ON views.feed_entry_id = feed_entries.id
+            INNER JOIN feeds ON feed_entries.feed_id = feeds.id
+            WHERE views.user_id = $1 AND views.viewed_at IS NOT NULL
+            ORDER BY views.viewed_at DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let viewed_at: String = row.try_get("viewed_at")?;
+            entries.push(ReadEntry {
+                id: row.try_get("id")?,
+                feed_id: row.try_get("feed_id")?,
+                feed_url: row.try_get("feed_url")?,
+                url: row.try_get("url")?,
+                title: row.try_get("title")?,
+                viewed_at: NaiveDateTime::parse_from_str(&viewed_at, TIMESTAMP_FORMAT)
+                    .with_context(|| format!("invalid view timestamp \"{}\"", viewed_at))?,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    async fn search_entries(
+        &self,
+        user_id: i64,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<FoundEntry>> {
+        let pattern = contains_pattern(query);
+
+        // The flag is cast so SQLite and PostgreSQL both return integers.
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                feed_entries.id, feed_entries.feed_id, feeds.url AS feed_url,
+                feeds.title AS feed_title, feed_entries.url, feed_entries.title,
+                CAST(CASE WHEN views.feed_entry_id IS NULL THEN 0 ELSE 1 END AS BIGINT) AS is_read
+            FROM subscriptions
+            INNER JOIN feeds ON subscriptions.feed_id = feeds.id
+            INNER JOIN feed_entries ON feed_entries.feed_id = subscriptions.feed_id
+            LEFT JOIN views
+                ON views.feed_entry_id = feed_entries.id AND views.user_id = subscriptions.user_id
+            WHERE subscriptions.user_id = $1
+                AND LOWER(feed_entries.title) LIKE $2 ESCAPE '\'
+            ORDER BY feed_entries.published_at DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(pattern)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let is_read: i64 = row.try_get("is_read")?;
+            entries.push(FoundEntry {
+                id: row.try_get("id")?,
+                feed_id: row.try_get("feed_id")?,
+                feed_url: row.try_get("feed_url")?,
+                feed_title: row.try_get("feed_title")?,
+                url: row.try_get("url")?,
+                title: row.try_get("title")?,
+                read: is_read != 0,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    async fn feed_entries(&self, user_id: i64, feed_id: i64) -> Result<Vec<FeedEntry>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                feed_entries.id, feed_entries.url, feed_entries.title, feed_entries.published_at,
+                CAST(CASE WHEN EXISTS (
+                    SELECT 1 FROM views
+                    WHERE views.feed_entry_id = feed_entries.id AND views.user_id = $1
+                ) THEN 1 ELSE 0 END AS BIGINT) AS is_read
+            FROM subscriptions
+            INNER JOIN feed_entries ON feed_entries.feed_id = subscriptions.feed_id
+            WHERE subscriptions.user_id = $1 AND subscriptions.feed_id = $2
+            ORDER BY feed_entries.id
+            "#,
+        )
+        .bind(user_id)
+        .bind(feed_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let published_at: String = row.try_get("published_at")?;
+            let is_read: i64 = row.try_get("is_read")?;
+            entries.push(FeedEntry {
+                id: row.try_get("id")?,
+                url: row.try_get("url")?,
+                title: row.try_get("title")?,
+                published_at: NaiveDateTime::parse_from_str(&published_at, TIMESTAMP_FORMAT)
+                    .with_context(|| format!("invalid publish timestamp \"{}\"", published_at))?,
+                read: is_read != 0,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    async fn can_access_entry(&self, user_id: i64, entry_id: i64) -> Result<bool> {
+        let row = sqlx::query(
+            r#"
+            SELECT 1 AS accessible
+            FROM feed_entries
+            WHERE feed_entries.id = $2
+                AND (
+                    EXISTS (
+                        SELECT 1 FROM subscriptions
+                        WHERE subscriptions.user_id = $1
+                            AND subscriptions.feed_id = feed_entries.feed_id
+                    )
+                    OR EXISTS (
+                        SELECT 1 FROM views
+                        INNER JOIN feed_entries AS read_entries
+                            ON views.feed_entry_id = read_entries.id
+                        WHERE views.user_id = $1
+                            AND read_entries.feed_id = feed_entries.feed_id
+                    )
+                )
+            "#,
+        )
+        .bind(user_id)
+        .bind(entry_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.is_some())
+    }
+
+    async fn entry_content(&self, _user_id: i64, entry_id: i64) -> Result<Option<EntryContent>> {
+        let row = sqlx::query(
+            r#"
+            SELECT entry_contents.mime_type, entry_contents.content
+            FROM entry_contents
+            WHERE entry_contents.feed_entry_id = $1
+            "#,
+        )
+        .bind(entry_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match row {
+            Some(row) => Ok(Some(EntryContent {
+                mime_type: row.try_get("mime_type")?,
+                content: row.try_get("content")?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn entry_summary(&self, entry_id: i64) -> Result<Option<String>> {
+        let summary: Option<Option<String>> =
+            sqlx::query("SELECT summary FROM feed_entries WHERE id = $1")
+                .bind(entry_id)
+                .fetch_optional(&self.pool)
+                .await?
+                .map(|row| row.try_get("summary"))
+                .transpose()?;
+
+        Ok(summary.flatten())
+    }
+
+    async fn feed_stats(&self) -> Result<Vec<FeedFetchStats>> {
+        // Aggregates are cast so SQLite and PostgreSQL both return integers.
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                feeds.id, feeds.url,
+                COUNT(*) AS fetches,
+                CAST(SUM(CASE WHEN fetch_log.error IS NULL THEN 0 ELSE 1 END) AS BIGINT) AS failures,
+                CAST(AVG(fetch_log.duration_ms) AS BIGINT) AS avg_duration_ms,
+                MAX(fetch_log.duration_ms) AS max_duration_ms,
+                CAST(SUM(fetch_log.bytes) AS BIGINT) AS bytes,
+                CAST(SUM(fetch_log.entries_inserted) AS BIGINT) AS entries_inserted
+            FROM fetch_log
+            INNER JOIN feeds ON fetch_log.feed_id = feeds.id
+            GROUP BY feeds.id, feeds.url
+            ORDER BY avg_duration_ms DESC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut stats = Vec::new();
+        for row in rows {
+            stats.push(FeedFetchStats {
+                feed_id: row.try_get("id")?,
+                url: row.try_get("url")?,
+                fetches: row.try_get("fetches")?,
+                failures: row.try_get("failures")?,
+                avg_duration_ms: row.try_get("avg_duration_ms")?,
+                max_duration_ms: row.try_get("max_duration_ms")?,
+                bytes: row.try_get("bytes")?,
+                entries_inserted: row.try_get("entries_inserted")?,
+            });
+        }
+
+        Ok(stats)
+    }
+
+    async fn slow_feeds(&self, limit: i64) -> Result<Vec<SlowFeed>> {
+        let query = format!(
+            r#"
+            SELECT
+                feeds.id, feeds.url,
+                COUNT(*) AS fetches,
+                CAST(SUM(fetch_log.duration_ms) AS BIGINT) AS total_duration_ms,
+                {}, {}
+            FROM fetch_log
+            INNER JOIN feeds ON fetch_log.feed_id = feeds.id
+            GROUP BY feeds.id, feeds.url
+            ORDER BY total_duration_ms DESC
+            LIMIT $1
+            "#,
+            histogram_columns(
+                "fetch_log.duration_ms",
+                "duration",
+                &FETCH_DURATION_BUCKETS_MS
+            ),
+            histogram_columns("fetch_log.bytes", "size", &FETCH_SIZE_BUCKETS_BYTES),
+        );
+        let rows = sqlx::query(&query)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut feeds = Vec::new();
+        for row in rows {
+            feeds.push(SlowFeed {
+                feed_id: row.try_get("id")?,
+                url: row.try_get("url")?,
+                fetches: row.try_get("fetches")?,
+                total_duration_ms: row.try_get("total_duration_ms")?,
+                durations: histogram(&row, "duration", &FETCH_DURATION_BUCKETS_MS)?,
+                sizes: histogram(&row, "size", &FETCH_SIZE_BUCKETS_BYTES)?,
+            });
+        }
+
+        Ok(feeds)
+    }
+
+    async fn all_feeds(&self) -> Result<Vec<ServerFeed>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                feeds.id, feeds.url, feeds.gone_at, feeds.failing_since, feeds.title,
+                feeds.failure_count, feeds.last_error,
+                CAST(COUNT(subscriptions.user_id) AS BIGINT) AS subscribers
+            FROM feeds
+            LEFT JOIN subscriptions ON subscriptions.feed_id = feeds.id
+            GROUP BY
+                feeds.id, feeds.url, feeds.gone_at, feeds.failing_since, feeds.title,
+                feeds.failure_count, feeds.last_error
+            ORDER BY feeds.id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut feeds = Vec::new();
+        for row in rows {
+            let gone_at: Option<String> = row.try_get("gone_at")?;
+            let failing_since: Option<String> = row.try_get("failing_since")?;
+            feeds.push(ServerFeed {
+                feed_id: row.try_get("id")?,
+                url: row.try_get("url")?,
+                gone: gone_at.is_some(),
+                failing: failing_since.is_some(),
+                title: row.try_get("title")?,
+                subscribers: row.try_get("subscribers")?,
+                failure_count: row.try_get("failure_count")?,
+                last_error: row.try_get("last_error")?,
+            });
+        }
+
+        Ok(feeds)
+    }
+
+    async fn fetch_attempts(&self, feed_id: i64, limit: i64) -> Result<Vec<FetchAttempt>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT fetched_at, status, duration_ms, error
+            FROM fetch_log
+            WHERE feed_id = $1
+            ORDER BY fetched_at DESC, id DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(feed_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut attempts = Vec::new();
+        for row in rows {
+            let fetched_at: String = row.try_get("fetched_at")?;
+            attempts.push(FetchAttempt {
+                fetched_at: NaiveDateTime::parse_from_str(&fetched_at, TIMESTAMP_FORMAT)
+                    .with_context(|| format!("invalid fetch timestamp \"{}\"", fetched_at))?,
+                status: row.try_get("status")?,
+                duration_ms: row.try_get("duration_ms")?,
+                error: row.try_get("error")?,
+            });
+        }
+
+        Ok(attempts)
+    }
+
+    async fn known_certificate(&self, host: &str, port: u16) -> Result<Option<KnownCertificate>> {
+        let row = sqlx::query(
+            "SELECT not_after, rotated_at FROM known_certificates WHERE host = $1 AND port = $2",
+        )
+        .bind(host)
+        .bind(i64::from(port))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let parse = |timestamp: Option<String>| -> Result<Option<NaiveDateTime>> {
+            timestamp
+                .map(|timestamp| {
+                    NaiveDateTime::parse_from_str(&timestamp, TIMESTAMP_FORMAT)
+                        .with_context(|| format!("invalid certificate timestamp \"{}\"", timestamp))
+                })
+                .transpose()
+        };
+        Ok(Some(KnownCertificate {
+            not_after: parse(row.try_get("not_after")?)?,
+            rotated_at: parse(row.try_get("rotated_at")?)?,
+        }))
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        let rows = sqlx::query("SELECT id, username FROM users ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut users = Vec::new();
+        for row in rows {
+            users.push(User {
+                id: row.try_get("id")?,
+                username: row.try_get("username")?,
+            });
+        }
+
+        Ok(users)
+    }
+
+    async fn is_admin(&self, user_id: i64) -> Result<bool> {
+        let is_admin: Option<i64> = sqlx::query("SELECT is_admin FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| row.try_get("is_admin"))
+            .transpose()?;
+
+        Ok(is_admin.unwrap_or(0) != 0)
+    }
+
+    async fn set_admin(&self, user_id: i64, admin: bool) -> Result<()> {
+        sqlx::query("UPDATE users SET is_admin = $1 WHERE id = $2")
+            .bind(admin as i64)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn record_audit(&self, username: &str, command: &str) -> Result<()> {
+        sqlx::query("INSERT INTO audit_log (username, command, created_at) VALUES ($1, $2, $3)")
+            .bind(username)
+            .bind(command)
+            .bind(format_timestamp(chrono::Utc::now().naive_utc()))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn audit_log(&self, limit: i64) -> Result<Vec<AuditEntry>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT username, command, created_at
+            FROM audit_log
+            ORDER BY created_at DESC, id DESC
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let created_at: String = row.try_get("created_at")?;
+            entries.push(AuditEntry {
+                created_at: NaiveDateTime::parse_from_str(&created_at, TIMESTAMP_FORMAT)?,
+                username: row.try_get("username")?,
+                command: row.try_get("command")?,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    async fn refresh_feed(&self, feed_id: i64) -> Result<bool> {
+        let affected_rows = sqlx::query("UPDATE feeds SET fetch_after = NULL WHERE id = $1")
+            .bind(feed_id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+
+        Ok(affected_rows > 0)
+    }
+
+    async fn refresh_all_feeds(&self) -> Result<u64> {
+        let affected_rows =
+            sqlx::query("UPDATE feeds SET fetch_after = NULL WHERE gone_at IS NULL")
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+
+        Ok(affected_rows)
+    }
+
+    async fn last_fetch_cycle(&self) -> Result<Option<NaiveDateTime>> {
+        let row = sqlx::query("SELECT completed_at FROM fetch_cycles WHERE id = 1")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        match row {
+            Some(row) => {
+                let completed_at: String = row.try_get("completed_at")?;
+                Ok(Some(
+                    NaiveDateTime::parse_from_str(&completed_at, TIMESTAMP_FORMAT).with_context(
+                        || format!("invalid fetch cycle timestamp \"{}\"", completed_at),
+                    )?,
+                ))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn user_for_certificate(&self, fingerprint: &str) -> Result<Option<i64>> {
+        let row = sqlx::query("SELECT user_id FROM client_certificates WHERE fingerprint = $1")
+            .bind(fingerprint)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| row.try_get("user_id")).transpose()?)
+    }
+
+    async fn link_certificate(&self, user_id: i64, fingerprint: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO client_certificates (fingerprint, user_id, created_at) VALUES ($1, $2, $3)
+            ON CONFLICT (fingerprint) DO UPDATE SET user_id = excluded.user_id
+            "#,
+        )
+        .bind(fingerprint)
+        .bind(user_id)
+        .bind(format_timestamp(chrono::Utc::now().naive_utc()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn user_for_token(&self, token_hash: &str) -> Result<Option<i64>> {
+        let row = sqlx::query("SELECT user_id FROM api_tokens WHERE token_hash = $1")
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| row.try_get("user_id")).transpose()?)
+    }
+
+    async fn add_token(&self, user_id: i64, token_hash: &str) -> Result<()> {
+        sqlx::query("INSERT INTO api_tokens (token_hash, user_id, created_at) VALUES ($1, $2, $3)")
+            .bind(token_hash)
+            .bind(user_id)
+            .bind(format_timestamp(chrono::Utc::now().naive_utc()))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// `VACUUM INTO` reads from a single transaction, so this is safe to run
+    /// while the server is fetching and serving clients.
+    async fn backup(&self, path: &Path) -> Result<()> {
+        if !self.database_url.starts_with("sqlite") {
+            return Err(format_err!(
+                "backups are only supported for SQLite, use pg_dump for PostgreSQL"
+            ));
+        }
+
+        if path.exists() {
+            return Err(format_err!("{} already exists", path.display()));
+        }
+
+        let target = path
+            .to_str()
+            .ok_or_else(|| format_err!("backup path {} is not valid UTF-8", path.display()))?;
+        let query = format!("VACUUM INTO '{}'", target.replace('\'', "''"));
+        sqlx::query(&query)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("failed to back up database to {}", path.display()))?;
+
+        info!("Backed up database to {}", path.display());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use chrono::{NaiveDate, NaiveDateTime};
+    use futures::future::join_all;
+    use futures::TryStreamExt;
+    use sqlx::Row;
+
+    use super::memory::MemoryStore;
+    use super::{SqlStore, Store};
+    use crate::{database_config, open_database};
+
+    const FEED_URL: &str = "gemini://example.com/gemlog/";
+
+    fn day(day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2021, 3, day)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .unwrap()
+    }
+
+    async fn unread_ids(store: &dyn Store, user_id: i64) -> Vec<i64> {
+        store
+            .unread_entries(user_id)
+            .map_ok(|entry| entry.id)
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn subscribers_share_a_feed() {
+        let store = MemoryStore::new();
+        let alice = store.user_id("alice").await.unwrap();
+        let bob = store.user_id("bob").await.unwrap();
+
+        let feed_id = store.subscribe(alice, FEED_URL, day(1)).await.unwrap();
+        assert_eq!(
+            store.subscribe(bob, FEED_URL, day(1)).await.unwrap(),
+            feed_id
+        );
+        // Subscribing again, even without the trailing slash, changes nothing.
+        let without_slash = FEED_URL.trim_end_matches('/');
+        assert_eq!(
+            store.subscribe(alice, without_slash, day(1)).await.unwrap(),
+            feed_id
+        );
+
+        let subscriptions = store.subscriptions(alice).await.unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].feed_id, feed_id);
+        assert_eq!(subscriptions[0].url, FEED_URL);
+    }
+
+    #[tokio::test]
+    async fn unsubscribing_hides_the_feed_and_its_entries() {
+        let store = MemoryStore::new();
+        let alice = store.user_id("alice").await.unwrap();
+        let feed_id = store.subscribe(alice, FEED_URL, day(1)).await.unwrap();
+        store.add_entry(feed_id, "First", "gemini://example.com/1", day(2));
+
+        assert!(store.unsubscribe(alice, feed_id).await.unwrap());
+        assert!(!store.unsubscribe(alice, feed_id).await.unwrap());
+        assert!(store.subscriptions(alice).await.unwrap().is_empty());
+        assert!(unread_ids(&store, alice).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unread_entries_are_oldest_first_until_read() {
+        let store = MemoryStore::new();
+        let alice = store.user_id("alice").await.unwrap();
+        let bob = store.user_id("bob").await.unwrap();
+        let feed_id = store.subscribe(alice, FEED_URL, day(1)).await.unwrap();
+        store.subscribe(bob, FEED_URL, day(1)).await.unwrap();
+        let newer = store.add_entry(feed_id, "Newer", "gemini://example.com/2", day(3));
+        let older = store.add_entry(feed_id, "Older", "gemini://example.com/1", day(2));
+
+        assert_eq!(unread_ids(&store, alice).await, vec![older, newer]);
+        assert_eq!(store.unread_count(alice).await.unwrap(), 2);
+
+        store.mark_read(alice, older, day(4)).await.unwrap();
+        assert_eq!(unread_ids(&store, alice).await, vec![newer]);
+        assert_eq!(store.unread_count(alice).await.unwrap(), 1);
+        // Read state is per user.
+        assert_eq!(unread_ids(&store, bob).await, vec![older, newer]);
+    }
+
+    #[tokio::test]
+    async fn reading_an_entry_again_keeps_the_first_read() {
+        let store = MemoryStore::new();
+        let alice = store.user_id("alice").await.unwrap();
+        let feed_id = store.subscribe(alice, FEED_URL, day(1)).await.unwrap();
+        let entry = store.add_entry(feed_id, "First", "gemini://example.com/1", day(2));
+
+        store.mark_read(alice, entry, day(3)).await.unwrap();
+        store.mark_read(alice, entry, day(4)).await.unwrap();
+
+        let history = store.read_history(alice, 10).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].viewed_at, day(3));
+    }
+
+    #[tokio::test]
+    async fn entries_before_the_backlog_cutoff_are_not_unread() {
+        let store = MemoryStore::new();
+        let alice = store.user_id("alice").await.unwrap();
+        let feed_id = store.subscribe(alice, FEED_URL, day(10)).await.unwrap();
+        store.add_entry(feed_id, "Old", "gemini://example.com/1", day(9));
+        let on_cutoff = store.add_entry(feed_id, "Cutoff", "gemini://example.com/2", day(10));
+        let new = store.add_entry(feed_id, "New", "gemini://example.com/3", day(11));
+
+        assert_eq!(unread_ids(&store, alice).await, vec![on_cutoff, new]);
+    }
+
+    /// A SQLite database in a file of its own, removed when dropped. An
+    /// in-memory one would be a different database on each connection.
+    struct TestDatabase {
+        path: PathBuf,
+        store: Arc<SqlStore>,
+    }
+
+    impl TestDatabase {
+        async fn new() -> Self {
+            let path =
+                std::env::temp_dir().join(format!("seymour-test-{}.db", rand::random::<u64>()));
+            let url = format!("sqlite://{}", path.display());
+            let pool = open_database(&url, &database_config().unwrap(), true)
+                .await
+                .unwrap();
+
+            Self {
+                path,
+                store: Arc::new(SqlStore::new(pool, url)),
+            }
+        }
+
+        async fn count(&self, query: &str) -> i64 {
+            sqlx::query(query)
+                .fetch_one(&self.store.pool)
+                .await
+                .unwrap()
+                .try_get("count")
+                .unwrap()
+        }
+    }
+
+    impl Drop for TestDatabase {
+        fn drop(&mut self) {
+            for suffix in &["", "-wal", "-shm"] {
+                let mut path = self.path.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    /// How many connections race in each test.
+    const RACERS: usize = 8;
+
+    #[tokio::test(threaded_scheduler)]
+    async fn concurrent_sign_ins_create_one_user() {
+        let database = TestDatabase::new().await;
+
+        let ids = join_all((0..RACERS).map(|_| {
+            let store = database.store.clone();
+            tokio::spawn(async move { store.user_id("alice").await.unwrap() })
+        }))
+        .await;
+
+        let first = *ids[0].as_ref().unwrap();
+        assert!(ids.iter().all(|id| *id.as_ref().unwrap() == first));
+        assert_eq!(
+            database.count("SELECT COUNT(*) AS count FROM users").await,
+            1
+        );
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn concurrent_subscribes_create_one_feed() {
+        let database = TestDatabase::new().await;
+        let mut users = Vec::new();
+        for i in 0..RACERS {
+            let username = format!("user{}", i);
+            users.push(database.store.user_id(&username).await.unwrap());
+        }
+
+        // Half of them leave off the trailing slash, which is the same feed.
+        let feed_ids = join_all(users.into_iter().enumerate().map(|(i, user_id)| {
+            let store = database.store.clone();
+            let url = if i % 2 == 0 {
+                FEED_URL
+            } else {
+                FEED_URL.trim_end_matches('/')
+            };
+            tokio::spawn(async move { store.subscribe(user_id, url, day(1)).await.unwrap() })
+        }))
+        .await;
+
+        let first = *feed_ids[0].as_ref().unwrap();
+        assert!(feed_ids.iter().all(|id| *id.as_ref().unwrap() == first));
+        assert_eq!(
+            database.count("SELECT COUNT(*) AS count FROM feeds").await,
+            1
+        );
+        assert_eq!(
+            database
+                .count("SELECT COUNT(*) AS count FROM subscriptions")
+                .await,
+            RACERS as i64
+        );
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn concurrent_mark_reads_record_one_view() {
+        let database = TestDatabase::new().await;
+        let store = database.store.clone();
+        let alice = store.user_id("alice").await.unwrap();
+        let feed_id = store.subscribe(alice, FEED_URL, day(1)).await.unwrap();
+        sqlx::query(
+            r#"INSERT INTO feed_entries (feed_id, title, published_at, url)
+                VALUES ($1, 'First', '2021-03-02 00:00:00', 'gemini://example.com/1')"#,
+        )
+        .bind(feed_id)
+        .execute(&store.pool)
+        .await
+        .unwrap();
+        let entry_id: i64 = sqlx::query("SELECT id FROM feed_entries")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap()
+            .try_get("id")
+            .unwrap();
+
+        let results = join_all((0..RACERS).map(|_| {
+            let store = store.clone();
+            tokio::spawn(async move { store.mark_read(alice, entry_id, day(3)).await })
+        }))
+        .await;
+
+        assert!(results.into_iter().all(|result| result.unwrap().is_ok()));
+        assert_eq!(
+            database.count("SELECT COUNT(*) AS count FROM views").await,
+            1
+        );
+        assert_eq!(store.unread_count(alice).await.unwrap(), 0);
+    }
+}
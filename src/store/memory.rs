@@ -0,0 +1,1115 @@
+//! A store that keeps everything in memory, for exercising protocol
+//! handling without a database.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{format_err, Result};
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use futures::stream::{self, BoxStream, StreamExt};
+
+use crate::feed_url::slash_variant;
+
+use super::{
+    AuditEntry, DirectoryFeed, EntryContent, FeedDetails, FeedEntry, FeedFetchStats, FeedReads,
+    FetchAttempt, FoundEntry, Group, KnownCertificate, ReadEntry, Recommendation, ServerFeed,
+    SlowFeed, SnoozedFeed, Store, Subscription, SyncChange, SyncPosition, UnreadEntry, User,
+};
+
+struct Feed {
+    id: i64,
+    url: String,
+    gone: bool,
+    title: Option<String>,
+    subtitle: Option<String>,
+}
+
+struct Entry {
+    id: i64,
+    feed_id: i64,
+    title: String,
+    published_at: NaiveDateTime,
+    url: String,
+}
+
+struct SubscriptionRow {
+    user_id: i64,
+    feed_id: i64,
+    backlog_from: Option<NaiveDateTime>,
+    notify: bool,
+    snoozed_until: Option<NaiveDateTime>,
+    max_unread: Option<i64>,
+}
+
+struct View {
+    user_id: i64,
+    feed_entry_id: i64,
+    viewed_at: NaiveDateTime,
+}
+
+#[derive(Default)]
+struct Tables {
+    users: Vec<(i64, String)>,
+    certificates: Vec<(String, i64)>,
+    tokens: Vec<(String, i64)>,
+    feeds: Vec<Feed>,
+    entries: Vec<Entry>,
+    subscriptions: Vec<SubscriptionRow>,
+    views: Vec<View>,
+    auto_mark_read_days: HashMap<i64, i64>,
+    hidden_from_directory: HashSet<i64>,
+    excerpts: HashSet<i64>,
+    dedupe: HashSet<i64>,
+    admins: HashSet<i64>,
+    timezones: HashMap<i64, Tz>,
+    audit_log: Vec<AuditEntry>,
+    groups: Vec<(i64, String)>,
+    group_members: HashSet<(i64, i64)>,
+    group_feeds: Vec<(i64, i64)>,
+    /// Users' changes, each one's ID being its position plus one.
+    changes: Vec<(i64, SyncChange)>,
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn tables(&self) -> std::sync::MutexGuard<'_, Tables> {
+        self.tables.lock().expect("memory store lock poisoned")
+    }
+
+    /// Adds an entry to a feed as if the fetcher had found it, returning
+    /// the entry's ID.
+    pub fn add_entry(
+        &self,
+        feed_id: i64,
+        title: &str,
+        url: &str,
+        published_at: NaiveDateTime,
+    ) -> i64 {
+        let mut tables = self.tables();
+        let id = tables.entries.len() as i64 + 1;
+        tables.entries.push(Entry {
+            id,
+            feed_id,
+            title: title.to_string(),
+            published_at,
+            url: url.to_string(),
+        });
+
+        id
+    }
+
+    /// Entries of the user's subscriptions they haven't read, oldest first,
+    /// leaving out snoozed feeds.
+    fn unread(tables: &Tables, user_id: i64) -> Vec<&Entry> {
+        let now = Utc::now().naive_utc();
+        let dedupe = tables.dedupe.contains(&user_id);
+        let mut entries: Vec<&Entry> = tables
+            .entries
+            .iter()
+            .filter(|entry| {
+                tables.subscriptions.iter().any(|row| {
+                    row.user_id == user_id
+                        && row.feed_id == entry.feed_id
+                        && row
+                            .backlog_from
+                            .is_none_or(|from| entry.published_at >= from)
+                        && row.snoozed_until.is_none_or(|until| until <= now)
+                })
+            })
+            .filter(|entry| {
+                !tables
+                    .views
+                    .iter()
+                    .any(|view| view.user_id == user_id && view.feed_entry_id == entry.id)
+            })
+            .filter(|entry| !dedupe || !Self::is_duplicate(tables, user_id, entry))
+            .collect();
+        entries.sort_by_key(|entry| (entry.published_at, entry.id));
+
+        entries
+    }
+
+    /// Whether the user has read another entry with the entry's URL, or
+    /// another subscribed feed has one first.
+    fn is_duplicate(tables: &Tables, user_id: i64, entry: &Entry) -> bool {
+        tables.entries.iter().any(|other| {
+            other.url == entry.url
+                && other.id != entry.id
+                && (tables
+                    .views
+                    .iter()
+                    .any(|view| view.user_id == user_id && view.feed_entry_id == other.id)
+                    || (other.id < entry.id
+                        && tables
+                            .subscriptions
+                            .iter()
+                            .any(|row| row.user_id == user_id && row.feed_id == other.feed_id)))
+        })
+    }
+
+    fn entry_of(tables: &Tables, id: i64) -> Option<(&Entry, &Feed)> {
+        let entry = tables.entries.iter().find(|entry| entry.id == id)?;
+        let feed = tables.feeds.iter().find(|feed| feed.id == entry.feed_id)?;
+        Some((entry, feed))
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn user_id(&self, username: &str) -> Result<i64> {
+        let mut tables = self.tables();
+        if let Some((id, _)) = tables.users.iter().find(|(_, name)| name == username) {
+            return Ok(*id);
+        }
+
+        let id = tables.users.len() as i64 + 1;
+        tables.users.push((id, username.to_string()));
+
+        Ok(id)
+    }
+
+    async fn subscribe(&self, user_id: i64, url: &str, backlog_from: NaiveDateTime) -> Result<i64> {
+        let variant = slash_variant(url);
+        let mut tables = self.tables();
+        let feed_id = match tables
+            .feeds
+            .iter()
+            .find(|feed| feed.url == url || Some(&feed.url) == variant.as_ref())
+        {
+            Some(feed) => feed.id,
+            None => {
+                let id = tables.feeds.len() as i64 + 1;
+                tables.feeds.push(Feed {
+                    id,
+                    url: url.to_string(),
+                    gone: false,
+                    title: None,
+                    subtitle: None,
+                });
+                id
+            }
+        };
+
+        let subscribed = tables
+            .subscriptions
+            .iter()
+            .any(|row| row.user_id == user_id && row.feed_id == feed_id);
+        if !subscribed {
+            tables.subscriptions.push(SubscriptionRow {
+                user_id,
+                feed_id,
+                backlog_from: Some(backlog_from),
+                notify: false,
+                snoozed_until: None,
+                max_unread: None,
+            });
+            tables
+                .changes
+                .push((user_id, SyncChange::Subscribed(feed_id)));
+        }
+
+        Ok(feed_id)
+    }
+
+    async fn subscriptions(&self, user_id: i64) -> Result<Vec<Subscription>> {
+        let tables = self.tables();
+        Ok(tables
+            .subscriptions
+            .iter()
+            .filter(|row| row.user_id == user_id)
+            .filter_map(|row| tables.feeds.iter().find(|feed| feed.id == row.feed_id))
+            .map(|feed| Subscription {
+                feed_id: feed.id,
+                url: feed.url.clone(),
+                gone: feed.gone,
+                failing: false,
+                title: feed.title.clone(),
+                subtitle: feed.subtitle.clone(),
+            })
+            .collect())
+    }
+
+    async fn create_group(&self, user_id: i64, name: &str) -> Result<Option<i64>> {
+        let mut tables = self.tables();
+        if tables.groups.iter().any(|(_, existing)| existing == name) {
+            return Ok(None);
+        }
+
+        let id = tables.groups.len() as i64 + 1;
+        tables.groups.push((id, name.to_string()));
+        tables.group_members.insert((id, user_id));
+
+        Ok(Some(id))
+    }
+
+    async fn group_id(&self, user_id: i64, name: &str) -> Result<Option<i64>> {
+        let tables = self.tables();
+        Ok(tables
+            .groups
+            .iter()
+            .find(|(_, group)| group == name)
+            .map(|(id, _)| *id)
+            .filter(|id| tables.group_members.contains(&(*id, user_id))))
+    }
+
+    async fn add_group_member(
+        &self,
+        group_id: i64,
+        user_id: i64,
+        backlog_from: NaiveDateTime,
+    ) -> Result<()> {
+        self.tables().group_members.insert((group_id, user_id));
+
+        for feed in self.group_feeds(group_id).await? {
+            self.subscribe(user_id, &feed.url, backlog_from).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn group_subscribe(
+        &self,
+        group_id: i64,
+        url: &str,
+        backlog_from: NaiveDateTime,
+    ) -> Result<i64> {
+        let members: Vec<i64> = self
+            .tables()
+            .group_members
+            .iter()
+            .filter(|(group, _)| *group == group_id)
+            .map(|(_, user_id)| *user_id)
+            .collect();
+
+        let mut feed_id = None;
+        for user_id in members {
+            feed_id = Some(self.subscribe(user_id, url, backlog_from).await?);
+        }
+        let feed_id = feed_id.ok_or_else(|| format_err!("group {} has no members", group_id))?;
+
+        let mut tables = self.tables();
+        if !tables.group_feeds.contains(&(group_id, feed_id)) {
+            tables.group_feeds.push((group_id, feed_id));
+        }
+
+        Ok(feed_id)
+    }
+
+    async fn group_unsubscribe(&self, group_id: i64, feed_id: i64) -> Result<bool> {
+        let mut tables = self.tables();
+        let before = tables.group_feeds.len();
+        tables.group_feeds.retain(|row| *row != (group_id, feed_id));
+
+        Ok(tables.group_feeds.len() < before)
+    }
+
+    async fn groups(&self, user_id: i64) -> Result<Vec<Group>> {
+        let tables = self.tables();
+        let mut groups: Vec<Group> = tables
+            .groups
+            .iter()
+            .filter(|(id, _)| tables.group_members.contains(&(*id, user_id)))
+            .map(|(id, name)| Group {
+                id: *id,
+                name: name.clone(),
+                members: tables
+                    .group_members
+                    .iter()
+                    .filter(|(group, _)| group == id)
+                    .count() as i64,
+                feeds: tables
+                    .group_feeds
+                    .iter()
+                    .filter(|(group, _)| group == id)
+                    .count() as i64,
+            })
+            .collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(groups)
+    }
+
+    async fn group_feeds(&self, group_id: i64) -> Result<Vec<Subscription>> {
+        let tables = self.tables();
+        Ok(tables
+            .group_feeds
+            .iter()
+            .filter(|(group, _)| *group == group_id)
+            .filter_map(|(_, feed_id)| tables.feeds.iter().find(|feed| feed.id == *feed_id))
+            .map(|feed| Subscription {
+                feed_id: feed.id,
+                url: feed.url.clone(),
+                gone: feed.gone,
+                failing: false,
+                title: feed.title.clone(),
+                subtitle: feed.subtitle.clone(),
+            })
+            .collect())
+    }
+
+    async fn unsubscribe(&self, user_id: i64, feed_id: i64) -> Result<bool> {
+        let mut tables = self.tables();
+        let before = tables.subscriptions.len();
+        tables
+            .subscriptions
+            .retain(|row| !(row.user_id == user_id && row.feed_id == feed_id));
+
+        let unsubscribed = tables.subscriptions.len() < before;
+        if unsubscribed {
+            tables
+                .changes
+                .push((user_id, SyncChange::Unsubscribed(feed_id)));
+        }
+
+        Ok(unsubscribed)
+    }
+
+    async fn show_archive(&self, user_id: i64, feed_id: i64) -> Result<bool> {
+        let mut tables = self.tables();
+        match tables
+            .subscriptions
+            .iter_mut()
+            .find(|row| row.user_id == user_id && row.feed_id == feed_id)
+        {
+            Some(row) => {
+                row.backlog_from = None;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn snooze(&self, user_id: i64, feed_id: i64, until: NaiveDateTime) -> Result<bool> {
+        let mut tables = self.tables();
+        match tables
+            .subscriptions
+            .iter_mut()
+            .find(|row| row.user_id == user_id && row.feed_id == feed_id)
+        {
+            Some(row) => {
+                row.snoozed_until = Some(until);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_max_unread(&self, user_id: i64, feed_id: i64, limit: Option<i64>) -> Result<bool> {
+        let mut tables = self.tables();
+        match tables
+            .subscriptions
+            .iter_mut()
+            .find(|row| row.user_id == user_id && row.feed_id == feed_id)
+        {
+            Some(row) => {
+                row.max_unread = limit;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn snoozed_feeds(&self, user_id: i64) -> Result<Vec<SnoozedFeed>> {
+        let tables = self.tables();
+        let now = Utc::now().naive_utc();
+        let mut feeds: Vec<SnoozedFeed> = tables
+            .subscriptions
+            .iter()
+            .filter(|row| row.user_id == user_id)
+            .filter_map(|row| {
+                let until = row.snoozed_until.filter(|until| *until > now)?;
+                let feed = tables.feeds.iter().find(|feed| feed.id == row.feed_id)?;
+                Some(SnoozedFeed {
+                    feed_id: feed.id,
+                    url: feed.url.clone(),
+                    title: feed.title.clone(),
+                    until,
+                })
+            })
+            .collect();
+        feeds.sort_by_key(|feed| feed.until);
+
+        Ok(feeds)
+    }
+
+    async fn set_notify(&self, user_id: i64, feed_id: i64, notify: bool) -> Result<bool> {
+        let mut tables = self.tables();
+        match tables
+            .subscriptions
+            .iter_mut()
+            .find(|row| row.user_id == user_id && row.feed_id == feed_id)
+        {
+            Some(row) => {
+                row.notify = notify;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn notified_usernames(&self, feed_id: i64) -> Result<Vec<String>> {
+        let tables = self.tables();
+        let mut usernames: Vec<String> = tables
+            .subscriptions
+            .iter()
+            .filter(|row| row.feed_id == feed_id && row.notify)
+            .filter_map(|row| {
+                tables
+                    .users
+                    .iter()
+                    .find(|(id, _)| *id == row.user_id)
+                    .map(|(_, username)| username.clone())
+            })
+            .collect();
+        usernames.sort();
+
+        Ok(usernames)
+    }
+
+    async fn subscribed_feed(&self, user_id: i64, feed_id: i64) -> Result<Option<FeedDetails>> {
+        let tables = self.tables();
+        let subscribed = tables
+            .subscriptions
+            .iter()
+            .any(|row| row.user_id == user_id && row.feed_id == feed_id);
+        if !subscribed {
+            return Ok(None);
+        }
+
+        Ok(tables
+            .feeds
+            .iter()
+            .find(|feed| feed.id == feed_id)
+            .map(|feed| FeedDetails {
+                url: feed.url.clone(),
+                gone: feed.gone,
+                failing: false,
+                title: feed.title.clone(),
+                subtitle: feed.subtitle.clone(),
+                failure_count: 0,
+                last_error: None,
+            }))
+    }
+
+    fn unread_entries(&self, user_id: i64) -> BoxStream<'_, Result<UnreadEntry>> {
+        let tables = self.tables();
+        let entries: Vec<_> = Self::unread(&tables, user_id)
+            .into_iter()
+            .filter_map(|entry| Self::entry_of(&tables, entry.id))
+            .map(|(entry, feed)| {
+                Ok(UnreadEntry {
+                    id: entry.id,
+                    feed_id: feed.id,
+                    feed_url: feed.url.clone(),
+                    feed_title: feed.title.clone(),
+                    url: entry.url.clone(),
+                    title: entry.title.clone(),
+                })
+            })
+            .collect();
+
+        stream::iter(entries).boxed()
+    }
+
+    async fn unread_count(&self, user_id: i64) -> Result<i64> {
+        Ok(Self::unread(&self.tables(), user_id).len() as i64)
+    }
+
+    async fn feed_unread_counts(&self, user_id: i64) -> Result<HashMap<i64, i64>> {
+        let mut counts = HashMap::new();
+        for entry in Self::unread(&self.tables(), user_id) {
+            *counts.entry(entry.feed_id).or_insert(0) += 1;
+        }
+
+        Ok(counts)
+    }
+
+    async fn mark_read(
+        &self,
+        user_id: i64,
+        feed_entry_id: i64,
+        viewed_at: NaiveDateTime,
+    ) -> Result<()> {
+        let mut tables = self.tables();
+        let read = tables
+            .views
+            .iter()
+            .any(|view| view.user_id == user_id && view.feed_entry_id == feed_entry_id);
+        if read {
+            return Ok(());
+        }
+
+        tables.views.push(View {
+            user_id,
+            feed_entry_id,
+            viewed_at,
+        });
+        tables
+            .changes
+            .push((user_id, SyncChange::Read(feed_entry_id)));
+
+        Ok(())
+    }
+
+    async fn mark_feed_read(
+        &self,
+        user_id: i64,
+        feed_id: i64,
+        viewed_at: NaiveDateTime,
+    ) -> Result<()> {
+        let mut tables = self.tables();
+        let unread: Vec<i64> = tables
+            .entries
+            .iter()
+            .filter(|entry| entry.feed_id == feed_id)
+            .map(|entry| entry.id)
+            .filter(|id| {
+                !tables
+                    .views
+                    .iter()
+                    .any(|view| view.user_id == user_id && view.feed_entry_id == *id)
+            })
+            .collect();
+        for feed_entry_id in unread {
+            tables.views.push(View {
+                user_id,
+                feed_entry_id,
+                viewed_at,
+            });
+            tables
+                .changes
+                .push((user_id, SyncChange::Read(feed_entry_id)));
+        }
+
+        Ok(())
+    }
+
+    async fn sync_position(&self) -> Result<SyncPosition> {
+        let tables = self.tables();
+        Ok(SyncPosition {
+            entry_id: tables
+                .entries
+                .iter()
+                .map(|entry| entry.id)
+                .max()
+                .unwrap_or(0),
+            change_id: tables.changes.len() as i64,
+        })
+    }
+
+    async fn entries_between(&self, user_id: i64, from: i64, to: i64) -> Result<Vec<UnreadEntry>> {
+        let tables = self.tables();
+        let mut entries: Vec<UnreadEntry> = tables
+            .entries
+            .iter()
+            .filter(|entry| entry.id > from && entry.id <= to)
+            .filter(|entry| {
+                tables.subscriptions.iter().any(|row| {
+                    row.user_id == user_id
+                        && row.feed_id == entry.feed_id
+                        && row
+                            .backlog_from
+                            .is_none_or(|backlog_from| entry.published_at >= backlog_from)
+                })
+            })
+            .filter_map(|entry| {
+                let feed = tables.feeds.iter().find(|feed| feed.id == entry.feed_id)?;
+                Some(UnreadEntry {
+                    id: entry.id,
+                    feed_id: entry.feed_id,
+                    feed_url: feed.url.clone(),
+                    feed_title: feed.title.clone(),
+                    url: entry.url.clone(),
+                    title: entry.title.clone(),
+                })
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.id);
+
+        Ok(entries)
+    }
+
+    async fn changes_between(&self, user_id: i64, from: i64, to: i64) -> Result<Vec<SyncChange>> {
+        let tables = self.tables();
+        Ok(tables
+            .changes
+            .iter()
+            .zip(1..)
+            .filter(|((user, _), id)| *user == user_id && *id > from && *id <= to)
+            .map(|((_, change), _)| *change)
+            .collect())
+    }
+
+    async fn set_auto_mark_read(&self, user_id: i64, days: Option<i64>) -> Result<()> {
+        // Nothing enforces it, since the maintenance task needs a database.
+        let mut tables = self.tables();
+        match days {
+            Some(days) => tables.auto_mark_read_days.insert(user_id, days),
+            None => tables.auto_mark_read_days.remove(&user_id),
+        };
+
+        Ok(())
+    }
+
+    async fn timezone(&self, user_id: i64) -> Result<Tz> {
+        Ok(self
+            .tables()
+            .timezones
+            .get(&user_id)
+            .copied()
+            .unwrap_or(Tz::UTC))
+    }
+
+    async fn set_timezone(&self, user_id: i64, timezone: Tz) -> Result<()> {
+        self.tables().timezones.insert(user_id, timezone);
+
+        Ok(())
+    }
+
+    async fn excerpts(&self, user_id: i64) -> Result<bool> {
+        Ok(self.tables().excerpts.contains(&user_id))
+    }
+
+    async fn set_excerpts(&self, user_id: i64, excerpts: bool) -> Result<()> {
+        let mut tables = self.tables();
+        if excerpts {
+            tables.excerpts.insert(user_id);
+        } else {
+            tables.excerpts.remove(&user_id);
+        }
+
+        Ok(())
+    }
+
+    async fn set_dedupe(&self, user_id: i64, dedupe: bool) -> Result<()> {
+        let mut tables = self.tables();
+        if dedupe {
+            tables.dedupe.insert(user_id);
+        } else {
+            tables.dedupe.remove(&user_id);
+        }
+
+        Ok(())
+    }
+
+    async fn set_hide_from_directory(&self, user_id: i64, hide: bool) -> Result<()> {
+        let mut tables = self.tables();
+        if hide {
+            tables.hidden_from_directory.insert(user_id);
+        } else {
+            tables.hidden_from_directory.remove(&user_id);
+        }
+
+        Ok(())
+    }
+
+    async fn directory(&self, query: Option<&str>, limit: i64) -> Result<Vec<DirectoryFeed>> {
+        let tables = self.tables();
+        let query = query.map(str::to_lowercase);
+        let mut feeds: Vec<DirectoryFeed> = tables
+            .feeds
+            .iter()
+            .filter(|feed| !feed.gone)
+            .filter(|feed| match &query {
+                Some(query) => {
+                    feed.url.to_lowercase().contains(query)
+                        || feed
+                            .title
+                            .as_ref()
+                            .is_some_and(|title| title.to_lowercase().contains(query))
+                }
+                None => true,
+            })
+            .map(|feed| DirectoryFeed {
+                feed_id: feed.id,
+                url: feed.url.clone(),
+                title: feed.title.clone(),
+                subscribers: tables
+                    .subscriptions
+                    .iter()
+                    .filter(|row| row.feed_id == feed.id)
+                    .filter(|row| !tables.hidden_from_directory.contains(&row.user_id))
+                    .count() as i64,
+            })
+            .filter(|feed| feed.subscribers > 0)
+            .collect();
+        feeds.sort_by_key(|feed| (std::cmp::Reverse(feed.subscribers), feed.feed_id));
+        feeds.truncate(limit.max(0) as usize);
+
+        Ok(feeds)
+    }
+
+    async fn recommendations(&self, user_id: i64, limit: i64) -> Result<Vec<Recommendation>> {
+        let tables = self.tables();
+        let feeds_of = |user_id: i64| -> HashSet<i64> {
+            tables
+                .subscriptions
+                .iter()
+                .filter(|row| row.user_id == user_id)
+                .map(|row| row.feed_id)
+                .collect()
+        };
+        let mine = feeds_of(user_id);
+
+        let mut scores: HashMap<i64, i64> = HashMap::new();
+        for (other, _) in &tables.users {
+            if *other == user_id || tables.hidden_from_directory.contains(other) {
+                continue;
+            }
+            let theirs = feeds_of(*other);
+            let shared = theirs.intersection(&mine).count() as i64;
+            for feed_id in theirs.difference(&mine) {
+                *scores.entry(*feed_id).or_default() += shared;
+            }
+        }
+
+        let mut recommendations: Vec<Recommendation> = tables
+            .feeds
+            .iter()
+            .filter(|feed| !feed.gone)
+            .filter_map(|feed| {
+                Some(Recommendation {
+                    feed_id: feed.id,
+                    url: feed.url.clone(),
+                    title: feed.title.clone(),
+                    score: *scores.get(&feed.id).filter(|score| **score > 0)?,
+                })
+            })
+            .collect();
+        recommendations.sort_by_key(|feed| (std::cmp::Reverse(feed.score), feed.feed_id));
+        recommendations.truncate(limit.max(0) as usize);
+
+        Ok(recommendations)
+    }
+
+    async fn read_count_since(&self, user_id: i64, since: NaiveDateTime) -> Result<i64> {
+        Ok(self
+            .tables()
+            .views
+            .iter()
+            .filter(|view| view.user_id == user_id && view.viewed_at >= since)
+            .count() as i64)
+    }
+
+    async fn read_times(&self, user_id: i64) -> Result<Vec<NaiveDateTime>> {
+        let mut times: Vec<NaiveDateTime> = self
+            .tables()
+            .views
+            .iter()
+            .filter(|view| view.user_id == user_id)
+            .map(|view| view.viewed_at)
+            .collect();
+        times.sort();
+
+        Ok(times)
+    }
+
+    async fn most_read_feeds(&self, user_id: i64, limit: i64) -> Result<Vec<FeedReads>> {
+        let tables = self.tables();
+        let mut reads: HashMap<i64, i64> = HashMap::new();
+        for view in tables.views.iter().filter(|view| view.user_id == user_id) {
+            if let Some((_, feed)) = Self::entry_of(&tables, view.feed_entry_id) {
+                *reads.entry(feed.id).or_default() += 1;
+            }
+        }
+
+        let mut feeds: Vec<FeedReads> = tables
+            .feeds
+            .iter()
+            .filter_map(|feed| {
+                Some(FeedReads {
+                    feed_id: feed.id,
+                    url: feed.url.clone(),
+                    title: feed.title.clone(),
+                    reads: *reads.get(&feed.id)?,
+                })
+            })
+            .collect();
+        feeds.sort_by_key(|feed| (std::cmp::Reverse(feed.reads), feed.feed_id));
+        feeds.truncate(limit.max(0) as usize);
+
+        Ok(feeds)
+    }
+
+    async fn read_history(&self, user_id: i64, limit: i64) -> Result<Vec<ReadEntry>> {
+        let tables = self.tables();
+        let mut views: Vec<&View> = tables
+            .views
+            .iter()
+            .filter(|view| view.user_id == user_id)
+            .collect();
+        views.sort_by_key(|view| std::cmp::Reverse(view.viewed_at));
+
+        Ok(views
+            .into_iter()
+            .filter_map(|view| {
+                let (entry, feed) = Self::entry_of(&tables, view.feed_entry_id)?;
+                Some(ReadEntry {
+                    id: entry.id,
+                    feed_id: feed.id,
+                    feed_url: feed.url.clone(),
+                    url: entry.url.clone(),
+                    title: entry.title.clone(),
+                    viewed_at: view.viewed_at,
+                })
+            })
+            .take(limit.max(0) as usize)
+            .collect())
+    }
+
+    async fn search_entries(
+        &self,
+        user_id: i64,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<FoundEntry>> {
+        let tables = self.tables();
+        let query = query.to_lowercase();
+        let mut entries: Vec<&Entry> = tables
+            .entries
+            .iter()
+            .filter(|entry| {
+                tables
+                    .subscriptions
+                    .iter()
+                    .any(|row| row.user_id == user_id && row.feed_id == entry.feed_id)
+            })
+            .filter(|entry| entry.title.to_lowercase().contains(&query))
+            .collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.published_at));
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|entry| Self::entry_of(&tables, entry.id))
+            .map(|(entry, feed)| FoundEntry {
+                id: entry.id,
+                feed_id: feed.id,
+                feed_url: feed.url.clone(),
+                feed_title: feed.title.clone(),
+                url: entry.url.clone(),
+                title: entry.title.clone(),
+                read: tables
+                    .views
+                    .iter()
+                    .any(|view| view.user_id == user_id && view.feed_entry_id == entry.id),
+            })
+            .take(limit.max(0) as usize)
+            .collect())
+    }
+
+    async fn feed_entries(&self, user_id: i64, feed_id: i64) -> Result<Vec<FeedEntry>> {
+        let tables = self.tables();
+        if !tables
+            .subscriptions
+            .iter()
+            .any(|row| row.user_id == user_id && row.feed_id == feed_id)
+        {
+            return Ok(Vec::new());
+        }
+
+        Ok(tables
+            .entries
+            .iter()
+            .filter(|entry| entry.feed_id == feed_id)
+            .map(|entry| FeedEntry {
+                id: entry.id,
+                url: entry.url.clone(),
+                title: entry.title.clone(),
+                published_at: entry.published_at,
+                read: tables
+                    .views
+                    .iter()
+                    .any(|view| view.user_id == user_id && view.feed_entry_id == entry.id),
+            })
+            .collect())
+    }
+
+    async fn can_access_entry(&self, user_id: i64, entry_id: i64) -> Result<bool> {
+        let tables = self.tables();
+        let feed_id = match tables.entries.iter().find(|entry| entry.id == entry_id) {
+            Some(entry) => entry.feed_id,
+            None => return Ok(false),
+        };
+
+        let subscribed = tables
+            .subscriptions
+            .iter()
+            .any(|row| row.user_id == user_id && row.feed_id == feed_id);
+        let read_before = tables.views.iter().any(|view| {
+            view.user_id == user_id
+                && tables
+                    .entries
+                    .iter()
+                    .any(|entry| entry.id == view.feed_entry_id && entry.feed_id == feed_id)
+        });
+
+        Ok(subscribed || read_before)
+    }
+
+    async fn entry_content(&self, _user_id: i64, _entry_id: i64) -> Result<Option<EntryContent>> {
+        // Nothing is ever fetched into a memory store.
+        Ok(None)
+    }
+
+    async fn entry_summary(&self, _entry_id: i64) -> Result<Option<String>> {
+        // Nothing is ever fetched into a memory store.
+        Ok(None)
+    }
+
+    async fn feed_stats(&self) -> Result<Vec<FeedFetchStats>> {
+        // Nothing is ever fetched into a memory store.
+        Ok(Vec::new())
+    }
+
+    async fn slow_feeds(&self, _limit: i64) -> Result<Vec<SlowFeed>> {
+        Ok(Vec::new())
+    }
+
+    async fn all_feeds(&self) -> Result<Vec<ServerFeed>> {
+        let tables = self.tables();
+        let mut feeds: Vec<ServerFeed> = tables
+            .feeds
+            .iter()
+            .map(|feed| ServerFeed {
+                feed_id: feed.id,
+                url: feed.url.clone(),
+                gone: feed.gone,
+                failing: false,
+                title: feed.title.clone(),
+                subscribers: tables
+                    .subscriptions
+                    .iter()
+                    .filter(|row| row.feed_id == feed.id)
+                    .count() as i64,
+                // Nothing is ever fetched into a memory store.
+                failure_count: 0,
+                last_error: None,
+            })
+            .collect();
+        feeds.sort_by_key(|feed| feed.feed_id);
+
+        Ok(feeds)
+    }
+
+    async fn fetch_attempts(&self, _feed_id: i64, _limit: i64) -> Result<Vec<FetchAttempt>> {
+        Ok(Vec::new())
+    }
+
+    async fn known_certificate(&self, _host: &str, _port: u16) -> Result<Option<KnownCertificate>> {
+        // Nothing is ever fetched into a memory store.
+        Ok(None)
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        Ok(self
+            .tables()
+            .users
+            .iter()
+            .map(|(id, username)| User {
+                id: *id,
+                username: username.clone(),
+            })
+            .collect())
+    }
+
+    async fn is_admin(&self, user_id: i64) -> Result<bool> {
+        Ok(self.tables().admins.contains(&user_id))
+    }
+
+    async fn set_admin(&self, user_id: i64, admin: bool) -> Result<()> {
+        let mut tables = self.tables();
+        if admin {
+            tables.admins.insert(user_id);
+        } else {
+            tables.admins.remove(&user_id);
+        }
+
+        Ok(())
+    }
+
+    async fn record_audit(&self, username: &str, command: &str) -> Result<()> {
+        self.tables().audit_log.push(AuditEntry {
+            created_at: Utc::now().naive_utc(),
+            username: username.to_string(),
+            command: command.to_string(),
+        });
+
+        Ok(())
+    }
+
+    async fn audit_log(&self, limit: i64) -> Result<Vec<AuditEntry>> {
+        Ok(self
+            .tables()
+            .audit_log
+            .iter()
+            .rev()
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn refresh_feed(&self, feed_id: i64) -> Result<bool> {
+        // Nothing is ever fetched, so there's no schedule to change.
+        Ok(self.tables().feeds.iter().any(|feed| feed.id == feed_id))
+    }
+
+    async fn refresh_all_feeds(&self) -> Result<u64> {
+        Ok(self.tables().feeds.iter().filter(|feed| !feed.gone).count() as u64)
+    }
+
+    async fn last_fetch_cycle(&self) -> Result<Option<NaiveDateTime>> {
+        Ok(None)
+    }
+
+    async fn user_for_certificate(&self, fingerprint: &str) -> Result<Option<i64>> {
+        Ok(self
+            .tables()
+            .certificates
+            .iter()
+            .find(|(linked, _)| linked == fingerprint)
+            .map(|(_, user_id)| *user_id))
+    }
+
+    async fn link_certificate(&self, user_id: i64, fingerprint: &str) -> Result<()> {
+        let mut tables = self.tables();
+        tables
+            .certificates
+            .retain(|(linked, _)| linked != fingerprint);
+        tables.certificates.push((fingerprint.to_string(), user_id));
+
+        Ok(())
+    }
+
+    async fn user_for_token(&self, token_hash: &str) -> Result<Option<i64>> {
+        Ok(self
+            .tables()
+            .tokens
+            .iter()
+            .find(|(hash, _)| hash == token_hash)
+            .map(|(_, user_id)| *user_id))
+    }
+
+    async fn add_token(&self, user_id: i64, token_hash: &str) -> Result<()> {
+        self.tables().tokens.push((token_hash.to_string(), user_id));
+
+        Ok(())
+    }
+
+    async fn backup(&self, path: &Path) -> Result<()> {
+        Err(format_err!(
+            "can't back up an in-memory store to {}",
+            path.display()
+        ))
+    }
+}
@@ -0,0 +1,362 @@
+//! Moves a single user's subscriptions and read state between instances.
+//!
+//! Feeds and entries get different IDs on every instance, so they're
+//! identified by URL (and an entry also by its publication time), which is
+//! what makes them unique within the database anyway.
+
+use anyhow::{format_err, Context, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{Any, Done, Pool, Row};
+use tracing::info;
+
+use crate::feed::{entry_key, TIMESTAMP_FORMAT};
+use crate::feed_url::{canonicalize, url_key};
+use crate::username;
+
+/// Bumped whenever the export format changes incompatibly.
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UserExport {
+    version: u32,
+    username: String,
+    subscriptions: Vec<ExportedSubscription>,
+    views: Vec<ExportedView>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedSubscription {
+    feed_url: String,
+    created_at: Option<String>,
+    backlog_from: Option<String>,
+    #[serde(default)]
+    notify: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedView {
+    feed_url: String,
+    entry_url: String,
+    published_at: String,
+    /// Kept so that the entry can be added ahead of the first fetch, which
+    /// the read marker needs to refer to.
+    title: String,
+    viewed_at: Option<String>,
+}
+
+/// Writes the user's data as JSON to stdout.
+pub async fn export_user(pool: &Pool<Any>, username: &str) -> Result<()> {
+    let user_id: i64 = sqlx::query("SELECT id FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| format_err!("no user named \"{}\" exists", username))?
+        .try_get("id")?;
+
+    let mut subscriptions = Vec::new();
+    for row in sqlx::query(
+        r#"
+        SELECT feeds.url, subscriptions.created_at, subscriptions.backlog_from, subscriptions.notify
+        FROM subscriptions
+        INNER JOIN feeds ON subscriptions.feed_id = feeds.id
+        WHERE subscriptions.user_id = $1
+        ORDER BY feeds.url
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?
+    {
+        subscriptions.push(ExportedSubscription {
+            feed_url: row.try_get("url")?,
+            created_at: row.try_get("created_at")?,
+            backlog_from: row.try_get("backlog_from")?,
+            notify: row.try_get::<i64, _>("notify")? != 0,
+        });
+    }
+
+    let mut views = Vec::new();
+    for row in sqlx::query(
+        r#"
+        SELECT
+            feeds.url AS feed_url, feed_entries.url, feed_entries.published_at,
+            feed_entries.title, views.viewed_at
+        FROM views
+        INNER JOIN feed_entries ON views.feed_entry_id = feed_entries.id
+        INNER JOIN feeds ON feed_entries.feed_id = feeds.id
+        WHERE views.user_id = $1
+        ORDER BY feeds.url, feed_entries.published_at
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?
+    {
+        views.push(ExportedView {
+            feed_url: row.try_get("feed_url")?,
+            entry_url: row.try_get("url")?,
+            published_at: row.try_get("published_at")?,
+            title: row.try_get("title")?,
+            viewed_at: row.try_get("viewed_at")?,
+        });
+    }
+
+    info!(
+        "Exporting {} subscriptions and {} read entries for \"{}\"",
+        subscriptions.len(),
+        views.len(),
+        username
+    );
+
+    let export = UserExport {
+        version: FORMAT_VERSION,
+        username: username.to_string(),
+        subscriptions,
+        views,
+    };
+    println!("{}", serde_json::to_string_pretty(&export)?);
+
+    Ok(())
+}
+
+/// Loads a user exported by [`export_user`], merging with anything the
+/// user already has on this instance.
+pub async fn import_user(pool: &Pool<Any>, path: &str) -> Result<()> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("failed to read {}", path))?;
+    let export: UserExport =
+        serde_json::from_str(&contents).with_context(|| format!("invalid export {}", path))?;
+    if export.version != FORMAT_VERSION {
+        return Err(format_err!(
+            "unsupported export format version {}",
+            export.version
+        ));
+    }
+
+    let username = username::normalize(&export.username)?;
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("INSERT INTO users (username) VALUES ($1) ON CONFLICT DO NOTHING")
+        .bind(&username)
+        .execute(&mut tx)
+        .await?;
+    let user_id: i64 = sqlx::query("SELECT id FROM users WHERE username = $1")
+        .bind(&username)
+        .fetch_one(&mut tx)
+        .await?
+        .try_get("id")?;
+
+    for subscription in &export.subscriptions {
+        let feed_id = feed_id(&mut tx, &subscription.feed_url).await?;
+
+        sqlx::query(
+            r#"INSERT INTO subscriptions (user_id, feed_id, created_at, backlog_from, notify)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT DO NOTHING"#,
+        )
+        .bind(user_id)
+        .bind(feed_id)
+        .bind(&subscription.created_at)
+        .bind(&subscription.backlog_from)
+        .bind(subscription.notify as i64)
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query("UPDATE feeds SET unsubscribed_at = NULL WHERE id = $1")
+            .bind(feed_id)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    for view in &export.views {
+        let feed_id = feed_id(&mut tx, &view.feed_url).await?;
+
+        // The fetcher won't have seen feeds new to this instance yet, so add
+        // the entry now. Its next fetch leaves it in place.
+        sqlx::query(
+            r#"INSERT INTO feed_entries (feed_id, title, published_at, url, entry_key)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT DO NOTHING"#,
+        )
+        .bind(feed_id)
+        .bind(&view.title)
+        .bind(&view.published_at)
+        .bind(&view.entry_url)
+        .bind(entry_key(&view.entry_url))
+        .execute(&mut tx)
+        .await?;
+        let feed_entry_id: i64 = sqlx::query(
+            "SELECT id FROM feed_entries WHERE feed_id = $1 AND published_at = $2 AND url = $3",
+        )
+        .bind(feed_id)
+        .bind(&view.published_at)
+        .bind(&view.entry_url)
+        .fetch_one(&mut tx)
+        .await?
+        .try_get("id")?;
+
+        sqlx::query(
+            r#"INSERT INTO views (user_id, feed_entry_id, viewed_at)
+                SELECT $1, $2, $3
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM views WHERE user_id = $1 AND feed_entry_id = $2
+                )"#,
+        )
+        .bind(user_id)
+        .bind(feed_entry_id)
+        .bind(&view.viewed_at)
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    info!(
+        "Imported {} subscriptions and {} read entries for \"{}\"",
+        export.subscriptions.len(),
+        export.views.len(),
+        username
+    );
+
+    Ok(())
+}
+
+/// Copies the subscriptions and read entries of `username` on the seymour
+/// instance at `address` to the same user here, merging with anything the
+/// user already has.
+///
+/// The protocol doesn't say when a read entry was published, so entries
+/// this instance hasn't fetched yet can't be added ahead of time as
+/// [`import_user`] does. Instead they're remembered and marked read once
+/// the fetcher first finds them.
+pub async fn import_instance(pool: &Pool<Any>, address: &str, username: &str) -> Result<()> {
+    let mut client = seymour_client::Client::connect(address)
+        .await
+        .with_context(|| format!("failed to connect to {}", address))?;
+    client
+        .login(username)
+        .await
+        .with_context(|| format!("failed to log in to {} as \"{}\"", address, username))?;
+    let subscriptions = client.subscriptions().await?;
+    let history = client.history(i64::MAX).await?;
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("INSERT INTO users (username) VALUES ($1) ON CONFLICT DO NOTHING")
+        .bind(username)
+        .execute(&mut tx)
+        .await?;
+    let user_id: i64 = sqlx::query("SELECT id FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_one(&mut tx)
+        .await?
+        .try_get("id")?;
+
+    let now = chrono::Utc::now()
+        .naive_utc()
+        .format(TIMESTAMP_FORMAT)
+        .to_string();
+    for subscription in &subscriptions {
+        let feed_id = feed_id(&mut tx, &subscription.url).await?;
+
+        sqlx::query(
+            r#"INSERT INTO subscriptions (user_id, feed_id, created_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT DO NOTHING"#,
+        )
+        .bind(user_id)
+        .bind(feed_id)
+        .bind(&now)
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query("UPDATE feeds SET unsubscribed_at = NULL WHERE id = $1")
+            .bind(feed_id)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    let mut pending = 0;
+    for read in &history {
+        let feed_id = feed_id(&mut tx, &read.entry.feed_url).await?;
+        let viewed_at = read
+            .viewed_at
+            .naive_utc()
+            .format(TIMESTAMP_FORMAT)
+            .to_string();
+
+        let marked = sqlx::query(
+            r#"INSERT INTO views (user_id, feed_entry_id, viewed_at)
+                SELECT $1, feed_entries.id, $2
+                FROM feed_entries
+                WHERE feed_entries.feed_id = $3 AND feed_entries.url = $4
+                    AND NOT EXISTS (
+                        SELECT 1 FROM views
+                        WHERE views.user_id = $1 AND views.feed_entry_id = feed_entries.id
+                    )"#,
+        )
+        .bind(user_id)
+        .bind(&viewed_at)
+        .bind(feed_id)
+        .bind(&read.entry.url)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+        if marked > 0 {
+            continue;
+        }
+
+        // Entries that exist but were read here already need nothing more.
+        let known = sqlx::query("SELECT id FROM feed_entries WHERE feed_id = $1 AND url = $2")
+            .bind(feed_id)
+            .bind(&read.entry.url)
+            .fetch_optional(&mut tx)
+            .await?
+            .is_some();
+        if !known {
+            sqlx::query(
+                r#"INSERT INTO pending_views (user_id, feed_id, entry_url, viewed_at)
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT DO NOTHING"#,
+            )
+            .bind(user_id)
+            .bind(feed_id)
+            .bind(&read.entry.url)
+            .bind(&viewed_at)
+            .execute(&mut tx)
+            .await?;
+            pending += 1;
+        }
+    }
+
+    tx.commit().await?;
+
+    info!(
+        "Imported {} subscriptions and {} read entries for \"{}\" from {}, {} of them waiting on a fetch",
+        subscriptions.len(),
+        history.len(),
+        username,
+        address,
+        pending
+    );
+
+    Ok(())
+}
+
+/// Returns the ID of the feed at `url`, adding it if it doesn't exist.
+async fn feed_id(tx: &mut sqlx::Transaction<'_, Any>, url: &str) -> Result<i64> {
+    let key = url_key(url);
+    sqlx::query("INSERT INTO feeds (url, url_key) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+        .bind(canonicalize(url))
+        .bind(&key)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(sqlx::query("SELECT id FROM feeds WHERE url_key = $1")
+        .bind(&key)
+        .fetch_one(&mut *tx)
+        .await?
+        .try_get("id")?)
+}
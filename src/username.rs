@@ -0,0 +1,60 @@
+//! What usernames may look like. They're case-insensitive, so they're
+//! lowercased before being looked up or stored.
+
+use anyhow::{format_err, Result};
+use sqlx::{Any, Pool, Row};
+use tracing::warn;
+
+pub const MAX_USERNAME_LEN: usize = 32;
+
+/// Lowercases a username, checking it's at most `MAX_USERNAME_LEN` ASCII
+/// letters, digits, `-`, `_` and `.`, starting with a letter or digit.
+pub fn normalize(username: &str) -> Result<String> {
+    let username = username.to_ascii_lowercase();
+
+    if username.is_empty() || username.len() > MAX_USERNAME_LEN {
+        return Err(format_err!(
+            "username must be 1 to {} characters long",
+            MAX_USERNAME_LEN
+        ));
+    }
+    if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err(format_err!("username must start with a letter or digit"));
+    }
+    if let Some(c) = username
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !"-_.".contains(*c))
+    {
+        return Err(format_err!("username may not contain '{}'", c));
+    }
+
+    Ok(username)
+}
+
+/// Warns about users created before usernames were normalized who can no
+/// longer sign in: those whose names aren't valid, and those differing
+/// only by case from another user, which the migration lowercasing names
+/// left alone. They need renaming or merging by hand.
+pub async fn warn_about_legacy_usernames(pool: &Pool<Any>) -> Result<()> {
+    let users = sqlx::query("SELECT id, username FROM users ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+
+    for user in users {
+        let id: i64 = user.try_get("id")?;
+        let username: String = user.try_get("username")?;
+        match normalize(&username) {
+            Ok(normalized) if normalized == username => {}
+            Ok(normalized) => warn!(
+                "User {} \"{}\" collides with another user named \"{}\" and can't sign in",
+                id, username, normalized
+            ),
+            Err(e) => warn!(
+                "User {} \"{}\" has an invalid username and can't sign in: {}",
+                id, username, e
+            ),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,39 @@
+//! Starts a fetch cycle on SIGUSR1 or SIGUSR2 instead of waiting for the
+//! scheduler, e.g. after adding a batch of feeds.
+
+use anyhow::Result;
+use tokio::sync::mpsc;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wake {
+    /// Fetch every feed, whether or not it's due.
+    All,
+    /// Fetch the feeds whose next fetch has passed.
+    Overdue,
+}
+
+/// Sends a `Wake` for every SIGUSR1 (`All`) and SIGUSR2 (`Overdue`). Signals
+/// arriving while one is already waiting to be handled are dropped.
+#[cfg(unix)]
+pub async fn wake_on_user_signals(mut sender: mpsc::Sender<Wake>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut all = signal(SignalKind::user_defined1())?;
+    let mut overdue = signal(SignalKind::user_defined2())?;
+    loop {
+        let wake = tokio::select! {
+            Some(()) = all.recv() => Wake::All,
+            Some(()) = overdue.recv() => Wake::Overdue,
+            else => return Ok(()),
+        };
+
+        match wake {
+            Wake::All => info!("Fetching all feeds now"),
+            Wake::Overdue => info!("Fetching overdue feeds now"),
+        }
+        if let Err(mpsc::error::TrySendError::Closed(_)) = sender.try_send(wake) {
+            return Ok(());
+        }
+    }
+}